@@ -11,6 +11,29 @@ pub enum Architecture {
   Riscv64,
 }
 
+/// Defines the formats of the kernel log on the console, which is selected when the
+/// kernel is built (`LOG_FORMAT`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum LogFormat {
+  /// Colored text for humans
+  Colored,
+  /// Text without colors
+  Plain,
+  /// One JSON object per record, which the helper can parse and render
+  Json,
+}
+
+impl LogFormat {
+  /// Returns the value of `LOG_FORMAT` that selects this format.
+  pub const fn as_str(self) -> &'static str {
+    match self {
+      Self::Colored => "colored",
+      Self::Plain => "plain",
+      Self::Json => "json",
+    }
+  }
+}
+
 /// Workspace member that eases working with `unCORE`.
 #[derive(Debug, clap::Parser)]
 #[command(
@@ -85,6 +108,9 @@ fn append_to_base_dir(suffix: &str) -> String {
   /// Returns the default set of arguments required to run QEMU.
   pub fn qemu_arguments(&self) -> Vec<&str> { self.qemu_arguments.clone() }
 
+  /// Returns the path to the kernel binary when `main.rs` is used.
+  pub fn kernel_binary_path(&self) -> &str { &self.kernel_binary_path }
+
   /// Like [`Self::qemu_arguments`], but implicitly adds the standard kernel binary to the
   /// set of arguments.
   pub fn qemu_arguments_with_kernel(&self) -> Vec<&str> {
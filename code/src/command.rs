@@ -10,32 +10,93 @@
 #[derive(Debug, Clone, PartialEq, Eq, Hash, clap::Subcommand)]
 pub enum Command {
   /// Build the kernel
-  Build,
+  Build {
+    /// Pack the given directory into an initramfs that is linked into the kernel
+    #[clap(long, value_name = "DIRECTORY")]
+    initramfs:   Option<std::path::PathBuf>,
+    /// Check the kernel heap for corruption and report leaks (`heap-debug` feature)
+    #[clap(long)]
+    heap_debug:  bool,
+    /// Also write the kernel log via semihosting (`semihosting` feature)
+    #[clap(long)]
+    semihosting: bool,
+    /// The format of the kernel log on the console
+    #[clap(long, value_enum, value_name = "FORMAT")]
+    log_format:  Option<arguments::LogFormat>,
+  },
   /// Run the kernel
   Run {
     /// Specify whether you want to debug the kernel
     #[clap(short, long)]
-    debug: bool,
+    debug:       bool,
+    /// Capture the display (in PPM format) after the kernel has shut down
+    #[clap(long, value_name = "FILE", conflicts_with = "debug")]
+    screenshot:  Option<std::path::PathBuf>,
+    /// Pack the given directory into an initramfs that QEMU passes to the kernel
+    #[clap(long, value_name = "DIRECTORY")]
+    initramfs:   Option<std::path::PathBuf>,
+    /// Attach the given disk image (e.g. created with `mkfs.vfat` or `mke2fs`) as a block
+    /// device
+    #[clap(long, value_name = "FILE")]
+    disk:        Option<std::path::PathBuf>,
+    /// Check the kernel heap for corruption and report leaks (`heap-debug` feature)
+    #[clap(long)]
+    heap_debug:  bool,
+    /// Also write the kernel log via semihosting (`semihosting` feature)
+    #[clap(long)]
+    semihosting: bool,
+    /// The format of the kernel log on the console
+    #[clap(long, value_enum, value_name = "FORMAT")]
+    log_format:  Option<arguments::LogFormat>,
+    /// Pass the given kernel command line to the kernel (e.g. `log.level=debug`)
+    #[clap(long, value_name = "ARGUMENTS")]
+    append:      Option<String>,
   },
   /// Test the kernel by running unit tests
   UTest {
     /// Specify whether you want to debug a test (only works when a specific test is
     /// supplied)
     #[clap(short, long)]
-    debug: bool,
+    debug:  bool,
+    /// Pass the given kernel command line to the kernel (e.g. `test.filter=log`)
+    #[clap(long, value_name = "ARGUMENTS")]
+    append: Option<String>,
   },
   /// Test the kernel by running integration tests
   ITest {
     /// Specify whether you want to debug a test (only works when a specific test is
     /// supplied)
     #[clap(short, long, requires = "test")]
-    debug: bool,
+    debug:       bool,
     /// Specify which test to run
     #[clap(short, long)]
-    test:  Option<String>,
+    test:        Option<String>,
+    /// Capture the display of every test (as `<TEST NAME>.ppm`) into the given directory
+    #[clap(long, value_name = "DIRECTORY", conflicts_with = "debug")]
+    screenshots: Option<std::path::PathBuf>,
+    /// Require a record of the kernel log whose `<MODULE>: <MESSAGE>` matches the given
+    /// regular expression in every test (implies `--log-format json`, may be repeated)
+    #[clap(long, value_name = "REGEX", conflicts_with_all = ["debug", "screenshots"])]
+    expect_log:  Vec<String>,
   },
   /// Check the code (e.g. with `clippy`)
   Check,
+  /// Render the kernel log in the JSON format (see `--log-format`) readably
+  RenderLog {
+    /// The file with the output of the kernel; standard input is read if it is omitted
+    #[clap(value_name = "FILE")]
+    input: Option<std::path::PathBuf>,
+  },
+  /// Decode the crash dumps in the output of the kernel (e.g. a CI log) into reports
+  DecodeDump {
+    /// The file with the output of the kernel; standard input is read if it is omitted
+    #[clap(value_name = "FILE")]
+    input:  Option<std::path::PathBuf>,
+    /// The kernel binary that wrote the dumps, which resolves addresses to function names
+    /// (the kernel built last by default)
+    #[clap(long, value_name = "FILE")]
+    kernel: Option<std::path::PathBuf>,
+  },
   /// Work with `unCORE`'s documentation
   Doc {
     /// Whether to open the documentation immediately
@@ -52,27 +113,96 @@ impl Command {
   /// running, debugging, etc.).
   pub fn execute(arguments: arguments::Arguments) -> anyhow::Result<()> {
     let architecture = arguments.architecture;
-    check_build_time_dependencies(architecture)?;
+    // Decoding crash dumps and rendering the log does not require a toolchain, e.g. on
+    // machines that only collect the output of CI runs
+    if !matches!(
+      arguments.command,
+      Self::DecodeDump { .. } | Self::RenderLog { .. }
+    ) {
+      check_build_time_dependencies(architecture)?;
+    }
     let architecture_specification: &arguments::ArchitectureSpecification = &arguments.architecture.into();
 
     match arguments.command {
-      Self::Build => build(architecture_specification)?,
-      Self::Run { debug } => {
+      Self::Build {
+        initramfs,
+        heap_debug,
+        semihosting,
+        log_format,
+      } => {
+        build(
+          architecture_specification,
+          initramfs.as_deref(),
+          heap_debug,
+          semihosting,
+          log_format,
+        )?;
+      },
+      Self::Run {
+        debug,
+        screenshot,
+        initramfs,
+        disk,
+        heap_debug,
+        semihosting,
+        log_format,
+        append,
+      } => {
         check_run_time_dependencies(architecture, debug)?;
-        build(architecture_specification)?;
-        run(architecture_specification, debug)?;
+        build(
+          architecture_specification,
+          None,
+          heap_debug,
+          semihosting,
+          log_format,
+        )?;
+        let initrd = initramfs.as_deref().map(super::cpio::pack).transpose()?;
+        let disk = disk.as_deref().map(super::disk::DiskImage::open).transpose()?;
+        run(
+          architecture_specification,
+          debug,
+          screenshot.as_deref(),
+          initrd.as_deref(),
+          disk.as_ref(),
+          semihosting,
+          append.as_deref(),
+        )?;
       },
-      Self::UTest { debug } => {
+      Self::UTest { debug, append } => {
         check_run_time_dependencies(architecture, debug)?;
-        run_unit_tests(architecture_specification, debug)?;
+        run_unit_tests(architecture_specification, debug, append.as_deref())?;
       },
-      Self::ITest { debug, test } => {
+      Self::ITest {
+        debug,
+        test,
+        screenshots,
+        expect_log,
+      } => {
         check_run_time_dependencies(architecture, debug)?;
-        run_integration_tests(architecture_specification, debug, test.as_ref())?;
+        check_integration_test_dependencies()?;
+        let expectations = expect_log
+          .iter()
+          .map(|expectation| {
+            regex::Regex::new(expectation).context(format!("Invalid regular expression '{expectation}'"))
+          })
+          .collect::<anyhow::Result<Vec<_>>>()?;
+        run_integration_tests(
+          architecture_specification,
+          debug,
+          test.as_ref(),
+          screenshots.as_deref(),
+          &expectations,
+        )?;
       },
       Self::Check => {
         check(architecture_specification)?;
       },
+      Self::RenderLog { input } => {
+        render_log(input.as_deref())?;
+      },
+      Self::DecodeDump { input, kernel } => {
+        decode_dump(architecture_specification, input.as_deref(), kernel.as_deref())?;
+      },
       Self::Doc { open, watch } => {
         documentation(architecture_specification, open, watch)?;
       },
@@ -141,6 +271,21 @@ fn check_run_time_dependencies(architecture: arguments::Architecture, is_debug:
   Ok(())
 }
 
+/// Checks the dependencies that are additionally required to run integration tests.
+fn check_integration_test_dependencies() -> anyhow::Result<()> {
+  log::debug!("Checking integration test dependencies");
+
+  check_bin!("mkfs.vfat", "dosfstools")?;
+  check_bin!("fsck.vfat", "dosfstools")?;
+  check_bin!("mmd", "mtools")?;
+  check_bin!("mcopy", "mtools")?;
+  check_bin!("mke2fs", "e2fsprogs")?;
+  check_bin!("e2fsck", "e2fsprogs")?;
+
+  log::trace!("Integration test dependencies are satisfied");
+  Ok(())
+}
+
 /// Run a given command, taking arguments and environment variables if necessary, and
 /// evaluates the exit status in the end.
 macro_rules! run_command_and_check {
@@ -208,37 +353,94 @@ macro_rules! run_command_and_check_with_timeout {
   }};
 }
 
-/// Build the kernel.
-fn build(arch_specification: &arguments::ArchitectureSpecification) -> anyhow::Result<()> {
+/// Build the kernel. If `initramfs` is `Some(directory)`, the directory is packed into an
+/// initramfs that is linked into the kernel. If `heap_debug` or `semihosting` is set, the
+/// kernel is built with the `heap-debug` or `semihosting` feature. If `log_format` is
+/// `Some(format)`, the kernel log on the console uses `format`.
+fn build(
+  arch_specification: &arguments::ArchitectureSpecification,
+  initramfs: Option<&std::path::Path>,
+  heap_debug: bool,
+  semihosting: bool,
+  log_format: Option<arguments::LogFormat>,
+) -> anyhow::Result<()> {
   log::info!("Building unCORE");
 
-  let cargo_build_environment =
-    super::environment::get_all_environment_variables_for_build(&arch_specification.linker_script_path)?;
+  let mut cargo_build_environment = super::environment::get_all_environment_variables_for_build(
+    &arch_specification.linker_script_path,
+    log_format,
+  )?;
+  if let Some(directory) = initramfs {
+    let archive = super::cpio::pack(directory)?;
+    cargo_build_environment.insert("INITRAMFS", archive.to_string_lossy().into_owned());
+  }
 
   // TODO Check that, when upgrading to Ubuntu 24.04, we may be able to use `-C
   // link-arg=...` TODO instead of requiring mold to intercept calls to ld.lld via
   // LD_PRELOAD
-  run_command_and_check!(
-    "mold",
-    [
-      "-run",
-      env!("CARGO"),
-      "build",
-      "--package",
-      "uncore",
-      "--target",
-      arch_specification.target,
-    ],
-    cargo_build_environment
-  )?;
+  let mut arguments = vec![
+    "-run",
+    env!("CARGO"),
+    "build",
+    "--package",
+    "uncore",
+    "--target",
+    arch_specification.target,
+  ];
+  if heap_debug {
+    arguments.extend(["--features", "heap-debug"]);
+  }
+  if semihosting {
+    arguments.extend(["--features", "semihosting"]);
+  }
+
+  run_command_and_check!("mold", arguments, cargo_build_environment)?;
+  super::symbols::embed(arch_specification.kernel_binary_path())?;
 
   log::trace!("Finished building unCORE");
   Ok(())
 }
 
-/// Run the kernel.
-fn run(arch_specification: &arguments::ArchitectureSpecification, is_debug: bool) -> anyhow::Result<()> {
+/// Run the kernel. If `screenshot` is `Some(path)`, the display is captured into `path`
+/// after the kernel has shut down. If `initrd` is `Some(path)`, QEMU passes the archive
+/// at `path` to the kernel as its initramfs. If `disk` is `Some(image)`, the image is
+/// attached as a block device. If `semihosting` is set, QEMU handles semihosting calls of
+/// the kernel. If `append` is `Some(arguments)`, QEMU passes `arguments` to the kernel as
+/// its command line.
+fn run(
+  arch_specification: &arguments::ArchitectureSpecification,
+  is_debug: bool,
+  screenshot: Option<&std::path::Path>,
+  initrd: Option<&std::path::Path>,
+  disk: Option<&super::disk::DiskImage>,
+  semihosting: bool,
+  append: Option<&str>,
+) -> anyhow::Result<()> {
   let mut arguments = arch_specification.qemu_arguments_with_kernel();
+  if semihosting {
+    arguments.extend(["-semihosting-config", "enable=on,target=native"]);
+  }
+  if let Some(append) = append {
+    arguments.extend(["-append", append]);
+  }
+  let disk_arguments = disk.map(|disk| disk.qemu_arguments(0)).unwrap_or_default();
+  arguments.extend(disk_arguments.iter().map(String::as_str));
+  if let Some(initrd) = initrd {
+    arguments.push("-initrd");
+    arguments.push(
+      initrd
+        .to_str()
+        .context("Path of the initramfs is not valid UTF-8")?,
+    );
+  }
+  if let Some(path) = screenshot {
+    log::info!("Running unCORE and capturing a screenshot");
+    let screenshot = super::monitor::Screenshot::new(path)?;
+    let extra_arguments = screenshot.qemu_arguments();
+    arguments.extend(extra_arguments.iter().map(String::as_str));
+    return screenshot.capture(arch_specification.qemu_command, &arguments, 60);
+  }
+
   if is_debug {
     log::info!("Debugging unCORE");
     log::debug!("You may use 'gdb-multiarch -q -x code/misc/gdb/<FILE>' to attach now");
@@ -246,25 +448,64 @@ fn run(arch_specification: &arguments::ArchitectureSpecification, is_debug: bool
     arguments.append(&mut vec!["-s", "-S"]);
   } else {
     log::info!("Running unCORE");
-  };
+  }
 
   run_command_and_check!(arch_specification.qemu_command, arguments)
 }
 
+/// Decodes the crash dumps in `input` (or standard input) and prints them as reports.
+/// Addresses are resolved with the symbols of `kernel`, or of the kernel built last if
+/// `kernel` is [`None`].
+fn decode_dump(
+  arch_specification: &arguments::ArchitectureSpecification,
+  input: Option<&std::path::Path>,
+  kernel: Option<&std::path::Path>,
+) -> anyhow::Result<()> {
+  let output = if let Some(input) = input {
+    std::fs::read(input).context(format!("Could not read '{}'", input.display()))?
+  } else {
+    let mut output = vec![];
+    std::io::Read::read_to_end(&mut std::io::stdin(), &mut output)?;
+    output
+  };
+
+  let dumps = super::crash_dump::decode(&String::from_utf8_lossy(&output))?;
+  if dumps.is_empty() {
+    anyhow::bail!("The output does not contain a crash dump");
+  }
+
+  let kernel = kernel.unwrap_or_else(|| std::path::Path::new(arch_specification.kernel_binary_path()));
+  let functions = match super::symbols::Functions::load(kernel) {
+    Ok(functions) => Some(functions),
+    Err(error) => {
+      log::warn!("Addresses are not resolved to function names: {error}");
+      None
+    },
+  };
+
+  for dump in &dumps {
+    println!("{}", super::crash_dump::report(dump, functions.as_ref()));
+  }
+  Ok(())
+}
+
 /// Builds test binaries. Depending on the input, this function builds unit or integration
 /// test binaries (or a single binary). The output is a list of binaries that are to be
-/// run.
+/// run. If `log_format` is `Some(format)`, the kernel log on the console uses `format`.
 fn create_test_binaries<I, S>(
   arch_specification: &arguments::ArchitectureSpecification,
   extra_cargo_arguments: I,
+  log_format: Option<arguments::LogFormat>,
 ) -> anyhow::Result<Vec<String>>
 where
   I: Clone + IntoIterator<Item = S>,
   S: AsRef<std::ffi::OsStr>,
 {
   // Prepare the environment for building the test binary
-  let cargo_build_environment =
-    super::environment::get_all_environment_variables_for_build(&arch_specification.linker_script_path)?;
+  let cargo_build_environment = super::environment::get_all_environment_variables_for_build(
+    &arch_specification.linker_script_path,
+    log_format,
+  )?;
 
   let cargo_arguments = [
     "test",
@@ -321,6 +562,9 @@ fn create_test_binaries<I, S>(
   if test_binaries.is_empty() {
     anyhow::bail!("Cargo did not create a test binary?!");
   }
+  for binary in &test_binaries {
+    super::symbols::embed(binary)?;
+  }
 
   Ok(test_binaries)
 }
@@ -379,18 +623,24 @@ fn create_gdb_init_file(binary_location: &String, test_name: &String) -> anyhow:
 /// Runs all unit tests. In case of `unCORE`, only the library part contains unit tests
 /// (i.e. only files associated with `lib.rs`, which are all files except for `main.rs`;
 /// hence, all files belong to the library). This makes running the tests and extracting
-/// the binary to run easy as there is only one.
+/// the binary to run easy as there is only one. If `append` is `Some(arguments)`, QEMU
+/// passes `arguments` to the kernel as its command line, e.g. to select tests with
+/// `test.filter`.
 fn run_unit_tests(
   arch_specification: &arguments::ArchitectureSpecification,
   is_debug: bool,
+  append: Option<&str>,
 ) -> anyhow::Result<()> {
   log::info!("Building unit test binary");
-  let binary_path = create_test_binaries(arch_specification, ["--lib"])?;
+  let binary_path = create_test_binaries(arch_specification, ["--lib"], None)?;
   let binary_path = binary_path
     .first()
     .expect("This is unreachable since create_test_binaries already checks for emptiness");
   let mut qemu_arguments = arch_specification.qemu_arguments();
   qemu_arguments.append(&mut vec!["-kernel", binary_path]);
+  if let Some(append) = append {
+    qemu_arguments.extend(["-append", append]);
+  }
 
   if is_debug {
     log::info!("Debugging unCORE unit tests");
@@ -409,22 +659,32 @@ fn run_unit_tests(
 
 /// Runs all or a specific integration test. When `is_debug` is `true`, then QEMU can be
 /// attached to debug the test. If `test` is `Some(test_name)`, then the integration test
-/// with the name `test_name` is built and run.
+/// with the name `test_name` is built and run. If `screenshots` is `Some(directory)`, the
+/// display of every test is captured into `directory` after the test has finished. If
+/// `expectations` is not empty, the tests log in the JSON format, their log is rendered
+/// readably, and each of them fails unless every expectation matches one of its records
+/// (see [`super::kernel_log::check_expectations`]).
+///
+/// Every test gets a fresh FAT32 image and a fresh ext2 image as block devices, which are
+/// checked with `fsck.vfat` and `e2fsck` after the test has finished.
 fn run_integration_tests(
   arch_specification: &arguments::ArchitectureSpecification,
   is_debug: bool,
   test: Option<&String>,
+  screenshots: Option<&std::path::Path>,
+  expectations: &[regex::Regex],
 ) -> anyhow::Result<()> {
   log::info!("Building integration test binaries");
+  let log_format = (!expectations.is_empty()).then_some(arguments::LogFormat::Json);
   let mut qemu_arguments = arch_specification.qemu_arguments();
   let test_binary_paths = if let Some(test) = test {
     // If a test name is supplied, we may debug it
     if is_debug {
       qemu_arguments.append(&mut vec!["-s", "-S"]);
     }
-    create_test_binaries(arch_specification, ["--test", test])?
+    create_test_binaries(arch_specification, ["--test", test], log_format)?
   } else {
-    create_test_binaries(arch_specification, ["--test", "*"])?
+    create_test_binaries(arch_specification, ["--test", "*"], log_format)?
   };
 
   // Run every test in the list of integration tests
@@ -437,15 +697,98 @@ fn run_integration_tests(
       log::info!("Running integration test '{}'", test_name);
     }
     log::trace!("The integration test binary file is '{}'", binary);
+    let disks = super::disk::DiskImage::create_test_images()?;
+    let disk_arguments: Vec<String> = disks
+      .iter()
+      .enumerate()
+      .flat_map(|(index, disk)| disk.qemu_arguments(index))
+      .collect();
     let mut current_arguments = qemu_arguments.clone();
     current_arguments.append(&mut vec!["-kernel", &binary]);
-    run_command_and_check_with_timeout!(arch_specification.qemu_command, current_arguments, 60)?;
+    current_arguments.extend(disk_arguments.iter().map(String::as_str));
+    if let Some(directory) = screenshots {
+      std::fs::create_dir_all(directory)?;
+      let screenshot = super::monitor::Screenshot::new(&directory.join(format!("{test_name}.ppm")))?;
+      let extra_arguments = screenshot.qemu_arguments();
+      current_arguments.extend(extra_arguments.iter().map(String::as_str));
+      screenshot.capture(arch_specification.qemu_command, &current_arguments, 60)?;
+    } else if !expectations.is_empty() {
+      run_and_check_log(
+        arch_specification.qemu_command,
+        &current_arguments,
+        60,
+        expectations,
+      )?;
+    } else {
+      run_command_and_check_with_timeout!(arch_specification.qemu_command, current_arguments, 60)?;
+    }
+    for disk in &disks {
+      disk.check()?;
+    }
     log::info!("Integration test '{}' finished successfully", test_name);
   }
 
   Ok(())
 }
 
+/// Runs `command` with `arguments` like [`run_command_and_check_with_timeout`], but
+/// renders the kernel log in its output readably and checks that the log contains
+/// records that match `expectations`.
+fn run_and_check_log(
+  command: &str,
+  arguments: &[&str],
+  timeout_in_secs: u64,
+  expectations: &[regex::Regex],
+) -> anyhow::Result<()> {
+  use std::io::BufRead;
+  use wait_timeout::ChildExt;
+
+  let mut child = std::process::Command::new(command)
+    .args(arguments)
+    .stdout(std::process::Stdio::piped())
+    .spawn()?;
+  let output = child.stdout.take().context("Could not acquire stdout of QEMU")?;
+  let reader = std::thread::spawn(move || {
+    let mut records = vec![];
+    for line in std::io::BufReader::new(output).lines().map_while(Result::ok) {
+      println!("{}", super::kernel_log::render(&line));
+      records.extend(super::kernel_log::Record::parse(&line));
+    }
+    records
+  });
+
+  let Some(status) = child.wait_timeout(std::time::Duration::from_secs(timeout_in_secs))? else {
+    child.kill()?;
+    child.wait()?;
+    anyhow::bail!("Failure: last command timed out");
+  };
+  let records = reader
+    .join()
+    .map_err(|_| anyhow::anyhow!("Could not read the output of QEMU"))?;
+  if !status.success() {
+    anyhow::bail!("Failure: command exited with {status}");
+  }
+  super::kernel_log::check_expectations(&records, expectations)
+}
+
+/// Renders the kernel log in `input` (or standard input) readably, see
+/// [`super::kernel_log::render`].
+fn render_log(input: Option<&std::path::Path>) -> anyhow::Result<()> {
+  use std::io::BufRead;
+
+  let input: Box<dyn BufRead> = if let Some(input) = input {
+    Box::new(std::io::BufReader::new(
+      std::fs::File::open(input).context(format!("Could not read '{}'", input.display()))?,
+    ))
+  } else {
+    Box::new(std::io::stdin().lock())
+  };
+  for line in input.lines() {
+    println!("{}", super::kernel_log::render(&line?));
+  }
+  Ok(())
+}
+
 /// Perform miscellaneous code (quality) checks:
 ///
 /// - `cargo clippy`: general code quality
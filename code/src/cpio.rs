@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module packs a directory into a `cpio` archive in the "newc" format, which
+//! `unCORE` unpacks as its initial RAM file system (initramfs).
+
+use anyhow::Context;
+
+/// The location of the archive, relative to the workspace directory.
+const ARCHIVE_PATH: &str = "target/initramfs.cpio";
+
+/// The type bits of the mode of a regular file.
+const TYPE_REGULAR: u32 = 0o100_000;
+/// The type bits of the mode of a directory.
+const TYPE_DIRECTORY: u32 = 0o040_000;
+/// The type bits of the mode of a symbolic link.
+const TYPE_SYMBOLIC_LINK: u32 = 0o120_000;
+
+/// Writes the entries of an archive.
+struct Writer {
+  /// The archive written so far
+  archive:    Vec<u8>,
+  /// Maps the (device, inode) pairs of the host to the inode numbers in the archive, so
+  /// that hard links are preserved
+  inodes:     std::collections::HashMap<(u64, u64), u32>,
+  /// The inode number of the next entry
+  next_inode: u32,
+}
+
+impl Writer {
+  /// Appends an entry named `name` with the given `mode`, number of `links` and `data`.
+  fn append(
+    &mut self,
+    name: &str,
+    inode: u32,
+    mode: u32,
+    links: u32,
+    modified: u32,
+    data: &[u8],
+  ) -> anyhow::Result<()> {
+    let file_size = u32::try_from(data.len()).context(format!("File '{name}' is too large"))?;
+    let name_size = u32::try_from(name.len() + 1).context(format!("Name '{name}' is too long"))?;
+
+    // The fields are the inode number, mode, user ID, group ID, number of links,
+    // modification time, file size, device major and minor number, rdev major and minor
+    // number, name size and checksum
+    let fields = [
+      inode, mode, 0, 0, links, modified, file_size, 0, 0, 0, 0, name_size, 0,
+    ];
+    self.archive.extend_from_slice(b"070701");
+    for field in fields {
+      self.archive.extend_from_slice(format!("{field:08X}").as_bytes());
+    }
+
+    self.archive.extend_from_slice(name.as_bytes());
+    self.archive.push(0);
+    self.pad();
+    self.archive.extend_from_slice(data);
+    self.pad();
+    Ok(())
+  }
+
+  /// Pads the archive to a multiple of 4 bytes.
+  fn pad(&mut self) { self.archive.resize(self.archive.len().next_multiple_of(4), 0); }
+
+  /// Appends the file or directory at `path` under the name `name`, and all entries of a
+  /// directory recursively.
+  fn append_path(&mut self, path: &std::path::Path, name: &str) -> anyhow::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::symlink_metadata(path).context(format!("Could not read '{}'", path.display()))?;
+    let permissions = metadata.mode() & 0o7777;
+    let modified = u32::try_from(metadata.mtime()).unwrap_or_default();
+    let links = u32::try_from(metadata.nlink()).unwrap_or(1);
+
+    // Hard links share one inode number; only the first entry carries the data
+    let key = (metadata.dev(), metadata.ino());
+    let (inode, is_first) = if let Some(inode) = self.inodes.get(&key) {
+      (*inode, false)
+    } else {
+      let inode = self.next_inode;
+      self.next_inode += 1;
+      self.inodes.insert(key, inode);
+      (inode, true)
+    };
+
+    let file_type = metadata.file_type();
+    if file_type.is_dir() {
+      self.append(name, inode, TYPE_DIRECTORY | permissions, links, modified, &[])?;
+
+      let mut entries = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+      entries.sort();
+      for entry in entries {
+        let entry_name = entry
+          .to_str()
+          .context(format!("Name '{}' is not valid UTF-8", entry.to_string_lossy()))?;
+        let child_name = if name == "." {
+          entry_name.to_string()
+        } else {
+          format!("{name}/{entry_name}")
+        };
+        self.append_path(&path.join(&entry), &child_name)?;
+      }
+    } else if file_type.is_symlink() {
+      let target = std::fs::read_link(path)?;
+      let target = target
+        .to_str()
+        .context(format!("Target of '{}' is not valid UTF-8", path.display()))?;
+      self.append(
+        name,
+        inode,
+        TYPE_SYMBOLIC_LINK | 0o777,
+        1,
+        modified,
+        target.as_bytes(),
+      )?;
+    } else if file_type.is_file() {
+      let data = if is_first {
+        std::fs::read(path)?
+      } else {
+        Vec::new()
+      };
+      self.append(name, inode, TYPE_REGULAR | permissions, links, modified, &data)?;
+    } else {
+      log::warn!(
+        "Skipping '{}' as it is not a file, directory or symbolic link",
+        path.display()
+      );
+    }
+
+    Ok(())
+  }
+}
+
+/// Returns an archive of the directory `directory`, which ends with the `TRAILER!!!`
+/// entry.
+fn archive(directory: &std::path::Path) -> anyhow::Result<Vec<u8>> {
+  if !directory.is_dir() {
+    anyhow::bail!("'{}' is not a directory", directory.display());
+  }
+
+  let mut writer = Writer {
+    archive:    Vec::new(),
+    inodes:     std::collections::HashMap::new(),
+    next_inode: 1,
+  };
+  writer.append_path(directory, ".")?;
+  writer.append("TRAILER!!!", 0, 0, 1, 0, &[])?;
+  Ok(writer.archive)
+}
+
+/// Packs the directory `directory` into an archive and returns the path of the archive.
+pub fn pack(directory: &std::path::Path) -> anyhow::Result<std::path::PathBuf> {
+  log::debug!("Packing '{}' into an initramfs archive", directory.display());
+  let archive = archive(directory)?;
+
+  let archive_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(ARCHIVE_PATH);
+  if let Some(parent) = archive_path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  std::fs::write(&archive_path, &archive).context(format!("Could not write '{}'", archive_path.display()))?;
+
+  log::trace!("The initramfs archive is '{}'", archive_path.display());
+  Ok(archive_path)
+}
+
+#[cfg(test)]
+mod tests {
+  /// The size of the header of an entry: the magic number and 13 fields.
+  const HEADER_SIZE: usize = 6 + 13 * 8;
+
+  /// Returns the header field at `index` of the entry that starts at `offset`.
+  fn field(archive: &[u8], offset: usize, index: usize) -> usize {
+    let start = offset + 6 + index * 8;
+    let text = std::str::from_utf8(&archive[start..start + 8]).unwrap();
+    usize::from_str_radix(text, 16).unwrap()
+  }
+
+  #[test]
+  fn entries_are_padded_and_the_archive_ends_with_the_trailer() {
+    let directory = std::env::temp_dir().join(format!("uncore-cpio-{}", std::process::id()));
+    std::fs::create_dir_all(directory.join("etc")).unwrap();
+    std::fs::write(directory.join("etc/hostname"), b"uncore").unwrap();
+    let archive = super::archive(&directory);
+    std::fs::remove_dir_all(&directory).unwrap();
+    let archive = archive.unwrap();
+
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset < archive.len() {
+      assert_eq!(&archive[offset..offset + 6], b"070701");
+      let name_start = offset + HEADER_SIZE;
+      let name_end = name_start + field(&archive, offset, 11);
+      // The name is terminated by a zero byte, and name and data are padded to 4 bytes
+      assert_eq!(archive[name_end - 1], 0);
+      let data_start = name_end.next_multiple_of(4);
+      let data_end = data_start + field(&archive, offset, 6);
+      let next = data_end.next_multiple_of(4);
+      assert!(archive[name_end..data_start].iter().all(|&byte| byte == 0));
+      assert!(archive[data_end..next].iter().all(|&byte| byte == 0));
+
+      entries.push((
+        std::str::from_utf8(&archive[name_start..name_end - 1]).unwrap(),
+        field(&archive, offset, 1) & 0o170_000,
+        &archive[data_start..data_end],
+      ));
+      offset = next;
+    }
+
+    assert_eq!(offset, archive.len());
+    assert_eq!(
+      entries,
+      [
+        (".", 0o040_000, &b""[..]),
+        ("etc", 0o040_000, b""),
+        ("etc/hostname", 0o100_000, b"uncore"),
+        ("TRAILER!!!", 0, b""),
+      ]
+    );
+  }
+}
@@ -0,0 +1,325 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module decodes the crash dumps that `unCORE` writes to the console when it
+//! panics into readable reports. The format of the dumps is documented in
+//! `uncore/src/library/crash/mod.rs`.
+
+use anyhow::Context;
+
+/// The line that starts a crash dump.
+const BEGIN: &str = "=== unCORE crash dump v1 ===";
+/// The prefix of lines that hold the data of a crash dump.
+const LINE_PREFIX: &str = "CD ";
+/// The prefix of the line that ends a crash dump.
+const END: &str = "=== end of crash dump";
+
+/// The ABI names of the general-purpose registers `x0` to `x31`.
+const REGISTER_NAMES: [&str; 32] = [
+  "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5", "a6",
+  "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+/// A crash dump.
+#[derive(Debug)]
+pub struct Dump {
+  /// The sections of the dump as pairs of tag and contents
+  sections: Vec<([u8; 4], Vec<u8>)>,
+  /// Whether the dump is complete and its checksum matches
+  verified: bool,
+  /// The number of bytes of the dump
+  length:   usize,
+}
+
+/// Returns the CRC-32 (IEEE 802.3) of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+  let mut crc = !0_u32;
+  for &byte in bytes {
+    crc ^= u32::from(byte);
+    for _ in 0..8 {
+      crc = if crc & 1 == 1 {
+        (crc >> 1) ^ 0xEDB8_8320
+      } else {
+        crc >> 1
+      };
+    }
+  }
+  !crc
+}
+
+impl Dump {
+  /// Splits the bytes of a dump into its sections. Trailing bytes that do not form a
+  /// complete section are ignored.
+  fn new(bytes: &[u8], verified: bool) -> Self {
+    let mut sections = vec![];
+    let mut rest = bytes;
+    while rest.len() >= 8 {
+      let tag = [rest[0], rest[1], rest[2], rest[3]];
+      let length = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+      let Some(contents) = rest.get(8..8 + length) else {
+        break;
+      };
+      sections.push((tag, contents.to_vec()));
+      rest = &rest[8 + length..];
+    }
+    Self {
+      sections,
+      verified,
+      length: bytes.len(),
+    }
+  }
+
+  /// Returns the contents of the section with `tag`.
+  fn section(&self, tag: [u8; 4]) -> Option<&[u8]> {
+    self
+      .sections
+      .iter()
+      .find(|(section_tag, _)| *section_tag == tag)
+      .map(|(_, contents)| contents.as_slice())
+  }
+}
+
+/// Returns the little-endian `u64` values in `bytes`.
+fn words(bytes: &[u8]) -> impl Iterator<Item = u64> + '_ {
+  bytes
+    .chunks_exact(8)
+    .map(|word| u64::from_le_bytes(word.try_into().unwrap_or_default()))
+}
+
+/// Finds and decodes all crash dumps in `output`, the output of the kernel. Dumps whose
+/// end is missing or whose checksum does not match are returned as well, but are marked
+/// as not verified.
+pub fn decode(output: &str) -> anyhow::Result<Vec<Dump>> {
+  let mut dumps = vec![];
+  let mut current: Option<Vec<u8>> = None;
+
+  for line in output.lines().map(str::trim) {
+    if line.ends_with(BEGIN) {
+      if let Some(bytes) = current.replace(vec![]) {
+        log::warn!("Crash dump is truncated");
+        dumps.push(Dump::new(&bytes, false));
+      }
+    } else if let Some(bytes) = current.as_mut() {
+      if let Some(data) = line.strip_prefix(LINE_PREFIX) {
+        for digits in data.as_bytes().chunks(2) {
+          let digits = std::str::from_utf8(digits)?;
+          bytes.push(u8::from_str_radix(digits, 16).context("Crash dump holds invalid data")?);
+        }
+      } else if let Some(trailer) = line.strip_prefix(END) {
+        let bytes = current.take().unwrap_or_default();
+        let (length, crc) = parse_trailer(trailer).context("Crash dump has an invalid end")?;
+        let verified = length == bytes.len() && crc == crc32(&bytes);
+        if !verified {
+          log::warn!("Crash dump is corrupted (length or checksum does not match)");
+        }
+        dumps.push(Dump::new(&bytes, verified));
+      }
+    }
+  }
+
+  if let Some(bytes) = current {
+    log::warn!("Crash dump is truncated");
+    dumps.push(Dump::new(&bytes, false));
+  }
+  Ok(dumps)
+}
+
+/// Parses the number of bytes and the checksum after [`END`], e.g. `: 1234 bytes, CRC-32
+/// 0a1b2c3d ===`.
+fn parse_trailer(trailer: &str) -> Option<(usize, u32)> {
+  let trailer = trailer.strip_prefix(": ")?.strip_suffix(" ===")?;
+  let (length, crc) = trailer.split_once(" bytes, CRC-32 ")?;
+  Some((length.parse().ok()?, u32::from_str_radix(crc, 16).ok()?))
+}
+
+/// Formats `address`, followed by the function that contains it if `functions` is
+/// available.
+fn code_address(address: u64, functions: Option<&super::symbols::Functions>) -> String {
+  match functions.and_then(|functions| functions.lookup(address)) {
+    Some((name, offset)) => format!("{address:#018x} <{name}+{offset:#x}>"),
+    None => format!("{address:#018x}"),
+  }
+}
+
+/// Renders `dump` as a readable report. If `functions` is available, addresses are
+/// resolved to function names.
+pub fn report(dump: &Dump, functions: Option<&super::symbols::Functions>) -> String {
+  use std::fmt::Write;
+
+  let mut report = String::new();
+  let _ = writeln!(
+    report,
+    "Crash dump ({} bytes, {})",
+    dump.length,
+    if dump.verified {
+      "checksum verified"
+    } else {
+      "INCOMPLETE OR CORRUPTED"
+    }
+  );
+
+  if let Some(information) = dump.section(*b"INFO") {
+    let _ = writeln!(
+      report,
+      "\n== Information ==\n{}",
+      String::from_utf8_lossy(information).trim_end()
+    );
+  }
+
+  if let Some(registers) = dump.section(*b"REGS") {
+    let registers: Vec<u64> = words(registers).collect();
+    let _ = writeln!(report, "\n== Registers ==");
+    let special = ["pc", "sstatus", "scause", "stval"];
+    for (index, value) in registers.iter().enumerate().skip(1) {
+      let name = REGISTER_NAMES
+        .get(index)
+        .or_else(|| special.get(index - REGISTER_NAMES.len()))
+        .unwrap_or(&"?");
+      let value = if matches!(*name, "pc" | "ra") {
+        code_address(*value, functions)
+      } else {
+        format!("{value:#018x}")
+      };
+      let _ = writeln!(report, "{name:>7} {value}");
+    }
+  }
+
+  if let Some(backtrace) = dump.section(*b"BTRC") {
+    let _ = writeln!(report, "\n== Backtrace ==");
+    for (frame, return_address) in words(backtrace).enumerate() {
+      // The return address follows the call, which may be the last instruction of the
+      // calling function
+      let _ = writeln!(
+        report,
+        "#{frame:<2} {}",
+        code_address(return_address.saturating_sub(1), functions)
+      );
+    }
+  }
+
+  if let Some(stack) = dump.section(*b"STCK") {
+    let stack_pointer = words(stack).next().unwrap_or_default();
+    let _ = writeln!(report, "\n== Stack (sp = {stack_pointer:#x}) ==");
+    for (index, value) in words(stack.get(8..).unwrap_or_default()).enumerate() {
+      let address = stack_pointer + 8 * index as u64;
+      let function = functions
+        .and_then(|functions| functions.lookup(value))
+        .map(|(name, offset)| format!(" <{name}+{offset:#x}>"))
+        .unwrap_or_default();
+      let _ = writeln!(report, "{address:#018x}: {value:#018x}{function}");
+    }
+  }
+
+  if let Some(log) = dump.section(*b"LOGS") {
+    let _ = writeln!(
+      report,
+      "\n== Recent log ==\n{}",
+      String::from_utf8_lossy(log).trim_end()
+    );
+  }
+
+  report
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    crc32,
+    decode,
+    report,
+    BEGIN,
+    END,
+    LINE_PREFIX,
+  };
+
+  /// Returns the bytes of a dump with `sections`.
+  fn dump(sections: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (tag, contents) in sections {
+      bytes.extend_from_slice(*tag);
+      bytes.extend_from_slice(&u32::try_from(contents.len()).unwrap().to_le_bytes());
+      bytes.extend_from_slice(contents);
+    }
+    bytes
+  }
+
+  /// Returns the console output of `bytes` as the kernel writes it, with lines of 16
+  /// bytes, and ends it with a trailer with `crc` unless `crc` is [`None`].
+  fn output(bytes: &[u8], crc: Option<u32>) -> String {
+    use std::fmt::Write;
+
+    let mut output = format!("[ERROR] kernel panicked\n{BEGIN}\n");
+    for line in bytes.chunks(16) {
+      output.push_str(LINE_PREFIX);
+      for byte in line {
+        let _ = write!(output, "{byte:02x}");
+      }
+      output.push('\n');
+    }
+    if let Some(crc) = crc {
+      let _ = writeln!(output, "{END}: {} bytes, CRC-32 {crc:08x} ===", bytes.len());
+    }
+    output
+  }
+
+  #[test]
+  fn the_checksum_is_the_crc_32_of_ieee_802_3() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+  }
+
+  #[test]
+  fn valid_dumps_are_verified_and_reported() {
+    let mut registers = vec![0_u8; 36 * 8];
+    registers[10 * 8] = 0x2A;
+    registers[32 * 8..33 * 8].copy_from_slice(&0x8020_0000_u64.to_le_bytes());
+    let bytes = dump(&[(b"INFO", b"panicked at main.rs:1:1"), (b"REGS", &registers)]);
+
+    let dumps = decode(&output(&bytes, Some(crc32(&bytes)))).unwrap();
+    assert_eq!(dumps.len(), 1);
+    assert!(dumps[0].verified);
+    assert_eq!(dumps[0].length, bytes.len());
+    assert_eq!(dumps[0].section(*b"INFO"), Some(&b"panicked at main.rs:1:1"[..]));
+
+    let report = report(&dumps[0], None);
+    assert!(report.starts_with(&format!("Crash dump ({} bytes, checksum verified)", bytes.len())));
+    assert!(report.contains("panicked at main.rs:1:1"));
+    assert!(report.contains("     a0 0x000000000000002a"));
+    assert!(report.contains("     pc 0x0000000080200000"));
+  }
+
+  #[test]
+  fn dumps_with_a_wrong_checksum_are_not_verified() {
+    let bytes = dump(&[(b"INFO", b"panicked")]);
+    let dumps = decode(&output(&bytes, Some(crc32(&bytes) ^ 1))).unwrap();
+
+    assert_eq!(dumps.len(), 1);
+    assert!(!dumps[0].verified);
+    assert_eq!(dumps[0].section(*b"INFO"), Some(&b"panicked"[..]));
+    assert!(report(&dumps[0], None).contains("INCOMPLETE OR CORRUPTED"));
+  }
+
+  #[test]
+  fn truncated_dumps_keep_their_complete_sections() {
+    let bytes = dump(&[(b"INFO", b"panicked"), (b"LOGS", b"the last records")]);
+    let truncated = &bytes[..bytes.len() - 4];
+
+    // A dump is truncated by the next one as well as by the end of the output
+    let complete = dump(&[(b"INFO", b"again")]);
+    let text =
+      output(truncated, None) + &output(&complete, Some(crc32(&complete))) + &output(truncated, None);
+    let dumps = decode(&text).unwrap();
+
+    assert_eq!(dumps.len(), 3);
+    assert!(!dumps[0].verified && dumps[1].verified && !dumps[2].verified);
+    for dump in [&dumps[0], &dumps[2]] {
+      assert_eq!(dump.length, truncated.len());
+      assert_eq!(dump.section(*b"INFO"), Some(&b"panicked"[..]));
+      assert_eq!(dump.section(*b"LOGS"), None);
+    }
+  }
+
+  #[test]
+  fn dumps_with_invalid_data_are_rejected() {
+    assert!(decode(&format!("{BEGIN}\n{LINE_PREFIX}0g\n")).is_err());
+    assert!(decode(&format!("{BEGIN}\n{END}: many bytes ===\n")).is_err());
+  }
+}
@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module creates and inspects disk images that are attached to `unCORE` as `VirtIO`
+//! block devices. The images are created with standard tools, so that files can be
+//! exchanged with the host: FAT32 images with `mkfs.vfat` (from `dosfstools`) and
+//! `mtools`, ext2 images with `mke2fs` (from `e2fsprogs`).
+
+use anyhow::Context;
+
+/// The location of the FAT32 image for integration tests, relative to the workspace
+/// directory.
+const FAT32_IMAGE_PATH: &str = "target/fat32.img";
+
+/// The size of the FAT32 image for integration tests in KiB. FAT32 requires at least
+/// 65525 clusters; with clusters of one 512-byte sector, the image must be larger than
+/// 32 MiB.
+const FAT32_IMAGE_SIZE: &str = "65536";
+
+/// The directories the FAT32 image for integration tests contains.
+const FAT32_DIRECTORIES: &[&str] = &["Directory"];
+
+/// The files the FAT32 image for integration tests contains, as pairs of path and
+/// contents. The integration test `fat32` expects exactly these files.
+const FAT32_FILES: &[(&str, &str)] = &[
+  ("A file with a long name.txt", "Hello from the host!\n"),
+  ("README", "This file has a short name.\n"),
+  ("Directory/nested.txt", "This file is located in a directory.\n"),
+  ("Remove me.txt", "The kernel removes this file.\n"),
+];
+
+/// The location of the ext2 image for integration tests, relative to the workspace
+/// directory.
+const EXT2_IMAGE_PATH: &str = "target/ext2.img";
+
+/// The size of the ext2 image for integration tests.
+const EXT2_IMAGE_SIZE: &str = "8M";
+
+/// The files the ext2 image for integration tests contains, as triples of path,
+/// permissions and contents. The integration test `ext2` expects exactly these files,
+/// together with the links in [`EXT2_HARD_LINKS`] and [`EXT2_SYMBOLIC_LINKS`] and the
+/// file `large.bin`.
+const EXT2_FILES: &[(&str, u32, &str)] = &[
+  ("hello.txt", 0o644, "Hello from the host!\n"),
+  ("secret", 0o600, "Only for root.\n"),
+  (
+    "directory/nested.txt",
+    0o644,
+    "This file is located in a directory.\n",
+  ),
+  ("remove-me.txt", 0o644, "The kernel removes this file.\n"),
+];
+
+/// The hard links of the ext2 image for integration tests, as pairs of path and
+/// existing file.
+const EXT2_HARD_LINKS: &[(&str, &str)] = &[("hello-link.txt", "hello.txt")];
+
+/// The symbolic links of the ext2 image for integration tests, as pairs of path and
+/// target.
+const EXT2_SYMBOLIC_LINKS: &[(&str, &str)] = &[("link-to-nested", "directory/nested.txt")];
+
+/// The size of the file `large.bin` on the ext2 image, whose byte at index `i` is
+/// `i % 251`. With blocks of 1 KiB, the file needs a double indirect block.
+const EXT2_LARGE_FILE_SIZE: usize = 300 * 1024;
+
+/// Runs `command` with `arguments` and fails if the command does not succeed.
+fn run(command: &str, arguments: &[&std::ffi::OsStr]) -> anyhow::Result<()> {
+  let output = std::process::Command::new(command)
+    .args(arguments)
+    .output()
+    .context(format!("Could not run '{command}'"))?;
+  if !output.status.success() {
+    anyhow::bail!(
+      "'{command}' failed: {}{}",
+      String::from_utf8_lossy(&output.stdout),
+      String::from_utf8_lossy(&output.stderr)
+    );
+  }
+  Ok(())
+}
+
+/// Returns the absolute path of `path`, which is relative to the workspace directory,
+/// after removing a previous file at this path.
+fn fresh_path(path: &str) -> anyhow::Result<std::path::PathBuf> {
+  let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(path);
+  if path.exists() {
+    std::fs::remove_file(&path)?;
+  }
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  Ok(path)
+}
+
+/// The file systems of the images for integration tests.
+#[derive(Debug, Copy, Clone)]
+enum FileSystem {
+  /// FAT32, checked with `fsck.vfat`
+  Fat32,
+  /// ext2, checked with `e2fsck`
+  Ext2,
+}
+
+/// A disk image that is attached to QEMU as a `VirtIO` block device.
+#[derive(Debug)]
+pub struct DiskImage {
+  /// The path of the image
+  path:        std::path::PathBuf,
+  /// The file system of the image if the helper created it
+  file_system: Option<FileSystem>,
+}
+
+impl DiskImage {
+  /// Uses the existing image at `path`.
+  pub fn open(path: &std::path::Path) -> anyhow::Result<Self> {
+    if !path.is_file() {
+      anyhow::bail!("Disk image '{}' does not exist", path.display());
+    }
+    Ok(Self {
+      path:        path.to_path_buf(),
+      file_system: None,
+    })
+  }
+
+  /// Creates fresh images for integration tests: a FAT32 image and an ext2 image.
+  pub fn create_test_images() -> anyhow::Result<Vec<Self>> {
+    Ok(vec![Self::create_fat32_image()?, Self::create_ext2_image()?])
+  }
+
+  /// Creates a FAT32 image that contains the files in [`FAT32_FILES`].
+  fn create_fat32_image() -> anyhow::Result<Self> {
+    let path = fresh_path(FAT32_IMAGE_PATH)?;
+    log::debug!("Creating the FAT32 image '{}'", path.display());
+
+    run(
+      "mkfs.vfat",
+      &[
+        "-F".as_ref(),
+        "32".as_ref(),
+        "-s".as_ref(),
+        "1".as_ref(),
+        "-n".as_ref(),
+        "UNCORE".as_ref(),
+        "-C".as_ref(),
+        path.as_os_str(),
+        FAT32_IMAGE_SIZE.as_ref(),
+      ],
+    )?;
+
+    let image = ["-i".as_ref(), path.as_os_str()];
+    for directory in FAT32_DIRECTORIES {
+      let target = format!("::/{directory}");
+      run("mmd", &[&image[..], &[target.as_ref()]].concat())?;
+    }
+
+    let files = path.with_extension("files");
+    std::fs::create_dir_all(&files)?;
+    for (index, (name, contents)) in FAT32_FILES.iter().enumerate() {
+      let source = files.join(index.to_string());
+      std::fs::write(&source, contents)?;
+      let target = format!("::/{name}");
+      run(
+        "mcopy",
+        &[&image[..], &[source.as_os_str(), target.as_ref()]].concat(),
+      )?;
+    }
+    std::fs::remove_dir_all(&files)?;
+
+    Ok(Self {
+      path,
+      file_system: Some(FileSystem::Fat32),
+    })
+  }
+
+  /// Creates an ext2 image with blocks of 1 KiB that contains the files in
+  /// [`EXT2_FILES`] and the links in [`EXT2_HARD_LINKS`] and [`EXT2_SYMBOLIC_LINKS`].
+  fn create_ext2_image() -> anyhow::Result<Self> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = fresh_path(EXT2_IMAGE_PATH)?;
+    log::debug!("Creating the ext2 image '{}'", path.display());
+
+    // `mke2fs` copies the directory including permissions and links
+    let files = path.with_extension("files");
+    if files.exists() {
+      std::fs::remove_dir_all(&files)?;
+    }
+    for (name, permissions, contents) in EXT2_FILES {
+      let file = files.join(name);
+      if let Some(parent) = file.parent() {
+        std::fs::create_dir_all(parent)?;
+      }
+      std::fs::write(&file, contents)?;
+      std::fs::set_permissions(&file, std::fs::Permissions::from_mode(*permissions))?;
+    }
+    for (name, existing) in EXT2_HARD_LINKS {
+      std::fs::hard_link(files.join(existing), files.join(name))?;
+    }
+    for (name, target) in EXT2_SYMBOLIC_LINKS {
+      std::os::unix::fs::symlink(target, files.join(name))?;
+    }
+    let large: Vec<u8> = (0..EXT2_LARGE_FILE_SIZE)
+      .map(|index| u8::try_from(index % 251).unwrap_or_default())
+      .collect();
+    std::fs::write(files.join("large.bin"), large)?;
+
+    run(
+      "mke2fs",
+      &[
+        "-q".as_ref(),
+        "-t".as_ref(),
+        "ext2".as_ref(),
+        "-b".as_ref(),
+        "1024".as_ref(),
+        "-L".as_ref(),
+        "UNCORE".as_ref(),
+        "-d".as_ref(),
+        files.as_os_str(),
+        path.as_os_str(),
+        EXT2_IMAGE_SIZE.as_ref(),
+      ],
+    )?;
+    std::fs::remove_dir_all(&files)?;
+
+    Ok(Self {
+      path,
+      file_system: Some(FileSystem::Ext2),
+    })
+  }
+
+  /// Returns the additional arguments QEMU needs to attach the image as the disk with
+  /// the index `index`.
+  pub fn qemu_arguments(&self, index: usize) -> Vec<String> {
+    vec![
+      "-drive".to_string(),
+      format!("file={},if=none,format=raw,id=disk{index}", self.path.display()),
+      "-device".to_string(),
+      format!("virtio-blk-device,drive=disk{index}"),
+    ]
+  }
+
+  /// Checks the file system on an image the helper created, without changing it.
+  pub fn check(&self) -> anyhow::Result<()> {
+    log::debug!("Checking the disk image '{}'", self.path.display());
+    let image = self.path.as_os_str();
+    match self.file_system {
+      Some(FileSystem::Fat32) => run("fsck.vfat", &["-n".as_ref(), image]),
+      Some(FileSystem::Ext2) => run("e2fsck", &["-f".as_ref(), "-n".as_ref(), image]),
+      None => Ok(()),
+    }
+    .context("The file system on the disk image is damaged")
+  }
+}
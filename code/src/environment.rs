@@ -57,9 +57,11 @@ fn get_toolchain() -> anyhow::Result<String> {
 
 /// Returns a [`std::collections::HashMap`] that contains environment variables names as
 /// keys and their respective values are the values of the map. This is used when
-/// building, as the map is provided to [`std::process::Command`].
+/// building, as the map is provided to [`std::process::Command`]. If `log_format` is
+/// `Some(format)`, the kernel log on the console uses `format`.
 pub fn get_all_environment_variables_for_build(
   linker_script_path: &str,
+  log_format: Option<super::arguments::LogFormat>,
 ) -> anyhow::Result<std::collections::HashMap<&'static str, String>> {
   let mut environment = std::collections::HashMap::new();
 
@@ -67,12 +69,23 @@ pub fn get_all_environment_variables_for_build(
   environment.insert("KERNEL_VERSION", get_kernel_version()?);
   environment.insert("RUST_TOOLCHAIN", get_toolchain()?);
   environment.insert("LOG_LEVEL", log::max_level().to_string());
+  // Directives that set the log level per module or hart, e.g. `uncore::mem=trace,warn`
+  if let Ok(log_filter) = std::env::var("LOG_FILTER") {
+    environment.insert("LOG_FILTER", log_filter);
+  }
+  if let Some(log_format) = log_format {
+    environment.insert("LOG_FORMAT", log_format.as_str().to_string());
+  }
   environment.insert(
     "COMPILATION_DATE_AND_TIME",
     chrono::offset::Local::now().format("%+").to_string(),
   );
 
-  environment.insert("RUSTFLAGS", format!("-C link-arg=-T{linker_script_path}"));
+  // Frame pointers are required to unwind the stack for backtraces
+  environment.insert(
+    "RUSTFLAGS",
+    format!("-C link-arg=-T{linker_script_path} -C force-frame-pointers=yes"),
+  );
 
   Ok(environment)
 }
@@ -0,0 +1,282 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module works with the kernel log in the JSON format, i.e. the output of a kernel
+//! that was built with `LOG_FORMAT=json`. Every record is a JSON object on a line of its
+//! own (see `uncore/src/library/log/sink/json.rs`); all other lines (e.g. crash dumps)
+//! are passed through unchanged.
+
+/// A record of the kernel log.
+#[derive(Debug, Clone)]
+pub struct Record {
+  /// The time since the machine started in seconds
+  uptime:  f64,
+  /// The level, e.g. `INFO`
+  level:   String,
+  /// The hart that logged the record
+  hart:    u64,
+  /// The module that logged the record
+  module:  String,
+  /// The source file and line that logged the record, if known
+  source:  Option<(String, u64)>,
+  /// The message
+  message: String,
+}
+
+/// A value of a field of a record.
+#[derive(Debug)]
+enum Value {
+  /// A string
+  String(String),
+  /// A number
+  Number(f64),
+  /// `null`
+  Null,
+}
+
+/// Parses the flat JSON objects the kernel writes; nested objects and arrays are not
+/// supported.
+struct Parser<'line> {
+  /// The characters that have not been parsed yet
+  characters: std::iter::Peekable<std::str::Chars<'line>>,
+}
+
+impl Parser<'_> {
+  /// Skips whitespace and consumes `expected`, or returns [`None`] if the next character
+  /// is a different one.
+  fn expect(&mut self, expected: char) -> Option<()> {
+    while self
+      .characters
+      .next_if(|character| character.is_whitespace())
+      .is_some()
+    {}
+    self.characters.next_if_eq(&expected).map(|_| ())
+  }
+
+  /// Parses a string, including its quotes.
+  fn string(&mut self) -> Option<String> {
+    self.expect('"')?;
+    let mut string = String::new();
+    loop {
+      match self.characters.next()? {
+        '"' => return Some(string),
+        '\\' => string.push(match self.characters.next()? {
+          'n' => '\n',
+          'r' => '\r',
+          't' => '\t',
+          'u' => {
+            let digits: String = (0..4).filter_map(|_| self.characters.next()).collect();
+            char::from_u32(u32::from_str_radix(&digits, 16).ok()?)?
+          },
+          character => character,
+        }),
+        character => string.push(character),
+      }
+    }
+  }
+
+  /// Parses a string, a number or `null`.
+  fn value(&mut self) -> Option<Value> {
+    while self
+      .characters
+      .next_if(|character| character.is_whitespace())
+      .is_some()
+    {}
+    match self.characters.peek()? {
+      '"' => self.string().map(Value::String),
+      'n' => {
+        let word: String = (0..4).filter_map(|_| self.characters.next()).collect();
+        (word == "null").then_some(Value::Null)
+      },
+      _ => {
+        let mut number = String::new();
+        while let Some(character) = self
+          .characters
+          .next_if(|character| character.is_ascii_digit() || matches!(character, '.' | '-' | '+' | 'e' | 'E'))
+        {
+          number.push(character);
+        }
+        number.parse().ok().map(Value::Number)
+      },
+    }
+  }
+
+  /// Parses an object into its fields.
+  fn object(&mut self) -> Option<std::collections::HashMap<String, Value>> {
+    let mut fields = std::collections::HashMap::new();
+    self.expect('{')?;
+    if self.expect('}').is_some() {
+      return Some(fields);
+    }
+    loop {
+      let key = self.string()?;
+      self.expect(':')?;
+      fields.insert(key, self.value()?);
+      if self.expect('}').is_some() {
+        return Some(fields);
+      }
+      self.expect(',')?;
+    }
+  }
+}
+
+impl Record {
+  /// Parses `line`, or returns [`None`] if it is not a record.
+  pub fn parse(line: &str) -> Option<Self> {
+    let mut fields = Parser {
+      characters: line.trim().chars().peekable(),
+    }
+    .object()?;
+
+    let mut string = |key: &str| match fields.remove(key) {
+      Some(Value::String(value)) => Some(value),
+      _ => None,
+    };
+    let (level, module, message, file) = (
+      string("level")?,
+      string("module")?,
+      string("message")?,
+      string("file"),
+    );
+    let number = |key: &str| match fields.get(key) {
+      Some(Value::Number(value)) => Some(*value),
+      _ => None,
+    };
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    Some(Self {
+      uptime: number("uptime")?,
+      level,
+      hart: number("hart")? as u64,
+      module,
+      source: file.zip(number("line").map(|line| line as u64)),
+      message,
+    })
+  }
+
+  /// Returns the module and the message as `<MODULE>: <MESSAGE>`, which expectations are
+  /// matched against.
+  pub fn text(&self) -> String { format!("{}: {}", self.module, self.message) }
+}
+
+impl std::fmt::Display for Record {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    use colored::Colorize;
+
+    // https://coolors.co/fb4934-fabd2f-458588-83a598-8f8f8f
+    let level = format!("{:<5}", self.level);
+    let level = match self.level.as_str() {
+      "ERROR" => level.truecolor(251, 73, 52),
+      "WARN" => level.truecolor(250, 189, 47),
+      "INFO" => level.truecolor(69, 133, 136),
+      "DEBUG" => level.truecolor(131, 165, 152),
+      _ => level.truecolor(143, 143, 143),
+    };
+    write!(
+      f,
+      "[{:>12.6}] {level} {} {} {}",
+      self.uptime,
+      self.hart,
+      format!("{}:", self.module).truecolor(143, 143, 143),
+      self.message
+    )?;
+    if let Some((file, line)) = &self.source {
+      write!(f, " {}", format!("({file}:{line})").truecolor(143, 143, 143))?;
+    }
+    Ok(())
+  }
+}
+
+/// Renders `line` of the output of the kernel: records are rendered readably, all other
+/// lines are returned unchanged.
+pub fn render(line: &str) -> String {
+  Record::parse(line).map_or_else(|| line.to_string(), |record| record.to_string())
+}
+
+/// Checks that every regular expression in `expectations` matches the text (see
+/// [`Record::text`]) of at least one of `records`.
+pub fn check_expectations(records: &[Record], expectations: &[regex::Regex]) -> anyhow::Result<()> {
+  let missing: Vec<&str> = expectations
+    .iter()
+    .filter(|expectation| !records.iter().any(|record| expectation.is_match(&record.text())))
+    .map(regex::Regex::as_str)
+    .collect();
+  if missing.is_empty() {
+    Ok(())
+  } else {
+    anyhow::bail!(
+      "The kernel log does not contain records that match: '{}'",
+      missing.join("', '")
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    check_expectations,
+    render,
+    Record,
+  };
+
+  /// Lines as a kernel built with `LOG_FORMAT=json` writes them: a normal record, a
+  /// record with escaped characters and without a source, and malformed lines.
+  const FIXTURE: &str = concat!(
+    r#"{"uptime":1.500000,"level":"INFO","hart":0,"module":"uncore::fs","#,
+    r#""file":"src/library/fs/mod.rs","line":42,"message":"Mounted the initramfs"}"#,
+    "\n",
+    r#"{"uptime":2.000250,"level":"WARN","hart":1,"module":"uncore","file":null,"line":null,"#,
+    r#""message":"quote \" backslash \\ tab \t bell \u0007 end"}"#,
+    "\n",
+    r#"{"uptime":3.000000,"level":"INFO","hart":0,"module":"uncore","file":null,"line":null,"#,
+    r#""message":"cut off"#,
+    "\n",
+    r#"{"uptime":3.000000,"level":"INFO","hart":0,"file":null,"line":null,"message":"no module"}"#,
+    "\n",
+    "=== unCORE crash dump v1 ===",
+  );
+
+  #[test]
+  fn records_are_parsed_and_malformed_lines_passed_through() {
+    let lines: Vec<&str> = FIXTURE.lines().collect();
+    let records: Vec<Record> = lines.iter().filter_map(|line| Record::parse(line)).collect();
+    assert_eq!(records.len(), 2);
+
+    let normal = &records[0];
+    assert!((normal.uptime - 1.5).abs() < f64::EPSILON);
+    assert_eq!(normal.level, "INFO");
+    assert_eq!(normal.hart, 0);
+    assert_eq!(normal.source, Some((String::from("src/library/fs/mod.rs"), 42)));
+    assert_eq!(normal.text(), "uncore::fs: Mounted the initramfs");
+
+    let escaped = &records[1];
+    assert_eq!(escaped.hart, 1);
+    assert_eq!(escaped.source, None);
+    assert_eq!(escaped.message, "quote \" backslash \\ tab \t bell \u{7} end");
+
+    colored::control::set_override(false);
+    assert_eq!(
+      render(lines[0]),
+      "[    1.500000] INFO  0 uncore::fs: Mounted the initramfs (src/library/fs/mod.rs:42)"
+    );
+    for line in &lines[2..] {
+      assert_eq!(render(line), *line);
+    }
+  }
+
+  #[test]
+  fn expectations_are_matched_against_the_records() {
+    let records: Vec<Record> = FIXTURE.lines().filter_map(Record::parse).collect();
+    let expectations = |patterns: &[&str]| -> Vec<regex::Regex> {
+      patterns
+        .iter()
+        .map(|pattern| regex::Regex::new(pattern).unwrap())
+        .collect()
+    };
+
+    assert!(check_expectations(&records, &expectations(&["^uncore::fs: Mounted", "backslash"])).is_ok());
+    let error = check_expectations(&records, &expectations(&["Mounted", "no module"])).unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      "The kernel log does not contain records that match: 'no module'"
+    );
+  }
+}
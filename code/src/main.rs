@@ -14,8 +14,14 @@
 
 mod arguments;
 mod command;
+mod cpio;
+mod crash_dump;
+mod disk;
 mod environment;
+mod kernel_log;
 mod log;
+mod monitor;
+mod symbols;
 
 /// A simple main function.
 fn main() {
@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module provides functionality to interact with the QEMU monitor. It is used to
+//! capture screenshots of the display of the virtual machine, e.g. to check the output of
+//! the framebuffer console in tests.
+
+use anyhow::Context;
+use std::io::{
+  Read,
+  Write,
+};
+
+/// The prompt QEMU's human monitor interface prints when it awaits the next command.
+const PROMPT: &str = "(qemu) ";
+
+/// Captures a screenshot of the display of a QEMU instance after the kernel has shut the
+/// machine down.
+///
+/// QEMU is started with `-no-shutdown`, so that the machine is only paused when the
+/// kernel shuts down successfully. The display can then be captured via `screendump`, and
+/// QEMU is told to quit afterwards. If the kernel signals an error (via the `SiFive` test
+/// device), QEMU exits immediately and no screenshot is taken.
+#[derive(Debug)]
+pub struct Screenshot {
+  /// The path to the UNIX socket the monitor listens on
+  socket_path: std::path::PathBuf,
+  /// The path the screenshot is written to
+  output_path: std::path::PathBuf,
+}
+
+impl Screenshot {
+  /// Creates a new screenshot that will be written to `output_path`. The screenshot is
+  /// written in the PPM format.
+  pub fn new(output_path: &std::path::Path) -> anyhow::Result<Self> {
+    let output_path = if output_path.is_absolute() {
+      output_path.to_path_buf()
+    } else {
+      std::env::current_dir()?.join(output_path)
+    };
+
+    Ok(Self {
+      socket_path: std::env::temp_dir().join(format!("uncore-qemu-monitor-{}.sock", std::process::id())),
+      output_path,
+    })
+  }
+
+  /// Returns the additional arguments QEMU needs so that the screenshot can be taken.
+  pub fn qemu_arguments(&self) -> Vec<String> {
+    vec![
+      "-no-shutdown".to_string(),
+      "-monitor".to_string(),
+      format!("unix:{},server=on,wait=off", self.socket_path.display()),
+    ]
+  }
+
+  /// Connects to the monitor socket, retrying until QEMU has created it.
+  fn connect(&self, child: &mut std::process::Child) -> anyhow::Result<std::os::unix::net::UnixStream> {
+    for _ in 0..100 {
+      if let Ok(stream) = std::os::unix::net::UnixStream::connect(&self.socket_path) {
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+        return Ok(stream);
+      }
+
+      if child.try_wait()?.is_some() {
+        anyhow::bail!("QEMU exited before the monitor became available");
+      }
+      std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    anyhow::bail!("Could not connect to the QEMU monitor")
+  }
+
+  /// Reads from the monitor until the prompt appears and returns everything read.
+  fn read_until_prompt(stream: &mut std::os::unix::net::UnixStream) -> anyhow::Result<String> {
+    let mut output = String::new();
+    let mut buffer = [0_u8; 1024];
+    while !output.ends_with(PROMPT) {
+      let read = stream
+        .read(&mut buffer)
+        .context("Could not read from the QEMU monitor")?;
+      if read == 0 {
+        anyhow::bail!("QEMU monitor closed the connection");
+      }
+      output.push_str(&String::from_utf8_lossy(&buffer[..read]));
+    }
+    Ok(output)
+  }
+
+  /// Sends `command` to the monitor and returns its output.
+  fn execute(stream: &mut std::os::unix::net::UnixStream, command: &str) -> anyhow::Result<String> {
+    writeln!(stream, "{command}").context("Could not write to the QEMU monitor")?;
+    Self::read_until_prompt(stream)
+  }
+
+  /// Runs QEMU with `arguments`, waits until the kernel has shut the machine down, and
+  /// captures the screenshot. If the kernel does not shut down within `timeout_in_secs`
+  /// seconds, QEMU is killed and an error is returned.
+  pub fn capture<S: AsRef<std::ffi::OsStr>>(
+    &self,
+    qemu_command: &str,
+    arguments: &[S],
+    timeout_in_secs: u64,
+  ) -> anyhow::Result<()> {
+    let _ = std::fs::remove_file(&self.socket_path);
+    let mut child = std::process::Command::new(qemu_command).args(arguments).spawn()?;
+    let result = self.wait_and_capture(&mut child, timeout_in_secs);
+    let _ = std::fs::remove_file(&self.socket_path);
+
+    if result.is_err() {
+      let _ = child.kill();
+    }
+    let status = child.wait()?;
+    result?;
+
+    if !status.success() {
+      anyhow::bail!("Failure: QEMU exited with status {}", status);
+    }
+
+    log::info!("Screenshot written to '{}'", self.output_path.display());
+    Ok(())
+  }
+
+  /// Polls the machine status until the kernel has shut down, then captures the
+  /// screenshot and quits QEMU.
+  fn wait_and_capture(&self, child: &mut std::process::Child, timeout_in_secs: u64) -> anyhow::Result<()> {
+    let mut stream = self.connect(child)?;
+    Self::read_until_prompt(&mut stream)?;
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(timeout_in_secs);
+    loop {
+      if let Some(status) = child.try_wait()? {
+        anyhow::bail!("QEMU exited ({}) before a screenshot could be taken", status);
+      }
+
+      if Self::execute(&mut stream, "info status")?.contains("shutdown") {
+        break;
+      }
+
+      if std::time::Instant::now() > deadline {
+        anyhow::bail!("Failure: kernel did not shut down in time");
+      }
+      std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    log::debug!("Kernel has shut down - capturing screenshot");
+    Self::execute(&mut stream, &format!("screendump {}", self.output_path.display()))?;
+    writeln!(stream, "quit")?;
+    Ok(())
+  }
+}
@@ -0,0 +1,340 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module writes the symbol table that `unCORE` uses to symbolize backtraces into a
+//! kernel binary. The kernel reserves space for the table in the static `KERNEL_SYMBOLS`;
+//! this module collects the function symbols of the ELF file, demangles their names and
+//! overwrites the reserved space with them. The format of the table is documented in
+//! `uncore/src/library/backtrace/mod.rs`.
+
+use anyhow::Context;
+
+/// The name of the static that holds the symbol table.
+const TABLE_SYMBOL: &str = "KERNEL_SYMBOLS";
+/// The magic number at the start of the symbol table.
+const MAGIC: &[u8; 8] = b"uCsymtab";
+/// The size of the header of the symbol table in bytes.
+const HEADER_SIZE: usize = 16;
+/// The size of an entry of the symbol table in bytes.
+const ENTRY_SIZE: usize = 24;
+/// Names that are longer are truncated to save space.
+const MAXIMUM_NAME_LENGTH: usize = 96;
+
+/// The section type of a symbol table.
+const SECTION_TYPE_SYMBOL_TABLE: u32 = 2;
+/// The symbol type of functions.
+const SYMBOL_TYPE_FUNCTION: u8 = 2;
+
+/// A symbol of an ELF file.
+struct Symbol {
+  /// The name of the symbol
+  name:    String,
+  /// The address of the symbol
+  address: u64,
+  /// The size of the symbol in bytes
+  size:    u64,
+  /// The type of the symbol
+  kind:    u8,
+}
+
+/// A section header of an ELF file.
+struct Section {
+  /// The type of the section
+  kind:    u32,
+  /// The address of the section in memory
+  address: u64,
+  /// The offset of the section in the file
+  offset:  u64,
+  /// The size of the section in bytes
+  size:    u64,
+  /// The index of a related section
+  link:    u32,
+}
+
+/// Reads the little-endian integer of type `$type` at `$offset` of `$bytes`.
+macro_rules! read {
+  ($bytes:expr, $offset:expr, $type:ty) => {{
+    let offset = usize::try_from($offset)?;
+    let bytes = $bytes
+      .get(offset..offset + std::mem::size_of::<$type>())
+      .context("ELF file is truncated")?;
+    <$type>::from_le_bytes(bytes.try_into()?)
+  }};
+}
+
+/// Parses the section headers of the ELF file `elf`.
+fn sections(elf: &[u8]) -> anyhow::Result<Vec<Section>> {
+  if elf.get(..5) != Some(b"\x7fELF\x02".as_slice()) {
+    anyhow::bail!("Not a 64-bit ELF file");
+  }
+
+  let headers = read!(elf, 0x28, u64);
+  let header_size = u64::from(read!(elf, 0x3A, u16));
+  let count = read!(elf, 0x3C, u16);
+  (0..u64::from(count))
+    .map(|index| {
+      let header = headers + index * header_size;
+      Ok(Section {
+        kind:    read!(elf, header + 0x04, u32),
+        address: read!(elf, header + 0x10, u64),
+        offset:  read!(elf, header + 0x18, u64),
+        size:    read!(elf, header + 0x20, u64),
+        link:    read!(elf, header + 0x28, u32),
+      })
+    })
+    .collect()
+}
+
+/// Parses the symbols of the ELF file `elf`.
+fn symbols(elf: &[u8], sections: &[Section]) -> anyhow::Result<Vec<Symbol>> {
+  let table = sections
+    .iter()
+    .find(|section| section.kind == SECTION_TYPE_SYMBOL_TABLE)
+    .context("ELF file has no symbol table")?;
+  let strings = sections
+    .get(usize::try_from(table.link)?)
+    .context("Symbol table has no string table")?;
+
+  let mut symbols = vec![];
+  for index in 0..table.size / 24 {
+    let entry = table.offset + index * 24;
+    let name_start = usize::try_from(strings.offset + u64::from(read!(elf, entry, u32)))?;
+    let name_length = elf
+      .get(name_start..)
+      .and_then(|name| name.iter().position(|&byte| byte == 0))
+      .context("Symbol name is not terminated")?;
+    symbols.push(Symbol {
+      name:    String::from_utf8_lossy(&elf[name_start..name_start + name_length]).into_owned(),
+      address: read!(elf, entry + 8, u64),
+      size:    read!(elf, entry + 16, u64),
+      kind:    read!(elf, entry + 4, u8) & 0xF,
+    });
+  }
+  Ok(symbols)
+}
+
+/// Returns the functions among `symbols` with their demangled names, sorted by address.
+fn functions(symbols: &[Symbol]) -> Vec<(u64, u64, String)> {
+  let mut functions: Vec<(u64, u64, String)> = symbols
+    .iter()
+    .filter(|symbol| symbol.kind == SYMBOL_TYPE_FUNCTION && symbol.address != 0)
+    .map(|symbol| {
+      (
+        symbol.address,
+        symbol.size,
+        format!("{:#}", rustc_demangle::demangle(&symbol.name)),
+      )
+    })
+    .collect();
+  functions.sort_unstable_by_key(|&(address, ..)| address);
+  functions.dedup_by_key(|&mut (address, ..)| address);
+  functions
+}
+
+/// Serializes the function `symbols` into a table of at most `capacity` bytes. Symbols
+/// that do not fit are left out, and their number is returned alongside the table.
+fn serialize(symbols: &[Symbol], capacity: usize) -> anyhow::Result<(Vec<u8>, usize)> {
+  let mut functions = functions(symbols);
+  for (.., name) in &mut functions {
+    if name.len() > MAXIMUM_NAME_LENGTH {
+      let mut end = MAXIMUM_NAME_LENGTH;
+      while !name.is_char_boundary(end) {
+        end -= 1;
+      }
+      name.truncate(end);
+    }
+  }
+
+  // Keep as many functions as fit
+  let mut used = HEADER_SIZE;
+  let fitting = functions
+    .iter()
+    .take_while(|(.., name)| {
+      used += ENTRY_SIZE + name.len();
+      used <= capacity
+    })
+    .count();
+  let omitted = functions.len() - fitting;
+  functions.truncate(fitting);
+
+  let mut entries = vec![];
+  let mut names = vec![];
+  for (address, size, name) in &functions {
+    entries.extend_from_slice(&address.to_le_bytes());
+    entries.extend_from_slice(&u32::try_from(*size).unwrap_or(u32::MAX).to_le_bytes());
+    entries.extend_from_slice(&u32::try_from(names.len())?.to_le_bytes());
+    entries.extend_from_slice(&u32::try_from(name.len())?.to_le_bytes());
+    entries.extend_from_slice(&[0; 4]);
+    names.extend_from_slice(name.as_bytes());
+  }
+
+  let mut table = MAGIC.to_vec();
+  table.extend_from_slice(&u32::try_from(functions.len())?.to_le_bytes());
+  table.extend_from_slice(&u32::try_from(HEADER_SIZE + entries.len())?.to_le_bytes());
+  table.append(&mut entries);
+  table.append(&mut names);
+  Ok((table, omitted))
+}
+
+/// Writes the symbol table into the kernel binary at `path`.
+pub fn embed(path: &str) -> anyhow::Result<()> {
+  log::trace!("Embedding the symbol table into '{path}'");
+  let mut elf = std::fs::read(path).context(format!("Could not read '{path}'"))?;
+  let sections = sections(&elf)?;
+  let symbols = symbols(&elf, &sections)?;
+
+  let reserved = symbols
+    .iter()
+    .find(|symbol| symbol.name == TABLE_SYMBOL)
+    .context(format!("'{path}' does not reserve space for a symbol table"))?;
+  let section = sections
+    .iter()
+    .find(|section| {
+      section.offset != 0 && (section.address..section.address + section.size).contains(&reserved.address)
+    })
+    .context("Symbol table is not in a section of the file")?;
+  let start = usize::try_from(section.offset + reserved.address - section.address)?;
+  let capacity = usize::try_from(reserved.size)?;
+
+  let (table, omitted) = serialize(&symbols, capacity)?;
+  if omitted > 0 {
+    log::warn!("The symbol table is too small; {omitted} symbols were left out");
+  }
+  elf
+    .get_mut(start..start + table.len())
+    .context("Symbol table is beyond the end of the file")?
+    .copy_from_slice(&table);
+  std::fs::write(path, elf).context(format!("Could not write '{path}'"))?;
+  Ok(())
+}
+
+/// The functions of a kernel binary, which resolve addresses to function names on the
+/// host, e.g. when decoding crash dumps.
+pub struct Functions(Vec<(u64, u64, String)>);
+
+impl Functions {
+  /// Reads the functions of the kernel binary at `path`.
+  pub fn load(path: &std::path::Path) -> anyhow::Result<Self> {
+    let elf = std::fs::read(path).context(format!("Could not read '{}'", path.display()))?;
+    let sections = sections(&elf)?;
+    Ok(Self(functions(&symbols(&elf, &sections)?)))
+  }
+
+  /// Returns the name of the function that contains `address` and the offset of
+  /// `address` in it.
+  pub fn lookup(&self, address: u64) -> Option<(&str, u64)> {
+    let index = self
+      .0
+      .partition_point(|&(start, ..)| start <= address)
+      .checked_sub(1)?;
+    let (start, size, name) = &self.0[index];
+    let offset = address - start;
+    (*size == 0 || offset < *size).then_some((name.as_str(), offset))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    serialize,
+    Functions,
+    Symbol,
+    ENTRY_SIZE,
+    HEADER_SIZE,
+    MAGIC,
+    MAXIMUM_NAME_LENGTH,
+    SYMBOL_TYPE_FUNCTION,
+  };
+
+  /// Returns a function symbol.
+  fn function(name: &str, address: u64, size: u64) -> Symbol {
+    Symbol {
+      name: name.to_string(),
+      address,
+      size,
+      kind: SYMBOL_TYPE_FUNCTION,
+    }
+  }
+
+  /// Returns the address, size and name of the entries of `table`.
+  fn entries(table: &[u8]) -> Vec<(u64, u32, String)> {
+    let word = |offset: usize| u32::from_le_bytes(table[offset..offset + 4].try_into().unwrap()) as usize;
+    assert_eq!(&table[..8], MAGIC);
+    let names = word(12);
+    (0..word(8))
+      .map(|index| {
+        let entry = HEADER_SIZE + index * ENTRY_SIZE;
+        let name = names + word(entry + 12);
+        (
+          u64::from_le_bytes(table[entry..entry + 8].try_into().unwrap()),
+          u32::try_from(word(entry + 8)).unwrap(),
+          String::from_utf8(table[name..name + word(entry + 16)].to_vec()).unwrap(),
+        )
+      })
+      .collect()
+  }
+
+  #[test]
+  fn functions_are_encoded_sorted_by_address() {
+    let long = "f".repeat(MAXIMUM_NAME_LENGTH + 10);
+    let symbols = [
+      function("_ZN6uncore4main17h0123456789abcdefE", 0x8020_0100, 0x40),
+      function("_start", 0x8020_0000, 0x10),
+      function(&long, 0x8020_0200, 0),
+      function("undefined", 0, 0),
+      Symbol {
+        kind: 1,
+        ..function("KERNEL_SYMBOLS", 0x8030_0000, 0x1000)
+      },
+    ];
+
+    let (table, omitted) = serialize(&symbols, 4096).unwrap();
+    assert_eq!(omitted, 0);
+    assert_eq!(
+      entries(&table),
+      [
+        (0x8020_0000, 0x10, String::from("_start")),
+        (0x8020_0100, 0x40, String::from("uncore::main")),
+        (0x8020_0200, 0, "f".repeat(MAXIMUM_NAME_LENGTH)),
+      ]
+    );
+  }
+
+  #[test]
+  fn functions_that_do_not_fit_are_left_out() {
+    let symbols = [
+      function("a", 0x100, 4),
+      function("bb", 0x200, 4),
+      function("ccc", 0x300, 4),
+    ];
+    let capacity = HEADER_SIZE + 2 * ENTRY_SIZE + 3;
+
+    let (table, omitted) = serialize(&symbols, capacity).unwrap();
+    assert_eq!(omitted, 1);
+    assert!(table.len() <= capacity);
+    assert_eq!(
+      entries(&table),
+      [(0x100, 4, String::from("a")), (0x200, 4, String::from("bb"))]
+    );
+
+    let (table, omitted) = serialize(&symbols, HEADER_SIZE).unwrap();
+    assert_eq!((table.len(), omitted), (HEADER_SIZE, 3));
+  }
+
+  #[test]
+  fn addresses_are_resolved_to_the_function_that_contains_them() {
+    let functions = Functions(vec![
+      (0x100, 0x10, String::from("first")),
+      (0x200, 0, String::from("unsized")),
+      (0x300, 0x08, String::from("last")),
+    ]);
+
+    assert_eq!(functions.lookup(0xFF), None);
+    assert_eq!(functions.lookup(0x100), Some(("first", 0)));
+    assert_eq!(functions.lookup(0x10F), Some(("first", 0xF)));
+    assert_eq!(functions.lookup(0x110), None);
+    // Functions without a size extend to the next one
+    assert_eq!(functions.lookup(0x2FF), Some(("unsized", 0xFF)));
+    assert_eq!(functions.lookup(0x304), Some(("last", 4)));
+    assert_eq!(functions.lookup(0x308), None);
+  }
+}
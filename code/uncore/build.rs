@@ -0,0 +1,15 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! The build script of `unCORE`. It links the initial RAM file system into the kernel
+//! image when the environment variable `INITRAMFS` holds the path of a `cpio` archive
+//! (which `uncore-helper build --initramfs <DIRECTORY>` creates).
+
+fn main() {
+  println!("cargo::rustc-check-cfg=cfg(initramfs)");
+  println!("cargo::rerun-if-env-changed=INITRAMFS");
+
+  if let Ok(path) = std::env::var("INITRAMFS") {
+    println!("cargo::rerun-if-changed={path}");
+    println!("cargo::rustc-cfg=initramfs");
+  }
+}
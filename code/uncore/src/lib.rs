@@ -27,6 +27,8 @@
 // Use custom test runners. Since we cannot use the standard
 // library, we have to use our own test framework.
 #![feature(custom_test_frameworks)]
+// Log the state of the kernel heap when an allocation fails.
+#![feature(alloc_error_handler)]
 
 // ? MODULES and GLOBAL / CRATE-LEVEL FUNCTIONS
 // ? ---------------------------------------------------------------------
@@ -47,6 +49,9 @@
 /// the kernel with `crate::`.
 pub use library::{
   arch,
+  drivers,
+  fs,
+  mem,
   test,
   prelude::*,
 };
@@ -55,13 +60,21 @@
 /// architecture-specific setup functions have run.
 pub fn setup_kernel(hart: usize) {
   if hart == 0 {
-    library::log::initialize();
+    library::cmdline::initialize();
+    library::log::configure();
     library::log::display_initial_information();
+    library::cmdline::log_arguments();
   }
 
   log::info!("Running on HART {}", hart);
 
   if hart == 0 {
     library::mem::heap::Heap::initialize();
+
+    if library::device_tree::get().is_none() {
+      log::warn!("No valid device tree available - devices could not be discovered");
+    }
+    library::drivers::log_devices();
+    library::fs::initialize();
   }
 }
@@ -0,0 +1,313 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! The interface every architecture implements.
+//!
+//! An architecture provides a unit structure (e.g. `RiscV`) that implements all traits of
+//! this module, which are combined in [`Architecture`]; the rest of the kernel only calls
+//! the architecture through the functions in [`crate::arch`], which forward to these
+//! traits.
+//!
+//! | Trait            | Functionality                                                  |
+//! | ---------------- | -------------------------------------------------------------- |
+//! | [`Boot`]         | setup before [`crate::setup_kernel`] runs                      |
+//! | [`Harts`]        | the ID of the current hart and the harts that are online       |
+//! | [`Interrupts`]   | disabling interrupts, trap context and trap counts             |
+//! | [`Timer`]        | the timer the monotonic clock is derived from                  |
+//! | [`Memory`]       | where the kernel heap and the stacks are                       |
+//! | [`Paging`]       | the page tables that translate the addresses of programs       |
+//! | [`ContextSwitch`] | switching between kernel contexts on a hart                   |
+//! | [`Unwinding`]    | registers, return addresses and backtraces                     |
+//! | [`Firmware`]     | services of the firmware, e.g. shutting the machine down       |
+//! | [`EarlyConsole`] | the console that is available before the driver framework is   |
+//!
+//! The state that a trap saves is described by [`TrapContext`], the page tables of an
+//! address space by [`PageTable`].
+
+/// Setup of the architecture.
+pub trait Boot {
+  /// Runs architecture-specific setup before the kernel setup in [`crate::setup_kernel`].
+  /// `device_tree_address` is the address of the device tree blob the firmware passed to
+  /// the kernel. This function is called on every hart.
+  ///
+  /// #### Attention
+  ///
+  /// The implementation initializes the kernel log on the first hart before devices are
+  /// probed; nothing may be logged before that.
+  fn initialize(hart: usize, device_tree_address: usize);
+}
+
+/// The harts (i.e. hardware threads) of the machine.
+pub trait Harts {
+  /// The maximum number of harts for which per-hart state is kept (e.g. trap counts).
+  const MAXIMUM_HARTS: usize;
+
+  /// Returns the ID of the hart this function runs on.
+  fn hart() -> usize;
+
+  /// Returns the IDs of all harts that have been initialized with [`Boot::initialize`],
+  /// in ascending order.
+  fn online_harts() -> impl Iterator<Item = usize>;
+}
+
+/// A cause of traps that is counted per hart.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TrapCause {
+  /// A software interrupt, which another hart sends
+  Software,
+  /// A timer interrupt
+  Timer,
+  /// A system call
+  SystemCall,
+  /// An interrupt of the source with this number of the external interrupt controller
+  /// (e.g. the PLIC on RISC-V)
+  External(u32),
+}
+
+impl core::fmt::Display for TrapCause {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::Software => write!(f, "software interrupt"),
+      Self::Timer => write!(f, "timer interrupt"),
+      Self::SystemCall => write!(f, "system call"),
+      Self::External(source) => write!(f, "external source {source}"),
+    }
+  }
+}
+
+/// The state of the code that a trap interrupted, which the trap entry saves and
+/// restores. Changes take effect when the trap returns, e.g. the result of a system call.
+pub trait TrapContext {
+  /// Returns whether the trap interrupted a program in user mode.
+  fn is_from_user(&self) -> bool;
+
+  /// Returns the address of the instruction that was interrupted or caused the
+  /// exception.
+  fn program_counter(&self) -> usize;
+
+  /// Sets the address of the instruction the interrupted code resumes at.
+  fn set_program_counter(&mut self, address: usize);
+
+  /// Returns the stack pointer of the interrupted code.
+  fn stack_pointer(&self) -> usize;
+
+  /// Returns the number and the arguments of the system call that caused the trap.
+  fn system_call(&self) -> (usize, [usize; 6]);
+
+  /// Hands `result` and `arguments` back to the caller of the system call; some system
+  /// calls return additional values in their arguments.
+  fn set_system_call_result(&mut self, result: isize, arguments: &[usize; 6]);
+}
+
+/// Interrupts and traps.
+pub trait Interrupts {
+  /// The state that the trap entry saves
+  type TrapFrame: TrapContext;
+
+  /// Runs `operation` with interrupts disabled on the current hart. Interrupts are
+  /// enabled again afterwards if they were enabled before.
+  fn without_interrupts<T>(operation: impl FnOnce() -> T) -> T;
+
+  /// Returns whether the current hart is handling a trap that interrupted the kernel.
+  ///
+  /// The interrupted code may hold locks, so such a trap must not wait for locks the
+  /// kernel takes with interrupts enabled.
+  fn in_kernel_trap() -> bool;
+
+  /// Returns the causes of traps that are counted (see [`Interrupts::trap_count`]).
+  /// Interrupts of external sources are only listed if at least one of them occurred.
+  fn trap_causes() -> impl Iterator<Item = TrapCause>;
+
+  /// Returns the number of traps with `cause` on `hart`; traps on harts with IDs beyond
+  /// [`Harts::MAXIMUM_HARTS`] are not counted.
+  fn trap_count(cause: TrapCause, hart: usize) -> u64;
+}
+
+/// The timer of the architecture.
+pub trait Timer {
+  /// Returns the number of ticks since the timer started, which is usually when the
+  /// machine was started. The frequency of the timer is read from the device tree.
+  fn ticks() -> u64;
+}
+
+/// The layout of the memory of the kernel.
+pub trait Memory {
+  /// Returns the starting address of the kernel heap.
+  fn heap_start() -> *mut u8;
+
+  /// Returns the size of the kernel heap in bytes.
+  fn heap_size() -> usize;
+
+  /// Returns the end of the stacks of all harts. Stacks grow downwards from there.
+  fn stack_end() -> usize;
+
+  /// Returns the size of the stack of every hart in bytes. The stack of hart `n` ends
+  /// `n` stack sizes below [`Memory::stack_end`].
+  fn hart_stack_size() -> usize;
+}
+
+/// The page tables of an address space, which translate the addresses of a program to
+/// physical addresses.
+///
+/// Only addresses that [`crate::library::mem::address_space`] can
+/// map are translated for the program; the memory of the kernel stays accessible to the
+/// kernel at its physical addresses while the page tables are active.
+pub trait PageTable: Sized + Send + core::fmt::Debug {
+  /// Creates page tables that map no page of the program.
+  ///
+  /// #### Errors
+  ///
+  /// If the tables cannot be allocated, [`crate::library::mem::Error::OutOfMemory`] is
+  /// returned.
+  fn new() -> crate::library::mem::Result<Self>;
+
+  /// Maps the page at `address` to the physical page at `physical`, accessible in user
+  /// mode with `protection`. An existing mapping of the page is replaced; a page without
+  /// any permitted access is unmapped.
+  ///
+  /// #### Errors
+  ///
+  /// If an address is not page-aligned or `address` cannot be mapped,
+  /// [`crate::library::mem::Error::InvalidArgument`] is returned; if a table cannot be
+  /// allocated, [`crate::library::mem::Error::OutOfMemory`].
+  fn map(
+    &mut self,
+    address: usize,
+    physical: usize,
+    protection: crate::library::mem::address_space::Protection,
+  ) -> crate::library::mem::Result<()>;
+
+  /// Removes the mapping of the page at `address`, if there is one.
+  fn unmap(&mut self, address: usize);
+
+  /// Returns the physical address `address` is mapped to and the accesses permitted, or
+  /// [`None`] if it is not mapped.
+  fn translate(&self, address: usize) -> Option<(usize, crate::library::mem::address_space::Protection)>;
+
+  /// Makes the current hart translate addresses with these page tables. Changes made
+  /// later with [`PageTable::map`] and [`PageTable::unmap`] take effect on the current
+  /// hart right away.
+  ///
+  /// #### Safety
+  ///
+  /// The page tables must stay alive until another one is activated or
+  /// [`PageTable::deactivate`] is called.
+  unsafe fn activate(&self);
+
+  /// Makes the current hart use physical addresses again.
+  ///
+  /// #### Safety
+  ///
+  /// No code may rely on the addresses of a program being translated afterwards.
+  unsafe fn deactivate();
+}
+
+/// Paging, i.e. the translation of the addresses of programs.
+pub trait Paging {
+  /// The page tables of an address space
+  type PageTable: PageTable;
+}
+
+/// Switching between kernel contexts, each of which runs on its own stack.
+///
+/// A context switch saves the registers that are preserved across function calls; the
+/// others are saved by the caller of [`ContextSwitch::switch_context`] as for any
+/// function call.
+pub trait ContextSwitch {
+  /// The registers that a context switch saves
+  type Context: Default + Send + core::fmt::Debug;
+
+  /// Prepares `context` so that switching to it calls `entry` with `argument` on the
+  /// stack that ends at `stack_end`.
+  fn prepare_context(
+    context: &mut Self::Context,
+    entry: extern "C" fn(usize) -> !,
+    argument: usize,
+    stack_end: usize,
+  );
+
+  /// Saves the registers of the current context in `from` and continues the context in
+  /// `to`. This function returns when another context switches back to `from`.
+  ///
+  /// #### Safety
+  ///
+  /// `to` must have been prepared with [`ContextSwitch::prepare_context`] or saved by a
+  /// context switch, and its stack must still be valid.
+  unsafe fn switch_context(from: *mut Self::Context, to: *const Self::Context);
+}
+
+/// Running programs in user mode. There are no processes yet, so a program runs on
+/// behalf of the kernel code that starts it, which continues once the program exits.
+pub trait UserMode {
+  /// Runs the program whose code starts at `entry` in user mode, with its stack pointer
+  /// at `stack_pointer`, until it calls `exit` or `exit_group` or a signal terminates it.
+  /// Returns the exit status of the program, which is 128 plus the number of the signal
+  /// if it was terminated, or [`None`] if the current hart cannot run programs.
+  ///
+  /// #### Safety
+  ///
+  /// The active page tables (see [`PageTable::activate`]) must map the code and the stack
+  /// of the program.
+  unsafe fn run_program(entry: usize, stack_pointer: usize) -> Option<i32>;
+}
+
+/// Inspection of the running code, used by backtraces and crash dumps. The functions must
+/// be inlined into their caller, as they describe the function they are called from.
+pub trait Unwinding {
+  /// Returns the return addresses of the function calls that led to the code calling
+  /// this function, from the innermost to the outermost call.
+  fn backtrace() -> impl Iterator<Item = usize>;
+
+  /// Returns the return addresses of the function calls that led to the function whose
+  /// frame pointer is `frame_pointer`, from the innermost to the outermost call.
+  fn backtrace_from(frame_pointer: usize) -> impl Iterator<Item = usize>;
+
+  /// Returns the general-purpose registers of the function this function is inlined
+  /// into. Architectures with fewer registers leave the remaining entries zero.
+  fn registers() -> [usize; 32];
+}
+
+/// Services of the firmware.
+pub trait Firmware {
+  /// Shuts the machine down, signaling `condition` to the emulator if there is one.
+  fn exit_kernel(condition: crate::UncoreResult) -> !;
+
+  /// Performs the semihosting call `operation` with `parameter` and returns its result.
+  #[cfg(feature = "semihosting")]
+  fn semihosting(operation: usize, parameter: usize) -> usize;
+}
+
+/// A device that can serve as the console.
+pub trait ConsoleDevice: core::fmt::Write + core::fmt::Debug + Copy + Send + Sync {
+  /// Writes a single byte, waiting until the device can accept it. This function must
+  /// not take locks, so that it can be used while the kernel panics.
+  fn put(&self, byte: u8);
+
+  /// Reads a single byte if one has been received.
+  fn get(&self) -> Option<u8>;
+}
+
+/// The console that carries the kernel log, which is available before the driver
+/// framework has bound all devices.
+pub trait EarlyConsole {
+  /// The device of the console
+  type Console: ConsoleDevice;
+
+  /// Returns the console, if it has been brought up.
+  fn console() -> Option<Self::Console>;
+}
+
+/// The complete interface of an architecture.
+pub trait Architecture:
+  Boot
+  + Harts
+  + Interrupts
+  + Timer
+  + Memory
+  + Paging
+  + ContextSwitch
+  + UserMode
+  + Unwinding
+  + Firmware
+  + EarlyConsole
+{
+}
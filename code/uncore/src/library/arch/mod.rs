@@ -2,25 +2,184 @@
 
 //! This module contains all architecture-specific functionality.
 //!
-//! The trick to having a uniform interface is to use `pub use architecture::{stuff, to,
-//! re-export}` where `architecture` is a local re-export of a specific architecture
-//! (.e.g, `use risc_v as architecture`) guarded by conditional compilation (e.g.,
-//! `#[cfg(target_arch = "riscv64`) so that only one architecture is enabled at any given
-//! time.
+//! Every architecture implements the traits of [`interface`], combined in
+//! [`Architecture`], on a unit structure of its module (e.g. `risc_v::RiscV`). The module
+//! of the architecture the kernel is built for is selected with conditional compilation
+//! (e.g. `#[cfg(target_arch = "riscv64")]`) and its structure becomes [`Current`]; the
+//! functions of this module forward to it, so that the rest of the kernel does not depend
+//! on a specific architecture. A compile-time check makes sure that [`Current`]
+//! implements the whole interface.
+//!
+//! To add an architecture, create its module next to `risc_v`, implement [`Architecture`]
+//! for its structure, and select the module for its `target_arch` below. Drivers of
+//! devices that only exist on one architecture stay in its `drivers` module.
 
-/// Re-exported intra-kernel API that any implementation of an architecture must satisfy.
-///
-/// #### Attention
-///
-/// Initialization functionality may not have a working logger available when the
-/// functions are called at run-time.
-pub use architecture::{
-  drivers,
-  heap,
-  exit_kernel,
-  initialize,
+#[cfg(test)]
+mod tests;
+
+pub mod interface;
+
+pub use interface::{
+  Architecture,
+  Boot,
+  ConsoleDevice,
+  ContextSwitch,
+  EarlyConsole,
+  Firmware,
+  Harts,
+  Interrupts,
+  Memory,
+  PageTable,
+  Paging,
+  Timer,
+  TrapCause,
+  TrapContext,
+  Unwinding,
+  UserMode,
 };
 
 #[cfg(target_arch = "riscv64")]
 mod risc_v;
-#[cfg(target_arch = "riscv64")] use risc_v as architecture;
+
+/// The architecture the kernel is built for.
+#[cfg(target_arch = "riscv64")]
+pub type Current = risc_v::RiscV;
+
+/// Fails to compile unless `A` implements the whole interface of an architecture.
+const fn assert_architecture<A: Architecture>() {}
+const _: () = assert_architecture::<Current>();
+
+/// The maximum number of harts for which per-hart state is kept (see
+/// [`Harts::MAXIMUM_HARTS`]).
+pub const MAXIMUM_HARTS: usize = <Current as Harts>::MAXIMUM_HARTS;
+
+/// The state that the trap entry saves (see [`Interrupts::TrapFrame`]).
+pub type TrapFrame = <Current as Interrupts>::TrapFrame;
+
+/// The page tables of an address space (see [`Paging::PageTable`]).
+pub type PageTables = <Current as Paging>::PageTable;
+
+/// The registers that a context switch saves (see [`ContextSwitch::Context`]).
+pub type Context = <Current as ContextSwitch>::Context;
+
+/// The device of the console (see [`EarlyConsole`]).
+pub type Console = <Current as EarlyConsole>::Console;
+
+/// Runs architecture-specific setup before the kernel setup (see [`Boot::initialize`]).
+///
+/// #### Attention
+///
+/// The kernel log is initialized here, before devices are probed, but the kernel command
+/// line is not applied to it yet.
+pub fn initialize(hart: usize, device_tree_address: usize) { Current::initialize(hart, device_tree_address); }
+
+/// Returns the ID of the hart this function runs on.
+#[must_use]
+pub fn hart() -> usize { Current::hart() }
+
+/// Returns the IDs of all harts that have been initialized with [`initialize`], in
+/// ascending order.
+pub fn online_harts() -> impl Iterator<Item = usize> { Current::online_harts() }
+
+/// Runs `operation` with interrupts disabled on the current hart (see
+/// [`Interrupts::without_interrupts`]).
+pub fn without_interrupts<T>(operation: impl FnOnce() -> T) -> T { Current::without_interrupts(operation) }
+
+/// Returns whether the current hart is handling a trap that interrupted the kernel (see
+/// [`Interrupts::in_kernel_trap`]).
+#[must_use]
+pub fn in_kernel_trap() -> bool { Current::in_kernel_trap() }
+
+/// Returns the number of traps per hart for every counted cause (see
+/// [`Interrupts::trap_causes`] and [`Interrupts::trap_count`]).
+pub fn trap_counts() -> impl Iterator<Item = (TrapCause, [u64; MAXIMUM_HARTS])> {
+  Current::trap_causes().map(|cause| {
+    (
+      cause,
+      core::array::from_fn(|hart| Current::trap_count(cause, hart)),
+    )
+  })
+}
+
+/// Returns the number of ticks of the timer since it started (see [`Timer::ticks`]).
+#[must_use]
+pub fn ticks() -> u64 { Current::ticks() }
+
+/// Returns the starting address of the kernel heap.
+#[must_use]
+pub fn heap_start() -> *mut u8 { Current::heap_start() }
+
+/// Returns the size of the kernel heap in bytes.
+#[must_use]
+pub fn heap_size() -> usize { Current::heap_size() }
+
+/// Returns the end of the stacks of all harts. Stacks grow downwards from there.
+#[must_use]
+pub fn stack_end() -> usize { Current::stack_end() }
+
+/// Returns the size of the stack of every hart in bytes (see
+/// [`Memory::hart_stack_size`]).
+#[must_use]
+pub fn hart_stack_size() -> usize { Current::hart_stack_size() }
+
+/// Prepares `context` so that switching to it calls `entry` with `argument` on the stack
+/// that ends at `stack_end` (see [`ContextSwitch::prepare_context`]).
+pub fn prepare_context(
+  context: &mut Context,
+  entry: extern "C" fn(usize) -> !,
+  argument: usize,
+  stack_end: usize,
+) {
+  Current::prepare_context(context, entry, argument, stack_end);
+}
+
+/// Saves the current context in `from` and continues the context in `to` (see
+/// [`ContextSwitch::switch_context`]).
+///
+/// #### Safety
+///
+/// `to` must have been prepared with [`prepare_context`] or saved by a context switch,
+/// and its stack must still be valid.
+pub unsafe fn switch_context(from: *mut Context, to: *const Context) { Current::switch_context(from, to) }
+
+/// Runs the program at `entry` in user mode until it exits and returns its exit status
+/// (see [`UserMode::run_program`]).
+///
+/// #### Safety
+///
+/// The active page tables must map the code and the stack of the program.
+#[must_use]
+pub unsafe fn run_program(entry: usize, stack_pointer: usize) -> Option<i32> {
+  Current::run_program(entry, stack_pointer)
+}
+
+/// The return addresses of the function calls that led to the code calling this
+/// function, from the innermost to the outermost call.
+#[inline(always)]
+#[allow(clippy::inline_always)]
+pub fn backtrace() -> impl Iterator<Item = usize> { Current::backtrace() }
+
+/// The return addresses of the function calls that led to the function whose frame
+/// pointer is `frame_pointer`, from the innermost to the outermost call.
+pub fn backtrace_from(frame_pointer: usize) -> impl Iterator<Item = usize> {
+  Current::backtrace_from(frame_pointer)
+}
+
+/// Returns the general-purpose registers of the function this function is inlined into
+/// (see [`Unwinding::registers`]).
+#[inline(always)]
+#[allow(clippy::inline_always)]
+#[must_use]
+pub fn registers() -> [usize; 32] { Current::registers() }
+
+/// Shuts the machine down (see [`Firmware::exit_kernel`]).
+pub fn exit_kernel(condition: crate::UncoreResult) -> ! { Current::exit_kernel(condition) }
+
+/// Performs the semihosting call `operation` with `parameter` and returns its result.
+#[cfg(feature = "semihosting")]
+#[allow(clippy::must_use_candidate)]
+pub fn semihosting(operation: usize, parameter: usize) -> usize { Current::semihosting(operation, parameter) }
+
+/// Returns the console, if it has been brought up (see [`EarlyConsole::console`]).
+#[must_use]
+pub fn console() -> Option<Console> { Current::console() }
@@ -0,0 +1,15 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Provides access to the RISC-V timer, which counts at a constant frequency (the
+//! `timebase-frequency` of the device tree) since the machine was started.
+
+impl crate::arch::Timer for super::RiscV {
+  /// Returns the current value of the `time` CSR.
+  fn ticks() -> u64 {
+    let ticks: u64;
+    unsafe {
+      core::arch::asm!("rdtime {}", out(reg) ticks, options(nomem, nostack));
+    }
+    ticks
+  }
+}
@@ -0,0 +1,88 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Contains the context switch of RISC-V, which saves and restores the registers that
+//! the calling convention preserves across calls: `ra`, `sp` and `s0` to `s11`. The
+//! kernel does not use floating-point registers, so they are not saved.
+
+/// The registers of a context that is not running.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Context {
+  /// `ra`, where the context continues
+  return_address: usize,
+  /// `sp`
+  stack_pointer:  usize,
+  /// `s0` to `s11`
+  saved:          [usize; 12],
+}
+
+extern "C" {
+  /// Saves the registers in the context `from` points to and loads those of `to`.
+  fn __uncore_switch_context(from: *mut Context, to: *const Context);
+  /// The first code a prepared context runs: it calls the entry in `s2` with the
+  /// argument in `s1`. `s0`, the frame pointer, is zero, which ends backtraces.
+  fn __uncore_start_context();
+}
+
+core::arch::global_asm!(
+  ".section .text",
+  ".global __uncore_switch_context",
+  ".align 2",
+  "__uncore_switch_context:",
+  "sd ra, 0 * 8(a0)",
+  "sd sp, 1 * 8(a0)",
+  "sd s0, 2 * 8(a0)",
+  "sd s1, 3 * 8(a0)",
+  "sd s2, 4 * 8(a0)",
+  "sd s3, 5 * 8(a0)",
+  "sd s4, 6 * 8(a0)",
+  "sd s5, 7 * 8(a0)",
+  "sd s6, 8 * 8(a0)",
+  "sd s7, 9 * 8(a0)",
+  "sd s8, 10 * 8(a0)",
+  "sd s9, 11 * 8(a0)",
+  "sd s10, 12 * 8(a0)",
+  "sd s11, 13 * 8(a0)",
+  "ld ra, 0 * 8(a1)",
+  "ld sp, 1 * 8(a1)",
+  "ld s0, 2 * 8(a1)",
+  "ld s1, 3 * 8(a1)",
+  "ld s2, 4 * 8(a1)",
+  "ld s3, 5 * 8(a1)",
+  "ld s4, 6 * 8(a1)",
+  "ld s5, 7 * 8(a1)",
+  "ld s6, 8 * 8(a1)",
+  "ld s7, 9 * 8(a1)",
+  "ld s8, 10 * 8(a1)",
+  "ld s9, 11 * 8(a1)",
+  "ld s10, 12 * 8(a1)",
+  "ld s11, 13 * 8(a1)",
+  "ret",
+  ".global __uncore_start_context",
+  ".align 2",
+  "__uncore_start_context:",
+  "mv a0, s1",
+  "jr s2",
+);
+
+impl crate::arch::ContextSwitch for super::RiscV {
+  type Context = Context;
+
+  fn prepare_context(
+    context: &mut Context,
+    entry: extern "C" fn(usize) -> !,
+    argument: usize,
+    stack_end: usize,
+  ) {
+    *context = Context {
+      return_address: __uncore_start_context as *const () as usize,
+      // The calling convention requires a 16-byte aligned stack
+      stack_pointer:  stack_end & !15,
+      saved:          [0; 12],
+    };
+    context.saved[1] = argument;
+    context.saved[2] = entry as usize;
+  }
+
+  unsafe fn switch_context(from: *mut Context, to: *const Context) { __uncore_switch_context(from, to) }
+}
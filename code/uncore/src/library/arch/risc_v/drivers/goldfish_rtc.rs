@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains a driver for the Goldfish real-time clock, which QEMU provides on
+//! RISC-V, see <https://github.com/qemu/qemu/blob/v8.1.2/hw/rtc/goldfish_rtc.c>.
+//!
+//! The clock counts the nanoseconds since the UNIX epoch. The driver reads it once during
+//! initialization to set the kernel's wall clock (see the `time` module).
+
+use core::sync::atomic::{
+  AtomicUsize,
+  Ordering,
+};
+
+/// Offset of the register that holds the lower 32 bits of the time. Reading it latches
+/// the upper 32 bits into [`TIME_HIGH`].
+const TIME_LOW: usize = 0x00;
+/// Offset of the register that holds the upper 32 bits of the time.
+const TIME_HIGH: usize = 0x04;
+
+/// The base address of the real-time clock; zero until the driver is bound.
+static BASE_ADDRESS: AtomicUsize = AtomicUsize::new(0);
+
+/// Reads the time of the real-time clock at `base_address` as the time since the UNIX
+/// epoch.
+fn read_at(base_address: usize) -> core::time::Duration {
+  let (low, high) = unsafe {
+    let low = ((base_address + TIME_LOW) as *const u32).read_volatile();
+    let high = ((base_address + TIME_HIGH) as *const u32).read_volatile();
+    (low, high)
+  };
+  core::time::Duration::from_nanos(u64::from(high) << 32 | u64::from(low))
+}
+
+/// The driver for the Goldfish real-time clock.
+#[derive(Debug)]
+pub struct Driver;
+
+impl crate::library::drivers::Driver for Driver {
+  fn name(&self) -> &'static str { "goldfish-rtc" }
+
+  fn compatible(&self) -> &'static [&'static str] { &["google,goldfish-rtc"] }
+
+  fn probe(&self, node: &crate::library::drivers::Node) -> Result<(), crate::library::drivers::Error> {
+    use crate::library::drivers::Error;
+
+    if BASE_ADDRESS.load(Ordering::Relaxed) != 0 {
+      return Err(Error::Unsupported("only one real-time clock is supported"));
+    }
+    node.base_address().ok_or(Error::MissingProperty("reg"))?;
+    Ok(())
+  }
+
+  fn init(&self, node: &crate::library::drivers::Node) -> Result<(), crate::library::drivers::Error> {
+    use crate::library::drivers::Error;
+
+    let base_address = node.base_address().ok_or(Error::MissingProperty("reg"))?;
+    let now = read_at(base_address);
+    if now.is_zero() {
+      return Err(Error::Failed("clock is not running"));
+    }
+
+    BASE_ADDRESS.store(base_address, Ordering::Release);
+    crate::library::time::set_realtime(now);
+    Ok(())
+  }
+}
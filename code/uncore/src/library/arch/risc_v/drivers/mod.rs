@@ -2,19 +2,34 @@
 
 //! This module holds all driver-related code for the RISC-V target.
 
-pub mod qemu_uart;
+#[cfg(test)]
+mod tests;
+
+pub mod goldfish_rtc;
+pub mod ns16550a;
+pub mod plic;
+pub mod virtio;
+
+/// All drivers for the RISC-V target. The framework in [`crate::library::drivers`]
+/// matches them against the device tree.
+static DRIVERS: [&dyn crate::library::drivers::Driver; 4] = [
+  &plic::Driver,
+  &ns16550a::Driver,
+  &goldfish_rtc::Driver,
+  &virtio::Driver,
+];
 
 /// Checks whether [`initialize`] has been called before.
 static mut INIT_WAS_CALLED: bool = false;
 
-/// Initializes all drivers for which code exists during startup of the kernel. This
-/// function usually runs before [`crate::setup_kernel`].
+/// Parses the device tree located at `device_tree_address` and binds drivers to the
+/// devices it describes. This function usually runs before [`crate::setup_kernel`].
 ///
 /// #### Panics
 ///
 /// If this function is called more than once, it panics, because initializing certain
 /// drivers more than once is undefined behavior.
-pub(super) fn initialize(hart: usize) {
+pub(super) fn initialize(hart: usize, device_tree_address: usize) {
   if hart != 0 {
     return;
   }
@@ -28,6 +43,13 @@ pub(super) fn initialize(hart: usize) {
     INIT_WAS_CALLED = true;
   }
 
-  qemu_uart::Uart::init();
-  super::super::super::log::KernelLogger::enable_qemu_logger();
+  if unsafe { crate::library::device_tree::initialize(device_tree_address) }.is_err() {
+    // Without a device tree, no devices can be discovered. The UART is still brought up at
+    // its well-known address, so that the kernel can report the problem.
+    ns16550a::initialize_fallback_console();
+    return;
+  }
+
+  crate::library::time::initialize();
+  crate::library::drivers::probe(&DRIVERS);
 }
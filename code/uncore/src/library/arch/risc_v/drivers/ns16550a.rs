@@ -0,0 +1,431 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains a driver for UARTs compatible with the NS16550A, like the one
+//! QEMU provides on RISC-V, see <https://github.com/qemu/qemu/blob/v8.1.2/hw/riscv/virt.c#L90>.
+//!
+//! The register layout is described in the data sheet of the PC16550D, see
+//! <https://www.ti.com/lit/ds/symlink/pc16550d.pdf>. Instances are created from the
+//! device tree, which provides the base address, the input clock frequency and the
+//! register layout of every UART. The first instance serves as the console (or the one
+//! `/chosen/stdout-path` points to), the second one as a data channel.
+
+use crate::library::drivers::Error;
+
+/// The registers of the UART. Some registers share an index and are selected by the
+/// direction of the access or the Divisor Latch Access Bit (DLAB).
+mod register {
+  /// Receiver Buffer Register (read), Transmitter Holding Register (write)
+  pub const DATA: usize = 0;
+  /// Interrupt Enable Register
+  pub const INTERRUPT_ENABLE: usize = 1;
+  /// FIFO Control Register (write)
+  pub const FIFO_CONTROL: usize = 2;
+  /// Line Control Register
+  pub const LINE_CONTROL: usize = 3;
+  /// Modem Control Register
+  pub const MODEM_CONTROL: usize = 4;
+  /// Line Status Register
+  pub const LINE_STATUS: usize = 5;
+  /// Divisor Latch, least significant byte (when DLAB is set)
+  pub const DIVISOR_LEAST: usize = 0;
+  /// Divisor Latch, most significant byte (when DLAB is set)
+  pub const DIVISOR_MOST: usize = 1;
+}
+
+/// Divisor Latch Access Bit in the Line Control Register
+const LINE_CONTROL_DLAB: u8 = 1 << 7;
+/// Enables the FIFOs and clears them
+const FIFO_CONTROL_ENABLE_AND_CLEAR: u8 = 0b111;
+/// Asserts Data Terminal Ready and Request To Send
+const MODEM_CONTROL_DTR_RTS: u8 = 0b11;
+/// Data Ready bit in the Line Status Register
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+/// Transmitter Holding Register Empty bit in the Line Status Register
+const LINE_STATUS_THR_EMPTY: u8 = 1 << 5;
+/// Transmitter Empty bit in the Line Status Register
+const LINE_STATUS_TRANSMITTER_EMPTY: u8 = 1 << 6;
+
+/// The base address of the UART on QEMU's `virt` machine, which is used when no device
+/// tree is available.
+pub const QEMU_BASE_ADDRESS: usize = 0x1000_0000;
+/// The input clock frequency of the UART on QEMU's `virt` machine.
+pub const QEMU_CLOCK_FREQUENCY: u32 = 3_686_400;
+
+/// The parity setting of a serial line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Parity {
+  /// No parity bit
+  None,
+  /// Odd parity
+  Odd,
+  /// Even parity
+  Even,
+}
+
+/// The line settings of a UART.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Configuration {
+  /// The baud rate in bits per second
+  pub baud_rate: u32,
+  /// The number of data bits (5 to 8)
+  pub data_bits: u8,
+  /// The parity setting
+  pub parity:    Parity,
+  /// The number of stop bits (1 or 2)
+  pub stop_bits: u8,
+}
+
+impl Configuration {
+  /// The configuration used when nothing else is specified: 115200 baud, eight data bits,
+  /// no parity and one stop bit.
+  pub const DEFAULT: Self = Self {
+    baud_rate: 115_200,
+    data_bits: 8,
+    parity:    Parity::None,
+    stop_bits: 1,
+  };
+
+  /// Parses a configuration in the format used by `stdout-path` options and the Linux
+  /// kernel's `console=` parameter, e.g. `115200n8` or `115200n82` with two stop bits.
+  /// Missing settings are taken from [`Self::DEFAULT`].
+  #[must_use]
+  pub fn parse(options: &str) -> Option<Self> {
+    let digits = options
+      .find(|c: char| !c.is_ascii_digit())
+      .unwrap_or(options.len());
+    let (baud_rate, rest) = options.split_at(digits);
+    let mut configuration = Self::DEFAULT;
+    configuration.baud_rate = baud_rate.parse().ok()?;
+
+    let mut rest = rest.chars();
+    if let Some(parity) = rest.next() {
+      configuration.parity = match parity {
+        'n' => Parity::None,
+        'o' => Parity::Odd,
+        'e' => Parity::Even,
+        _ => return None,
+      };
+    }
+    if let Some(data_bits) = rest.next() {
+      configuration.data_bits = data_bits.to_digit(10).and_then(|bits| u8::try_from(bits).ok())?;
+    }
+    if let Some(stop_bits) = rest.next() {
+      configuration.stop_bits = match stop_bits {
+        '1' => 1,
+        '2' => 2,
+        _ => return None,
+      };
+    }
+
+    (rest.next().is_none() && (5..=8).contains(&configuration.data_bits)).then_some(configuration)
+  }
+
+  /// Returns the value of the Line Control Register for this configuration.
+  const fn line_control(self) -> u8 {
+    // Bits 0 and 1 select the word length (0b00 = 5 bits, ..., 0b11 = 8 bits), bit 2
+    // selects two stop bits, bit 3 enables the parity bit and bit 4 selects even parity.
+    let word_length = self.data_bits.saturating_sub(5) & 0b11;
+    let stop_bits = if self.stop_bits == 2 { 1 << 2 } else { 0 };
+    let parity = match self.parity {
+      Parity::None => 0,
+      Parity::Odd => 1 << 3,
+      Parity::Even => (1 << 3) | (1 << 4),
+    };
+    word_length | stop_bits | parity
+  }
+}
+
+/// A single NS16550A UART.
+#[derive(Debug, Copy, Clone)]
+pub struct Uart {
+  /// The base address of the MMIO registers used for communicating with the device
+  base_address:    usize,
+  /// The frequency of the input clock in Hz, which the baud rate is derived from
+  clock_frequency: u32,
+  /// Registers are `1 << register_shift` bytes apart
+  register_shift:  u32,
+  /// Whether registers have to be accessed with 32bit instead of 8bit accesses
+  wide_registers:  bool,
+}
+
+impl Uart {
+  /// Creates a new UART whose registers start at `base_address` and are `1 <<
+  /// register_shift` bytes apart. `register_width` is the width of register accesses in
+  /// bytes (1 or 4).
+  ///
+  /// #### Safety
+  ///
+  /// `base_address` must point to the registers of an NS16550A-compatible UART.
+  #[must_use]
+  pub const unsafe fn new(
+    base_address: usize,
+    clock_frequency: u32,
+    register_shift: u32,
+    register_width: u32,
+  ) -> Self {
+    Self {
+      base_address,
+      clock_frequency,
+      register_shift,
+      wide_registers: register_width == 4,
+    }
+  }
+
+  /// Creates a new UART from the device tree node that describes it.
+  ///
+  /// #### Errors
+  ///
+  /// If a required property is missing or the register layout is not supported, an error
+  /// is returned.
+  fn from_node(node: &crate::library::drivers::Node) -> Result<Self, Error> {
+    let base_address = node.base_address().ok_or(Error::MissingProperty("reg"))?;
+    let clock_frequency = node
+      .property("clock-frequency")
+      .and_then(|property| property.as_u32())
+      .ok_or(Error::MissingProperty("clock-frequency"))?;
+    let register_shift = node
+      .property("reg-shift")
+      .and_then(|property| property.as_u32())
+      .unwrap_or(0);
+    let register_width = node
+      .property("reg-io-width")
+      .and_then(|property| property.as_u32())
+      .unwrap_or(1);
+
+    if register_shift > 2 || !(register_width == 1 || register_width == 4) {
+      return Err(Error::Unsupported("register layout"));
+    }
+
+    Ok(unsafe { Self::new(base_address, clock_frequency, register_shift, register_width) })
+  }
+
+  /// Returns the base address of this UART.
+  #[must_use]
+  pub const fn base_address(&self) -> usize { self.base_address }
+
+  /// Reads the register with index `register`.
+  fn read(&self, register: usize) -> u8 {
+    let address = self.base_address + (register << self.register_shift);
+    unsafe {
+      if self.wide_registers {
+        (address as *const u32).read_volatile().to_le_bytes()[0]
+      } else {
+        (address as *const u8).read_volatile()
+      }
+    }
+  }
+
+  /// Writes `value` to the register with index `register`.
+  fn write(&self, register: usize, value: u8) {
+    let address = self.base_address + (register << self.register_shift);
+    unsafe {
+      if self.wide_registers {
+        (address as *mut u32).write_volatile(u32::from(value));
+      } else {
+        (address as *mut u8).write_volatile(value);
+      }
+    }
+  }
+
+  /// Configures the baud rate and line settings of this UART. Interrupts are disabled, as
+  /// the UART is used by polling.
+  ///
+  /// #### Errors
+  ///
+  /// If the baud rate cannot be derived from the input clock, or the line settings are
+  /// invalid, an error is returned.
+  pub fn configure(&self, configuration: Configuration) -> Result<(), Error> {
+    if !(5..=8).contains(&configuration.data_bits) || !(1..=2).contains(&configuration.stop_bits) {
+      return Err(Error::Unsupported("line settings"));
+    }
+
+    // The formula given in the NS16550A specification for calculating the divisor is
+    //
+    //   divisor = clock_hz / (baud_sps x 16)
+    //
+    // which we round to the nearest integer. The divisor latch is 16 bits wide.
+    let sixteen_times_baud_rate = u64::from(configuration.baud_rate) * 16;
+    let divisor = (u64::from(self.clock_frequency) + sixteen_times_baud_rate / 2)
+      .checked_div(sixteen_times_baud_rate)
+      .and_then(|divisor| u16::try_from(divisor).ok())
+      .filter(|&divisor| divisor != 0)
+      .ok_or(Error::Unsupported("baud rate"))?;
+    let [divisor_least, divisor_most] = divisor.to_le_bytes();
+
+    self.write(register::INTERRUPT_ENABLE, 0);
+
+    // The divisor latch shares its registers with the data and interrupt enable
+    // registers; it is accessible while the DLAB is set.
+    self.write(register::LINE_CONTROL, LINE_CONTROL_DLAB);
+    self.write(register::DIVISOR_LEAST, divisor_least);
+    self.write(register::DIVISOR_MOST, divisor_most);
+
+    // Writing the line settings clears the DLAB again.
+    self.write(register::LINE_CONTROL, configuration.line_control());
+    self.write(register::FIFO_CONTROL, FIFO_CONTROL_ENABLE_AND_CLEAR);
+    self.write(register::MODEM_CONTROL, MODEM_CONTROL_DTR_RTS);
+    Ok(())
+  }
+
+  /// Writes a single byte, waiting until the transmitter can accept it.
+  pub fn put(&self, byte: u8) {
+    while self.read(register::LINE_STATUS) & LINE_STATUS_THR_EMPTY == 0 {
+      core::hint::spin_loop();
+    }
+    self.write(register::DATA, byte);
+  }
+
+  /// Reads a single byte if one has been received.
+  #[must_use]
+  pub fn get(&self) -> Option<u8> {
+    (self.read(register::LINE_STATUS) & LINE_STATUS_DATA_READY != 0).then(|| self.read(register::DATA))
+  }
+
+  /// Waits until all bytes have been transmitted.
+  pub fn flush(&self) {
+    while self.read(register::LINE_STATUS) & LINE_STATUS_TRANSMITTER_EMPTY == 0 {
+      core::hint::spin_loop();
+    }
+  }
+}
+
+impl core::fmt::Write for Uart {
+  fn write_str(&mut self, out: &str) -> core::fmt::Result {
+    for byte in out.bytes() {
+      self.put(byte);
+    }
+    Ok(())
+  }
+}
+
+impl crate::arch::ConsoleDevice for Uart {
+  fn put(&self, byte: u8) { Self::put(self, byte); }
+
+  fn get(&self) -> Option<u8> { Self::get(self) }
+}
+
+/// The purpose a UART instance serves.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Role {
+  /// The UART carries the kernel log.
+  Console,
+  /// The UART is available as a debug or data channel.
+  Data,
+}
+
+/// The maximum number of UART instances.
+const MAXIMUM_INSTANCES: usize = 4;
+
+/// All UART instances that have been initialized.
+static INSTANCES: spin::Mutex<[Option<(Uart, Role)>; MAXIMUM_INSTANCES]> =
+  spin::Mutex::new([None; MAXIMUM_INSTANCES]);
+
+/// Returns the first UART instance that serves `role`.
+#[must_use]
+pub fn instance(role: Role) -> Option<Uart> {
+  INSTANCES
+    .lock()
+    .iter()
+    .flatten()
+    .find(|(_, instance_role)| *instance_role == role)
+    .map(|(uart, _)| *uart)
+}
+
+/// Brings up the UART at QEMU's well-known address as the console. This is used when no
+/// device tree is available, so that the kernel can still report problems.
+pub fn initialize_fallback_console() {
+  let uart = unsafe { Uart::new(QEMU_BASE_ADDRESS, QEMU_CLOCK_FREQUENCY, 0, 1) };
+  if uart.configure(Configuration::DEFAULT).is_ok() {
+    INSTANCES.lock()[0] = Some((uart, Role::Console));
+    crate::library::log::KernelLogger::enable_uart_logger(uart);
+  }
+}
+
+/// Returns the node and the options of `/chosen/stdout-path`, if it is set.
+fn stdout() -> Option<(crate::library::drivers::Node, Option<&'static str>)> {
+  let tree = crate::library::device_tree::get()?;
+  let chosen = tree.find_node("/chosen")?;
+  let stdout_path = chosen
+    .property("stdout-path")
+    .or_else(|| chosen.property("linux,stdout-path"))?
+    .as_str()?;
+
+  let mut parts = stdout_path.splitn(2, ':');
+  let node = tree.resolve(parts.next()?)?;
+  Some((node, parts.next()))
+}
+
+/// The driver for NS16550A-compatible UARTs.
+#[derive(Debug)]
+pub struct Driver;
+
+impl crate::library::drivers::Driver for Driver {
+  fn name(&self) -> &'static str { "ns16550a" }
+
+  fn compatible(&self) -> &'static [&'static str] { &["ns16550a", "ns16550"] }
+
+  fn probe(&self, node: &crate::library::drivers::Node) -> Result<(), Error> {
+    Uart::from_node(node)?;
+    crate::library::drivers::require_interrupt_parent(node)
+  }
+
+  fn init(&self, node: &crate::library::drivers::Node) -> Result<(), Error> {
+    let uart = Uart::from_node(node)?;
+
+    let stdout = stdout();
+    let is_stdout = stdout.is_some_and(|(stdout_node, _)| stdout_node == *node);
+    let configuration = node
+      .property("current-speed")
+      .and_then(|property| property.as_u32())
+      .map(|baud_rate| Configuration {
+        baud_rate,
+        ..Configuration::DEFAULT
+      })
+      .or_else(|| {
+        stdout
+          .filter(|_| is_stdout)
+          .and_then(|(_, options)| options)
+          .and_then(Configuration::parse)
+      })
+      .unwrap_or(Configuration::DEFAULT);
+
+    // The UART `/chosen/stdout-path` points to becomes the console. If it does not point
+    // to a UART, the first one becomes the console.
+    let stdout_is_other_uart = stdout.is_some_and(|(stdout_node, _)| {
+      stdout_node != *node
+        && self
+          .compatible()
+          .iter()
+          .any(|compatible| stdout_node.is_compatible(compatible))
+    });
+
+    // The slot is reserved before the UART is configured, so that a UART that is
+    // rejected is left untouched.
+    let mut instances = INSTANCES.lock();
+    let has_console = instances.iter().flatten().any(|(_, role)| *role == Role::Console);
+    let slot = instances
+      .iter_mut()
+      .find(|slot| slot.is_none())
+      .ok_or(Error::Unsupported("too many UARTs"))?;
+    let role = if !has_console && !stdout_is_other_uart {
+      Role::Console
+    } else {
+      Role::Data
+    };
+
+    uart.configure(configuration)?;
+    *slot = Some((uart, role));
+    drop(instances);
+
+    if role == Role::Console {
+      crate::library::log::KernelLogger::enable_uart_logger(uart);
+    }
+    Ok(())
+  }
+
+  fn shutdown(&self, node: &crate::library::drivers::Node) {
+    if let Ok(uart) = Uart::from_node(node) {
+      uart.flush();
+    }
+  }
+}
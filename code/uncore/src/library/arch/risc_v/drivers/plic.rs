@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains a driver for the RISC-V Platform-Level Interrupt Controller
+//! (PLIC).
+//!
+//! The PLIC routes the interrupts of external devices to the harts, see
+//! <https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc>.
+
+use core::sync::atomic::{
+  AtomicU32,
+  AtomicUsize,
+  Ordering,
+};
+
+/// Offset of the interrupt source priority registers.
+const PRIORITY_OFFSET: usize = 0x00_0000;
+/// Offset of the interrupt enable bits of the first context.
+const ENABLE_OFFSET: usize = 0x00_2000;
+/// Distance between the interrupt enable bits of two contexts.
+const ENABLE_STRIDE: usize = 0x80;
+/// Offset of the priority threshold register of the first context.
+const CONTEXT_OFFSET: usize = 0x20_0000;
+/// Distance between the priority threshold registers of two contexts.
+const CONTEXT_STRIDE: usize = 0x1000;
+/// Offset of the claim/complete register relative to the priority threshold register.
+const CLAIM_OFFSET: usize = 4;
+/// The maximum number of interrupt sources the PLIC supports.
+const MAXIMUM_SOURCES: u32 = 1024;
+
+/// The base address of the PLIC; zero until the driver is bound.
+static BASE_ADDRESS: AtomicUsize = AtomicUsize::new(0);
+/// The number of interrupt sources the PLIC provides (`riscv,ndev`).
+static SOURCE_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Returns the context of the supervisor mode of `hart`. On QEMU's `virt` machine, every
+/// hart has two contexts, the first one for machine mode and the second one for
+/// supervisor mode.
+const fn supervisor_context(hart: usize) -> usize { 2 * hart + 1 }
+
+/// Returns a pointer to the 32bit register at `offset`, or [`None`] if the PLIC is not
+/// bound.
+fn register(offset: usize) -> Option<*mut u32> {
+  match BASE_ADDRESS.load(Ordering::Acquire) {
+    0 => None,
+    base_address => Some((base_address + offset) as *mut u32),
+  }
+}
+
+/// Writes `value` to the register at `offset` if the PLIC is bound.
+fn write(offset: usize, value: u32) {
+  if let Some(register) = register(offset) {
+    unsafe { register.write_volatile(value) };
+  }
+}
+
+/// Reads the register at `offset` if the PLIC is bound.
+fn read(offset: usize) -> Option<u32> { register(offset).map(|register| unsafe { register.read_volatile() }) }
+
+/// Enables the interrupt `source` with `priority` for the supervisor mode of hart 0. A
+/// priority of zero effectively disables the interrupt.
+// No driver takes interrupts yet
+#[allow(dead_code)]
+pub fn enable(source: u32, priority: u32) {
+  if source == 0 || source >= SOURCE_COUNT.load(Ordering::Relaxed) {
+    return;
+  }
+
+  let source = source as usize;
+  write(PRIORITY_OFFSET + 4 * source, priority);
+
+  let enable = ENABLE_OFFSET + ENABLE_STRIDE * supervisor_context(0) + 4 * (source / 32);
+  if let Some(bits) = read(enable) {
+    write(enable, bits | 1 << (source % 32));
+  }
+}
+
+/// Disables the interrupt `source` for the supervisor mode of hart 0.
+pub fn disable(source: u32) {
+  let source = source as usize;
+  let enable = ENABLE_OFFSET + ENABLE_STRIDE * supervisor_context(0) + 4 * (source / 32);
+  if let Some(bits) = read(enable) {
+    write(enable, bits & !(1 << (source % 32)));
+  }
+}
+
+/// Claims the highest-priority pending interrupt, if there is one.
+#[must_use]
+pub fn claim() -> Option<u32> {
+  read(CONTEXT_OFFSET + CONTEXT_STRIDE * supervisor_context(0) + CLAIM_OFFSET).filter(|&source| source != 0)
+}
+
+/// Signals that the interrupt `source`, which was obtained with [`claim`], has been
+/// handled.
+pub fn complete(source: u32) {
+  write(
+    CONTEXT_OFFSET + CONTEXT_STRIDE * supervisor_context(0) + CLAIM_OFFSET,
+    source,
+  );
+}
+
+/// The driver for the PLIC.
+#[derive(Debug)]
+pub struct Driver;
+
+impl crate::library::drivers::Driver for Driver {
+  fn name(&self) -> &'static str { "plic" }
+
+  fn compatible(&self) -> &'static [&'static str] { &["sifive,plic-1.0.0", "riscv,plic0"] }
+
+  fn probe(&self, node: &crate::library::drivers::Node) -> Result<(), crate::library::drivers::Error> {
+    use crate::library::drivers::Error;
+
+    if BASE_ADDRESS.load(Ordering::Relaxed) != 0 {
+      return Err(Error::Unsupported("only one PLIC is supported"));
+    }
+
+    node.base_address().ok_or(Error::MissingProperty("reg"))?;
+    node
+      .property("riscv,ndev")
+      .and_then(|property| property.as_u32())
+      .ok_or(Error::MissingProperty("riscv,ndev"))?;
+    Ok(())
+  }
+
+  fn init(&self, node: &crate::library::drivers::Node) -> Result<(), crate::library::drivers::Error> {
+    use crate::library::drivers::Error;
+
+    let base_address = node.base_address().ok_or(Error::MissingProperty("reg"))?;
+    let sources = node
+      .property("riscv,ndev")
+      .and_then(|property| property.as_u32())
+      .ok_or(Error::MissingProperty("riscv,ndev"))?;
+
+    // Source zero does not exist, hence the number of sources is `riscv,ndev` plus one.
+    SOURCE_COUNT.store((sources + 1).min(MAXIMUM_SOURCES), Ordering::Relaxed);
+    BASE_ADDRESS.store(base_address, Ordering::Release);
+
+    for source in 1..SOURCE_COUNT.load(Ordering::Relaxed) {
+      disable(source);
+      write(PRIORITY_OFFSET + 4 * source as usize, 0);
+    }
+    write(CONTEXT_OFFSET + CONTEXT_STRIDE * supervisor_context(0), 0);
+    Ok(())
+  }
+
+  fn shutdown(&self, _node: &crate::library::drivers::Node) {
+    for source in 1..SOURCE_COUNT.load(Ordering::Relaxed) {
+      disable(source);
+    }
+  }
+}
@@ -0,0 +1,34 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unit tests of the RISC-V drivers.
+
+use super::ns16550a::{
+  Configuration,
+  Parity,
+};
+
+#[test_case]
+fn uart_options_are_parsed() {
+  assert_eq!(Configuration::parse("115200"), Some(Configuration::DEFAULT));
+  assert_eq!(
+    Configuration::parse("9600e7"),
+    Some(Configuration {
+      baud_rate: 9600,
+      data_bits: 7,
+      parity:    Parity::Even,
+      stop_bits: 1,
+    })
+  );
+  assert_eq!(Configuration::parse("115200n81"), Some(Configuration::DEFAULT));
+  assert_eq!(
+    Configuration::parse("115200n82"),
+    Some(Configuration {
+      stop_bits: 2,
+      ..Configuration::DEFAULT
+    })
+  );
+
+  for invalid in ["", "n8", "115200x8", "115200n9", "115200n83", "115200n81r"] {
+    assert_eq!(Configuration::parse(invalid), None, "'{invalid}' was accepted");
+  }
+}
@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains a driver for the `VirtIO` block device, see section 5.2 ("Block
+//! Device") of the `VirtIO` specification.
+//!
+//! QEMU provides such a device for every `-device virtio-blk-device`. The driver
+//! registers the devices as `vda`, `vdb`, etc. with the kernel's block device registry
+//! (`library::fs::block`). Requests are processed synchronously: the driver waits until
+//! the device has completed each request.
+
+use super::{
+  MmioTransport,
+  VirtQueue,
+};
+use crate::library::fs;
+
+/// The maximum number of block devices the driver manages.
+const MAXIMUM_DEVICES: usize = 4;
+/// The names under which the devices are registered.
+const NAMES: [&str; MAXIMUM_DEVICES] = ["vda", "vdb", "vdc", "vdd"];
+
+/// The size of the request queue. A request uses three descriptors.
+const QUEUE_SIZE: usize = 4;
+/// The index of the request queue.
+const QUEUE_INDEX: u32 = 0;
+
+/// The size of a sector, in which the device measures its capacity and positions.
+const SECTOR_SIZE: usize = 512;
+
+/// Feature bit that indicates that the device is read-only.
+const FEATURE_READ_ONLY: u64 = 1 << 5;
+
+/// Request types, see section 5.2.6 ("Device Operation").
+mod request {
+  /// Read sectors
+  pub const IN: u32 = 0;
+  /// Write sectors
+  pub const OUT: u32 = 1;
+  /// Flush the write cache
+  pub const FLUSH: u32 = 4;
+}
+
+/// The status the device writes after processing a request.
+const STATUS_OK: u8 = 0;
+
+/// The header of every request.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct RequestHeader {
+  /// The type of the request
+  kind:     u32,
+  /// Reserved
+  reserved: u32,
+  /// The first sector the request refers to
+  sector:   u64,
+}
+
+impl RequestHeader {
+  /// Returns the bytes of the header, so that it can be handed to the device.
+  const fn as_bytes(&self) -> &[u8] {
+    unsafe {
+      core::slice::from_raw_parts(
+        core::ptr::from_ref(self).cast::<u8>(),
+        core::mem::size_of::<Self>(),
+      )
+    }
+  }
+}
+
+/// The memory of the request queues of all devices.
+static mut QUEUE_MEMORY: [super::queue::QueueMemory<QUEUE_SIZE>; MAXIMUM_DEVICES] =
+  [const { super::queue::QueueMemory::new() }; MAXIMUM_DEVICES];
+
+/// The devices the driver manages.
+static mut DEVICES: [Option<Disk>; MAXIMUM_DEVICES] = [const { None }; MAXIMUM_DEVICES];
+
+/// A `VirtIO` block device.
+#[derive(Debug)]
+pub struct Disk {
+  /// The MMIO transport of the device
+  transport: MmioTransport,
+  /// The request queue
+  queue:     spin::Mutex<VirtQueue<QUEUE_SIZE>>,
+  /// The capacity of the device in sectors
+  capacity:  u64,
+  /// Whether the device is read-only
+  read_only: bool,
+}
+
+impl Disk {
+  /// Initializes the device and sets up the request queue in `memory`.
+  fn new(
+    transport: MmioTransport,
+    memory: &'static mut super::queue::QueueMemory<QUEUE_SIZE>,
+  ) -> Result<Self, super::Error> {
+    let features = transport.begin_initialization(FEATURE_READ_ONLY)?;
+    let queue = VirtQueue::new(memory);
+    transport.setup_queue(QUEUE_INDEX, &queue)?;
+    transport.finish_initialization();
+
+    let capacity = u64::from(transport.read_config(0)) | (u64::from(transport.read_config(4)) << 32);
+    Ok(Self {
+      transport,
+      queue: spin::Mutex::new(queue),
+      capacity,
+      read_only: features & FEATURE_READ_ONLY != 0,
+    })
+  }
+
+  /// Submits a request of type `kind` for the sectors starting at `sector`. The device
+  /// reads from `input` and writes into `output`.
+  fn request(&self, kind: u32, sector: u64, input: &[u8], output: &mut [u8]) -> fs::Result<()> {
+    let header = RequestHeader {
+      kind,
+      reserved: 0,
+      sector,
+    };
+    let mut status = [0xFF];
+
+    let mut queue = self.queue.lock();
+    let notify = || self.transport.notify(QUEUE_INDEX);
+    if input.is_empty() && output.is_empty() {
+      queue.submit(&[header.as_bytes()], &mut [&mut status], notify);
+    } else if output.is_empty() {
+      queue.submit(&[header.as_bytes(), input], &mut [&mut status], notify);
+    } else {
+      queue.submit(&[header.as_bytes()], &mut [output, &mut status], notify);
+    }
+
+    if status[0] == STATUS_OK {
+      Ok(())
+    } else {
+      Err(fs::Error::Io)
+    }
+  }
+}
+
+impl fs::block::BlockDevice for Disk {
+  fn block_size(&self) -> usize { SECTOR_SIZE }
+
+  fn block_count(&self) -> u64 { self.capacity }
+
+  fn read_blocks(&self, block: u64, buffer: &mut [u8]) -> fs::Result<()> {
+    fs::block::check_request(self, block, buffer.len())?;
+    if buffer.is_empty() {
+      return Ok(());
+    }
+    self.request(request::IN, block, &[], buffer)
+  }
+
+  fn write_blocks(&self, block: u64, buffer: &[u8]) -> fs::Result<()> {
+    if self.read_only {
+      return Err(fs::Error::ReadOnly);
+    }
+    fs::block::check_request(self, block, buffer.len())?;
+    if buffer.is_empty() {
+      return Ok(());
+    }
+    self.request(request::OUT, block, buffer, &mut [])
+  }
+
+  fn flush(&self) -> fs::Result<()> { self.request(request::FLUSH, 0, &[], &mut []) }
+}
+
+/// Initializes the block device behind `transport` and registers it with the kernel's
+/// block device registry.
+///
+/// #### Errors
+///
+/// If there are too many block devices or the device cannot be initialized, an error is
+/// returned.
+pub(super) fn initialize(transport: MmioTransport) -> Result<(), crate::library::drivers::Error> {
+  let devices = unsafe { &mut *core::ptr::addr_of_mut!(DEVICES) };
+  let index = devices
+    .iter()
+    .position(Option::is_none)
+    .ok_or(crate::library::drivers::Error::Unsupported(
+      "too many block devices",
+    ))?;
+
+  let memory = unsafe { &mut (*core::ptr::addr_of_mut!(QUEUE_MEMORY))[index] };
+  let device: &'static Disk = devices[index].insert(Disk::new(transport, memory)?);
+  fs::block::register(NAMES[index], device)
+    .map_err(|_| crate::library::drivers::Error::Failed("could not register block device"))
+}
@@ -0,0 +1,400 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains a driver for the 2D mode of the `VirtIO` GPU device, see section
+//! 5.7 ("GPU Device") of the `VirtIO` specification.
+//!
+//! The driver creates a single host resource, attaches a statically allocated framebuffer
+//! as its backing pages and uses it as the scanout of the first display. Changes to the
+//! framebuffer are made visible by transferring them to the host and flushing the
+//! resource. The framebuffer is handed to the kernel's text console (`library::console`).
+
+// The device uses 32bit values for sizes and positions, while the framebuffer dimensions
+// are bounded by `MAXIMUM_WIDTH` and `MAXIMUM_HEIGHT`.
+#![allow(clippy::cast_possible_truncation)]
+
+use super::{
+  Error,
+  MmioTransport,
+  VirtQueue,
+};
+
+/// The maximum width of the framebuffer in pixels.
+const MAXIMUM_WIDTH: usize = 800;
+/// The maximum height of the framebuffer in pixels.
+const MAXIMUM_HEIGHT: usize = 600;
+
+/// The size of the control queue.
+const CONTROL_QUEUE_SIZE: usize = 16;
+/// The index of the control queue.
+const CONTROL_QUEUE_INDEX: u32 = 0;
+
+/// The ID of the (only) resource the driver creates. Resource ID 0 is reserved.
+const RESOURCE_ID: u32 = 1;
+/// The ID of the scanout (display) the driver uses.
+const SCANOUT_ID: u32 = 0;
+
+/// The maximum number of scanouts the device reports.
+const MAXIMUM_SCANOUTS: usize = 16;
+
+/// Command and response types, see section 5.7.6.7 ("Device Operation: Request header").
+mod command {
+  /// Retrieve the current output configuration
+  pub const GET_DISPLAY_INFO: u32 = 0x0100;
+  /// Create a 2D resource on the host
+  pub const RESOURCE_CREATE_2D: u32 = 0x0101;
+  /// Set the scanout parameters for a single output
+  pub const SET_SCANOUT: u32 = 0x0103;
+  /// Flush a scanout resource
+  pub const RESOURCE_FLUSH: u32 = 0x0104;
+  /// Transfer from guest memory to host resource
+  pub const TRANSFER_TO_HOST_2D: u32 = 0x0105;
+  /// Assign backing pages to a resource
+  pub const RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+
+  /// Success without data
+  pub const RESPONSE_OK_NO_DATA: u32 = 0x1100;
+  /// Success with display information
+  pub const RESPONSE_OK_DISPLAY_INFO: u32 = 0x1101;
+}
+
+/// The pixel format `B8G8R8X8`, which corresponds to a little-endian `u32` with the
+/// layout `0x00RRGGBB`.
+const FORMAT_B8G8R8X8_UNORM: u32 = 2;
+
+/// The header of every request and response.
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+struct ControlHeader {
+  /// The command or response type
+  kind:       u32,
+  /// Request / response flags
+  flags:      u32,
+  /// Fence ID (unused)
+  fence_id:   u64,
+  /// 3D context ID (unused)
+  context_id: u32,
+  /// Ring index (unused)
+  ring_index: u8,
+  /// Padding
+  padding:    [u8; 3],
+}
+
+impl ControlHeader {
+  /// Creates a new header for a request of type `kind`.
+  const fn new(kind: u32) -> Self {
+    Self {
+      kind,
+      flags: 0,
+      fence_id: 0,
+      context_id: 0,
+      ring_index: 0,
+      padding: [0; 3],
+    }
+  }
+}
+
+/// A rectangle as understood by the device.
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+struct DeviceRectangle {
+  /// Horizontal position
+  x:      u32,
+  /// Vertical position
+  y:      u32,
+  /// Width
+  width:  u32,
+  /// Height
+  height: u32,
+}
+
+impl From<crate::library::console::Rectangle> for DeviceRectangle {
+  fn from(rectangle: crate::library::console::Rectangle) -> Self {
+    Self {
+      x:      rectangle.x as u32,
+      y:      rectangle.y as u32,
+      width:  rectangle.width as u32,
+      height: rectangle.height as u32,
+    }
+  }
+}
+
+/// Information about a single display.
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+struct DisplayMode {
+  /// The preferred position and size of the display
+  rectangle: DeviceRectangle,
+  /// Whether the display is enabled
+  enabled:   u32,
+  /// Flags (unused)
+  flags:     u32,
+}
+
+/// The response to [`command::GET_DISPLAY_INFO`].
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+struct DisplayInformation {
+  /// The response header
+  header: ControlHeader,
+  /// The display modes of all scanouts
+  modes:  [DisplayMode; MAXIMUM_SCANOUTS],
+}
+
+/// The request [`command::RESOURCE_CREATE_2D`].
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct ResourceCreate2D {
+  /// The request header
+  header:      ControlHeader,
+  /// The ID of the new resource
+  resource_id: u32,
+  /// The pixel format
+  format:      u32,
+  /// The width of the resource
+  width:       u32,
+  /// The height of the resource
+  height:      u32,
+}
+
+/// The request [`command::RESOURCE_ATTACH_BACKING`] with exactly one memory entry.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct ResourceAttachBacking {
+  /// The request header
+  header:        ControlHeader,
+  /// The ID of the resource
+  resource_id:   u32,
+  /// The number of memory entries (always 1)
+  entries_count: u32,
+  /// The physical address of the backing memory
+  address:       u64,
+  /// The length of the backing memory
+  length:        u32,
+  /// Padding
+  padding:       u32,
+}
+
+/// The request [`command::SET_SCANOUT`].
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct SetScanout {
+  /// The request header
+  header:      ControlHeader,
+  /// The area of the resource to display
+  rectangle:   DeviceRectangle,
+  /// The ID of the scanout
+  scanout_id:  u32,
+  /// The ID of the resource
+  resource_id: u32,
+}
+
+/// The request [`command::TRANSFER_TO_HOST_2D`].
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct TransferToHost2D {
+  /// The request header
+  header:      ControlHeader,
+  /// The area to transfer
+  rectangle:   DeviceRectangle,
+  /// The offset of the area into the backing memory
+  offset:      u64,
+  /// The ID of the resource
+  resource_id: u32,
+  /// Padding
+  padding:     u32,
+}
+
+/// The request [`command::RESOURCE_FLUSH`].
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct ResourceFlush {
+  /// The request header
+  header:      ControlHeader,
+  /// The area to flush
+  rectangle:   DeviceRectangle,
+  /// The ID of the resource
+  resource_id: u32,
+  /// Padding
+  padding:     u32,
+}
+
+/// Returns the bytes of `value`, so that it can be handed to the device.
+const fn as_bytes<T>(value: &T) -> &[u8] {
+  unsafe { core::slice::from_raw_parts(core::ptr::from_ref(value).cast::<u8>(), core::mem::size_of::<T>()) }
+}
+
+/// Returns the bytes of `value` mutably, so that the device can write into it.
+const fn as_bytes_mut<T>(value: &mut T) -> &mut [u8] {
+  unsafe {
+    core::slice::from_raw_parts_mut(core::ptr::from_mut(value).cast::<u8>(), core::mem::size_of::<T>())
+  }
+}
+
+/// The memory of the control queue.
+static mut CONTROL_QUEUE_MEMORY: super::queue::QueueMemory<CONTROL_QUEUE_SIZE> =
+  super::queue::QueueMemory::new();
+
+/// The memory that backs the framebuffer resource. It is page-aligned so that it can be
+/// handed to the device as a single memory entry.
+#[repr(C, align(4096))]
+struct FramebufferMemory([u32; MAXIMUM_WIDTH * MAXIMUM_HEIGHT]);
+
+/// The framebuffer the driver attaches to its resource.
+static mut FRAMEBUFFER_MEMORY: FramebufferMemory = FramebufferMemory([0; MAXIMUM_WIDTH * MAXIMUM_HEIGHT]);
+
+/// The (only) GPU the driver manages. It is handed to the text console after
+/// initialization.
+static mut GPU: Option<Gpu> = None;
+
+/// A `VirtIO` GPU device in 2D mode.
+#[derive(Debug)]
+pub struct Gpu {
+  /// The MMIO transport of the device
+  transport:     MmioTransport,
+  /// The control queue
+  control_queue: VirtQueue<CONTROL_QUEUE_SIZE>,
+  /// The pixels of the framebuffer
+  framebuffer:   &'static mut [u32],
+  /// The width of the framebuffer
+  width:         usize,
+  /// The height of the framebuffer
+  height:        usize,
+}
+
+impl Gpu {
+  /// Sends `request` to the device and returns the response type.
+  fn request<T, R>(&mut self, request: &T, response: &mut R) -> u32 {
+    let transport = &self.transport;
+    self
+      .control_queue
+      .submit(&[as_bytes(request)], &mut [as_bytes_mut(response)], || {
+        transport.notify(CONTROL_QUEUE_INDEX);
+      });
+    // Every response starts with a control header.
+    unsafe { core::ptr::from_mut(response).cast::<ControlHeader>().read().kind }
+  }
+
+  /// Sends `request` to the device, which is expected to respond without data.
+  fn request_without_data<T>(&mut self, request: &T) -> Result<(), Error> {
+    let mut response = ControlHeader::default();
+    match self.request(request, &mut response) {
+      command::RESPONSE_OK_NO_DATA => Ok(()),
+      other => Err(Error::UnexpectedResponse(other)),
+    }
+  }
+
+  /// Initializes the device: sets up the control queue, creates the framebuffer resource
+  /// and attaches the statically allocated backing memory to it.
+  fn new(transport: MmioTransport) -> Result<Self, Error> {
+    transport.begin_initialization(0)?;
+    let control_queue = VirtQueue::new(unsafe { &mut *core::ptr::addr_of_mut!(CONTROL_QUEUE_MEMORY) });
+    transport.setup_queue(CONTROL_QUEUE_INDEX, &control_queue)?;
+    transport.finish_initialization();
+
+    let framebuffer = unsafe { &mut (*core::ptr::addr_of_mut!(FRAMEBUFFER_MEMORY)).0 };
+    let mut gpu = Self {
+      transport,
+      control_queue,
+      framebuffer,
+      width: MAXIMUM_WIDTH,
+      height: MAXIMUM_HEIGHT,
+    };
+
+    let mut display_information = DisplayInformation::default();
+    let request = ControlHeader::new(command::GET_DISPLAY_INFO);
+    match gpu.request(&request, &mut display_information) {
+      command::RESPONSE_OK_DISPLAY_INFO => {
+        let mode = display_information.modes[SCANOUT_ID as usize];
+        if mode.enabled != 0 {
+          gpu.width = (mode.rectangle.width as usize).clamp(1, MAXIMUM_WIDTH);
+          gpu.height = (mode.rectangle.height as usize).clamp(1, MAXIMUM_HEIGHT);
+        }
+      },
+      other => return Err(Error::UnexpectedResponse(other)),
+    }
+
+    gpu.request_without_data(&ResourceCreate2D {
+      header:      ControlHeader::new(command::RESOURCE_CREATE_2D),
+      resource_id: RESOURCE_ID,
+      format:      FORMAT_B8G8R8X8_UNORM,
+      width:       gpu.width as u32,
+      height:      gpu.height as u32,
+    })?;
+
+    gpu.request_without_data(&ResourceAttachBacking {
+      header:        ControlHeader::new(command::RESOURCE_ATTACH_BACKING),
+      resource_id:   RESOURCE_ID,
+      entries_count: 1,
+      address:       gpu.framebuffer.as_ptr() as u64,
+      length:        (gpu.width * gpu.height * core::mem::size_of::<u32>()) as u32,
+      padding:       0,
+    })?;
+
+    gpu.request_without_data(&SetScanout {
+      header:      ControlHeader::new(command::SET_SCANOUT),
+      rectangle:   DeviceRectangle {
+        x:      0,
+        y:      0,
+        width:  gpu.width as u32,
+        height: gpu.height as u32,
+      },
+      scanout_id:  SCANOUT_ID,
+      resource_id: RESOURCE_ID,
+    })?;
+
+    Ok(gpu)
+  }
+
+  /// Transfers `rectangle` from the framebuffer to the host resource and flushes it to
+  /// the display.
+  fn flush_rectangle(&mut self, rectangle: DeviceRectangle) -> Result<(), Error> {
+    let offset = (rectangle.y as usize * self.width + rectangle.x as usize) * core::mem::size_of::<u32>();
+    self.request_without_data(&TransferToHost2D {
+      header: ControlHeader::new(command::TRANSFER_TO_HOST_2D),
+      rectangle,
+      offset: offset as u64,
+      resource_id: RESOURCE_ID,
+      padding: 0,
+    })?;
+
+    self.request_without_data(&ResourceFlush {
+      header: ControlHeader::new(command::RESOURCE_FLUSH),
+      rectangle,
+      resource_id: RESOURCE_ID,
+      padding: 0,
+    })
+  }
+}
+
+impl crate::library::console::Framebuffer for Gpu {
+  fn width(&self) -> usize { self.width }
+
+  fn height(&self) -> usize { self.height }
+
+  fn pixels(&mut self) -> &mut [u32] { &mut self.framebuffer[..self.width * self.height] }
+
+  fn flush(&mut self, area: crate::library::console::Rectangle) {
+    // There is nobody to report this error to, as this function is called by the
+    // console that may be used by the kernel log itself.
+    let _ = self.flush_rectangle(area.into());
+  }
+}
+
+/// Initializes the GPU behind `transport` and hands its framebuffer to the text console.
+/// Only one GPU is supported.
+///
+/// #### Errors
+///
+/// If there already is a GPU or the GPU cannot be initialized, an error is returned.
+pub(super) fn initialize(transport: MmioTransport) -> Result<(), crate::library::drivers::Error> {
+  let gpu = unsafe { &mut *core::ptr::addr_of_mut!(GPU) };
+  if gpu.is_some() {
+    return Err(crate::library::drivers::Error::Unsupported(
+      "only one GPU is supported",
+    ));
+  }
+
+  let new_gpu = Gpu::new(transport)?;
+  crate::library::console::initialize(gpu.insert(new_gpu));
+  Ok(())
+}
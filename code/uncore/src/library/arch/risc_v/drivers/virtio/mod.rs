@@ -0,0 +1,391 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains code to work with `VirtIO` devices that QEMU exposes via MMIO on
+//! RISC-V, see <https://github.com/qemu/qemu/blob/v8.1.2/hw/riscv/virt.c#L91>.
+//!
+//! The implementation follows the `VirtIO` specification, version 1.2, section 4.2
+//! ("Virtio Over MMIO"), see
+//! <https://docs.oasis-open.org/virtio/virtio/v1.2/csd01/virtio-v1.2-csd01.html>. Both the
+//! legacy interface (version 1, which QEMU uses by default) and the modern interface
+//! (version 2) are supported.
+
+// All registers are 32bit wide; 64bit values (addresses, feature sets) are split into
+// two registers explicitly.
+#![allow(clippy::cast_possible_truncation)]
+
+pub mod block;
+pub mod gpu;
+mod queue;
+
+pub use queue::VirtQueue;
+
+/// The value every `VirtIO` MMIO device returns when reading the magic value register
+/// (little-endian "virt").
+const MAGIC_VALUE: u32 = 0x7472_6976;
+
+/// The page size the legacy interface uses to calculate queue addresses.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Offsets of the registers in the `VirtIO` MMIO register layout.
+#[allow(dead_code)]
+mod register {
+  /// Magic value, must be [`super::MAGIC_VALUE`]
+  pub const MAGIC_VALUE: usize = 0x000;
+  /// Device version number (1 = legacy, 2 = modern)
+  pub const VERSION: usize = 0x004;
+  /// `VirtIO` subsystem device ID
+  pub const DEVICE_ID: usize = 0x008;
+  /// `VirtIO` subsystem vendor ID
+  pub const VENDOR_ID: usize = 0x00C;
+  /// Flags representing features the device supports
+  pub const DEVICE_FEATURES: usize = 0x010;
+  /// Device (host) features word selection
+  pub const DEVICE_FEATURES_SELECT: usize = 0x014;
+  /// Flags representing device features understood and activated by the driver
+  pub const DRIVER_FEATURES: usize = 0x020;
+  /// Activated (guest) features word selection
+  pub const DRIVER_FEATURES_SELECT: usize = 0x024;
+  /// Guest page size (legacy interface only)
+  pub const GUEST_PAGE_SIZE: usize = 0x028;
+  /// Virtual queue index
+  pub const QUEUE_SELECT: usize = 0x030;
+  /// Maximum virtual queue size
+  pub const QUEUE_NUMBER_MAX: usize = 0x034;
+  /// Virtual queue size
+  pub const QUEUE_NUMBER: usize = 0x038;
+  /// Used ring alignment in the virtual queue (legacy interface only)
+  pub const QUEUE_ALIGN: usize = 0x03C;
+  /// Guest physical page number of the virtual queue (legacy interface only)
+  pub const QUEUE_PFN: usize = 0x040;
+  /// Virtual queue ready bit (modern interface only)
+  pub const QUEUE_READY: usize = 0x044;
+  /// Queue notifier
+  pub const QUEUE_NOTIFY: usize = 0x050;
+  /// Interrupt status
+  pub const INTERRUPT_STATUS: usize = 0x060;
+  /// Interrupt acknowledge
+  pub const INTERRUPT_ACK: usize = 0x064;
+  /// Device status
+  pub const STATUS: usize = 0x070;
+  /// Virtual queue's descriptor area, low 32 bits (modern interface only)
+  pub const QUEUE_DESCRIPTOR_LOW: usize = 0x080;
+  /// Virtual queue's descriptor area, high 32 bits (modern interface only)
+  pub const QUEUE_DESCRIPTOR_HIGH: usize = 0x084;
+  /// Virtual queue's driver area, low 32 bits (modern interface only)
+  pub const QUEUE_DRIVER_LOW: usize = 0x090;
+  /// Virtual queue's driver area, high 32 bits (modern interface only)
+  pub const QUEUE_DRIVER_HIGH: usize = 0x094;
+  /// Virtual queue's device area, low 32 bits (modern interface only)
+  pub const QUEUE_DEVICE_LOW: usize = 0x0A0;
+  /// Virtual queue's device area, high 32 bits (modern interface only)
+  pub const QUEUE_DEVICE_HIGH: usize = 0x0A4;
+  /// Configuration atomicity value (modern interface only)
+  pub const CONFIG_GENERATION: usize = 0x0FC;
+  /// Start of the device-specific configuration space
+  pub const CONFIG: usize = 0x100;
+}
+
+/// Bits of the device status register.
+mod status {
+  /// The guest OS has found the device and recognized it as a valid virtio device.
+  pub const ACKNOWLEDGE: u32 = 1;
+  /// The guest OS knows how to drive the device.
+  pub const DRIVER: u32 = 2;
+  /// The driver is set up and ready to drive the device.
+  pub const DRIVER_OK: u32 = 4;
+  /// The driver has acknowledged all the features it understands.
+  pub const FEATURES_OK: u32 = 8;
+  /// Something went wrong in the guest, and it has given up on the device.
+  pub const FAILED: u32 = 128;
+}
+
+/// Feature bit that indicates compliance with the modern (version 1.0+) specification.
+const FEATURE_VERSION_1: u64 = 1 << 32;
+
+/// The types of `VirtIO` devices this kernel knows about. The values correspond to the
+/// device IDs from the specification, section 5 ("Device Types").
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u32)]
+pub enum DeviceType {
+  /// Network card
+  Network = 1,
+  /// Block device
+  Block   = 2,
+  /// Console
+  Console = 3,
+  /// Entropy source
+  Entropy = 4,
+  /// GPU device
+  Gpu     = 16,
+  /// Input device (e.g. a keyboard)
+  Input   = 18,
+}
+
+impl DeviceType {
+  /// Converts a raw device ID into a [`DeviceType`], if the kernel knows about it.
+  const fn from_id(id: u32) -> Option<Self> {
+    match id {
+      1 => Some(Self::Network),
+      2 => Some(Self::Block),
+      3 => Some(Self::Console),
+      4 => Some(Self::Entropy),
+      16 => Some(Self::Gpu),
+      18 => Some(Self::Input),
+      _ => None,
+    }
+  }
+}
+
+/// Errors that may occur when working with `VirtIO` devices.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+  /// The device did not accept the features the driver selected.
+  FeaturesNotAccepted,
+  /// The requested queue is not available on the device.
+  QueueUnavailable,
+  /// The requested queue is smaller than required by the driver.
+  QueueTooSmall,
+  /// The device responded with an unexpected response type.
+  UnexpectedResponse(u32),
+}
+
+impl core::fmt::Display for Error {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::FeaturesNotAccepted => write!(f, "device did not accept the selected features"),
+      Self::QueueUnavailable => write!(f, "queue is not available"),
+      Self::QueueTooSmall => write!(f, "queue is too small"),
+      Self::UnexpectedResponse(response) => write!(f, "unexpected response {response:#X}"),
+    }
+  }
+}
+
+/// The MMIO transport of a single `VirtIO` device.
+#[derive(Debug)]
+pub struct MmioTransport {
+  /// The base address of the device's MMIO register block
+  base_address: usize,
+  /// The version of the interface (1 = legacy, 2 = modern)
+  version:      u32,
+}
+
+impl MmioTransport {
+  /// Creates a new transport for the device at `base_address` if there is a `VirtIO`
+  /// device present at this address. Returns the transport together with the type of the
+  /// device, or [`None`] if no (known) device is present.
+  ///
+  /// #### Safety
+  ///
+  /// `base_address` must point to a `VirtIO` MMIO register block.
+  #[must_use]
+  pub unsafe fn probe(base_address: usize) -> Option<(Self, DeviceType)> {
+    let transport = Self {
+      base_address,
+      version: 0,
+    };
+
+    if transport.read(register::MAGIC_VALUE) != MAGIC_VALUE {
+      return None;
+    }
+
+    // A device ID of zero denotes an empty slot.
+    let device_type = DeviceType::from_id(transport.read(register::DEVICE_ID))?;
+    let version = transport.read(register::VERSION);
+    if version != 1 && version != 2 {
+      return None;
+    }
+
+    Some((
+      Self {
+        base_address,
+        version,
+      },
+      device_type,
+    ))
+  }
+
+  /// Reads the register at `offset`.
+  fn read(&self, offset: usize) -> u32 {
+    unsafe { ((self.base_address + offset) as *const u32).read_volatile() }
+  }
+
+  /// Writes `value` to the register at `offset`.
+  fn write(&self, offset: usize, value: u32) {
+    unsafe { ((self.base_address + offset) as *mut u32).write_volatile(value) }
+  }
+
+  /// Returns whether the device uses the legacy interface.
+  const fn is_legacy(&self) -> bool { self.version == 1 }
+
+  /// Reads a 32bit value from the device-specific configuration space.
+  #[must_use]
+  pub fn read_config(&self, offset: usize) -> u32 { self.read(register::CONFIG + offset) }
+
+  /// Resets the device and negotiates features with the device, as described in section
+  /// 3.1.1 ("Driver Requirements: Device Initialization") of the specification. The
+  /// driver accepts the features in `driver_features` that the device also offers.
+  ///
+  /// After this function has returned, the queues can be set up with
+  /// [`Self::setup_queue`], and [`Self::finish_initialization`] must be called
+  /// afterwards.
+  ///
+  /// #### Errors
+  ///
+  /// If the device does not accept the selected features, an error is returned.
+  pub fn begin_initialization(&self, driver_features: u64) -> Result<u64, Error> {
+    self.write(register::STATUS, 0);
+    self.write(register::STATUS, status::ACKNOWLEDGE);
+    self.write(register::STATUS, status::ACKNOWLEDGE | status::DRIVER);
+
+    self.write(register::DEVICE_FEATURES_SELECT, 0);
+    let mut device_features = u64::from(self.read(register::DEVICE_FEATURES));
+    self.write(register::DEVICE_FEATURES_SELECT, 1);
+    device_features |= u64::from(self.read(register::DEVICE_FEATURES)) << 32;
+
+    let mut negotiated_features = device_features & driver_features;
+    if !self.is_legacy() {
+      negotiated_features |= device_features & FEATURE_VERSION_1;
+    }
+
+    self.write(register::DRIVER_FEATURES_SELECT, 0);
+    self.write(register::DRIVER_FEATURES, negotiated_features as u32);
+    self.write(register::DRIVER_FEATURES_SELECT, 1);
+    self.write(register::DRIVER_FEATURES, (negotiated_features >> 32) as u32);
+
+    if self.is_legacy() {
+      self.write(register::GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+    } else {
+      let status = status::ACKNOWLEDGE | status::DRIVER | status::FEATURES_OK;
+      self.write(register::STATUS, status);
+      if self.read(register::STATUS) & status::FEATURES_OK == 0 {
+        self.write(register::STATUS, status | status::FAILED);
+        return Err(Error::FeaturesNotAccepted);
+      }
+    }
+
+    Ok(negotiated_features)
+  }
+
+  /// Makes the queue `queue` with index `index` known to the device.
+  ///
+  /// #### Errors
+  ///
+  /// If the device does not provide a queue with this index or the queue is too small, an
+  /// error is returned.
+  pub fn setup_queue<const SIZE: usize>(&self, index: u32, queue: &VirtQueue<SIZE>) -> Result<(), Error> {
+    self.write(register::QUEUE_SELECT, index);
+
+    let maximum_size = self.read(register::QUEUE_NUMBER_MAX) as usize;
+    if maximum_size == 0 {
+      return Err(Error::QueueUnavailable);
+    }
+    if maximum_size < SIZE {
+      return Err(Error::QueueTooSmall);
+    }
+
+    self.write(register::QUEUE_NUMBER, SIZE as u32);
+
+    if self.is_legacy() {
+      self.write(register::QUEUE_ALIGN, PAGE_SIZE as u32);
+      self.write(register::QUEUE_PFN, (queue.descriptor_area() / PAGE_SIZE) as u32);
+    } else {
+      /// Writes a 64bit address into a pair of registers.
+      fn write_address(transport: &MmioTransport, low: usize, high: usize, address: usize) {
+        transport.write(low, address as u32);
+        transport.write(high, (address >> 32) as u32);
+      }
+
+      write_address(
+        self,
+        register::QUEUE_DESCRIPTOR_LOW,
+        register::QUEUE_DESCRIPTOR_HIGH,
+        queue.descriptor_area(),
+      );
+      write_address(
+        self,
+        register::QUEUE_DRIVER_LOW,
+        register::QUEUE_DRIVER_HIGH,
+        queue.driver_area(),
+      );
+      write_address(
+        self,
+        register::QUEUE_DEVICE_LOW,
+        register::QUEUE_DEVICE_HIGH,
+        queue.device_area(),
+      );
+      self.write(register::QUEUE_READY, 1);
+    }
+
+    Ok(())
+  }
+
+  /// Tells the device that the driver is ready to drive it.
+  pub fn finish_initialization(&self) {
+    let status = self.read(register::STATUS);
+    self.write(register::STATUS, status | status::DRIVER_OK);
+  }
+
+  /// Notifies the device that new buffers are available in the queue with index `index`.
+  pub fn notify(&self, index: u32) { self.write(register::QUEUE_NOTIFY, index); }
+
+  /// Acknowledges all pending interrupts of this device and returns the interrupt status.
+  // No driver takes interrupts yet
+  #[allow(dead_code)]
+  #[must_use]
+  pub fn acknowledge_interrupts(&self) -> u32 {
+    let interrupt_status = self.read(register::INTERRUPT_STATUS);
+    self.write(register::INTERRUPT_ACK, interrupt_status);
+    interrupt_status
+  }
+}
+
+impl From<Error> for crate::library::drivers::Error {
+  fn from(error: Error) -> Self {
+    match error {
+      Error::FeaturesNotAccepted => Self::Failed("device did not accept the selected features"),
+      Error::QueueUnavailable => Self::Failed("queue is not available"),
+      Error::QueueTooSmall => Self::Failed("queue is too small"),
+      Error::UnexpectedResponse(_) => Self::Failed("unexpected response"),
+    }
+  }
+}
+
+/// The driver for `VirtIO` MMIO slots. It detects which device is located in a slot and
+/// hands the device to the driver for the device type.
+#[derive(Debug)]
+pub struct Driver;
+
+impl Driver {
+  /// Returns the transport and the device type of the device located in the slot
+  /// described by `node`.
+  fn transport(
+    node: &crate::library::drivers::Node,
+  ) -> Result<(MmioTransport, DeviceType), crate::library::drivers::Error> {
+    use crate::library::drivers::Error;
+
+    let base_address = node.base_address().ok_or(Error::MissingProperty("reg"))?;
+    unsafe { MmioTransport::probe(base_address) }.ok_or(Error::NoDevice)
+  }
+}
+
+impl crate::library::drivers::Driver for Driver {
+  fn name(&self) -> &'static str { "virtio-mmio" }
+
+  fn compatible(&self) -> &'static [&'static str] { &["virtio,mmio"] }
+
+  fn probe(&self, node: &crate::library::drivers::Node) -> Result<(), crate::library::drivers::Error> {
+    match Self::transport(node)? {
+      (_, DeviceType::Block | DeviceType::Gpu) => Ok(()),
+      // Slots with devices for which no driver exists are treated like empty slots.
+      _ => Err(crate::library::drivers::Error::NoDevice),
+    }
+  }
+
+  fn init(&self, node: &crate::library::drivers::Node) -> Result<(), crate::library::drivers::Error> {
+    match Self::transport(node)? {
+      (transport, DeviceType::Block) => block::initialize(transport),
+      (transport, DeviceType::Gpu) => gpu::initialize(transport),
+      _ => Err(crate::library::drivers::Error::NoDevice),
+    }
+  }
+}
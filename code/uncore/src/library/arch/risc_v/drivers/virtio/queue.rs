@@ -0,0 +1,228 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module implements split virtual queues, see section 2.7 ("Split Virtqueues") of
+//! the `VirtIO` specification.
+//!
+//! As the kernel does not use virtual memory yet, the addresses of the buffers that are
+//! handed to the device are the physical addresses of the buffers.
+
+// Buffer lengths and descriptor indices are bounded by the queue size and the size of the
+// buffers the drivers use, which fit into the types the specification prescribes.
+#![allow(clippy::cast_possible_truncation)]
+
+use core::sync::atomic::{
+  fence,
+  Ordering,
+};
+
+/// This marks a buffer as continuing via the `next` field.
+const DESCRIPTOR_FLAG_NEXT: u16 = 1;
+/// This marks a buffer as device write-only (otherwise device read-only).
+const DESCRIPTOR_FLAG_WRITE: u16 = 2;
+
+/// A single descriptor in the descriptor table.
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+struct Descriptor {
+  /// Physical address of the buffer
+  address: u64,
+  /// Length of the buffer
+  length:  u32,
+  /// Flags, see `DESCRIPTOR_FLAG_*`
+  flags:   u16,
+  /// Index of the next descriptor if `flags` contains [`DESCRIPTOR_FLAG_NEXT`]
+  next:    u16,
+}
+
+/// The available ring, which is written by the driver and read by the device.
+#[derive(Debug)]
+#[repr(C, align(2))]
+struct AvailableRing<const SIZE: usize> {
+  /// Flags (unused)
+  flags:       u16,
+  /// Where the driver would put the next descriptor entry in the ring (modulo the queue
+  /// size)
+  index:       u16,
+  /// The ring of descriptor chain heads
+  ring:        [u16; SIZE],
+  /// Only used if `VIRTIO_F_EVENT_IDX` is negotiated
+  _used_event: u16,
+}
+
+/// A single element of the used ring.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct UsedElement {
+  /// Index of the start of the used descriptor chain
+  id:     u32,
+  /// The number of bytes written into the device-writable portion of the buffer
+  length: u32,
+}
+
+/// The used ring, which is written by the device and read by the driver. For the legacy
+/// interface, this ring must be aligned to a page boundary.
+#[derive(Debug)]
+#[repr(C, align(4096))]
+struct UsedRing<const SIZE: usize> {
+  /// Flags (unused)
+  flags:        u16,
+  /// Where the device would put the next descriptor entry in the ring (modulo the queue
+  /// size)
+  index:        u16,
+  /// The ring of used descriptor chains
+  ring:         [UsedElement; SIZE],
+  /// Only used if `VIRTIO_F_EVENT_IDX` is negotiated
+  _avail_event: u16,
+}
+
+/// The memory a virtual queue occupies. The layout is chosen so that it satisfies the
+/// requirements of the legacy interface (descriptor table and available ring contiguously
+/// in memory, the used ring on the next page boundary), which also satisfies the
+/// requirements of the modern interface.
+#[derive(Debug)]
+#[repr(C, align(4096))]
+pub struct QueueMemory<const SIZE: usize> {
+  /// The descriptor table
+  descriptors: [Descriptor; SIZE],
+  /// The available ring
+  available:   AvailableRing<SIZE>,
+  /// The used ring
+  used:        UsedRing<SIZE>,
+}
+
+impl<const SIZE: usize> QueueMemory<SIZE> {
+  /// Creates zeroed queue memory, suitable for placing it in a `static`.
+  #[must_use]
+  pub const fn new() -> Self {
+    Self {
+      descriptors: [Descriptor {
+        address: 0,
+        length:  0,
+        flags:   0,
+        next:    0,
+      }; SIZE],
+      available:   AvailableRing {
+        flags:       0,
+        index:       0,
+        ring:        [0; SIZE],
+        _used_event: 0,
+      },
+      used:        UsedRing {
+        flags:        0,
+        index:        0,
+        ring:         [UsedElement { id: 0, length: 0 }; SIZE],
+        _avail_event: 0,
+      },
+    }
+  }
+}
+
+impl<const SIZE: usize> Default for QueueMemory<SIZE> {
+  fn default() -> Self { Self::new() }
+}
+
+/// A split virtual queue with `SIZE` entries.
+///
+/// The queue is used synchronously: a request is submitted with [`Self::submit`], and the
+/// driver polls the used ring until the device has processed the request.
+#[derive(Debug)]
+pub struct VirtQueue<const SIZE: usize> {
+  /// The memory backing this queue
+  memory:    &'static mut QueueMemory<SIZE>,
+  /// The index of the used ring that we have seen last
+  last_used: u16,
+}
+
+impl<const SIZE: usize> VirtQueue<SIZE> {
+  /// Creates a new virtual queue that uses `memory`.
+  ///
+  /// #### Panics
+  ///
+  /// If `SIZE` is not a power of two, this function panics.
+  pub fn new(memory: &'static mut QueueMemory<SIZE>) -> Self {
+    assert!(
+      SIZE.is_power_of_two(),
+      "virtual queue size must be a power of two"
+    );
+    *memory = QueueMemory::new();
+    Self { memory, last_used: 0 }
+  }
+
+  /// Returns the physical address of the descriptor table.
+  #[must_use]
+  pub fn descriptor_area(&self) -> usize { core::ptr::addr_of!(self.memory.descriptors) as usize }
+
+  /// Returns the physical address of the available ring.
+  #[must_use]
+  pub fn driver_area(&self) -> usize { core::ptr::addr_of!(self.memory.available) as usize }
+
+  /// Returns the physical address of the used ring.
+  #[must_use]
+  pub fn device_area(&self) -> usize { core::ptr::addr_of!(self.memory.used) as usize }
+
+  /// Places a descriptor chain consisting of the device-readable buffers in `inputs`,
+  /// followed by the device-writable buffers in `outputs`, in the queue, notifies the
+  /// device via `notify` and waits until the device has processed the chain. Returns the
+  /// number of bytes the device has written.
+  ///
+  /// #### Panics
+  ///
+  /// If the number of buffers exceeds the size of the queue, this function panics.
+  pub fn submit(&mut self, inputs: &[&[u8]], outputs: &mut [&mut [u8]], notify: impl FnOnce()) -> u32 {
+    let total = inputs.len() + outputs.len();
+    assert!(
+      total > 0 && total <= SIZE,
+      "descriptor chain does not fit into virtual queue"
+    );
+
+    let buffers = inputs
+      .iter()
+      .map(|buffer| (buffer.as_ptr() as u64, buffer.len(), 0))
+      .chain(
+        outputs
+          .iter_mut()
+          .map(|buffer| (buffer.as_mut_ptr() as u64, buffer.len(), DESCRIPTOR_FLAG_WRITE)),
+      );
+
+    for (index, (address, length, flags)) in buffers.enumerate() {
+      let next = if index + 1 < total {
+        DESCRIPTOR_FLAG_NEXT
+      } else {
+        0
+      };
+      let descriptor = core::ptr::addr_of_mut!(self.memory.descriptors[index]);
+      unsafe {
+        core::ptr::addr_of_mut!((*descriptor).address).write_volatile(address);
+        core::ptr::addr_of_mut!((*descriptor).length).write_volatile(length as u32);
+        core::ptr::addr_of_mut!((*descriptor).flags).write_volatile(flags | next);
+        core::ptr::addr_of_mut!((*descriptor).next).write_volatile((index + 1) as u16);
+      }
+    }
+
+    let available_index = unsafe { core::ptr::addr_of!(self.memory.available.index).read_volatile() };
+    let slot = core::ptr::addr_of_mut!(self.memory.available.ring[usize::from(available_index) % SIZE]);
+    unsafe { slot.write_volatile(0) };
+    fence(Ordering::SeqCst);
+    unsafe {
+      core::ptr::addr_of_mut!(self.memory.available.index).write_volatile(available_index.wrapping_add(1));
+    }
+    fence(Ordering::SeqCst);
+
+    notify();
+
+    loop {
+      fence(Ordering::SeqCst);
+      let used_index = unsafe { core::ptr::addr_of!(self.memory.used.index).read_volatile() };
+      if used_index != self.last_used {
+        break;
+      }
+      core::hint::spin_loop();
+    }
+
+    let length = unsafe {
+      core::ptr::addr_of!(self.memory.used.ring[usize::from(self.last_used) % SIZE].length).read_volatile()
+    };
+    self.last_used = self.last_used.wrapping_add(1);
+    length
+  }
+}
@@ -1,14 +1,453 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 //! Contains all interrupt handlers. These handlers are set up by [`riscv-rt`].
+//!
+//! The kernel provides its own trap entry (`_start_trap`), which `riscv-rt` installs in
+//! `stvec`. In contrast to the default entry of `riscv-rt`, it saves all registers and
+//! hands a mutable trap frame to [`handle_trap`], so that system calls can return values
+//! to the caller and resume after the `ecall` instruction. Only `ecall` in user mode is a
+//! system call; in supervisor mode, it calls the SBI firmware.
+//!
+//! Interrupts and system calls are counted per hart, see [`crate::arch::trap_counts`].
 
-/// This function is used by [`riscv-rt`] to provide an exception handler.
+use core::sync::atomic::{
+  AtomicU64,
+  AtomicUsize,
+  Ordering,
+};
+
+use crate::{
+  arch::{
+    TrapCause,
+    TrapContext,
+  },
+  library::signal::{
+    self,
+    Information,
+    Signal,
+  },
+};
+
+/// The size of [`TrapFrame`] in bytes, which must keep the stack 16-byte aligned.
+const TRAP_FRAME_SIZE: usize = core::mem::size_of::<TrapFrame>();
+const _: () = assert!(TRAP_FRAME_SIZE.trailing_zeros() >= 4);
+
+/// The state of the interrupted code, which `_start_trap` saves on the stack.
+#[derive(Debug)]
+#[repr(C)]
+pub struct TrapFrame {
+  /// The general-purpose registers `x0` to `x31` (`x0` is not saved, see `_start_trap`)
+  pub registers: [usize; 32],
+  /// The address of the instruction that was interrupted or caused the exception
+  pub sepc:      usize,
+  /// The status register at the time of the trap
+  pub sstatus:   usize,
+}
+
+/// Index of register `sp`, the stack pointer.
+const SP: usize = 2;
+/// Index of register `a0`, which holds the first argument and the return value.
+const A0: usize = 10;
+/// Index of register `a7`, which holds the system call number.
+const A7: usize = 17;
+
+impl TrapContext for TrapFrame {
+  fn is_from_user(&self) -> bool { self.sstatus & SSTATUS_SUPERVISOR_PREVIOUS == 0 }
+
+  fn program_counter(&self) -> usize { self.sepc }
+
+  fn set_program_counter(&mut self, address: usize) { self.sepc = address; }
+
+  fn stack_pointer(&self) -> usize { self.registers[SP] }
+
+  fn system_call(&self) -> (usize, [usize; 6]) {
+    let mut arguments = [0; 6];
+    arguments.copy_from_slice(&self.registers[A0..A0 + 6]);
+    (self.registers[A7], arguments)
+  }
+
+  fn set_system_call_result(&mut self, result: isize, arguments: &[usize; 6]) {
+    self.registers[A0 + 1..A0 + 6].copy_from_slice(&arguments[1..]);
+    // Negative results (error numbers) are handed back in two's complement
+    #[allow(clippy::cast_sign_loss)]
+    {
+      self.registers[A0] = result as usize;
+    }
+  }
+}
+
+/// Interrupt code of a supervisor software interrupt.
+const SUPERVISOR_SOFTWARE_INTERRUPT: usize = 1;
+/// Interrupt code of a supervisor timer interrupt.
+const SUPERVISOR_TIMER_INTERRUPT: usize = 5;
+/// Interrupt code of a supervisor external interrupt, which the PLIC raises.
+const SUPERVISOR_EXTERNAL_INTERRUPT: usize = 9;
+
+/// The bit of `sstatus` that is set if the trap came from supervisor mode (`SPP`).
+pub(super) const SSTATUS_SUPERVISOR_PREVIOUS: usize = 1 << 8;
+
+/// Exception code of an illegal instruction.
+const ILLEGAL_INSTRUCTION: usize = 2;
+/// Exception code of a page fault on a load.
+const LOAD_PAGE_FAULT: usize = 13;
+/// Exception code of a page fault on a store.
+const STORE_PAGE_FAULT: usize = 15;
+/// Exception code of an environment call from user mode. Environment calls from
+/// supervisor mode go to the SBI firmware and never trap to the kernel.
+const USER_ENVIRONMENT_CALL: usize = 8;
+
+/// The maximum number of harts whose traps are counted. Traps on harts with higher IDs
+/// are not counted.
+pub const MAXIMUM_HARTS: usize = 8;
+
+/// The number of PLIC interrupt sources whose interrupts are counted. QEMU's `virt`
+/// machine has 95 sources.
+const COUNTED_SOURCES: usize = 128;
+
+/// The number of causes in [`TrapCause`] that are not interrupt sources of the PLIC.
+const LOCAL_CAUSES: usize = 3;
+
+/// Returns the index of the counters of `cause` in [`COUNTERS`], or [`None`] if it is not
+/// counted.
+fn index(cause: TrapCause) -> Option<usize> {
+  match cause {
+    TrapCause::Software => Some(0),
+    TrapCause::Timer => Some(1),
+    TrapCause::SystemCall => Some(2),
+    TrapCause::External(source) => usize::try_from(source)
+      .ok()
+      .filter(|&source| source < COUNTED_SOURCES)
+      .map(|source| LOCAL_CAUSES + source),
+  }
+}
+
+/// Returns the cause whose counters are at `index` in [`COUNTERS`].
+fn cause(index: usize) -> TrapCause {
+  match index {
+    0 => TrapCause::Software,
+    1 => TrapCause::Timer,
+    2 => TrapCause::SystemCall,
+    _ => TrapCause::External(u32::try_from(index - LOCAL_CAUSES).unwrap_or(u32::MAX)),
+  }
+}
+
+/// The number of traps per cause (see [`index`]) and hart.
+static COUNTERS: [[AtomicU64; MAXIMUM_HARTS]; LOCAL_CAUSES + COUNTED_SOURCES] =
+  [const { [const { AtomicU64::new(0) }; MAXIMUM_HARTS] }; LOCAL_CAUSES + COUNTED_SOURCES];
+
+/// The number of traps that interrupted the kernel (and not user mode) each hart is
+/// handling.
+static KERNEL_TRAP_DEPTH: [AtomicUsize; MAXIMUM_HARTS] = [const { AtomicUsize::new(0) }; MAXIMUM_HARTS];
+
+/// The bit of `sstatus` that enables interrupts in supervisor mode (`SIE`).
+const SSTATUS_INTERRUPTS_ENABLED: usize = 1 << 1;
+
+impl crate::arch::Interrupts for super::RiscV {
+  type TrapFrame = TrapFrame;
+
+  fn without_interrupts<T>(operation: impl FnOnce() -> T) -> T {
+    let sstatus: usize;
+    unsafe {
+      core::arch::asm!(
+        "csrrc {}, sstatus, {}",
+        out(reg) sstatus,
+        in(reg) SSTATUS_INTERRUPTS_ENABLED,
+        options(nostack)
+      );
+    }
+    let result = operation();
+    if sstatus & SSTATUS_INTERRUPTS_ENABLED != 0 {
+      unsafe {
+        core::arch::asm!(
+          "csrs sstatus, {}",
+          in(reg) SSTATUS_INTERRUPTS_ENABLED,
+          options(nostack)
+        );
+      }
+    }
+    result
+  }
+
+  /// Harts with IDs beyond [`MAXIMUM_HARTS`] are never considered to handle such a trap.
+  fn in_kernel_trap() -> bool {
+    KERNEL_TRAP_DEPTH
+      .get(crate::arch::hart())
+      .is_some_and(|depth| depth.load(Ordering::Relaxed) != 0)
+  }
+
+  /// Interrupts of PLIC sources beyond the counted ones are not listed.
+  fn trap_causes() -> impl Iterator<Item = TrapCause> {
+    COUNTERS
+      .iter()
+      .enumerate()
+      .filter(|(index, counters)| {
+        *index < LOCAL_CAUSES || counters.iter().any(|count| count.load(Ordering::Relaxed) != 0)
+      })
+      .map(|(index, _)| cause(index))
+  }
+
+  fn trap_count(cause: TrapCause, hart: usize) -> u64 {
+    index(cause)
+      .and_then(|index| COUNTERS[index].get(hart))
+      .map_or(0, |count| count.load(Ordering::Relaxed))
+  }
+}
+
+/// Counts a trap with `cause` on the current hart.
+fn count(cause: TrapCause) {
+  if let Some(counter) = index(cause).and_then(|index| COUNTERS[index].get(crate::arch::hart())) {
+    counter.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+// Traps from user mode must not run on the stack of the program, which the kernel cannot
+// trust. While a hart runs in user mode, `sscratch` holds the top of its kernel stack;
+// while it runs in supervisor mode, `sscratch` is zero. The entry swaps `sp` and
+// `sscratch` to find out where the trap came from and switches to the kernel stack for
+// traps from user mode. The program may also have changed `tp`, which holds the hart ID
+// in the kernel; it is kept in the slot of `x0` of the trap frame, which the next trap
+// from user mode puts at the same address. User mode is entered for the first time
+// through the exit below (see `user.rs`), which sets up `sscratch` and this slot.
+core::arch::global_asm!(
+  ".section .trap, \"ax\"",
+  ".global _start_trap",
+  ".align 4",
+  "_start_trap:",
+  "csrrw sp, sscratch, sp",
+  "bnez sp, 1f",
+  // The trap came from supervisor mode, whose stack is used further
+  "csrrw sp, sscratch, sp",
+  "1:",
+  "addi sp, sp, -{size}",
+  "sd x1, 1 * 8(sp)",
+  "sd x3, 3 * 8(sp)",
+  "sd x4, 4 * 8(sp)",
+  "sd x5, 5 * 8(sp)",
+  "sd x6, 6 * 8(sp)",
+  "sd x7, 7 * 8(sp)",
+  "sd x8, 8 * 8(sp)",
+  "sd x9, 9 * 8(sp)",
+  "sd x10, 10 * 8(sp)",
+  "sd x11, 11 * 8(sp)",
+  "sd x12, 12 * 8(sp)",
+  "sd x13, 13 * 8(sp)",
+  "sd x14, 14 * 8(sp)",
+  "sd x15, 15 * 8(sp)",
+  "sd x16, 16 * 8(sp)",
+  "sd x17, 17 * 8(sp)",
+  "sd x18, 18 * 8(sp)",
+  "sd x19, 19 * 8(sp)",
+  "sd x20, 20 * 8(sp)",
+  "sd x21, 21 * 8(sp)",
+  "sd x22, 22 * 8(sp)",
+  "sd x23, 23 * 8(sp)",
+  "sd x24, 24 * 8(sp)",
+  "sd x25, 25 * 8(sp)",
+  "sd x26, 26 * 8(sp)",
+  "sd x27, 27 * 8(sp)",
+  "sd x28, 28 * 8(sp)",
+  "sd x29, 29 * 8(sp)",
+  "sd x30, 30 * 8(sp)",
+  "sd x31, 31 * 8(sp)",
+  // The stack pointer of the interrupted code, which `sscratch` holds if it ran in user
+  // mode. Traps that occur from now on come from supervisor mode.
+  "csrrw t0, sscratch, zero",
+  "bnez t0, 2f",
+  "addi t0, sp, {size}",
+  "j 3f",
+  "2:",
+  "ld tp, 0(sp)",
+  "sd zero, 0(sp)",
+  "3:",
+  "sd t0, 2 * 8(sp)",
+  "csrr t0, sepc",
+  "sd t0, 32 * 8(sp)",
+  "csrr t0, sstatus",
+  "sd t0, 33 * 8(sp)",
+  "mv a0, sp",
+  "call {handler}",
+  // The exit, which returns from the trap whose frame `sp` points to; see `user.rs`
+  ".global __uncore_return_from_trap",
+  "__uncore_return_from_trap:",
+  "ld t0, 32 * 8(sp)",
+  "csrw sepc, t0",
+  "ld t0, 33 * 8(sp)",
+  "csrw sstatus, t0",
+  // When returning to user mode, the next trap has to find the kernel stack and the
+  // hart ID
+  "andi t0, t0, {supervisor_previous}",
+  "bnez t0, 4f",
+  "addi t0, sp, {size}",
+  "csrw sscratch, t0",
+  "sd tp, 0(sp)",
+  "4:",
+  "ld x1, 1 * 8(sp)",
+  "ld x3, 3 * 8(sp)",
+  "ld x4, 4 * 8(sp)",
+  "ld x5, 5 * 8(sp)",
+  "ld x6, 6 * 8(sp)",
+  "ld x7, 7 * 8(sp)",
+  "ld x8, 8 * 8(sp)",
+  "ld x9, 9 * 8(sp)",
+  "ld x10, 10 * 8(sp)",
+  "ld x11, 11 * 8(sp)",
+  "ld x12, 12 * 8(sp)",
+  "ld x13, 13 * 8(sp)",
+  "ld x14, 14 * 8(sp)",
+  "ld x15, 15 * 8(sp)",
+  "ld x16, 16 * 8(sp)",
+  "ld x17, 17 * 8(sp)",
+  "ld x18, 18 * 8(sp)",
+  "ld x19, 19 * 8(sp)",
+  "ld x20, 20 * 8(sp)",
+  "ld x21, 21 * 8(sp)",
+  "ld x22, 22 * 8(sp)",
+  "ld x23, 23 * 8(sp)",
+  "ld x24, 24 * 8(sp)",
+  "ld x25, 25 * 8(sp)",
+  "ld x26, 26 * 8(sp)",
+  "ld x27, 27 * 8(sp)",
+  "ld x28, 28 * 8(sp)",
+  "ld x29, 29 * 8(sp)",
+  "ld x30, 30 * 8(sp)",
+  "ld x31, 31 * 8(sp)",
+  // Signal delivery may have changed the stack pointer
+  "ld sp, 2 * 8(sp)",
+  "sret",
+  size = const TRAP_FRAME_SIZE,
+  supervisor_previous = const SSTATUS_SUPERVISOR_PREVIOUS,
+  handler = sym handle_trap,
+);
+
+/// Handles all traps. It is called by `_start_trap` with the saved state of the
+/// interrupted code; changes to `trap_frame` take effect when the trap returns.
+///
+/// Faults in user mode raise a signal instead of stopping the kernel. Before the trap
+/// returns to user mode, pending signals are delivered.
+extern "C" fn handle_trap(trap_frame: &mut TrapFrame) {
+  let scause: usize;
+  let stval: usize;
+  unsafe {
+    core::arch::asm!("csrr {}, scause", out(reg) scause, options(nomem, nostack));
+    core::arch::asm!("csrr {}, stval", out(reg) stval, options(nomem, nostack));
+  }
+
+  let is_interrupt = scause >> (usize::BITS - 1) == 1;
+  let code = scause & !(1 << (usize::BITS - 1));
+  let from_user = trap_frame.is_from_user();
+  let depth = KERNEL_TRAP_DEPTH.get(crate::arch::hart()).filter(|_| !from_user);
+  if let Some(depth) = depth {
+    depth.fetch_add(1, Ordering::Relaxed);
+  }
+
+  if is_interrupt {
+    handle_interrupt(code);
+  } else {
+    match code {
+      USER_ENVIRONMENT_CALL => {
+        count(TrapCause::SystemCall);
+        handle_system_call(trap_frame);
+      },
+      ILLEGAL_INSTRUCTION if from_user => signal::current().force(Information {
+        signal:  Signal::SIGILL,
+        code:    signal::code::ILLEGAL_OPCODE,
+        address: trap_frame.program_counter(),
+      }),
+      LOAD_PAGE_FAULT | STORE_PAGE_FAULT if from_user => signal::current().force(Information {
+        signal:  Signal::SIGSEGV,
+        code:    signal::code::MAPPING_ERROR,
+        address: stval,
+      }),
+      _ => {
+        crate::library::crash::record_trap(&crate::library::crash::Registers {
+          general: trap_frame.registers,
+          pc:      trap_frame.sepc,
+          status:  trap_frame.sstatus,
+          cause:   scause,
+          value:   stval,
+        });
+        panic!(
+          "Unhandled exception {code} at {} (stval = {stval:#X})",
+          crate::library::backtrace::CodeAddress(trap_frame.sepc)
+        )
+      },
+    }
+  }
+
+  if let Some(depth) = depth {
+    depth.fetch_sub(1, Ordering::Relaxed);
+  }
+  if from_user {
+    super::signal::deliver(trap_frame);
+  }
+}
+
+/// Handles the system call in `trap_frame`. `rt_sigreturn` replaces all registers and
+/// `exit` and `exit_group` do not return, so they are handled here instead of by
+/// [`crate::library::syscall::dispatch`].
+fn handle_system_call(trap_frame: &mut TrapFrame) {
+  use crate::library::syscall::number;
+
+  let (number, mut arguments) = trap_frame.system_call();
+  match number {
+    number::RT_SIGRETURN => {
+      if !super::signal::restore(trap_frame) {
+        signal::current().force(Information::kernel(Signal::SIGSEGV));
+      }
+      return;
+    },
+    // Only the lowest byte of the status is reported, as in Linux
+    number::EXIT | number::EXIT_GROUP => {
+      super::user::exit(i32::try_from(arguments[0] & 0xFF).unwrap_or_default())
+    },
+    _ => (),
+  }
+
+  let result = crate::library::syscall::dispatch(number, &mut arguments);
+  trap_frame.set_system_call_result(result, &arguments);
+  // Resume after the `ecall` instruction
+  trap_frame.set_program_counter(trap_frame.program_counter() + 4);
+}
+
+/// Handles the interrupt with the interrupt code `code`. No driver handles interrupts
+/// yet, so they are only counted and acknowledged.
+fn handle_interrupt(code: usize) {
+  match code {
+    SUPERVISOR_EXTERNAL_INTERRUPT => {
+      while let Some(source) = super::drivers::plic::claim() {
+        count(TrapCause::External(source));
+        super::drivers::plic::complete(source);
+      }
+    },
+    SUPERVISOR_SOFTWARE_INTERRUPT => {
+      count(TrapCause::Software);
+      // Clear `sip.SSIP`, otherwise the interrupt stays pending
+      let pending = 1 << SUPERVISOR_SOFTWARE_INTERRUPT;
+      unsafe { core::arch::asm!("csrc sip, {}", in(reg) pending, options(nomem, nostack)) };
+    },
+    // The kernel does not program the timer, so there is no next interrupt. Moving the
+    // deadline out of reach acknowledges the interrupt; should the firmware not support
+    // that, the interrupt is disabled instead, so that it does not stay pending.
+    SUPERVISOR_TIMER_INTERRUPT => {
+      count(TrapCause::Timer);
+      if sbi::timer::set_timer(u64::MAX).is_err() {
+        let enabled = 1 << SUPERVISOR_TIMER_INTERRUPT;
+        unsafe { core::arch::asm!("csrc sie, {}", in(reg) enabled, options(nomem, nostack)) };
+      }
+    },
+    _ => panic!("Unhandled interrupt {code}"),
+  }
+}
+
+/// This function is used by [`riscv-rt`] to provide an exception handler. As the kernel
+/// uses its own trap entry, it is only referenced, but never called.
 #[export_name = "ExceptionHandler"]
 fn default_exception_handler(_trap_frame: &riscv_rt::TrapFrame) -> ! {
   todo!("Exception occurred but handler has not been written");
 }
 
-/// This function is used by [`riscv-rt`] to provide an interrupt handler.
+/// This function is used by [`riscv-rt`] to provide an interrupt handler. As the kernel
+/// uses its own trap entry, it is only referenced, but never called.
 #[export_name = "DefaultHandler"]
 fn default_interrupt_handler(_trap_frame: &riscv_rt::TrapFrame) -> ! {
   todo!("Exception occurred but handler has not been written");
@@ -0,0 +1,23 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Contains architecture-specific information about the memory of the kernel, like the
+//! starting address and the size of the kernel heap and the end and size of the stacks.
+
+extern "C" {
+  static mut __heap__start: u8;
+  static __heap__size: u8;
+  /// The end of the stacks of all harts, provided by the linker script.
+  static _stack_start: u8;
+  /// The size of the stack of every hart, provided by the linker script.
+  static _hart_stack_size: u8;
+}
+
+impl crate::arch::Memory for super::RiscV {
+  fn heap_start() -> *mut u8 { crate::transform_linker_symbol_to_value!(mut __heap__start) }
+
+  fn heap_size() -> usize { crate::transform_linker_symbol_to_value!(__heap__size, usize) }
+
+  fn stack_end() -> usize { crate::transform_linker_symbol_to_value!(_stack_start, usize) }
+
+  fn hart_stack_size() -> usize { crate::transform_linker_symbol_to_value!(_hart_stack_size, usize) }
+}
@@ -6,52 +6,240 @@
 //! The QEMU variant is based on this code:
 //! <https://github.com/qemu/qemu/blob/v8.1.2/hw/riscv/virt.c>.
 
-pub mod drivers;
-pub mod heap;
+#[cfg(test)]
+mod tests;
+
+mod clock;
+mod context;
+mod drivers;
+mod memory;
 mod interrupts_exceptions;
+mod paging;
+mod signal;
+mod user;
+
+use core::sync::atomic::{
+  AtomicUsize,
+  Ordering,
+};
+
+/// The harts that have called [`crate::arch::initialize`], as a bit set indexed by the
+/// hart ID.
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// The RISC-V 64bit architecture, which implements the interface of an architecture (see
+/// [`crate::arch::Architecture`]).
+#[derive(Debug)]
+pub struct RiscV;
+
+impl crate::arch::Architecture for RiscV {}
+
+impl crate::arch::Boot for RiscV {
+  fn initialize(hart: usize, device_tree_address: usize) {
+    // The thread pointer holds the hart ID while the kernel runs; `riscv-rt` does not use
+    // it, and the trap entry saves and restores it. `sscratch` is zero while the hart runs
+    // in supervisor mode (see `_start_trap`).
+    unsafe {
+      core::arch::asm!("mv tp, {}", in(reg) hart, options(nomem, nostack));
+      core::arch::asm!("csrw sscratch, zero", options(nomem, nostack));
+    }
+    if let Some(bit) = 1_usize.checked_shl(u32::try_from(hart).unwrap_or(u32::MAX)) {
+      ONLINE_HARTS.fetch_or(bit, Ordering::Relaxed);
+    }
+
+    // The log needs the hart ID in the thread pointer. Records of the drivers are kept in
+    // the ring buffer until the console comes up.
+    if hart == 0 {
+      crate::library::log::initialize();
+    }
+
+    drivers::initialize(hart, device_tree_address);
+  }
+}
+
+impl crate::arch::Harts for RiscV {
+  const MAXIMUM_HARTS: usize = interrupts_exceptions::MAXIMUM_HARTS;
+
+  fn hart() -> usize {
+    let hart: usize;
+    unsafe { core::arch::asm!("mv {}, tp", out(reg) hart, options(nomem, nostack)) };
+    hart
+  }
+
+  /// Harts with IDs beyond the number of bits of `usize` are not listed.
+  fn online_harts() -> impl Iterator<Item = usize> {
+    let online = ONLINE_HARTS.load(Ordering::Relaxed);
+    (0..usize::BITS as usize).filter(move |hart| online & (1 << hart) != 0)
+  }
+}
+
+impl crate::arch::Unwinding for RiscV {
+  #[inline(always)]
+  #[allow(clippy::inline_always)]
+  fn backtrace() -> impl Iterator<Item = usize> {
+    let frame_pointer: usize;
+    unsafe { core::arch::asm!("mv {}, s0", out(reg) frame_pointer, options(nomem, nostack)) };
+    Self::backtrace_from(frame_pointer)
+  }
+
+  /// The stack is unwound by following the frame pointers in `s0`: with frame pointers
+  /// enabled, `fp - 8` holds the return address of a function and `fp - 16` the frame
+  /// pointer of its caller. Unwinding stops at the first frame pointer that is
+  /// misaligned, does not point further up the stack, or is beyond the end of the
+  /// stacks.
+  fn backtrace_from(mut frame_pointer: usize) -> impl Iterator<Item = usize> {
+    let stack_end = crate::arch::stack_end();
+
+    core::iter::from_fn(move || {
+      if frame_pointer % 16 != 0 || frame_pointer < 16 || frame_pointer > stack_end {
+        return None;
+      }
+      let (return_address, previous) = unsafe {
+        let record = frame_pointer as *const usize;
+        (record.sub(1).read(), record.sub(2).read())
+      };
+      if previous <= frame_pointer || return_address == 0 {
+        frame_pointer = 0;
+      } else {
+        frame_pointer = previous;
+      }
+      (return_address != 0).then_some(return_address)
+    })
+  }
+
+  /// Returns the registers `x0` to `x31`. Caller-saved registers hold whatever the
+  /// function left in them.
+  #[inline(always)]
+  #[allow(clippy::inline_always)]
+  fn registers() -> [usize; 32] {
+    let mut registers = [0_usize; 32];
+    unsafe {
+      core::arch::asm!(
+        "sd x1, 1 * 8({0})",
+        "sd x2, 2 * 8({0})",
+        "sd x3, 3 * 8({0})",
+        "sd x4, 4 * 8({0})",
+        "sd x5, 5 * 8({0})",
+        "sd x6, 6 * 8({0})",
+        "sd x7, 7 * 8({0})",
+        "sd x8, 8 * 8({0})",
+        "sd x9, 9 * 8({0})",
+        "sd x10, 10 * 8({0})",
+        "sd x11, 11 * 8({0})",
+        "sd x12, 12 * 8({0})",
+        "sd x13, 13 * 8({0})",
+        "sd x14, 14 * 8({0})",
+        "sd x15, 15 * 8({0})",
+        "sd x16, 16 * 8({0})",
+        "sd x17, 17 * 8({0})",
+        "sd x18, 18 * 8({0})",
+        "sd x19, 19 * 8({0})",
+        "sd x20, 20 * 8({0})",
+        "sd x21, 21 * 8({0})",
+        "sd x22, 22 * 8({0})",
+        "sd x23, 23 * 8({0})",
+        "sd x24, 24 * 8({0})",
+        "sd x25, 25 * 8({0})",
+        "sd x26, 26 * 8({0})",
+        "sd x27, 27 * 8({0})",
+        "sd x28, 28 * 8({0})",
+        "sd x29, 29 * 8({0})",
+        "sd x30, 30 * 8({0})",
+        "sd x31, 31 * 8({0})",
+        in(reg) registers.as_mut_ptr(),
+        options(nostack)
+      );
+    }
+    registers
+  }
+}
+
+impl crate::arch::EarlyConsole for RiscV {
+  type Console = drivers::ns16550a::Uart;
+
+  fn console() -> Option<Self::Console> { drivers::ns16550a::instance(drivers::ns16550a::Role::Console) }
+}
+
+/// Takes the current hart offline for good, e.g. after a program that no kernel code
+/// waits for exited (see `user::exit`). Once there are processes, the hart runs another
+/// one instead.
+pub fn park_hart() -> ! {
+  if let Some(bit) = 1_usize.checked_shl(u32::try_from(crate::arch::hart()).unwrap_or(u32::MAX)) {
+    ONLINE_HARTS.fetch_and(!bit, Ordering::Relaxed);
+  }
+
+  loop {
+    unsafe { core::arch::asm!("wfi", options(nomem, nostack)) };
+  }
+}
+
+impl crate::arch::Firmware for RiscV {
+  /// Architecture-specific kernel exit.
+  ///
+  /// This function uses [`sbi`] to stop the machine. In case of an error, we need to use
+  /// the SiFive-Test device because the [`sbi`] crate does not exit QEMU in a way that an
+  /// error is produced.
+  fn exit_kernel(condition: crate::UncoreResult) -> ! {
+    use sbi::system_reset;
+
+    #[cfg(feature = "heap-debug")]
+    crate::library::mem::heap::Heap::report_leaks();
+
+    if condition == crate::UncoreResult::Ok {
+      log::info!("Terminating unCORE - execution successful");
+      log::logger().flush();
+      crate::library::drivers::shutdown();
+      let _ = system_reset::system_reset(
+        system_reset::ResetType::Shutdown,
+        system_reset::ResetReason::NoReason,
+      );
+    } else {
+      log::warn!("Terminating unCORE - execution unsuccessful");
+      log::logger().flush();
+      crate::library::drivers::shutdown();
+      unsafe {
+        core::arch::asm!(
+            "sw {0}, 0({1})",
+            in(reg)(1 << 16) | 0x3333, in(reg)0x10_0000
+        );
+      }
+    };
+
+    // Happens on when there is a bug in the SBI implementation or when SBI is not present
+    // We need to ensure we do not panic again.
+    log::error!("Shutdown failed - this is undefined behavior");
+
+    // For the case that the QEMU exit attempt did not work, transition into an infinite
+    // loop. Calling `panic!()` here is unfeasible, since there is a good chance
+    // this function here is the last expression in the `panic!()` handler
+    // itself. This prevents a possible infinite loop.
+    unsafe {
+      core::arch::asm!("wfi", options(nomem, nostack));
+      core::hint::unreachable_unchecked();
+    }
+  }
 
-/// Architecture-specific functionality before the kernel setup in [`crate::setup_kernel`]
-/// should run.
-///
-/// #### Attention
-///
-/// No logging is available here yet.
-pub fn initialize(hart: usize) { drivers::initialize(hart); }
-
-/// Architecture-specific kernel exit.
-///
-/// This function uses [`sbi`] to stop the machine. In case of an error, we need to use
-/// the SiFive-Test device because the [`sbi`] crate does not exit QEMU in a way that an
-/// error is produced.
-pub fn exit_kernel(condition: crate::UncoreResult) -> ! {
-  use sbi::system_reset;
-
-  if condition == crate::UncoreResult::Ok {
-    log::info!("Terminating unCORE - execution successful");
-    let _ = system_reset::system_reset(
-      system_reset::ResetType::Shutdown,
-      system_reset::ResetReason::NoReason,
-    );
-  } else {
-    log::warn!("Terminating unCORE - execution unsuccessful");
+  /// The debugger or emulator (e.g. QEMU with `-semihosting`) handles the call; without
+  /// one, the call raises a breakpoint exception.
+  #[cfg(feature = "semihosting")]
+  fn semihosting(operation: usize, parameter: usize) -> usize {
+    let result: usize;
+    // The specification requires exactly this sequence of uncompressed instructions,
+    // which must not cross a page boundary
     unsafe {
       core::arch::asm!(
-          "sw {0}, 0({1})",
-          in(reg)(1 << 16) | 0x3333, in(reg)0x10_0000
+        ".balign 16",
+        ".option push",
+        ".option norvc",
+        "slli x0, x0, 0x1f",
+        "ebreak",
+        "srai x0, x0, 7",
+        ".option pop",
+        inout("a0") operation => result,
+        in("a1") parameter,
+        options(nostack),
       );
     }
-  };
-
-  // Happens on when there is a bug in the SBI implementation or when SBI is not present
-  // We need to ensure we do not panic again.
-  log::error!("Shutdown failed - this is undefined behavior");
-
-  // For the case that the QEMU exit attempt did not work, transition into an infinite
-  // loop. Calling `panic!()` here is unfeasible, since there is a good chance
-  // this function here is the last expression in the `panic!()` handler
-  // itself. This prevents a possible infinite loop.
-  unsafe {
-    core::arch::asm!("wfi", options(nomem, nostack));
-    core::hint::unreachable_unchecked();
+    result
   }
 }
@@ -0,0 +1,246 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Contains the Sv39 page tables of RISC-V, which translate 39-bit virtual addresses in
+//! three levels of tables with 512 entries each.
+//!
+//! The kernel runs at the physical addresses of its memory. Every root table therefore
+//! maps the addresses below [`address_space::USER_START`] to themselves with 1 GiB pages
+//! that only supervisor mode may access; the pages of programs lie between
+//! [`address_space::USER_START`] and [`address_space::USER_END`], which are the entries
+//! 128 to 255 of the root table. The tables are pages of the kernel heap (see
+//! [`Page`]), whose addresses are physical addresses.
+
+use alloc::{
+  collections::BTreeMap,
+  sync::Arc,
+};
+
+use crate::library::mem::{
+  address_space::{
+    self,
+    Protection,
+  },
+  object::Page,
+  Error,
+  Result,
+  PAGE_SIZE,
+};
+
+/// The number of entries of a table.
+const ENTRIES: usize = PAGE_SIZE / 8;
+/// The number of levels of tables.
+const LEVELS: u32 = 3;
+/// The number of address bits a table entry at the lowest level covers.
+const PAGE_SHIFT: u32 = PAGE_SIZE.trailing_zeros();
+/// The number of address bits that select the entry of a table.
+const INDEX_BITS: u32 = ENTRIES.trailing_zeros();
+
+/// The entry is valid.
+const VALID: u64 = 1 << 0;
+/// The page may be read.
+const READ: u64 = 1 << 1;
+/// The page may be written.
+const WRITE: u64 = 1 << 2;
+/// Instructions may be fetched from the page.
+const EXECUTE: u64 = 1 << 3;
+/// The page may be accessed in user mode.
+const USER: u64 = 1 << 4;
+/// The mapping exists in all address spaces.
+const GLOBAL: u64 = 1 << 5;
+/// The page was accessed; set in advance, as harts may not set it themselves.
+const ACCESSED: u64 = 1 << 6;
+/// The page was written; set in advance, as harts may not set it themselves.
+const DIRTY: u64 = 1 << 7;
+/// The offset of the physical page number in an entry.
+const PPN_SHIFT: u32 = 10;
+
+/// The mode of `satp` that selects Sv39.
+const SATP_SV39: usize = 8 << 60;
+
+/// The page tables of an address space.
+pub struct PageTable {
+  /// The root table
+  root:   Arc<Page>,
+  /// The tables of the lower levels, indexed by their address
+  tables: BTreeMap<usize, Arc<Page>>,
+}
+
+impl PageTable {
+  /// Returns the entry at `index` of the table at `table`.
+  fn entry(&self, table: usize, index: usize) -> u64 {
+    let mut bytes = [0; 8];
+    if let Some(page) = self.table(table) {
+      // The entry is within the table
+      let _ = page.read(index * 8, &mut bytes);
+    }
+    u64::from_ne_bytes(bytes)
+  }
+
+  /// Sets the entry at `index` of the table at `table` to `entry`.
+  fn set_entry(&self, table: usize, index: usize, entry: u64) {
+    if let Some(page) = self.table(table) {
+      // The entry is within the table
+      let _ = page.write(index * 8, &entry.to_ne_bytes());
+    }
+  }
+
+  /// Returns the table at `address`.
+  fn table(&self, address: usize) -> Option<&Arc<Page>> {
+    if address == self.root.address() {
+      Some(&self.root)
+    } else {
+      self.tables.get(&address)
+    }
+  }
+
+  /// Returns the index of the entry for `address` in the table at `level`, where level 0
+  /// holds the entries of single pages.
+  const fn index(address: usize, level: u32) -> usize {
+    (address >> (PAGE_SHIFT + INDEX_BITS * level)) % ENTRIES
+  }
+
+  /// Returns the address the physical page number of `entry` refers to.
+  #[allow(clippy::cast_possible_truncation)]
+  const fn target(entry: u64) -> usize { ((entry >> PPN_SHIFT) as usize) << PAGE_SHIFT }
+
+  /// Returns the table and the index of the entry of the page at `address` in it, if its
+  /// tables exist.
+  fn leaf(&self, address: usize) -> Option<(usize, usize)> {
+    let mut table = self.root.address();
+    for level in (1..LEVELS).rev() {
+      let entry = self.entry(table, Self::index(address, level));
+      if entry & VALID == 0 || entry & (READ | WRITE | EXECUTE) != 0 {
+        return None;
+      }
+      table = Self::target(entry);
+    }
+    Some((table, Self::index(address, 0)))
+  }
+
+  /// Flushes the translations of `address` that the current hart cached.
+  fn flush(address: usize) {
+    unsafe { core::arch::asm!("sfence.vma {}, zero", in(reg) address, options(nostack)) };
+  }
+}
+
+impl crate::arch::Paging for super::RiscV {
+  type PageTable = PageTable;
+}
+
+impl crate::arch::PageTable for PageTable {
+  fn new() -> Result<Self> {
+    let page_table = Self {
+      root:   Page::new()?,
+      tables: BTreeMap::new(),
+    };
+    let root = page_table.root.address();
+    for index in 0..address_space::USER_START >> (PAGE_SHIFT + INDEX_BITS * 2) {
+      let address = index << (PAGE_SHIFT + INDEX_BITS * 2);
+      let entry = ((address >> PAGE_SHIFT) as u64) << PPN_SHIFT;
+      page_table.set_entry(
+        root,
+        index,
+        entry | VALID | READ | WRITE | EXECUTE | GLOBAL | ACCESSED | DIRTY,
+      );
+    }
+    Ok(page_table)
+  }
+
+  fn map(&mut self, address: usize, physical: usize, protection: Protection) -> Result<()> {
+    if address % PAGE_SIZE != 0
+      || physical % PAGE_SIZE != 0
+      || !address_space::is_user_range(address, PAGE_SIZE)
+    {
+      return Err(Error::InvalidArgument);
+    }
+
+    let mut flags = 0;
+    if protection.contains(Protection::READ) {
+      flags |= READ;
+    }
+    // Pages that may be written but not read are reserved
+    if protection.contains(Protection::WRITE) {
+      flags |= READ | WRITE | DIRTY;
+    }
+    if protection.contains(Protection::EXECUTE) {
+      flags |= EXECUTE;
+    }
+    if flags == 0 {
+      self.unmap(address);
+      return Ok(());
+    }
+
+    let mut table = self.root.address();
+    for level in (1..LEVELS).rev() {
+      let index = Self::index(address, level);
+      let entry = self.entry(table, index);
+      table = if entry & VALID == 0 {
+        let next = Page::new()?;
+        let next_address = next.address();
+        self.tables.insert(next_address, next);
+        self.set_entry(
+          table,
+          index,
+          ((next_address >> PAGE_SHIFT) as u64) << PPN_SHIFT | VALID,
+        );
+        next_address
+      } else {
+        Self::target(entry)
+      };
+    }
+
+    let entry = ((physical >> PAGE_SHIFT) as u64) << PPN_SHIFT | flags | VALID | USER | ACCESSED;
+    self.set_entry(table, Self::index(address, 0), entry);
+    Self::flush(address);
+    Ok(())
+  }
+
+  fn unmap(&mut self, address: usize) {
+    if let Some((table, index)) = self.leaf(address) {
+      self.set_entry(table, index, 0);
+      Self::flush(address);
+    }
+  }
+
+  fn translate(&self, address: usize) -> Option<(usize, Protection)> {
+    if !address_space::is_user_range(address, 1) {
+      return None;
+    }
+    let (table, index) = self.leaf(address)?;
+    let entry = self.entry(table, index);
+    if entry & VALID == 0 {
+      return None;
+    }
+
+    let mut bits = 0;
+    if entry & READ != 0 {
+      bits |= 0b001;
+    }
+    if entry & WRITE != 0 {
+      bits |= 0b010;
+    }
+    if entry & EXECUTE != 0 {
+      bits |= 0b100;
+    }
+    Some((
+      Self::target(entry) + address % PAGE_SIZE,
+      Protection::from_bits(bits)?,
+    ))
+  }
+
+  unsafe fn activate(&self) {
+    let satp = SATP_SV39 | self.root.address() >> PAGE_SHIFT;
+    core::arch::asm!("csrw satp, {}", "sfence.vma", in(reg) satp, options(nostack));
+  }
+
+  unsafe fn deactivate() { core::arch::asm!("csrw satp, zero", "sfence.vma", options(nostack)) }
+}
+
+impl core::fmt::Debug for PageTable {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("PageTable")
+      .field("root", &self.root.address())
+      .field("tables", &self.tables.len())
+      .finish()
+  }
+}
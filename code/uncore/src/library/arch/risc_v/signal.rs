@@ -0,0 +1,250 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Contains the RISC-V part of signal delivery: the frame a signal handler runs on and
+//! its removal by `rt_sigreturn`.
+//!
+//! The handler is called with the signal number in `a0` and pointers to the
+//! `siginfo_t` and the `ucontext_t` in `a1` and `a2`, whose layouts follow Linux. As
+//! there is no vDSO, the frame also contains the trampoline that calls `rt_sigreturn`
+//! when the handler returns.
+//!
+//! The frame lies on the stack of the program, which the kernel cannot trust. Like the
+//! buffers of system calls, it is therefore only accessed through the address space of
+//! the program (see [`crate::library::mem::address_space`]).
+
+use super::interrupts_exceptions::{
+  TrapFrame,
+  SSTATUS_SUPERVISOR_PREVIOUS,
+};
+use crate::{
+  arch::TrapContext,
+  library::{
+    mem::address_space,
+    signal::{
+      self,
+      Delivery,
+      Information,
+      Signal,
+      SignalSet,
+    },
+    syscall,
+  },
+};
+
+/// Index of register `ra`, which holds the return address.
+const RA: usize = 1;
+/// Index of register `sp`, the stack pointer.
+const SP: usize = 2;
+/// Index of register `a0`, which holds the first argument.
+const A0: usize = 10;
+
+/// The trampoline: `li a7, 139` and `ecall`, i.e. `rt_sigreturn`.
+const TRAMPOLINE: [u32; 2] = [0x08B0_0893, 0x0000_0073];
+const _: () = assert!(syscall::number::RT_SIGRETURN == 139);
+
+/// `siginfo_t`.
+#[repr(C)]
+struct SignalInformation {
+  /// `si_signo`
+  number:   i32,
+  /// `si_errno`
+  error:    i32,
+  /// `si_code`
+  code:     i32,
+  /// Aligns the union of signal-specific fields
+  padding:  i32,
+  /// `si_addr`, the first signal-specific field
+  address:  usize,
+  /// The other signal-specific fields, which are not used
+  reserved: [usize; 13],
+}
+
+/// `stack_t`, which describes an alternate signal stack. There are none, so it is
+/// always empty.
+#[repr(C)]
+struct Stack {
+  /// `ss_sp`
+  pointer: usize,
+  /// `ss_flags`
+  flags:   usize,
+  /// `ss_size`
+  size:    usize,
+}
+
+/// `struct sigcontext`, the registers of the interrupted code.
+#[repr(C, align(16))]
+struct MachineContext {
+  /// `sc_regs`: the program counter followed by `x1` to `x31`
+  registers:      [usize; 32],
+  /// `sc_fpregs`; the kernel does not save floating-point registers, so they are zero
+  floating_point: [u64; 66],
+}
+
+/// `ucontext_t`.
+#[repr(C)]
+struct UserContext {
+  /// `uc_flags`
+  flags:   usize,
+  /// `uc_link`
+  link:    usize,
+  /// `uc_stack`
+  stack:   Stack,
+  /// `uc_sigmask`, the signal mask that is restored by `rt_sigreturn`
+  mask:    u64,
+  /// Reserved for a larger `sigset_t`
+  unused:  [u8; 120],
+  /// Aligns `uc_mcontext`
+  padding: u64,
+  /// `uc_mcontext`
+  machine: MachineContext,
+}
+
+const _: () = assert!(core::mem::size_of::<SignalInformation>() == 128);
+const _: () = assert!(core::mem::offset_of!(UserContext, machine) == 176);
+
+/// The frame that is put onto the stack before a signal handler is called.
+#[repr(C)]
+struct Frame {
+  /// The information passed in `a1`
+  information: SignalInformation,
+  /// The context passed in `a2`
+  context:     UserContext,
+  /// The code the handler returns to
+  trampoline:  [u32; 2],
+  /// Keeps the frame free of implicit padding, so that all of its bytes can be copied
+  padding:     u64,
+}
+
+const _: () = assert!(core::mem::size_of::<Frame>() == 128 + 960 + 16);
+
+/// Puts a frame for the handler at `handler` onto the stack of the interrupted code and
+/// makes the trap return into the handler. Returns [`None`] if the stack pointer does not
+/// point to memory of the program that can be written.
+fn push_frame(
+  trap_frame: &mut TrapFrame,
+  information: &Information,
+  handler: usize,
+  previous_mask: SignalSet,
+) -> Option<()> {
+  let address = trap_frame
+    .stack_pointer()
+    .checked_sub(core::mem::size_of::<Frame>())?
+    & !0xF;
+  if !address_space::is_user_range(address, core::mem::size_of::<Frame>()) {
+    return None;
+  }
+
+  let mut registers = trap_frame.registers;
+  registers[0] = trap_frame.program_counter();
+  let frame = Frame {
+    information: SignalInformation {
+      number:   i32::from(u8::try_from(information.signal.number()).ok()?),
+      error:    0,
+      code:     information.code,
+      padding:  0,
+      address:  information.address,
+      reserved: [0; 13],
+    },
+    context:     UserContext {
+      flags:   0,
+      link:    0,
+      stack:   Stack {
+        pointer: 0,
+        flags:   0,
+        size:    0,
+      },
+      mask:    previous_mask.bits(),
+      unused:  [0; 120],
+      padding: 0,
+      machine: MachineContext {
+        registers,
+        floating_point: [0; 66],
+      },
+    },
+    trampoline:  TRAMPOLINE,
+    padding:     0,
+  };
+  let bytes = unsafe {
+    core::slice::from_raw_parts(
+      core::ptr::from_ref(&frame).cast::<u8>(),
+      core::mem::size_of::<Frame>(),
+    )
+  };
+  address_space::current().write(address, bytes).ok()?;
+  // The trampoline was written as data and is about to be executed
+  unsafe { core::arch::asm!("fence.i", options(nostack)) };
+
+  trap_frame.registers[A0] = information.signal.number();
+  trap_frame.registers[A0 + 1] = address + core::mem::offset_of!(Frame, information);
+  trap_frame.registers[A0 + 2] = address + core::mem::offset_of!(Frame, context);
+  trap_frame.registers[RA] = address + core::mem::offset_of!(Frame, trampoline);
+  trap_frame.registers[SP] = address;
+  trap_frame.set_program_counter(handler);
+  Some(())
+}
+
+/// Delivers the pending signals of the running program before the trap returns to user
+/// mode. If a handler is called, `trap_frame` is changed to return into it.
+pub(super) fn deliver(trap_frame: &mut TrapFrame) {
+  let state = signal::current();
+  while let Some(delivery) = state.next() {
+    match delivery {
+      Delivery::Handle {
+        information,
+        handler,
+        previous_mask,
+      } => {
+        if push_frame(trap_frame, &information, handler, previous_mask).is_some() {
+          return;
+        }
+        // Without its frame, the handler cannot run
+        state.set_mask(previous_mask);
+        state.force(Information::kernel(Signal::SIGSEGV));
+      },
+      Delivery::Stop(signal) => {
+        log::info!("Program stopped by {signal}");
+        state.wait_while_stopped();
+      },
+      Delivery::Terminate(signal) => {
+        log::error!("Program terminated by {signal}");
+        super::user::exit(128 + i32::try_from(signal.number()).unwrap_or_default());
+      },
+    }
+  }
+}
+
+/// Removes the frame of a signal handler, which called `rt_sigreturn` on return, and
+/// restores the interrupted registers and signal mask. Returns whether the frame was
+/// valid, i.e. whether `rt_sigreturn` came from user mode and the frame lies in memory
+/// of the program that can be read.
+///
+/// `sstatus` is not part of the frame: the trap returns with the one of the current trap,
+/// in which the kernel controls the interrupt bits and the previous privilege level is
+/// forced to user mode, so that the frame cannot raise it.
+pub(super) fn restore(trap_frame: &mut TrapFrame) -> bool {
+  let address = trap_frame.stack_pointer();
+  let length = core::mem::size_of::<UserContext>();
+  if !trap_frame.is_from_user()
+    || address & 0xF != 0
+    || !address_space::is_user_range(address, core::mem::size_of::<Frame>())
+  {
+    return false;
+  }
+
+  // All fields are integers, so the bytes of the program are a valid context
+  let mut context = core::mem::MaybeUninit::<UserContext>::zeroed();
+  let bytes = unsafe { core::slice::from_raw_parts_mut(context.as_mut_ptr().cast::<u8>(), length) };
+  if address_space::current()
+    .read(address + core::mem::offset_of!(Frame, context), bytes)
+    .is_err()
+  {
+    return false;
+  }
+  let context = unsafe { context.assume_init() };
+
+  trap_frame.set_program_counter(context.machine.registers[0]);
+  trap_frame.registers[1..].copy_from_slice(&context.machine.registers[1..]);
+  trap_frame.sstatus &= !SSTATUS_SUPERVISOR_PREVIOUS;
+  signal::current().set_mask(SignalSet::from_bits(context.mask));
+  true
+}
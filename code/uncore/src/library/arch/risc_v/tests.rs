@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unit tests that run programs in user mode. The programs consist of a few instructions,
+//! which are copied into memory of the current address space and run with page tables
+//! that map its pages.
+
+use alloc::vec::Vec;
+
+use crate::{
+  arch::{
+    PageTable,
+    PageTables,
+    TrapCause,
+  },
+  library::{
+    mem::{
+      address_space::{
+        self,
+        Mapping,
+        Placement,
+        Protection,
+      },
+      object::Anonymous,
+      PAGE_SIZE,
+    },
+    signal::{
+      self,
+      Action,
+      Handler,
+      Signal,
+    },
+  },
+};
+
+/// `li a7, 172` and `ecall`, i.e. `getpid`.
+const GETPID: [u32; 2] = [0x0AC0_0893, 0x0000_0073];
+/// `li a7, 93` and `ecall`, i.e. `exit` with the status in `a0`.
+const EXIT: [u32; 2] = [0x05D0_0893, 0x0000_0073];
+
+/// `li s0, 42`, an illegal instruction (which raises `SIGILL`) and `add a0, s0, s1`.
+const FAULT: [u32; 3] = [0x02A0_0413, 0x0000_0000, 0x0094_0533];
+/// A signal handler that skips the faulting instruction and places the signal number in
+/// the saved `s1`, before it clobbers `s0`: `ld t0, 176(a2)`, `addi t0, t0, 4`,
+/// `sd t0, 176(a2)`, `sd a0, 248(a2)`, `li s0, 0` and `ret`. As in Linux, the registers
+/// in `uc_mcontext` start with the program counter at offset 176 of the context.
+const SKIP: [u32; 6] = [
+  0x0B06_3283,
+  0x0042_8293,
+  0x0A56_3823,
+  0x0EA6_3C23,
+  0x0000_0413,
+  0x0000_8067,
+];
+
+/// A program with a page of code and a page of stack in the current address space. The
+/// stack is executable, as the trampoline of signal handlers is put onto it.
+struct Program {
+  /// The address of the code
+  code:   usize,
+  /// The address of the stack
+  stack:  usize,
+  /// The page tables that map the code and the stack
+  tables: PageTables,
+}
+
+impl Program {
+  /// Maps a program that consists of `instructions`.
+  fn new(instructions: &[u32]) -> Self {
+    let space = address_space::current();
+    let mapping = |protection| Mapping {
+      object: Anonymous::new(),
+      offset: 0,
+      protection,
+      maximum: protection,
+      shared: true,
+    };
+    let code = space
+      .map(
+        Placement::Anywhere,
+        PAGE_SIZE,
+        mapping(Protection::READ | Protection::WRITE | Protection::EXECUTE),
+      )
+      .unwrap();
+    let stack = space
+      .map(
+        Placement::Anywhere,
+        PAGE_SIZE,
+        mapping(Protection::READ | Protection::WRITE | Protection::EXECUTE),
+      )
+      .unwrap();
+    let bytes: Vec<u8> = instructions
+      .iter()
+      .flat_map(|instruction| instruction.to_le_bytes())
+      .collect();
+    space.write(code, &bytes).unwrap();
+
+    let mut tables = PageTables::new().unwrap();
+    let page = space.resolve(code, Protection::READ).unwrap();
+    tables
+      .map(code, page.address(), Protection::READ | Protection::EXECUTE)
+      .unwrap();
+    let page = space
+      .resolve(stack, Protection::READ | Protection::WRITE)
+      .unwrap();
+    tables
+      .map(
+        stack,
+        page.address(),
+        Protection::READ | Protection::WRITE | Protection::EXECUTE,
+      )
+      .unwrap();
+    Self { code, stack, tables }
+  }
+
+  /// Runs the program from its first instruction and returns its exit status.
+  fn run(&self) -> Option<i32> {
+    unsafe {
+      // The code was written as data
+      core::arch::asm!("fence.i", options(nostack));
+      self.tables.activate();
+      let status = crate::arch::run_program(self.code, self.stack + PAGE_SIZE);
+      PageTables::deactivate();
+      status
+    }
+  }
+}
+
+impl Drop for Program {
+  fn drop(&mut self) {
+    let _ = address_space::current().unmap(self.code, PAGE_SIZE);
+    let _ = address_space::current().unmap(self.stack, PAGE_SIZE);
+  }
+}
+
+#[test_case]
+fn system_calls_return_to_the_program_in_user_mode() {
+  let hart = crate::arch::hart();
+  let calls = || {
+    crate::arch::trap_counts()
+      .find(|&(cause, _)| cause == TrapCause::SystemCall)
+      .map_or(0, |(_, counts)| counts[hart])
+  };
+  let before = calls();
+
+  // The program exits with its process ID as the status
+  let program = Program::new(&[GETPID, EXIT].concat());
+  assert_eq!(program.run(), Some(1));
+  assert_eq!(calls(), before + 2);
+}
+
+#[test_case]
+fn signal_handlers_run_on_faults_and_sigreturn_restores_the_context() {
+  let instructions = [&FAULT[..], &EXIT, &SKIP].concat();
+  let program = Program::new(&instructions);
+  let handler = program.code + (FAULT.len() + EXIT.len()) * 4;
+  let mask = signal::current().mask();
+  let previous = signal::current().set_action(
+    Signal::SIGILL,
+    Action {
+      handler: Handler::Function(handler),
+      ..Action::DEFAULT
+    },
+  );
+
+  // The handler received `SIGILL` in `s1`, while `s0` was restored to 42 and the
+  // program continued after the faulting instruction
+  let status = program.run();
+  signal::current().set_action(Signal::SIGILL, previous.unwrap());
+  assert_eq!(status, Some(42 + 4));
+  assert_eq!(signal::current().mask(), mask);
+
+  // Without a handler, the fault terminates the program
+  assert_eq!(Program::new(&FAULT).run(), Some(128 + 4));
+}
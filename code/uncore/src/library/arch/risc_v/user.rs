@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Runs programs in user mode. There are no processes yet, so a program runs on behalf of
+//! the kernel code that started it with [`crate::arch::run_program`]: the hart enters
+//! user mode through the exit of the trap entry, and returns to the caller once the
+//! program calls `exit` or `exit_group` or is terminated by a signal.
+
+use core::sync::atomic::{
+  AtomicPtr,
+  Ordering,
+};
+
+use super::{
+  context::Context,
+  interrupts_exceptions::{
+    TrapFrame,
+    MAXIMUM_HARTS,
+    SSTATUS_SUPERVISOR_PREVIOUS,
+  },
+};
+
+/// Index of register `sp`, the stack pointer.
+const SP: usize = 2;
+
+/// The bit of `sstatus` that enables interrupts in supervisor mode (`SIE`).
+const SSTATUS_INTERRUPTS_ENABLED: usize = 1 << 1;
+/// The bit of `sstatus` that becomes `SIE` when the trap returns (`SPIE`).
+const SSTATUS_PREVIOUS_INTERRUPTS_ENABLED: usize = 1 << 5;
+
+/// The kernel code that waits for the program running on a hart.
+struct Caller {
+  /// The context of the kernel code, saved when the program was entered
+  context: Context,
+  /// The exit status of the program
+  status:  i32,
+}
+
+/// The caller of the program running on each hart, or null if no program runs. Harts
+/// with IDs beyond [`MAXIMUM_HARTS`] cannot run programs.
+static CALLERS: [AtomicPtr<Caller>; MAXIMUM_HARTS] =
+  [const { AtomicPtr::new(core::ptr::null_mut()) }; MAXIMUM_HARTS];
+
+extern "C" {
+  /// Saves the registers of the current context in `caller`, copies `trap_frame` onto the
+  /// stack and returns from a trap with it, like `_start_trap` does. The next trap from
+  /// user mode puts its frame at the same place, just below the stack of the caller.
+  fn __uncore_enter_user(caller: *mut Context, trap_frame: *const TrapFrame);
+}
+
+core::arch::global_asm!(
+  ".section .text",
+  ".global __uncore_enter_user",
+  ".align 2",
+  "__uncore_enter_user:",
+  "sd ra, 0 * 8(a0)",
+  "sd sp, 1 * 8(a0)",
+  "sd s0, 2 * 8(a0)",
+  "sd s1, 3 * 8(a0)",
+  "sd s2, 4 * 8(a0)",
+  "sd s3, 5 * 8(a0)",
+  "sd s4, 6 * 8(a0)",
+  "sd s5, 7 * 8(a0)",
+  "sd s6, 8 * 8(a0)",
+  "sd s7, 9 * 8(a0)",
+  "sd s8, 10 * 8(a0)",
+  "sd s9, 11 * 8(a0)",
+  "sd s10, 12 * 8(a0)",
+  "sd s11, 13 * 8(a0)",
+  "addi sp, sp, -{size}",
+  "li t0, 0",
+  "1:",
+  "add t1, a1, t0",
+  "ld t2, 0(t1)",
+  "add t1, sp, t0",
+  "sd t2, 0(t1)",
+  "addi t0, t0, 8",
+  "li t1, {size}",
+  "bltu t0, t1, 1b",
+  "tail __uncore_return_from_trap",
+  size = const core::mem::size_of::<TrapFrame>(),
+);
+
+impl crate::arch::UserMode for super::RiscV {
+  /// Harts with IDs beyond [`MAXIMUM_HARTS`] cannot run programs.
+  unsafe fn run_program(entry: usize, stack_pointer: usize) -> Option<i32> {
+    let slot = CALLERS.get(crate::arch::hart())?;
+    let mut caller = Caller {
+      context: Context::default(),
+      status:  0,
+    };
+
+    let sstatus: usize;
+    core::arch::asm!("csrr {}, sstatus", out(reg) sstatus, options(nomem, nostack));
+    let mut trap_frame = TrapFrame {
+      registers: [0; 32],
+      sepc:      entry,
+      // Interrupts stay disabled until `sret`, as the exit of the trap entry expects
+      sstatus:   (sstatus & !(SSTATUS_SUPERVISOR_PREVIOUS | SSTATUS_INTERRUPTS_ENABLED))
+        | SSTATUS_PREVIOUS_INTERRUPTS_ENABLED,
+    };
+    trap_frame.registers[SP] = stack_pointer;
+
+    slot.store(&raw mut caller, Ordering::Relaxed);
+    __uncore_enter_user(&raw mut caller.context, &raw const trap_frame);
+
+    // The program exited in a trap, which disabled interrupts
+    if sstatus & SSTATUS_INTERRUPTS_ENABLED != 0 {
+      core::arch::asm!("csrs sstatus, {}", in(reg) SSTATUS_INTERRUPTS_ENABLED, options(nostack));
+    }
+    Some(caller.status)
+  }
+}
+
+/// Ends the program running on the current hart with `status` and continues the kernel
+/// code that started it. The stack of the current trap is given up. If no kernel code
+/// waits for the program, the hart is parked (see [`super::park_hart`]).
+pub(super) fn exit(status: i32) -> ! {
+  let caller = CALLERS
+    .get(crate::arch::hart())
+    .map_or(core::ptr::null_mut(), |slot| {
+      slot.swap(core::ptr::null_mut(), Ordering::Relaxed)
+    });
+  if caller.is_null() {
+    super::park_hart();
+  }
+
+  let mut discarded = Context::default();
+  unsafe {
+    (*caller).status = status;
+    crate::arch::switch_context(&raw mut discarded, &raw const (*caller).context);
+  }
+  unreachable!("the context of an exited program is never continued");
+}
@@ -0,0 +1,116 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unit tests of the page tables and the context switch of the architecture.
+
+use alloc::vec;
+
+use super::{
+  Context,
+  PageTable,
+  PageTables,
+};
+use crate::library::mem::{
+  address_space::{
+    Protection,
+    USER_END,
+    USER_START,
+  },
+  object::Page,
+  Error,
+  PAGE_SIZE,
+};
+
+#[test_case]
+fn page_tables_translate_the_pages_of_programs() {
+  let mut tables = PageTables::new().unwrap();
+  let page = Page::new().unwrap();
+  let address = USER_START + 5 * PAGE_SIZE;
+
+  assert_eq!(tables.translate(address), None);
+  tables.map(address, page.address(), Protection::READ).unwrap();
+  let (physical, protection) = tables.translate(address + 8).unwrap();
+  assert_eq!(physical, page.address() + 8);
+  assert_eq!(protection, Protection::READ);
+  assert_eq!(tables.translate(address + PAGE_SIZE), None);
+
+  // Writable pages are also readable
+  tables.map(address, page.address(), Protection::WRITE).unwrap();
+  assert_eq!(
+    tables.translate(address).unwrap().1,
+    Protection::READ | Protection::WRITE
+  );
+  tables.unmap(address);
+  assert_eq!(tables.translate(address), None);
+
+  // The memory of the kernel cannot be mapped
+  assert_eq!(
+    tables.map(USER_START - PAGE_SIZE, page.address(), Protection::READ),
+    Err(Error::InvalidArgument)
+  );
+  assert_eq!(
+    tables.map(USER_END, page.address(), Protection::READ),
+    Err(Error::InvalidArgument)
+  );
+  assert_eq!(
+    tables.map(address + 1, page.address(), Protection::READ),
+    Err(Error::InvalidArgument)
+  );
+}
+
+#[test_case]
+fn the_kernel_keeps_running_with_active_page_tables() {
+  let mut tables = PageTables::new().unwrap();
+  let page = Page::new().unwrap();
+  tables
+    .map(USER_START, page.address(), Protection::READ | Protection::WRITE)
+    .unwrap();
+
+  unsafe { tables.activate() };
+  // The heap, the stack and the code of the kernel are still reachable
+  let values: alloc::vec::Vec<u64> = (1..=3).collect();
+  let sum: u64 = values.iter().sum();
+  unsafe { PageTables::deactivate() };
+  assert_eq!(sum, 6);
+}
+
+/// The contexts of [`contexts_are_switched_back_and_forth`] and the values the second
+/// context saw.
+#[derive(Default)]
+struct Switch {
+  /// The context of the test
+  test:   Context,
+  /// The context that is switched to
+  other:  Context,
+  /// The arguments the other context received
+  values: [usize; 2],
+}
+
+/// Records `argument` twice, switching back to the test after each time.
+extern "C" fn record(argument: usize) -> ! {
+  let switch = argument as *mut Switch;
+  for round in 0..2 {
+    unsafe {
+      (*switch).values[round] = argument + round;
+      super::switch_context(&raw mut (*switch).other, &raw const (*switch).test);
+    }
+  }
+  unreachable!("the context is not switched to a third time");
+}
+
+#[test_case]
+fn contexts_are_switched_back_and_forth() {
+  let stack = vec![0_u8; 4 * PAGE_SIZE];
+  let mut switch = Switch::default();
+  let switch_address = (&raw mut switch).addr();
+  super::prepare_context(
+    &mut switch.other,
+    record,
+    switch_address,
+    stack.as_ptr().addr() + stack.len(),
+  );
+
+  unsafe { super::switch_context(&raw mut switch.test, &raw const switch.other) };
+  assert_eq!(switch.values, [switch_address, 0]);
+  unsafe { super::switch_context(&raw mut switch.test, &raw const switch.other) };
+  assert_eq!(switch.values, [switch_address, switch_address + 1]);
+}
@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module prints backtraces of the kernel, e.g. when it panics.
+//!
+//! The stack is unwound by following the frame pointers (see
+//! [`crate::arch::backtrace`]), which the kernel is compiled with. Functions of the
+//! precompiled `core` and `alloc` libraries do not maintain frame pointers, so frames of
+//! these functions may be missing.
+//!
+//! Return addresses are resolved to function names with the symbol table of the kernel.
+//! The kernel reserves space for the table ([`KERNEL_SYMBOLS`]) that `uncore-helper`
+//! fills with the function symbols of the ELF file after building. Kernels built without
+//! the helper have an empty table, and their backtraces show addresses only.
+//!
+//! The table starts with a header, followed by the entries sorted by address and the
+//! names (all numbers are little-endian):
+//!
+//! | Offset | Size | Contents                                           |
+//! | ------ | ---- | -------------------------------------------------- |
+//! | 0      | 8    | the magic number [`MAGIC`]                         |
+//! | 8      | 4    | the number of entries                              |
+//! | 12     | 4    | the offset of the names from the start of the table |
+//! | 16     | 24n  | the entries                                        |
+//!
+//! Every entry holds the address of a function (8 bytes), its size (4 bytes), and the
+//! offset (4 bytes) and length (4 bytes) of its name, followed by 4 bytes of padding.
+
+#[cfg(test)]
+mod tests;
+
+/// The magic number at the start of the symbol table.
+pub const MAGIC: [u8; 8] = *b"uCsymtab";
+/// The size of the symbol table in bytes, including the header.
+pub const TABLE_SIZE: usize = 512 * 1024;
+/// The size of the header of the symbol table in bytes.
+const HEADER_SIZE: usize = 16;
+/// The size of an entry of the symbol table in bytes.
+const ENTRY_SIZE: usize = 24;
+/// The maximum number of frames a backtrace shows.
+const MAXIMUM_FRAMES: usize = 64;
+
+/// The space for the symbol table of the kernel.
+#[repr(C, align(8))]
+pub struct Storage {
+  /// The magic number
+  magic: [u8; 8],
+  /// The rest of the table
+  table: [u8; TABLE_SIZE - MAGIC.len()],
+}
+
+/// The symbol table of the kernel, which `uncore-helper` writes into the ELF file after
+/// building. The helper finds it by its name, which must therefore not be mangled.
+#[used]
+#[no_mangle]
+pub static KERNEL_SYMBOLS: Storage = Storage {
+  magic: MAGIC,
+  table: [0; TABLE_SIZE - MAGIC.len()],
+};
+
+/// A symbol table in the format described in the [module documentation](self).
+#[derive(Debug, Copy, Clone)]
+pub struct SymbolTable<'a> {
+  /// The bytes of the table
+  bytes: &'a [u8],
+  /// The number of entries
+  count: usize,
+  /// The offset of the names
+  names: usize,
+}
+
+impl<'a> SymbolTable<'a> {
+  /// Returns the table in `bytes`, or [`None`] if `bytes` does not hold a valid table.
+  #[must_use]
+  pub fn new(bytes: &'a [u8]) -> Option<Self> {
+    if bytes.get(..MAGIC.len())? != MAGIC {
+      return None;
+    }
+    let count = usize::try_from(read_u32(bytes, 8)?).ok()?;
+    let names = usize::try_from(read_u32(bytes, 12)?).ok()?;
+    let entries_end = count.checked_mul(ENTRY_SIZE)?.checked_add(HEADER_SIZE)?;
+    if entries_end > bytes.len() || names > bytes.len() {
+      return None;
+    }
+    Some(Self { bytes, count, names })
+  }
+
+  /// Returns the symbol table of the kernel.
+  #[must_use]
+  pub fn kernel() -> Option<Self> {
+    // The table is written into the kernel image after compiling, so its contents must
+    // not be assumed to be the initial ones
+    let storage = core::hint::black_box(core::ptr::from_ref(&KERNEL_SYMBOLS));
+    Self::new(unsafe { core::slice::from_raw_parts(storage.cast::<u8>(), TABLE_SIZE) })
+  }
+
+  /// Returns whether the table is empty.
+  #[must_use]
+  pub const fn is_empty(&self) -> bool { self.count == 0 }
+
+  /// Returns the address, size and name of the entry with `index`.
+  fn entry(&self, index: usize) -> Option<(usize, usize, &'a str)> {
+    let offset = HEADER_SIZE + index * ENTRY_SIZE;
+    let address = usize::try_from(read_u64(self.bytes, offset)?).ok()?;
+    let size = usize::try_from(read_u32(self.bytes, offset + 8)?).ok()?;
+    let name_offset = self.names + usize::try_from(read_u32(self.bytes, offset + 12)?).ok()?;
+    let name_length = usize::try_from(read_u32(self.bytes, offset + 16)?).ok()?;
+    let name = self
+      .bytes
+      .get(name_offset..name_offset.checked_add(name_length)?)?;
+    Some((address, size, core::str::from_utf8(name).ok()?))
+  }
+
+  /// Returns the name of the function that contains `address` and the offset of
+  /// `address` in it.
+  #[must_use]
+  pub fn lookup(&self, address: usize) -> Option<(&'a str, usize)> {
+    // Find the last entry that starts at or before the address
+    let (mut low, mut high) = (0, self.count);
+    while low < high {
+      let middle = low + (high - low) / 2;
+      if self.entry(middle)?.0 <= address {
+        low = middle + 1;
+      } else {
+        high = middle;
+      }
+    }
+
+    let (start, size, name) = self.entry(low.checked_sub(1)?)?;
+    let offset = address - start;
+    (size == 0 || offset < size).then_some((name, offset))
+  }
+}
+
+/// Reads the little-endian `u32` at `offset` of `bytes`.
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+  Some(u32::from_le_bytes(
+    bytes.get(offset..offset + 4)?.try_into().ok()?,
+  ))
+}
+
+/// Reads the little-endian `u64` at `offset` of `bytes`.
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+  Some(u64::from_le_bytes(
+    bytes.get(offset..offset + 8)?.try_into().ok()?,
+  ))
+}
+
+/// An address in the code of the kernel, which is displayed together with the name of
+/// the function that contains it, e.g. `0x20001234 <uncore::setup_kernel+0x20>`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CodeAddress(pub usize);
+
+impl core::fmt::Display for CodeAddress {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "{:#x}", self.0)?;
+    if let Some((name, offset)) = SymbolTable::kernel().and_then(|table| table.lookup(self.0)) {
+      write!(f, " <{name}+{offset:#x}>")?;
+    }
+    Ok(())
+  }
+}
+
+/// Logs the backtrace of the code that called this function.
+#[inline(never)]
+pub fn log() {
+  if SymbolTable::kernel().is_none_or(|table| table.is_empty()) {
+    ::log::error!("Backtrace (no symbols, the kernel was not built with uncore-helper):");
+  } else {
+    ::log::error!("Backtrace:");
+  }
+  let mut frames = 0;
+  for return_address in crate::arch::backtrace().take(MAXIMUM_FRAMES) {
+    // The return address follows the call, which may be the last instruction of the
+    // calling function
+    ::log::error!("  #{frames:<2} {}", CodeAddress(return_address - 1));
+    frames += 1;
+  }
+  if frames == 0 {
+    ::log::error!("  (no frames)");
+  }
+}
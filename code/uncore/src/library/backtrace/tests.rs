@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unit tests of the symbol table.
+
+use alloc::vec::Vec;
+
+use super::{
+  SymbolTable,
+  ENTRY_SIZE,
+  HEADER_SIZE,
+  MAGIC,
+};
+
+#[test_case]
+fn addresses_are_resolved_to_the_containing_function() {
+  let symbols: [(u64, u32, &str); 3] = [
+    (0x1000, 0x20, "uncore::setup_kernel"),
+    (0x1020, 0x10, "uncore::main"),
+    (0x1040, 0, "_start"),
+  ];
+
+  let mut bytes = Vec::from(MAGIC);
+  let names_offset = HEADER_SIZE + symbols.len() * ENTRY_SIZE;
+  bytes.extend_from_slice(&u32::try_from(symbols.len()).unwrap().to_le_bytes());
+  bytes.extend_from_slice(&u32::try_from(names_offset).unwrap().to_le_bytes());
+  let mut names = Vec::new();
+  for (address, size, name) in symbols {
+    bytes.extend_from_slice(&address.to_le_bytes());
+    bytes.extend_from_slice(&size.to_le_bytes());
+    bytes.extend_from_slice(&u32::try_from(names.len()).unwrap().to_le_bytes());
+    bytes.extend_from_slice(&u32::try_from(name.len()).unwrap().to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    names.extend_from_slice(name.as_bytes());
+  }
+  bytes.extend_from_slice(&names);
+
+  let table = SymbolTable::new(&bytes).unwrap();
+  assert!(!table.is_empty());
+  assert_eq!(table.lookup(0x1000), Some(("uncore::setup_kernel", 0)));
+  assert_eq!(table.lookup(0x102C), Some(("uncore::main", 0xC)));
+  // Between two functions and before the first one
+  assert_eq!(table.lookup(0x1030), None);
+  assert_eq!(table.lookup(0xFFF), None);
+  // Symbols without a size contain every address after them
+  assert_eq!(table.lookup(0x2000), Some(("_start", 0xFC0)));
+
+  assert!(SymbolTable::new(&bytes[..HEADER_SIZE + ENTRY_SIZE]).is_none());
+  assert!(SymbolTable::new(b"notatable").is_none());
+}
@@ -0,0 +1,318 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module parses the kernel command line, which the firmware (or QEMU with
+//! `-append`) passes as `/chosen/bootargs` in the device tree. The command line consists
+//! of arguments separated by whitespace; an argument is either `<NAME>=<VALUE>` or a
+//! bare `<NAME>`, which sets a boolean parameter. Values that contain whitespace can be
+//! enclosed in double quotes, e.g. `log="uncore::fs=debug, warn"`.
+//!
+//! Modules declare the parameters they understand with [`kernel_param!`], which creates a
+//! typed [`Parameter`] with a default value and an optional validation. The macro
+//! registers the parameter in the linker section `.kernel_parameters`, from which the
+//! command line finds it (see [`parameters`]).
+//!
+//! | Parameter     | Type    | Effect                                                   |
+//! | ------------- | ------- | -------------------------------------------------------- |
+//! | `log.level`   | level   | the default level of the log filter (`LOG_LEVEL`)        |
+//! | `log`         | string  | directives of the log filter (see [`super::log::filter`]) |
+//! | `test.filter` | string  | only unit tests whose name contains the string are run   |
+//! | `init`        | path    | the program that is started first (see [`super::fs::initramfs`]) |
+//!
+//! There is no scheduler yet, so there is no parameter that selects a scheduling policy;
+//! it is declared together with the scheduler.
+//!
+//! The command line is parsed before the kernel log is available. Therefore, arguments
+//! that are ignored (because the parameter is unknown or the value is invalid) are
+//! recorded and reported later by [`log_arguments`]. If an argument is given several
+//! times, the last one wins.
+
+#[cfg(test)]
+mod tests;
+
+/// The maximum number of ignored arguments that are recorded.
+const MAXIMUM_IGNORED: usize = 8;
+
+extern "C" {
+  /// The start of the parameters that [`kernel_param!`] registers, provided by the
+  /// linker script.
+  static __kernel_parameters_start: u8;
+  /// The end of the parameters that [`kernel_param!`] registers, provided by the linker
+  /// script.
+  static __kernel_parameters_end: u8;
+}
+
+/// The arguments that were ignored, with the reason why.
+static IGNORED: spin::Mutex<[Option<(&str, Error)>; MAXIMUM_IGNORED]> =
+  spin::Mutex::new([None; MAXIMUM_IGNORED]);
+
+/// Errors that may occur when an argument of the command line is applied.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+  /// No parameter has the name of the argument.
+  UnknownParameter,
+  /// The argument has no value, but the parameter is not boolean.
+  MissingValue,
+  /// The value cannot be parsed or was rejected by the validation of the parameter.
+  InvalidValue(&'static str),
+}
+
+impl core::fmt::Display for Error {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::UnknownParameter => write!(f, "unknown parameter"),
+      Self::MissingValue => write!(f, "missing value"),
+      Self::InvalidValue(reason) => write!(f, "invalid value: {reason}"),
+    }
+  }
+}
+
+/// A type that values of parameters can have.
+pub trait Value: Copy + core::fmt::Debug + core::fmt::Display + Send + Sync + 'static {
+  /// Parses `text`, which is [`None`] if the argument has no value.
+  ///
+  /// #### Errors
+  ///
+  /// If `text` is not a valid value, an error is returned.
+  fn parse(text: Option<&'static str>) -> Result<Self, Error>;
+}
+
+impl Value for bool {
+  fn parse(text: Option<&'static str>) -> Result<Self, Error> {
+    match text {
+      None | Some("1" | "on" | "true" | "yes") => Ok(true),
+      Some("0" | "off" | "false" | "no") => Ok(false),
+      Some(_) => Err(Error::InvalidValue("not a boolean")),
+    }
+  }
+}
+
+impl Value for usize {
+  fn parse(text: Option<&'static str>) -> Result<Self, Error> {
+    text
+      .ok_or(Error::MissingValue)?
+      .parse()
+      .map_err(|_| Error::InvalidValue("not a number"))
+  }
+}
+
+impl Value for &'static str {
+  fn parse(text: Option<&'static str>) -> Result<Self, Error> { text.ok_or(Error::MissingValue) }
+}
+
+impl Value for log::LevelFilter {
+  fn parse(text: Option<&'static str>) -> Result<Self, Error> {
+    text
+      .ok_or(Error::MissingValue)?
+      .parse()
+      .map_err(|_| Error::InvalidValue("not a log level"))
+  }
+}
+
+/// A parameter of the command line with a value of type `T`. Parameters are declared
+/// with [`kernel_param!`].
+#[derive(Debug)]
+pub struct Parameter<T: 'static> {
+  /// The name on the command line, e.g. `log.level`
+  name:     &'static str,
+  /// Returns the value if the command line does not set the parameter
+  default:  fn() -> T,
+  /// Checks a value before it is set
+  validate: fn(T) -> Result<(), &'static str>,
+  /// The value from the command line, if it set the parameter
+  value:    spin::RwLock<Option<T>>,
+}
+
+impl<T: Value> Parameter<T> {
+  /// Creates a parameter with `name` whose value is returned by `default` unless the
+  /// command line sets it.
+  #[must_use]
+  pub const fn new(name: &'static str, default: fn() -> T) -> Self {
+    Self {
+      name,
+      default,
+      validate: |_| Ok(()),
+      value: spin::RwLock::new(None),
+    }
+  }
+
+  /// Lets `validate` check every value before it is set; a value for which `validate`
+  /// returns an error (with the reason) is ignored.
+  #[must_use]
+  pub const fn with_validation(mut self, validate: fn(T) -> Result<(), &'static str>) -> Self {
+    self.validate = validate;
+    self
+  }
+
+  /// Returns the value of the parameter.
+  pub fn get(&self) -> T { self.value.read().unwrap_or_else(self.default) }
+}
+
+/// The interface of [`Parameter`] that does not depend on the type of the value, which
+/// the command line uses to set parameters.
+pub trait KernelParameter: core::fmt::Debug + Sync {
+  /// Returns the name of the parameter on the command line.
+  fn name(&self) -> &'static str;
+
+  /// Parses, validates and sets `text` (see [`Value::parse`]).
+  ///
+  /// #### Errors
+  ///
+  /// If `text` is not a valid value, an error is returned and the value is kept.
+  fn set(&self, text: Option<&'static str>) -> Result<(), Error>;
+
+  /// Writes the value of the parameter.
+  fn write_value(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result;
+}
+
+impl core::fmt::Display for dyn KernelParameter {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "{}=", self.name())?;
+    self.write_value(f)
+  }
+}
+
+impl<T: Value> KernelParameter for Parameter<T> {
+  fn name(&self) -> &'static str { self.name }
+
+  fn set(&self, text: Option<&'static str>) -> Result<(), Error> {
+    let value = T::parse(text)?;
+    (self.validate)(value).map_err(Error::InvalidValue)?;
+    *self.value.write() = Some(value);
+    Ok(())
+  }
+
+  fn write_value(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result { write!(f, "{}", self.get()) }
+}
+
+/// Declares a parameter of the command line as a `static` [`Parameter`] and registers it,
+/// so that the command line can set it (see [`parameters`]).
+///
+/// ```ignore
+/// kernel_param! {
+///   /// The number of retries.
+///   pub RETRIES: usize = 3, "retries", validate = retries_are_bounded
+/// }
+/// ```
+///
+/// The default value is evaluated whenever the parameter is read and the command line did
+/// not set it, so it may call (non-`const`) functions.
+macro_rules! kernel_param {
+  (
+    $(#[$attribute:meta])*
+    $visibility:vis $identifier:ident: $type:ty = $default:expr, $name:literal
+    $(, validate = $validate:path)? $(,)?
+  ) => {
+    $(#[$attribute])*
+    $visibility static $identifier: $crate::library::cmdline::Parameter<$type> =
+      $crate::library::cmdline::Parameter::new($name, || $default)$(.with_validation($validate))?;
+
+    const _: () = {
+      #[used]
+      #[link_section = ".kernel_parameters"]
+      static REGISTRATION: &dyn $crate::library::cmdline::KernelParameter = &$identifier;
+    };
+  };
+}
+pub(crate) use kernel_param;
+
+/// Returns the parameters that [`kernel_param!`] registered, i.e. all parameters the
+/// command line can set.
+#[must_use]
+#[allow(clippy::cast_ptr_alignment)]
+pub fn parameters() -> &'static [&'static dyn KernelParameter] {
+  // The linker script aligns the section for the references
+  let start = crate::transform_linker_symbol_to_value!(__kernel_parameters_start)
+    .cast::<&'static dyn KernelParameter>();
+  let end = crate::transform_linker_symbol_to_value!(__kernel_parameters_end, usize);
+  let length = (end - start.addr()) / core::mem::size_of::<&dyn KernelParameter>();
+  unsafe { core::slice::from_raw_parts(start, length) }
+}
+
+/// Splits `line` into arguments, which are separated by whitespace outside of double
+/// quotes, and the arguments into name and value (without quotes).
+fn arguments(line: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+  let mut rest = line.trim_start();
+  core::iter::from_fn(move || {
+    if rest.is_empty() {
+      return None;
+    }
+    let mut quoted = false;
+    let end = rest
+      .char_indices()
+      .find(|&(_, character)| {
+        if character == '"' {
+          quoted = !quoted;
+        }
+        character.is_whitespace() && !quoted
+      })
+      .map_or(rest.len(), |(index, _)| index);
+    let argument = &rest[..end];
+    rest = rest[end..].trim_start();
+
+    Some(
+      argument
+        .split_once('=')
+        .map_or((argument, None), |(name, value)| {
+          let value = value.strip_prefix('"').unwrap_or(value);
+          (name, Some(value.strip_suffix('"').unwrap_or(value)))
+        }),
+    )
+  })
+}
+
+/// Returns the command line, if the device tree has one.
+#[must_use]
+pub fn command_line() -> Option<&'static str> {
+  crate::library::device_tree::get()
+    .and_then(|tree| tree.find_node("/chosen"))
+    .and_then(|chosen| chosen.property("bootargs"))
+    .and_then(|bootargs| bootargs.as_str())
+}
+
+/// Applies the arguments of `line` to `parameters` and returns the arguments that were
+/// ignored, with the reason why.
+fn apply<'parameters>(
+  line: &'static str,
+  parameters: &'parameters [&'parameters dyn KernelParameter],
+) -> impl Iterator<Item = (&'static str, Error)> + 'parameters {
+  arguments(line).filter_map(|(name, value)| {
+    parameters
+      .iter()
+      .find(|parameter| parameter.name() == name)
+      .map_or(Err(Error::UnknownParameter), |parameter| parameter.set(value))
+      .err()
+      .map(|error| (name, error))
+  })
+}
+
+/// Sets the parameters from the command line. This function is called before the kernel
+/// log is available; see [`log_arguments`].
+pub fn initialize() {
+  let Some(line) = command_line() else {
+    return;
+  };
+  let mut ignored = IGNORED.lock();
+  // All arguments are applied, even if there are more ignored ones than can be recorded
+  for (index, argument) in apply(line, parameters()).enumerate() {
+    if let Some(slot) = ignored.get_mut(index) {
+      *slot = Some(argument);
+    }
+  }
+}
+
+/// Logs the command line, the value of every parameter and the arguments that were
+/// ignored.
+pub fn log_arguments() {
+  log::debug!("Kernel command line is '{}'", command_line().unwrap_or_default());
+  for parameter in parameters() {
+    log::trace!("Kernel parameter {parameter}");
+  }
+  for (name, error) in IGNORED.lock().iter().flatten() {
+    log::warn!("Ignoring kernel command line argument '{name}': {error}");
+  }
+}
+
+/// Writes the command line.
+pub fn write(out: &mut impl core::fmt::Write) -> core::fmt::Result {
+  writeln!(out, "{}", command_line().unwrap_or_default())
+}
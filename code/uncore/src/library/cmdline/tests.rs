@@ -0,0 +1,106 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unit tests of the kernel command line.
+
+use alloc::vec::Vec;
+
+use super::{
+  apply,
+  arguments,
+  kernel_param,
+  parameters,
+  Error,
+  KernelParameter,
+};
+
+kernel_param! {
+  /// A boolean parameter.
+  VERBOSE: bool = false, "test.verbose"
+}
+
+kernel_param! {
+  /// A bounded number.
+  RETRIES: usize = 3, "test.retries", validate = at_most_ten
+}
+
+kernel_param! {
+  /// A string.
+  PATH: &'static str = "/init", "test.path"
+}
+
+/// Rejects numbers greater than ten.
+fn at_most_ten(retries: usize) -> Result<(), &'static str> {
+  if retries <= 10 {
+    Ok(())
+  } else {
+    Err("more than 10")
+  }
+}
+
+#[test_case]
+fn arguments_are_split_outside_of_quotes() {
+  let arguments: Vec<_> = arguments("  quiet log=\"uncore::fs=debug, warn\"  path=/bin/sh empty= ").collect();
+  assert_eq!(
+    arguments,
+    [
+      ("quiet", None),
+      ("log", Some("uncore::fs=debug, warn")),
+      ("path", Some("/bin/sh")),
+      ("empty", Some("")),
+    ]
+  );
+}
+
+#[test_case]
+fn parameters_are_set_from_the_command_line() {
+  let parameters: [&dyn KernelParameter; 3] = [&VERBOSE, &RETRIES, &PATH];
+  assert!(!VERBOSE.get());
+  assert_eq!(RETRIES.get(), 3);
+
+  let ignored: Vec<_> = apply(
+    "test.verbose test.retries=11 test.retries=7 test.path=\"/bin/my init\" test.path other=1 \
+     test.verbose=maybe",
+    &parameters,
+  )
+  .collect();
+  assert_eq!(
+    ignored,
+    [
+      ("test.retries", Error::InvalidValue("more than 10")),
+      ("test.path", Error::MissingValue),
+      ("other", Error::UnknownParameter),
+      ("test.verbose", Error::InvalidValue("not a boolean")),
+    ]
+  );
+
+  assert!(VERBOSE.get());
+  assert_eq!(RETRIES.get(), 7);
+  assert_eq!(PATH.get(), "/bin/my init");
+  assert_eq!(alloc::format!("{}", parameters[1]), "test.retries=7");
+}
+
+#[test_case]
+fn declared_parameters_are_registered() {
+  let names: Vec<_> = parameters().iter().map(|parameter| parameter.name()).collect();
+  for name in [
+    "log.level",
+    "log",
+    "test.filter",
+    "test.verbose",
+    "test.retries",
+    "test.path",
+    "init",
+  ] {
+    assert!(names.contains(&name), "parameter '{name}' is not registered");
+  }
+}
+
+#[test_case]
+fn the_init_program_needs_an_absolute_path() {
+  use crate::library::fs::initramfs::INIT;
+
+  let parameters: [&dyn KernelParameter; 1] = [&INIT];
+  let ignored: Vec<_> = apply("init=bin/sh", &parameters).collect();
+  assert_eq!(ignored, [("init", Error::InvalidValue("not an absolute path"))]);
+  assert_eq!(INIT.get(), "/init");
+}
@@ -0,0 +1,445 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module implements a text console on top of a framebuffer. The console renders
+//! characters with a bitmap font (see [`font`]) and understands the ANSI escape sequences
+//! for colors that the kernel log uses, so that it can mirror the kernel log.
+//!
+//! The framebuffer itself is provided by a driver (e.g. the `VirtIO` GPU driver) that
+//! implements [`Framebuffer`] and calls [`initialize`].
+
+mod font;
+
+/// A rectangular area on a framebuffer, measured in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rectangle {
+  /// The horizontal position of the upper-left corner
+  pub x:      usize,
+  /// The vertical position of the upper-left corner
+  pub y:      usize,
+  /// The width of the area
+  pub width:  usize,
+  /// The height of the area
+  pub height: usize,
+}
+
+/// The interface a driver has to provide so that the console can draw on its framebuffer.
+///
+/// Pixels are stored row by row without padding; each pixel is a `u32` with the layout
+/// `0x00RRGGBB`.
+pub trait Framebuffer: Send {
+  /// Returns the width of the framebuffer in pixels.
+  fn width(&self) -> usize;
+
+  /// Returns the height of the framebuffer in pixels.
+  fn height(&self) -> usize;
+
+  /// Returns the pixels of the framebuffer.
+  fn pixels(&mut self) -> &mut [u32];
+
+  /// Makes the changes inside `area` visible on the display.
+  fn flush(&mut self, area: Rectangle);
+}
+
+/// A color as used by the console, in the layout `0x00RRGGBB`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Color(u32);
+
+impl Color {
+  /// The default background color.
+  const DEFAULT_BACKGROUND: Self = Self::from_rgb(0x1D, 0x20, 0x21);
+  /// The default foreground color.
+  const DEFAULT_FOREGROUND: Self = Self::from_rgb(0xD5, 0xC4, 0xA1);
+  /// The 16 standard ANSI colors (8 normal ones followed by 8 bright ones).
+  const PALETTE: [Self; 16] = [
+    Self::from_rgb(0x00, 0x00, 0x00),
+    Self::from_rgb(0xCD, 0x00, 0x00),
+    Self::from_rgb(0x00, 0xCD, 0x00),
+    Self::from_rgb(0xCD, 0xCD, 0x00),
+    Self::from_rgb(0x00, 0x00, 0xEE),
+    Self::from_rgb(0xCD, 0x00, 0xCD),
+    Self::from_rgb(0x00, 0xCD, 0xCD),
+    Self::from_rgb(0xE5, 0xE5, 0xE5),
+    Self::from_rgb(0x7F, 0x7F, 0x7F),
+    Self::from_rgb(0xFF, 0x00, 0x00),
+    Self::from_rgb(0x00, 0xFF, 0x00),
+    Self::from_rgb(0xFF, 0xFF, 0x00),
+    Self::from_rgb(0x5C, 0x5C, 0xFF),
+    Self::from_rgb(0xFF, 0x00, 0xFF),
+    Self::from_rgb(0x00, 0xFF, 0xFF),
+    Self::from_rgb(0xFF, 0xFF, 0xFF),
+  ];
+
+  /// Creates a color from its red, green and blue components.
+  #[must_use]
+  pub const fn from_rgb(red: u8, green: u8, blue: u8) -> Self {
+    Self(((red as u32) << 16) | ((green as u32) << 8) | blue as u32)
+  }
+
+  /// Returns the color with index `index` from the 256-color palette of `xterm`.
+  fn from_256_palette(index: u8) -> Self {
+    /// The intensities of the 6x6x6 color cube.
+    const CUBE_STEPS: [u8; 6] = [0x00, 0x5F, 0x87, 0xAF, 0xD7, 0xFF];
+
+    match index {
+      0..=15 => Self::PALETTE[usize::from(index)],
+      16..=231 => {
+        let index = usize::from(index - 16);
+        Self::from_rgb(
+          CUBE_STEPS[index / 36],
+          CUBE_STEPS[(index / 6) % 6],
+          CUBE_STEPS[index % 6],
+        )
+      },
+      232..=255 => {
+        let level = 8 + (index - 232) * 10;
+        Self::from_rgb(level, level, level)
+      },
+    }
+  }
+}
+
+/// The maximum number of numeric parameters of an escape sequence the console keeps
+/// track of.
+const MAXIMUM_ESCAPE_PARAMETERS: usize = 8;
+
+/// The state of the parser for ANSI escape sequences.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ParserState {
+  /// Characters are printed as they are.
+  Ground,
+  /// An escape character (`ESC`) has been read.
+  Escape,
+  /// A control sequence introducer (`ESC [`) has been read; parameters follow.
+  ControlSequence,
+}
+
+/// A text console that renders characters onto a [`Framebuffer`].
+pub struct TextConsole {
+  /// The framebuffer the console draws onto
+  framebuffer:     &'static mut dyn Framebuffer,
+  /// The number of character columns
+  columns:         usize,
+  /// The number of character rows
+  rows:            usize,
+  /// The column of the cursor
+  column:          usize,
+  /// The row of the cursor
+  row:             usize,
+  /// The current foreground color
+  foreground:      Color,
+  /// The current background color
+  background:      Color,
+  /// The state of the escape sequence parser
+  state:           ParserState,
+  /// The parameters of the escape sequence that is currently parsed
+  parameters:      [u16; MAXIMUM_ESCAPE_PARAMETERS],
+  /// The number of parameters of the escape sequence that is currently parsed
+  parameter_count: usize,
+  /// The first and last (exclusive) pixel row that need to be flushed
+  dirty:           Option<(usize, usize)>,
+}
+
+impl core::fmt::Debug for TextConsole {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("TextConsole")
+      .field("columns", &self.columns)
+      .field("rows", &self.rows)
+      .field("column", &self.column)
+      .field("row", &self.row)
+      .finish_non_exhaustive()
+  }
+}
+
+impl TextConsole {
+  /// Creates a new console on `framebuffer` and clears the screen. If the framebuffer
+  /// cannot hold a single character, [`None`] is returned.
+  fn new(framebuffer: &'static mut dyn Framebuffer) -> Option<Self> {
+    let columns = framebuffer.width() / font::GLYPH_WIDTH;
+    let rows = framebuffer.height() / font::GLYPH_HEIGHT;
+    if columns == 0 || rows == 0 {
+      return None;
+    }
+
+    let mut console = Self {
+      framebuffer,
+      columns,
+      rows,
+      column: 0,
+      row: 0,
+      foreground: Color::DEFAULT_FOREGROUND,
+      background: Color::DEFAULT_BACKGROUND,
+      state: ParserState::Ground,
+      parameters: [0; MAXIMUM_ESCAPE_PARAMETERS],
+      parameter_count: 0,
+      dirty: None,
+    };
+    console.clear();
+    console.flush();
+    Some(console)
+  }
+
+  /// Marks the pixel rows from `start` to `end` (exclusive) as dirty.
+  fn mark_dirty(&mut self, start: usize, end: usize) {
+    self.dirty = Some(self.dirty.map_or((start, end), |(old_start, old_end)| {
+      (old_start.min(start), old_end.max(end))
+    }));
+  }
+
+  /// Makes all changes since the last flush visible.
+  fn flush(&mut self) {
+    if let Some((start, end)) = self.dirty.take() {
+      let width = self.framebuffer.width();
+      self.framebuffer.flush(Rectangle {
+        x: 0,
+        y: start,
+        width,
+        height: end - start,
+      });
+    }
+  }
+
+  /// Clears the whole screen with the current background color and moves the cursor to
+  /// the upper-left corner.
+  fn clear(&mut self) {
+    let background = self.background.0;
+    self.framebuffer.pixels().fill(background);
+    self.column = 0;
+    self.row = 0;
+    let height = self.framebuffer.height();
+    self.mark_dirty(0, height);
+  }
+
+  /// Draws `character` at the current cursor position with the current colors.
+  fn draw(&mut self, character: u8) {
+    let width = self.framebuffer.width();
+    let (x, y) = (self.column * font::GLYPH_WIDTH, self.row * font::GLYPH_HEIGHT);
+    let (foreground, background) = (self.foreground.0, self.background.0);
+    let pixels = self.framebuffer.pixels();
+
+    for (line, bits) in font::glyph(character).iter().enumerate() {
+      let offset = (y + line) * width + x;
+      for (index, pixel) in pixels[offset..offset + font::GLYPH_WIDTH].iter_mut().enumerate() {
+        *pixel = if bits & (1 << index) == 0 {
+          background
+        } else {
+          foreground
+        };
+      }
+    }
+
+    self.mark_dirty(y, y + font::GLYPH_HEIGHT);
+  }
+
+  /// Moves the cursor to the beginning of the next line, scrolling the screen if
+  /// required.
+  fn new_line(&mut self) {
+    self.column = 0;
+    if self.row + 1 < self.rows {
+      self.row += 1;
+      return;
+    }
+
+    let width = self.framebuffer.width();
+    let text_height = self.rows * font::GLYPH_HEIGHT;
+    let line_size = width * font::GLYPH_HEIGHT;
+    let background = self.background.0;
+    let pixels = self.framebuffer.pixels();
+    pixels.copy_within(line_size..text_height * width, 0);
+    pixels[(text_height - font::GLYPH_HEIGHT) * width..text_height * width].fill(background);
+    self.mark_dirty(0, text_height);
+  }
+
+  /// Handles a single byte of output, interpreting escape sequences.
+  fn write_byte(&mut self, byte: u8) {
+    match self.state {
+      ParserState::Ground => match byte {
+        0x1B => self.state = ParserState::Escape,
+        b'\n' => self.new_line(),
+        b'\r' => self.column = 0,
+        b'\t' => {
+          let next = (self.column / 8 + 1) * 8;
+          while self.column < next.min(self.columns) {
+            self.write_byte(b' ');
+          }
+        },
+        0x08 => self.column = self.column.saturating_sub(1),
+        _ => {
+          if self.column >= self.columns {
+            self.new_line();
+          }
+          self.draw(byte);
+          self.column += 1;
+        },
+      },
+      ParserState::Escape => {
+        if byte == b'[' {
+          self.parameters = [0; MAXIMUM_ESCAPE_PARAMETERS];
+          self.parameter_count = 0;
+          self.state = ParserState::ControlSequence;
+        } else {
+          self.state = ParserState::Ground;
+        }
+      },
+      ParserState::ControlSequence => match byte {
+        b'0'..=b'9' => {
+          if self.parameter_count == 0 {
+            self.parameter_count = 1;
+          }
+          if let Some(parameter) = self.parameters.get_mut(self.parameter_count - 1) {
+            *parameter = parameter
+              .saturating_mul(10)
+              .saturating_add(u16::from(byte - b'0'));
+          }
+        },
+        b';' => {
+          if self.parameter_count == 0 {
+            self.parameter_count = 1;
+          }
+          self.parameter_count += 1;
+        },
+        0x40..=0x7E => {
+          self.execute_control_sequence(byte);
+          self.state = ParserState::Ground;
+        },
+        _ => {},
+      },
+    }
+  }
+
+  /// Executes a control sequence with the final byte `command` and the parameters parsed
+  /// before.
+  fn execute_control_sequence(&mut self, command: u8) {
+    let count = self.parameter_count.min(MAXIMUM_ESCAPE_PARAMETERS);
+    let parameters = self.parameters;
+    let parameters = &parameters[..count];
+
+    match command {
+      b'm' => self.select_graphic_rendition(parameters),
+      b'J' if parameters.first() == Some(&2) => self.clear(),
+      b'H' => {
+        let row = usize::from(parameters.first().copied().unwrap_or(1).max(1)) - 1;
+        let column = usize::from(parameters.get(1).copied().unwrap_or(1).max(1)) - 1;
+        self.row = row.min(self.rows.saturating_sub(1));
+        self.column = column.min(self.columns.saturating_sub(1));
+      },
+      _ => {},
+    }
+  }
+
+  /// Handles the "Select Graphic Rendition" control sequence, i.e. changes colors.
+  fn select_graphic_rendition(&mut self, parameters: &[u16]) {
+    if parameters.is_empty() {
+      self.foreground = Color::DEFAULT_FOREGROUND;
+      self.background = Color::DEFAULT_BACKGROUND;
+      return;
+    }
+
+    let mut index = 0;
+    while index < parameters.len() {
+      let parameter = parameters[index];
+      match parameter {
+        0 => {
+          self.foreground = Color::DEFAULT_FOREGROUND;
+          self.background = Color::DEFAULT_BACKGROUND;
+        },
+        30..=37 => self.foreground = Color::PALETTE[usize::from(parameter - 30)],
+        90..=97 => self.foreground = Color::PALETTE[usize::from(parameter - 90 + 8)],
+        40..=47 => self.background = Color::PALETTE[usize::from(parameter - 40)],
+        100..=107 => self.background = Color::PALETTE[usize::from(parameter - 100 + 8)],
+        39 => self.foreground = Color::DEFAULT_FOREGROUND,
+        49 => self.background = Color::DEFAULT_BACKGROUND,
+        38 | 48 => {
+          /// Converts a parameter into a color component, saturating at the maximum.
+          fn component(value: u16) -> u8 { u8::try_from(value).unwrap_or(u8::MAX) }
+
+          let color = match parameters.get(index + 1) {
+            Some(5) => {
+              index += 2;
+              parameters
+                .get(index)
+                .map(|color| Color::from_256_palette(component(*color)))
+            },
+            Some(2) => {
+              index += 4;
+              parameters
+                .get(index - 2..=index)
+                .map(|rgb| Color::from_rgb(component(rgb[0]), component(rgb[1]), component(rgb[2])))
+            },
+            _ => None,
+          };
+
+          if let Some(color) = color {
+            if parameter == 38 {
+              self.foreground = color;
+            } else {
+              self.background = color;
+            }
+          }
+        },
+        _ => {},
+      }
+      index += 1;
+    }
+  }
+}
+
+impl core::fmt::Write for TextConsole {
+  fn write_str(&mut self, string: &str) -> core::fmt::Result {
+    for byte in string.bytes() {
+      self.write_byte(byte);
+    }
+    Ok(())
+  }
+}
+
+/// The global text console. It is [`None`] until a driver has called [`initialize`].
+static CONSOLE: spin::Mutex<Option<TextConsole>> = spin::Mutex::new(None);
+
+/// Sets up the global text console on `framebuffer`. This function is called by drivers
+/// that provide a framebuffer. Framebuffers that are too small to hold a single character
+/// are rejected.
+pub fn initialize(framebuffer: &'static mut dyn Framebuffer) {
+  let (width, height) = (framebuffer.width(), framebuffer.height());
+  let Some(console) = TextConsole::new(framebuffer) else {
+    log::warn!("Framebuffer of {width}x{height} pixels is too small for the text console");
+    return;
+  };
+  crate::arch::without_interrupts(|| *CONSOLE.lock() = Some(console));
+}
+
+/// Runs `function` with the global text console if it is available and makes all output
+/// visible afterwards. If no console is available, `function` is not run.
+///
+/// #### Errors
+///
+/// If `function` returns an error, the error is returned.
+pub fn with(function: impl FnOnce(&mut TextConsole) -> core::fmt::Result) -> core::fmt::Result {
+  crate::arch::without_interrupts(|| run(CONSOLE.lock().as_mut(), function))
+}
+
+/// Runs `function` like [`with`], but only if the global text console is not in use;
+/// otherwise, `function` is not run. This function may be called from traps.
+///
+/// #### Errors
+///
+/// If `function` returns an error, the error is returned.
+pub fn try_with(function: impl FnOnce(&mut TextConsole) -> core::fmt::Result) -> core::fmt::Result {
+  crate::arch::without_interrupts(|| {
+    CONSOLE
+      .try_lock()
+      .map_or(Ok(()), |mut console| run(console.as_mut(), function))
+  })
+}
+
+/// Runs `function` with `console` if it is not [`None`] and makes all output visible
+/// afterwards.
+fn run(
+  console: Option<&mut TextConsole>,
+  function: impl FnOnce(&mut TextConsole) -> core::fmt::Result,
+) -> core::fmt::Result {
+  console.map_or(Ok(()), |console| {
+    let result = function(console);
+    console.flush();
+    result
+  })
+}
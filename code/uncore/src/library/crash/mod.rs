@@ -0,0 +1,393 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module writes a post-mortem crash dump to the console when the kernel panics,
+//! e.g. because of an unhandled trap. `uncore-helper decode-dump` turns a dump in the
+//! output of QEMU back into a readable report.
+//!
+//! The dump is a sequence of sections, each of which starts with a four-byte tag and its
+//! length as a little-endian `u32`:
+//!
+//! | Tag    | Contents                                                                  |
+//! | ------ | ------------------------------------------------------------------------- |
+//! | `INFO` | `key: value` lines about the crash and the state of the kernel            |
+//! | `REGS` | the registers `x0` to `x31`, `pc`, `sstatus`, `scause` and `stval` as `u64` |
+//! | `STCK` | the address of the stack pointer as `u64`, followed by the stack above it |
+//! | `BTRC` | the return addresses of the backtrace as `u64`                            |
+//! | `LOGS` | the most recent records of the kernel log (see [`crate::library::log::records`]) |
+//!
+//! So that it survives the serial line and mixed output, the dump is written as text:
+//! after the line [`BEGIN`], every line that starts with [`LINE_PREFIX`] holds up to
+//! [`BYTES_PER_LINE`] bytes in hexadecimal. The dump ends with a line that starts with
+//! [`END`], followed by the number of bytes and their CRC-32 in hexadecimal.
+
+#[cfg(test)]
+mod tests;
+
+use core::{
+  fmt::Write,
+  sync::atomic::{
+    AtomicBool,
+    Ordering,
+  },
+};
+
+/// The line that starts a crash dump.
+pub const BEGIN: &str = "=== unCORE crash dump v1 ===";
+/// The prefix of lines that hold the data of a crash dump.
+pub const LINE_PREFIX: &str = "CD ";
+/// The prefix of the line that ends a crash dump.
+pub const END: &str = "=== end of crash dump";
+/// The number of bytes per line of a crash dump.
+pub const BYTES_PER_LINE: usize = 32;
+
+/// The maximum number of bytes of the stack that a dump contains.
+const MAXIMUM_STACK: usize = 4 * 1024;
+/// The maximum number of return addresses that a dump contains.
+const MAXIMUM_FRAMES: usize = 64;
+
+/// The tags of the sections of a dump.
+mod section {
+  /// Information about the crash and the state of the kernel
+  pub const INFORMATION: [u8; 4] = *b"INFO";
+  /// The registers
+  pub const REGISTERS: [u8; 4] = *b"REGS";
+  /// The stack
+  pub const STACK: [u8; 4] = *b"STCK";
+  /// The backtrace
+  pub const BACKTRACE: [u8; 4] = *b"BTRC";
+  /// The most recent records of the kernel log
+  pub const LOG: [u8; 4] = *b"LOGS";
+}
+
+/// The state of a hart when the kernel crashed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Registers {
+  /// The general-purpose registers `x0` to `x31`
+  pub general: [usize; 32],
+  /// The program counter
+  pub pc:      usize,
+  /// The status register (`sstatus`), or zero if the kernel did not trap
+  pub status:  usize,
+  /// The cause of the trap (`scause`), or zero if the kernel did not trap
+  pub cause:   usize,
+  /// The trap value (`stval`), or zero if the kernel did not trap
+  pub value:   usize,
+}
+
+impl Registers {
+  /// Index of the frame pointer in [`Registers::general`].
+  const FRAME_POINTER: usize = 8;
+  /// Index of the stack pointer in [`Registers::general`].
+  const STACK_POINTER: usize = 2;
+}
+
+/// The registers of the trap that the kernel cannot handle, see [`record_trap`].
+static TRAP: spin::Mutex<Option<Registers>> = spin::Mutex::new(None);
+/// Whether a dump has been written, so that a crash while dumping does not dump again.
+static DUMPED: AtomicBool = AtomicBool::new(false);
+
+/// Records the state of the hart when a trap occurred that the kernel cannot handle. The
+/// dump that follows shows this state instead of the one of the panic handler.
+pub fn record_trap(registers: &Registers) {
+  if let Some(mut trap) = TRAP.try_lock() {
+    *trap = Some(*registers);
+  }
+}
+
+/// CRC-32 (IEEE 802.3) as used by zlib and PNG.
+#[derive(Debug, Copy, Clone)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+  /// Creates the checksum of no bytes.
+  #[must_use]
+  pub const fn new() -> Self { Self(!0) }
+
+  /// Adds `bytes` to the checksum.
+  pub fn update(&mut self, bytes: &[u8]) {
+    for &byte in bytes {
+      self.0 ^= u32::from(byte);
+      for _ in 0..8 {
+        self.0 = if self.0 & 1 == 1 {
+          (self.0 >> 1) ^ 0xEDB8_8320
+        } else {
+          self.0 >> 1
+        };
+      }
+    }
+  }
+
+  /// Returns the checksum of the bytes added so far.
+  #[must_use]
+  pub const fn value(self) -> u32 { !self.0 }
+}
+
+impl Default for Crc32 {
+  fn default() -> Self { Self::new() }
+}
+
+/// Counts the bytes that are written to it, so that the length of text sections is known
+/// before they are written.
+struct Counter(usize);
+
+impl Write for Counter {
+  fn write_str(&mut self, out: &str) -> core::fmt::Result {
+    self.0 += out.len();
+    Ok(())
+  }
+}
+
+/// Writes at most `remaining` bytes to a dump and drops the rest.
+struct Limited<'a, W: Write> {
+  /// The dump
+  encoder:   &'a mut Encoder<W>,
+  /// The number of bytes that may still be written
+  remaining: usize,
+}
+
+impl<W: Write> Write for Limited<'_, W> {
+  fn write_str(&mut self, out: &str) -> core::fmt::Result {
+    let bytes = &out.as_bytes()[..out.len().min(self.remaining)];
+    self.remaining -= bytes.len();
+    self.encoder.write_bytes(bytes)
+  }
+}
+
+/// Writes the bytes of a dump to `output` in the framing described in the [module
+/// documentation](self).
+#[derive(Debug)]
+pub struct Encoder<W: Write> {
+  /// Where the text of the dump goes
+  output: W,
+  /// The checksum of the bytes written so far
+  crc:    Crc32,
+  /// The number of bytes written so far
+  length: usize,
+}
+
+impl<W: Write> Encoder<W> {
+  /// Starts a dump on `output`.
+  ///
+  /// #### Errors
+  ///
+  /// Fails if `output` fails.
+  pub fn new(mut output: W) -> Result<Self, core::fmt::Error> {
+    writeln!(output, "\n{BEGIN}")?;
+    Ok(Self {
+      output,
+      crc: Crc32::new(),
+      length: 0,
+    })
+  }
+
+  /// Appends `bytes` to the dump.
+  ///
+  /// #### Errors
+  ///
+  /// Fails if the output fails.
+  pub fn write_bytes(&mut self, bytes: &[u8]) -> core::fmt::Result {
+    for &byte in bytes {
+      if self.length % BYTES_PER_LINE == 0 {
+        if self.length != 0 {
+          self.output.write_char('\n')?;
+        }
+        self.output.write_str(LINE_PREFIX)?;
+      }
+      write!(self.output, "{byte:02x}")?;
+      self.length += 1;
+    }
+    self.crc.update(bytes);
+    Ok(())
+  }
+
+  /// Appends the header of a section with `tag` and `length` bytes to the dump.
+  ///
+  /// #### Errors
+  ///
+  /// Fails if the output fails or `length` does not fit into a `u32`.
+  pub fn section(&mut self, tag: [u8; 4], length: usize) -> core::fmt::Result {
+    let length = u32::try_from(length).map_err(|_| core::fmt::Error)?;
+    self.write_bytes(&tag)?;
+    self.write_bytes(&length.to_le_bytes())
+  }
+
+  /// Appends a section with `tag` whose contents `text` writes. `text` is called twice,
+  /// first to determine the length of the section; if it writes a different number of
+  /// bytes the second time, the contents are truncated or padded with spaces.
+  ///
+  /// #### Errors
+  ///
+  /// Fails if the output or `text` fails.
+  pub fn text_section(
+    &mut self,
+    tag: [u8; 4],
+    text: impl Fn(&mut dyn Write) -> core::fmt::Result,
+  ) -> core::fmt::Result {
+    let mut counter = Counter(0);
+    text(&mut counter)?;
+    self.section(tag, counter.0)?;
+
+    let mut limited = Limited {
+      encoder:   self,
+      remaining: counter.0,
+    };
+    text(&mut limited)?;
+    for _ in 0..limited.remaining {
+      self.write_bytes(b" ")?;
+    }
+    Ok(())
+  }
+
+  /// Ends the dump and returns the output.
+  ///
+  /// #### Errors
+  ///
+  /// Fails if the output fails.
+  pub fn finish(mut self) -> Result<W, core::fmt::Error> {
+    writeln!(
+      self.output,
+      "\n{END}: {} bytes, CRC-32 {:08x} ===",
+      self.length,
+      self.crc.value()
+    )?;
+    Ok(self.output)
+  }
+}
+
+impl<W: Write> Write for Encoder<W> {
+  fn write_str(&mut self, out: &str) -> core::fmt::Result { self.write_bytes(out.as_bytes()) }
+}
+
+/// Writes a dump of the crash described by `info` to the console. Only the first crash
+/// is dumped.
+#[inline(never)]
+pub fn dump(info: &core::panic::PanicInfo) {
+  if DUMPED.swap(true, Ordering::AcqRel) {
+    return;
+  }
+  let Some(uart) = crate::arch::console() else {
+    return;
+  };
+
+  let trap = TRAP.try_lock().and_then(|mut trap| trap.take());
+  let registers = trap.unwrap_or_else(|| {
+    let general = crate::arch::registers();
+    Registers {
+      general,
+      pc: general[1],
+      status: 0,
+      cause: 0,
+      value: 0,
+    }
+  });
+  if write(uart, info, &registers, trap.is_some()).is_err() {
+    ::log::error!("Could not write the crash dump");
+  }
+}
+
+/// Returns the number of bytes of the stack above `stack_pointer` that a dump of `hart`
+/// contains. Only the stack of the hart is dumped, as the memory around a corrupted stack
+/// pointer may not exist.
+fn stack_length(stack_pointer: usize, hart: usize) -> usize {
+  let stack_size = crate::arch::hart_stack_size();
+  let stack_end = hart
+    .checked_mul(stack_size)
+    .and_then(|offset| crate::arch::stack_end().checked_sub(offset))
+    .unwrap_or(0);
+  if stack_pointer < stack_end && stack_end - stack_pointer <= stack_size {
+    (stack_end - stack_pointer).min(MAXIMUM_STACK)
+  } else {
+    0
+  }
+}
+
+/// Writes the dump of the crash described by `info` and `registers` to `output`.
+/// `trapped` tells whether the registers are the ones of a trap the kernel could not
+/// handle.
+fn write(
+  output: impl Write,
+  info: &core::panic::PanicInfo,
+  registers: &Registers,
+  trapped: bool,
+) -> core::fmt::Result {
+  let mut encoder = Encoder::new(output)?;
+
+  let uptime = crate::library::time::uptime();
+  let heap = crate::library::mem::heap::Heap::try_statistics();
+  encoder.text_section(section::INFORMATION, |text| {
+    writeln!(
+      text,
+      "reason: {}",
+      if trapped { "unhandled trap" } else { "panic" }
+    )?;
+    writeln!(text, "message: {}", info.message())?;
+    if let Some(location) = info.location() {
+      writeln!(text, "location: {location}")?;
+    }
+    writeln!(
+      text,
+      "kernel: {}",
+      crate::library::log::KernelInformation::get_kernel_version()
+    )?;
+    writeln!(text, "hart: {}", crate::arch::hart())?;
+    write!(text, "online harts:")?;
+    for hart in crate::arch::online_harts() {
+      write!(text, " {hart}")?;
+    }
+    writeln!(text)?;
+    writeln!(
+      text,
+      "uptime: {}.{:06}s",
+      uptime.as_secs(),
+      uptime.subsec_micros()
+    )?;
+    if let Some(heap) = &heap {
+      writeln!(
+        text,
+        "heap: {} bytes, {} used, {} allocated, {} largest free",
+        heap.size, heap.used, heap.allocated, heap.largest_free
+      )?;
+    }
+    writeln!(text, "log records dropped: {}", crate::library::log::dropped())?;
+    for (cause, counts) in crate::arch::trap_counts() {
+      writeln!(text, "traps ({cause}): {}", counts.iter().sum::<u64>())?;
+    }
+    Ok(())
+  })?;
+
+  encoder.section(section::REGISTERS, 36 * 8)?;
+  for register in registers
+    .general
+    .iter()
+    .chain([registers.pc, registers.status, registers.cause, registers.value].iter())
+  {
+    encoder.write_bytes(&(*register as u64).to_le_bytes())?;
+  }
+
+  let stack_pointer = registers.general[Registers::STACK_POINTER];
+  let stack_length = stack_length(stack_pointer, crate::arch::hart());
+  encoder.section(section::STACK, 8 + stack_length)?;
+  encoder.write_bytes(&(stack_pointer as u64).to_le_bytes())?;
+  if stack_length != 0 {
+    encoder.write_bytes(unsafe { core::slice::from_raw_parts(stack_pointer as *const u8, stack_length) })?;
+  }
+
+  let frame_pointer = registers.general[Registers::FRAME_POINTER];
+  let frames = crate::arch::backtrace_from(frame_pointer)
+    .take(MAXIMUM_FRAMES)
+    .count();
+  encoder.section(section::BACKTRACE, frames * 8)?;
+  for return_address in crate::arch::backtrace_from(frame_pointer).take(frames) {
+    encoder.write_bytes(&(return_address as u64).to_le_bytes())?;
+  }
+
+  let end = crate::library::log::next_sequence();
+  encoder.text_section(section::LOG, |text| {
+    for entry in crate::library::log::records(0, end) {
+      writeln!(text, "{entry}")?;
+    }
+    Ok(())
+  })?;
+
+  encoder.finish().map(|_| ())
+}
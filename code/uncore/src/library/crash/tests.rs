@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unit tests of the encoding of crash dumps.
+
+use alloc::{
+  format,
+  string::String,
+  vec::Vec,
+};
+
+use super::{
+  stack_length,
+  Crc32,
+  Encoder,
+  BEGIN,
+  END,
+  LINE_PREFIX,
+  MAXIMUM_STACK,
+};
+
+#[test_case]
+fn dumps_are_framed_and_checksummed() {
+  let mut crc = Crc32::new();
+  crc.update(b"123456789");
+  assert_eq!(crc.value(), 0xCBF4_3926);
+
+  let mut encoder = Encoder::new(String::new()).unwrap();
+  encoder
+    .text_section(*b"INFO", |text| write!(text, "reason: {}", 0xBAD))
+    .unwrap();
+  encoder.write_bytes(&[0xAB; 40]).unwrap();
+  let output = encoder.finish().unwrap();
+
+  let lines: Vec<&str> = output.lines().collect();
+  assert_eq!(lines[1], BEGIN);
+  // The section header (tag and length) and the text, followed by the raw bytes
+  assert_eq!(
+    lines[2],
+    format!(
+      "{LINE_PREFIX}494e464f0c000000726561736f6e3a2032393839{}",
+      "ab".repeat(12)
+    )
+  );
+  assert_eq!(lines[3], format!("{LINE_PREFIX}{}", "ab".repeat(28)));
+
+  let mut expected = Crc32::new();
+  expected.update(b"INFO\x0c\0\0\0reason: 2989");
+  expected.update(&[0xAB; 40]);
+  assert_eq!(
+    lines[4],
+    format!("{END}: 60 bytes, CRC-32 {:08x} ===", expected.value())
+  );
+}
+
+#[test_case]
+fn only_the_stack_of_the_crashed_hart_is_dumped() {
+  let (end, size) = (crate::arch::stack_end(), crate::arch::hart_stack_size());
+  assert_eq!(stack_length(end - 64, 0), 64);
+  assert_eq!(stack_length(end - size, 0), MAXIMUM_STACK);
+  assert_eq!(stack_length(end - size - 64, 1), 64);
+
+  // Stack pointers outside of the stack of the hart
+  assert_eq!(stack_length(end - size - 64, 0), 0);
+  assert_eq!(stack_length(end, 0), 0);
+  assert_eq!(stack_length(0, 0), 0);
+  assert_eq!(stack_length(usize::MAX, 0), 0);
+}
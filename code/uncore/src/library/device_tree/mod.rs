@@ -0,0 +1,562 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains a parser for flattened device trees (FDT), which the firmware
+//! hands to the kernel on startup. The device tree describes the hardware of the machine,
+//! e.g. which devices exist, where their registers are located, and which interrupts they
+//! use.
+//!
+//! The format is described in chapter 5 ("Flattened Devicetree (DTB) Format") of the
+//! Devicetree specification, see <https://www.devicetree.org/specifications/>.
+//!
+//! The parser does not allocate, because the device tree is needed before the kernel
+//! heap is available. All values borrow from the device tree blob.
+
+/// The magic value at the beginning of every device tree blob.
+const MAGIC: u32 = 0xD00D_FEED;
+/// The oldest version of the format this parser understands (the version of the format
+/// the blob is compatible with, `last_comp_version`).
+const COMPATIBLE_VERSION: u32 = 16;
+/// The size of the device tree header in bytes.
+const HEADER_SIZE: usize = 40;
+/// The maximum depth of nodes this parser supports.
+const MAXIMUM_DEPTH: usize = 16;
+
+/// The tokens of the structure block, see section 5.4.1 ("Lexical structure") of the
+/// specification.
+mod token {
+  /// Marks the beginning of a node's representation.
+  pub const BEGIN_NODE: u32 = 1;
+  /// Marks the end of a node's representation.
+  pub const END_NODE: u32 = 2;
+  /// Marks the beginning of the representation of one property.
+  pub const PROPERTY: u32 = 3;
+  /// Should be ignored by any program parsing the device tree.
+  pub const NOP: u32 = 4;
+}
+
+/// The default value of `#address-cells` if a node does not specify it.
+const DEFAULT_ADDRESS_CELLS: u32 = 2;
+/// The default value of `#size-cells` if a node does not specify it.
+const DEFAULT_SIZE_CELLS: u32 = 1;
+
+/// Errors that may occur when parsing a device tree blob.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+  /// The address of the blob is not aligned or is the null address.
+  InvalidAddress,
+  /// The blob does not start with [`MAGIC`].
+  InvalidMagic(u32),
+  /// The blob uses a version of the format that is not supported.
+  UnsupportedVersion(u32),
+  /// The header describes blocks that do not fit into the blob.
+  Truncated,
+}
+
+impl core::fmt::Display for Error {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::InvalidAddress => write!(f, "invalid device tree address"),
+      Self::InvalidMagic(magic) => write!(f, "invalid device tree magic value {magic:#X}"),
+      Self::UnsupportedVersion(version) => write!(f, "unsupported device tree version {version}"),
+      Self::Truncated => write!(f, "device tree is truncated"),
+    }
+  }
+}
+
+/// Reads the big-endian 32bit value at `offset` in `data`.
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+  let bytes = data.get(offset..offset + 4)?;
+  Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Reads the nul-terminated string at `offset` in `data`.
+fn read_string(data: &[u8], offset: usize) -> Option<&str> {
+  let data = data.get(offset..)?;
+  let length = data.iter().position(|&byte| byte == 0)?;
+  core::str::from_utf8(&data[..length]).ok()
+}
+
+/// Rounds `offset` up to the next multiple of four, as all tokens are 32bit aligned.
+const fn align(offset: usize) -> usize { (offset + 3) & !3 }
+
+/// A parsed flattened device tree.
+#[derive(Debug, Copy, Clone)]
+pub struct DeviceTree<'a> {
+  /// The structure block
+  structure: &'a [u8],
+  /// The strings block
+  strings:   &'a [u8],
+}
+
+impl<'a> DeviceTree<'a> {
+  /// Parses the header of the device tree blob in `data`.
+  ///
+  /// #### Errors
+  ///
+  /// If `data` does not contain a valid device tree blob, an error is returned.
+  pub fn from_bytes(data: &'a [u8]) -> Result<Self, Error> {
+    let header = |index: usize| read_u32(data, index * 4).ok_or(Error::Truncated);
+
+    let magic = header(0)?;
+    if magic != MAGIC {
+      return Err(Error::InvalidMagic(magic));
+    }
+
+    let compatible_version = header(6)?;
+    if compatible_version > COMPATIBLE_VERSION {
+      return Err(Error::UnsupportedVersion(compatible_version));
+    }
+
+    let total_size = header(1)? as usize;
+    let structure_offset = header(2)? as usize;
+    let strings_offset = header(3)? as usize;
+    let strings_size = header(8)? as usize;
+    let structure_size = header(9)? as usize;
+
+    let data = data.get(..total_size).ok_or(Error::Truncated)?;
+    Ok(Self {
+      structure: data
+        .get(structure_offset..structure_offset + structure_size)
+        .ok_or(Error::Truncated)?,
+      strings:   data
+        .get(strings_offset..strings_offset + strings_size)
+        .ok_or(Error::Truncated)?,
+    })
+  }
+
+  /// Returns an iterator over all nodes of the tree in depth-first order, starting with
+  /// the root node.
+  #[must_use]
+  pub const fn nodes(&self) -> Nodes<'a> {
+    Nodes {
+      tree:    *self,
+      offset:  0,
+      depth:   0,
+      parents: [Context::ROOT; MAXIMUM_DEPTH],
+    }
+  }
+
+  /// Returns the node at `path` (e.g. `/chosen` or `/soc/serial@10000000`). A path
+  /// component without a unit address matches a node with any unit address.
+  #[must_use]
+  pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+    let components = path.split('/').filter(|component| !component.is_empty());
+    let length = components.clone().count();
+    if length == 0 {
+      return self.nodes().next();
+    }
+
+    // The number of path components the current branch of the tree matches
+    let mut matched = 0;
+    for node in self.nodes().skip(1) {
+      if node.depth <= matched {
+        matched = node.depth - 1;
+      }
+
+      if node.depth == matched + 1
+        && components
+          .clone()
+          .nth(matched)
+          .is_some_and(|component| node.matches_name(component))
+      {
+        matched += 1;
+        if matched == length {
+          return Some(node);
+        }
+      }
+    }
+
+    None
+  }
+
+  /// Returns the node `path_or_alias` refers to. If it does not start with a slash, it is
+  /// looked up in the `/aliases` node first.
+  #[must_use]
+  pub fn resolve(&self, path_or_alias: &str) -> Option<Node<'a>> {
+    if path_or_alias.starts_with('/') {
+      return self.find_node(path_or_alias);
+    }
+
+    let path = self.find_node("/aliases")?.property(path_or_alias)?.as_str()?;
+    self.find_node(path)
+  }
+
+  /// Returns the name of the property stored at `offset` in the strings block.
+  fn string(&self, offset: usize) -> &'a str { read_string(self.strings, offset).unwrap_or("") }
+
+  /// Reads the token at `offset` in the structure block.
+  fn token(&self, offset: usize) -> Option<u32> { read_u32(self.structure, offset) }
+}
+
+impl DeviceTree<'static> {
+  /// Parses the device tree blob located at `address`.
+  ///
+  /// #### Errors
+  ///
+  /// If there is no valid device tree blob at `address`, an error is returned.
+  ///
+  /// #### Safety
+  ///
+  /// `address` must point to memory that stays valid and unmodified for the rest of the
+  /// kernel's lifetime.
+  pub unsafe fn from_address(address: usize) -> Result<Self, Error> {
+    if address == 0 || address & 7 != 0 {
+      return Err(Error::InvalidAddress);
+    }
+
+    let header = core::slice::from_raw_parts(address as *const u8, HEADER_SIZE);
+    if read_u32(header, 0) != Some(MAGIC) {
+      return Err(Error::InvalidMagic(read_u32(header, 0).unwrap_or(0)));
+    }
+
+    let total_size = read_u32(header, 4).ok_or(Error::Truncated)? as usize;
+    Self::from_bytes(core::slice::from_raw_parts(address as *const u8, total_size))
+  }
+}
+
+/// Values that nodes inherit from their parent node.
+#[derive(Debug, Copy, Clone)]
+struct Context {
+  /// The number of cells used to encode addresses in the children's `reg` properties
+  address_cells:    u32,
+  /// The number of cells used to encode sizes in the children's `reg` properties
+  size_cells:       u32,
+  /// The `phandle` of the interrupt parent the children inherit
+  interrupt_parent: Option<u32>,
+}
+
+impl Context {
+  /// The context of the root node (which has no parent).
+  const ROOT: Self = Self {
+    address_cells:    DEFAULT_ADDRESS_CELLS,
+    size_cells:       DEFAULT_SIZE_CELLS,
+    interrupt_parent: None,
+  };
+}
+
+/// An iterator over the nodes of a device tree, see [`DeviceTree::nodes`].
+#[derive(Debug, Clone)]
+pub struct Nodes<'a> {
+  /// The tree that is iterated over
+  tree:    DeviceTree<'a>,
+  /// The current offset into the structure block
+  offset:  usize,
+  /// The depth of the next node
+  depth:   usize,
+  /// The contexts of all nodes on the path from the root to the next node
+  parents: [Context; MAXIMUM_DEPTH],
+}
+
+impl<'a> Iterator for Nodes<'a> {
+  type Item = Node<'a>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      match self.tree.token(self.offset)? {
+        token::BEGIN_NODE => break,
+        token::END_NODE => {
+          self.depth = self.depth.checked_sub(1)?;
+          self.offset += 4;
+        },
+        token::PROPERTY => {
+          let length = self.tree.token(self.offset + 4)? as usize;
+          self.offset = align(self.offset + 12 + length);
+        },
+        token::NOP => self.offset += 4,
+        // The end of the structure block or an invalid token
+        _ => return None,
+      }
+    }
+
+    if self.depth >= MAXIMUM_DEPTH {
+      return None;
+    }
+
+    let name = read_string(self.tree.structure, self.offset + 4)?;
+    let properties_offset = align(self.offset + 4 + name.len() + 1);
+    let parent = self.parents[self.depth];
+    let mut node = Node {
+      tree: self.tree,
+      name,
+      depth: self.depth,
+      properties_offset,
+      parent,
+    };
+
+    let context = Context {
+      address_cells:    node
+        .property("#address-cells")
+        .and_then(|property| property.as_u32())
+        .unwrap_or(DEFAULT_ADDRESS_CELLS),
+      size_cells:       node
+        .property("#size-cells")
+        .and_then(|property| property.as_u32())
+        .unwrap_or(DEFAULT_SIZE_CELLS),
+      interrupt_parent: node
+        .property("interrupt-parent")
+        .and_then(|property| property.as_u32())
+        .or(parent.interrupt_parent),
+    };
+    node.parent.interrupt_parent = context.interrupt_parent;
+
+    self.depth += 1;
+    if let Some(slot) = self.parents.get_mut(self.depth) {
+      *slot = context;
+    }
+    self.offset = properties_offset;
+    Some(node)
+  }
+}
+
+/// A single node of the device tree.
+#[derive(Debug, Copy, Clone)]
+pub struct Node<'a> {
+  /// The tree this node belongs to
+  tree:              DeviceTree<'a>,
+  /// The name of the node, including the unit address
+  name:              &'a str,
+  /// The depth of the node (the root node has depth zero)
+  depth:             usize,
+  /// The offset of the first property in the structure block
+  properties_offset: usize,
+  /// The values this node inherits from its parent
+  parent:            Context,
+}
+
+impl<'a> Node<'a> {
+  /// Returns the name of this node, including the unit address (e.g. `uart@10000000`).
+  #[must_use]
+  pub const fn name(&self) -> &'a str { self.name }
+
+  /// Returns the depth of this node in the tree; the root node has depth zero.
+  #[must_use]
+  pub const fn depth(&self) -> usize { self.depth }
+
+  /// Checks whether `name` is the name of this node. If `name` does not contain a unit
+  /// address, the unit address of this node is ignored.
+  fn matches_name(&self, name: &str) -> bool {
+    self.name == name || (!name.contains('@') && self.name.split('@').next() == Some(name))
+  }
+
+  /// Returns an iterator over all properties of this node.
+  #[must_use]
+  pub const fn properties(&self) -> Properties<'a> {
+    Properties {
+      tree:   self.tree,
+      offset: self.properties_offset,
+    }
+  }
+
+  /// Returns the property called `name`, if this node has such a property.
+  #[must_use]
+  pub fn property(&self, name: &str) -> Option<Property<'a>> {
+    self.properties().find(|property| property.name == name)
+  }
+
+  /// Returns an iterator over the strings in the `compatible` property of this node.
+  pub fn compatible(&self) -> impl Iterator<Item = &'a str> {
+    self
+      .property("compatible")
+      .into_iter()
+      .flat_map(|property| property.strings())
+  }
+
+  /// Checks whether this node is compatible with `compatible`.
+  #[must_use]
+  pub fn is_compatible(&self, compatible: &str) -> bool { self.compatible().any(|entry| entry == compatible) }
+
+  /// Checks whether this node is enabled, i.e. whether its `status` property is absent or
+  /// `okay`.
+  #[must_use]
+  pub fn is_enabled(&self) -> bool {
+    self
+      .property("status")
+      .and_then(|property| property.as_str())
+      .is_none_or(|status| status == "okay" || status == "ok")
+  }
+
+  /// Returns the `phandle` of this node, if it has one.
+  #[must_use]
+  pub fn phandle(&self) -> Option<u32> {
+    self
+      .property("phandle")
+      .or_else(|| self.property("linux,phandle"))
+      .and_then(|property| property.as_u32())
+  }
+
+  /// Returns the `phandle` of this node's interrupt parent, which may be inherited from
+  /// its parent nodes.
+  #[must_use]
+  pub const fn interrupt_parent(&self) -> Option<u32> { self.parent.interrupt_parent }
+
+  /// Returns an iterator over the cells of the `interrupts` property of this node.
+  pub fn interrupts(&self) -> impl Iterator<Item = u32> + 'a {
+    self
+      .property("interrupts")
+      .into_iter()
+      .flat_map(|property| property.cells())
+  }
+
+  /// Returns an iterator over the `(address, size)` pairs in the `reg` property of this
+  /// node.
+  pub fn reg(&self) -> impl Iterator<Item = (u64, u64)> + 'a {
+    let address_cells = self.parent.address_cells as usize;
+    let size_cells = self.parent.size_cells as usize;
+    let entry_size = (address_cells + size_cells) * 4;
+    let value = self.property("reg").map_or(&[][..], |property| property.value);
+
+    (0..value.len().checked_div(entry_size).unwrap_or(0)).filter_map(move |index| {
+      let entry = &value[index * entry_size..(index + 1) * entry_size];
+      Some((
+        read_cells(entry, 0, address_cells)?,
+        read_cells(entry, address_cells * 4, size_cells)?,
+      ))
+    })
+  }
+
+  /// Returns the address of the first entry of the `reg` property of this node.
+  #[must_use]
+  pub fn base_address(&self) -> Option<usize> {
+    self
+      .reg()
+      .next()
+      .and_then(|(address, _)| usize::try_from(address).ok())
+  }
+}
+
+impl PartialEq for Node<'_> {
+  fn eq(&self, other: &Self) -> bool {
+    core::ptr::eq(self.tree.structure, other.tree.structure)
+      && self.properties_offset == other.properties_offset
+  }
+}
+
+impl Eq for Node<'_> {}
+
+/// Reads a value consisting of `count` cells (at most two) starting at `offset`.
+fn read_cells(data: &[u8], offset: usize, count: usize) -> Option<u64> {
+  match count {
+    0 => Some(0),
+    1 => read_u32(data, offset).map(u64::from),
+    2 => Some(u64::from(read_u32(data, offset)?) << 32 | u64::from(read_u32(data, offset + 4)?)),
+    _ => None,
+  }
+}
+
+/// An iterator over the properties of a node, see [`Node::properties`].
+#[derive(Debug, Clone)]
+pub struct Properties<'a> {
+  /// The tree the node belongs to
+  tree:   DeviceTree<'a>,
+  /// The current offset into the structure block
+  offset: usize,
+}
+
+impl<'a> Iterator for Properties<'a> {
+  type Item = Property<'a>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      match self.tree.token(self.offset)? {
+        token::NOP => self.offset += 4,
+        token::PROPERTY => break,
+        _ => return None,
+      }
+    }
+
+    let length = self.tree.token(self.offset + 4)? as usize;
+    let name_offset = self.tree.token(self.offset + 8)? as usize;
+    let value = self
+      .tree
+      .structure
+      .get(self.offset + 12..self.offset + 12 + length)?;
+
+    self.offset = align(self.offset + 12 + length);
+    Some(Property {
+      name: self.tree.string(name_offset),
+      value,
+    })
+  }
+}
+
+/// A single property of a node.
+#[derive(Debug, Copy, Clone)]
+pub struct Property<'a> {
+  /// The name of the property
+  name:  &'a str,
+  /// The raw value of the property
+  value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+  /// Returns the name of this property.
+  #[must_use]
+  pub const fn name(&self) -> &'a str { self.name }
+
+  /// Returns the raw value of this property.
+  #[must_use]
+  pub const fn value(&self) -> &'a [u8] { self.value }
+
+  /// Interprets the value as a single 32bit cell.
+  #[must_use]
+  pub fn as_u32(&self) -> Option<u32> {
+    if self.value.len() == 4 {
+      read_u32(self.value, 0)
+    } else {
+      None
+    }
+  }
+
+  /// Interprets the value as a 64bit value, which may be encoded in one or two cells.
+  #[must_use]
+  pub fn as_u64(&self) -> Option<u64> {
+    match self.value.len() {
+      4 => read_cells(self.value, 0, 1),
+      8 => read_cells(self.value, 0, 2),
+      _ => None,
+    }
+  }
+
+  /// Interprets the value as a single string.
+  #[must_use]
+  pub fn as_str(&self) -> Option<&'a str> { read_string(self.value, 0) }
+
+  /// Interprets the value as a list of strings.
+  pub fn strings(&self) -> impl Iterator<Item = &'a str> {
+    self
+      .value
+      .split(|&byte| byte == 0)
+      .filter(|string| !string.is_empty())
+      .filter_map(|string| core::str::from_utf8(string).ok())
+  }
+
+  /// Interprets the value as a list of 32bit cells.
+  pub fn cells(&self) -> impl Iterator<Item = u32> + 'a {
+    let value = self.value;
+    (0..value.len() / 4).filter_map(move |index| read_u32(value, index * 4))
+  }
+}
+
+/// The device tree the firmware handed to the kernel.
+static DEVICE_TREE: spin::Once<DeviceTree<'static>> = spin::Once::new();
+
+/// Parses the device tree blob at `address` and makes it available via [`get`].
+///
+/// #### Errors
+///
+/// If there is no valid device tree blob at `address`, an error is returned.
+///
+/// #### Safety
+///
+/// `address` must point to memory that stays valid and unmodified for the rest of the
+/// kernel's lifetime.
+pub unsafe fn initialize(address: usize) -> Result<(), Error> {
+  let tree = DeviceTree::from_address(address)?;
+  DEVICE_TREE.call_once(|| tree);
+  Ok(())
+}
+
+/// Returns the device tree the firmware handed to the kernel, if it has been parsed
+/// successfully by [`initialize`].
+#[must_use]
+pub fn get() -> Option<&'static DeviceTree<'static>> { DEVICE_TREE.get() }
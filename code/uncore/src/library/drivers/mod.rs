@@ -0,0 +1,368 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains the architecture-independent driver framework.
+//!
+//! Drivers implement [`Driver`] and announce which devices they can handle via
+//! `compatible` strings. [`probe`] walks the device tree, matches every enabled node
+//! against the given drivers, and binds the best matching driver to the node. Binding
+//! happens in two steps: [`Driver::probe`] checks whether the device is present and all
+//! dependencies (e.g. the interrupt controller) are available, and [`Driver::init`]
+//! brings the device up. When a dependency is not available yet, the driver returns
+//! [`Error::ProbeDeferred`] and the framework retries after other devices have been
+//! bound.
+//!
+//! Probing usually happens before the kernel logger is available. Therefore, errors are
+//! not reported immediately but recorded, so that they can be listed later with
+//! [`devices`] or [`log_devices`]. A driver that fails to initialize does not stop the
+//! kernel; its device simply stays unbound.
+
+#[cfg(test)]
+mod tests;
+
+/// The maximum number of devices the framework keeps track of.
+const MAXIMUM_DEVICES: usize = 32;
+
+/// How often the registry lock is tried during shutdown before it is broken.
+const SHUTDOWN_ATTEMPTS: usize = 1_000_000;
+
+/// Errors that drivers may report when binding to a device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+  /// A dependency of the device is not bound yet; probing is retried later.
+  ProbeDeferred,
+  /// A dependency of the device was never bound, so probing was given up.
+  DependencyUnavailable,
+  /// There is no (supported) device behind the node.
+  NoDevice,
+  /// The node lacks a property the driver requires.
+  MissingProperty(&'static str),
+  /// The driver cannot handle the device as described by the node.
+  Unsupported(&'static str),
+  /// The device did not respond as expected during initialization.
+  Failed(&'static str),
+}
+
+impl core::fmt::Display for Error {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::ProbeDeferred => write!(f, "probing deferred"),
+      Self::DependencyUnavailable => write!(f, "dependency unavailable"),
+      Self::NoDevice => write!(f, "no device present"),
+      Self::MissingProperty(property) => write!(f, "missing property '{property}'"),
+      Self::Unsupported(reason) => write!(f, "unsupported: {reason}"),
+      Self::Failed(reason) => write!(f, "initialization failed: {reason}"),
+    }
+  }
+}
+
+/// The node of the device tree a device is described by.
+pub type Node = crate::library::device_tree::Node<'static>;
+
+/// The interface every driver implements. Drivers are stateless objects (usually unit
+/// structures); the state of the devices they drive lives in the driver's module.
+pub trait Driver: Sync {
+  /// Returns the name of the driver.
+  fn name(&self) -> &'static str;
+
+  /// Returns the `compatible` strings of the devices this driver can handle.
+  fn compatible(&self) -> &'static [&'static str];
+
+  /// Checks whether the device described by `node` is present and can be handled by this
+  /// driver. This function must not change the state of the device.
+  ///
+  /// #### Errors
+  ///
+  /// If a dependency is not bound yet, [`Error::ProbeDeferred`] is returned. If the
+  /// device cannot be handled, another error is returned.
+  fn probe(&self, node: &Node) -> Result<(), Error>;
+
+  /// Initializes the device described by `node`. This function is only called after
+  /// [`Self::probe`] has succeeded.
+  ///
+  /// #### Errors
+  ///
+  /// If the device cannot be initialized, an error is returned.
+  fn init(&self, node: &Node) -> Result<(), Error>;
+
+  /// Shuts the device described by `node` down when the kernel exits.
+  fn shutdown(&self, _node: &Node) {}
+}
+
+/// The state of a device known to the framework.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+  /// A driver was matched, but the device has not been bound yet.
+  Pending,
+  /// The driver is bound to the device.
+  Bound,
+  /// Binding the driver failed.
+  Failed(Error),
+}
+
+/// A device known to the framework, i.e. a node of the device tree that a driver was
+/// matched with.
+#[derive(Copy, Clone)]
+pub struct Device {
+  /// The driver that was matched with the node
+  driver: &'static dyn Driver,
+  /// The node that describes the device
+  node:   Node,
+  /// Whether the driver is bound to the device
+  state:  State,
+}
+
+impl Device {
+  /// Returns the driver that was matched with this device.
+  #[must_use]
+  pub fn driver(&self) -> &'static dyn Driver { self.driver }
+
+  /// Returns the node of the device tree that describes this device.
+  #[must_use]
+  pub const fn node(&self) -> &Node { &self.node }
+
+  /// Returns the state of this device.
+  #[must_use]
+  pub const fn state(&self) -> State { self.state }
+}
+
+impl core::fmt::Debug for Device {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Device")
+      .field("driver", &self.driver.name())
+      .field("node", &self.node.name())
+      .field("state", &self.state)
+      .finish()
+  }
+}
+
+/// All devices known to the framework, in the order they were matched.
+struct Registry {
+  /// The devices
+  devices:       [Option<Device>; MAXIMUM_DEVICES],
+  /// The indices of bound devices in `devices`, in the order they were bound
+  binding_order: [usize; MAXIMUM_DEVICES],
+  /// The number of bound devices
+  bound:         usize,
+  /// Whether a node had to be ignored because `devices` is full
+  full:          bool,
+}
+
+impl Registry {
+  /// Creates a registry without devices.
+  const fn new() -> Self {
+    Self {
+      devices:       [None; MAXIMUM_DEVICES],
+      binding_order: [0; MAXIMUM_DEVICES],
+      bound:         0,
+      full:          false,
+    }
+  }
+}
+
+/// The global device registry.
+static REGISTRY: spin::Mutex<Registry> = spin::Mutex::new(Registry::new());
+
+/// Returns the device at `index` in `registry`. The registry is not locked while the
+/// caller uses the device, so that drivers can query the registry during probing.
+fn device(registry: &spin::Mutex<Registry>, index: usize) -> Option<Device> {
+  registry.lock().devices.get(index).copied().flatten()
+}
+
+/// Sets the state of the device at `index` in `registry`.
+fn set_state(registry: &spin::Mutex<Registry>, index: usize, state: State) {
+  let mut registry = registry.lock();
+  if let Some(Some(device)) = registry.devices.get_mut(index) {
+    device.state = state;
+    if state == State::Bound {
+      let bound = registry.bound;
+      registry.binding_order[bound] = index;
+      registry.bound += 1;
+    }
+  }
+}
+
+/// Returns the driver in `drivers` that matches `node` best. The `compatible` property
+/// lists the most specific entry first, so earlier entries take precedence.
+fn find_driver(drivers: &[&'static dyn Driver], node: &Node) -> Option<&'static dyn Driver> {
+  node.compatible().find_map(|compatible| {
+    drivers
+      .iter()
+      .find(|driver| driver.compatible().contains(&compatible))
+      .copied()
+  })
+}
+
+/// Matches all enabled nodes of the device tree against `drivers` and binds the matching
+/// drivers. Drivers whose dependencies are not available are retried until no more
+/// progress is made.
+pub fn probe(drivers: &[&'static dyn Driver]) {
+  if let Some(tree) = crate::library::device_tree::get() {
+    probe_nodes(&REGISTRY, tree.nodes(), drivers);
+  }
+}
+
+/// Matches the enabled nodes in `nodes` against `drivers`, adds the matches to
+/// `registry` and binds the drivers (see [`probe`]).
+fn probe_nodes(
+  registry: &spin::Mutex<Registry>,
+  nodes: impl Iterator<Item = Node>,
+  drivers: &[&'static dyn Driver],
+) {
+  {
+    let mut registry = registry.lock();
+    let mut free_slots = registry.devices.iter_mut().filter(|slot| slot.is_none());
+    let mut full = false;
+    for node in nodes.filter(crate::library::device_tree::Node::is_enabled) {
+      let Some(driver) = find_driver(drivers, &node) else {
+        continue;
+      };
+
+      if let Some(slot) = free_slots.next() {
+        *slot = Some(Device {
+          driver,
+          node,
+          state: State::Pending,
+        });
+      } else {
+        full = true;
+      }
+    }
+    registry.full |= full;
+  }
+
+  loop {
+    let mut progress = false;
+    for index in 0..MAXIMUM_DEVICES {
+      let Some(device) = device(registry, index) else {
+        continue;
+      };
+      if device.state != State::Pending {
+        continue;
+      }
+
+      let result = device
+        .driver
+        .probe(&device.node)
+        .and_then(|()| device.driver.init(&device.node));
+      match result {
+        Err(Error::ProbeDeferred) => continue,
+        Ok(()) => set_state(registry, index, State::Bound),
+        Err(error) => set_state(registry, index, State::Failed(error)),
+      }
+      progress = true;
+    }
+
+    if !progress {
+      break;
+    }
+  }
+
+  for index in 0..MAXIMUM_DEVICES {
+    if device(registry, index).is_some_and(|device| device.state == State::Pending) {
+      set_state(registry, index, State::Failed(Error::DependencyUnavailable));
+    }
+  }
+}
+
+/// Checks whether a driver is bound to the node with the given `phandle`.
+#[must_use]
+pub fn is_bound(phandle: u32) -> bool {
+  devices().any(|device| device.state == State::Bound && device.node.phandle() == Some(phandle))
+}
+
+/// Returns [`Error::ProbeDeferred`] if the interrupt parent of `node` is not bound yet.
+/// Drivers call this function in [`Driver::probe`] when they need their interrupt
+/// controller.
+///
+/// #### Errors
+///
+/// If `node` has an interrupt parent that is not bound yet, an error is returned.
+pub fn require_interrupt_parent(node: &Node) -> Result<(), Error> {
+  match node.interrupt_parent() {
+    Some(phandle) if !is_bound(phandle) => Err(Error::ProbeDeferred),
+    _ => Ok(()),
+  }
+}
+
+/// Returns an iterator over all devices known to the framework, including devices that
+/// could not be bound.
+pub fn devices() -> impl Iterator<Item = Device> {
+  (0..MAXIMUM_DEVICES).filter_map(|index| device(&REGISTRY, index))
+}
+
+/// Returns an iterator over all devices a driver is bound to.
+pub fn bound_devices() -> impl Iterator<Item = Device> {
+  devices().filter(|device| device.state == State::Bound)
+}
+
+/// Logs all devices known to the framework and whether a driver is bound to them.
+pub fn log_devices() {
+  for device in devices() {
+    match device.state {
+      State::Bound => log::debug!(
+        "Device '{}' bound to driver '{}'",
+        device.node.name(),
+        device.driver.name()
+      ),
+      State::Failed(Error::NoDevice) => log::trace!(
+        "Device '{}' not bound to driver '{}': {}",
+        device.node.name(),
+        device.driver.name(),
+        Error::NoDevice
+      ),
+      State::Failed(error) => log::warn!(
+        "Device '{}' not bound to driver '{}': {}",
+        device.node.name(),
+        device.driver.name(),
+        error
+      ),
+      State::Pending => log::warn!("Device '{}' has not been probed", device.node.name()),
+    }
+  }
+
+  if REGISTRY.lock().full {
+    log::warn!("Some devices were ignored because the device registry is full");
+  }
+}
+
+/// Shuts down all bound devices in the reverse order of binding. Calling this function
+/// more than once has no effect.
+///
+/// The kernel may exit because it panics while the registry is locked, e.g. by a driver
+/// during probing. The lock is therefore broken if it is not released in time.
+pub fn shutdown() {
+  /// Ensures that devices are only shut down once, even if shutting down causes a panic.
+  static SHUTDOWN_WAS_CALLED: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+  if SHUTDOWN_WAS_CALLED.swap(true, core::sync::atomic::Ordering::SeqCst) {
+    return;
+  }
+
+  shutdown_devices(&REGISTRY);
+}
+
+/// Shuts down the bound devices of `registry` in the reverse order of binding. The
+/// registry is not locked while the drivers shut their devices down.
+fn shutdown_devices(registry: &spin::Mutex<Registry>) {
+  let (devices, bound) = {
+    let registry = (0..SHUTDOWN_ATTEMPTS)
+      .find_map(|_| {
+        let lock = registry.try_lock();
+        if lock.is_none() {
+          core::hint::spin_loop();
+        }
+        lock
+      })
+      .unwrap_or_else(|| {
+        unsafe { registry.force_unlock() };
+        registry.lock()
+      });
+    let devices: [Option<Device>; MAXIMUM_DEVICES] =
+      core::array::from_fn(|position| registry.devices[registry.binding_order[position]]);
+    (devices, registry.bound)
+  };
+
+  for device in devices[..bound].iter().rev().flatten() {
+    device.driver.shutdown(&device.node);
+  }
+}
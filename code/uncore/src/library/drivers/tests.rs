@@ -0,0 +1,290 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unit tests of the driver framework. The devices are described by small device trees
+//! that are built by the tests.
+
+use alloc::{
+  string::String,
+  vec::Vec,
+};
+use core::sync::atomic::{
+  AtomicBool,
+  Ordering,
+};
+
+use super::{
+  probe_nodes,
+  shutdown_devices,
+  Driver,
+  Error,
+  Node,
+  Registry,
+  State,
+  MAXIMUM_DEVICES,
+};
+use crate::library::device_tree::DeviceTree;
+
+/// Returns the nodes of a device tree whose root node has a child with each of the names
+/// and `compatible` strings in `devices`, starting with the root node.
+fn nodes(devices: &[(&str, &[&str])]) -> impl Iterator<Item = Node> {
+  /// Appends the big-endian `value` to `data`.
+  fn push(data: &mut Vec<u8>, value: u32) { data.extend_from_slice(&value.to_be_bytes()); }
+
+  /// Appends `bytes` to `data`, padded with zeros to a multiple of four bytes.
+  fn push_padded(data: &mut Vec<u8>, bytes: &[u8]) {
+    data.extend_from_slice(bytes);
+    data.resize((data.len() + 3) & !3, 0);
+  }
+
+  let mut structure = Vec::new();
+  push(&mut structure, 1);
+  push(&mut structure, 0);
+  for (name, compatible) in devices {
+    push(&mut structure, 1);
+    push_padded(&mut structure, alloc::format!("{name}\0").as_bytes());
+    let value: Vec<u8> = compatible
+      .iter()
+      .flat_map(|compatible| compatible.bytes().chain([0]))
+      .collect();
+    push(&mut structure, 3);
+    push(&mut structure, u32::try_from(value.len()).unwrap());
+    push(&mut structure, 0);
+    push_padded(&mut structure, &value);
+    push(&mut structure, 2);
+  }
+  push(&mut structure, 2);
+  push(&mut structure, 9);
+
+  let strings = b"compatible\0";
+  let structure_offset = 40 + 16;
+  let strings_offset = structure_offset + structure.len();
+  let mut blob = Vec::new();
+  for field in [
+    0xD00D_FEED,
+    strings_offset + strings.len(),
+    structure_offset,
+    strings_offset,
+    40,
+    17,
+    16,
+    0,
+    strings.len(),
+    structure.len(),
+  ] {
+    push(&mut blob, u32::try_from(field).unwrap());
+  }
+  blob.resize(structure_offset, 0);
+  blob.extend_from_slice(&structure);
+  blob.extend_from_slice(strings);
+
+  DeviceTree::from_bytes(blob.leak()).unwrap().nodes()
+}
+
+/// The calls of the drivers, in the order they were made.
+static CALLS: spin::Mutex<Vec<String>> = spin::Mutex::new(Vec::new());
+
+/// A driver that records its calls. It defers probing until `ready` is set, and sets
+/// `bound` when it is initialized.
+struct Recorder {
+  /// The name of the driver
+  name:       &'static str,
+  /// The `compatible` strings the driver handles
+  compatible: &'static [&'static str],
+  /// Whether the dependency of the devices is available
+  ready:      Option<&'static AtomicBool>,
+  /// Set when a device was initialized
+  bound:      Option<&'static AtomicBool>,
+}
+
+impl Driver for Recorder {
+  fn name(&self) -> &'static str { self.name }
+
+  fn compatible(&self) -> &'static [&'static str] { self.compatible }
+
+  fn probe(&self, node: &Node) -> Result<(), Error> {
+    CALLS
+      .lock()
+      .push(alloc::format!("probe {} {}", self.name, node.name()));
+    match self.ready {
+      Some(ready) if !ready.load(Ordering::Relaxed) => Err(Error::ProbeDeferred),
+      _ => Ok(()),
+    }
+  }
+
+  fn init(&self, node: &Node) -> Result<(), Error> {
+    CALLS
+      .lock()
+      .push(alloc::format!("init {} {}", self.name, node.name()));
+    if let Some(bound) = self.bound {
+      bound.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+  }
+
+  fn shutdown(&self, node: &Node) { CALLS.lock().push(alloc::format!("shutdown {}", node.name())); }
+}
+
+/// Returns the names and states of the devices in `registry`.
+fn states(registry: &spin::Mutex<Registry>) -> Vec<(&'static str, &'static str, State)> {
+  registry
+    .lock()
+    .devices
+    .iter()
+    .flatten()
+    .map(|device| (device.node.name(), device.driver.name(), device.state))
+    .collect()
+}
+
+#[test_case]
+fn drivers_are_matched_by_the_most_specific_compatible_string() {
+  static GENERIC: Recorder = Recorder {
+    name:       "generic",
+    compatible: &["ns16550a"],
+    ready:      None,
+    bound:      None,
+  };
+  static SPECIFIC: Recorder = Recorder {
+    name:       "specific",
+    compatible: &["vendor,uart"],
+    ready:      None,
+    bound:      None,
+  };
+
+  let registry = spin::Mutex::new(Registry::new());
+  probe_nodes(
+    &registry,
+    nodes(&[
+      ("serial@1", &["vendor,uart", "ns16550a"]),
+      ("serial@2", &["ns16550a"]),
+      ("unknown@3", &["vendor,unknown"]),
+    ]),
+    &[&GENERIC, &SPECIFIC],
+  );
+
+  assert_eq!(
+    states(&registry),
+    [
+      ("serial@1", "specific", State::Bound),
+      ("serial@2", "generic", State::Bound),
+    ]
+  );
+}
+
+#[test_case]
+fn deferred_devices_are_bound_once_their_dependency_is() {
+  static READY: AtomicBool = AtomicBool::new(false);
+  static CONTROLLER: Recorder = Recorder {
+    name:       "controller",
+    compatible: &["test,controller"],
+    ready:      None,
+    bound:      Some(&READY),
+  };
+  static DEPENDENT: Recorder = Recorder {
+    name:       "dependent",
+    compatible: &["test,dependent"],
+    ready:      Some(&READY),
+    bound:      None,
+  };
+  static NEVER: AtomicBool = AtomicBool::new(false);
+  static ORPHAN: Recorder = Recorder {
+    name:       "orphan",
+    compatible: &["test,orphan"],
+    ready:      Some(&NEVER),
+    bound:      None,
+  };
+
+  CALLS.lock().clear();
+  let registry = spin::Mutex::new(Registry::new());
+  probe_nodes(
+    &registry,
+    nodes(&[
+      ("dependent@1", &["test,dependent"]),
+      ("controller@2", &["test,controller"]),
+      ("orphan@3", &["test,orphan"]),
+    ]),
+    &[&CONTROLLER, &DEPENDENT, &ORPHAN],
+  );
+
+  assert_eq!(
+    states(&registry),
+    [
+      ("dependent@1", "dependent", State::Bound),
+      ("controller@2", "controller", State::Bound),
+      ("orphan@3", "orphan", State::Failed(Error::DependencyUnavailable)),
+    ]
+  );
+  assert_eq!(
+    CALLS.lock()[..4],
+    [
+      "probe dependent dependent@1",
+      "probe controller controller@2",
+      "init controller controller@2",
+      "probe orphan orphan@3",
+    ]
+  );
+  assert_eq!(CALLS.lock()[4], "probe dependent dependent@1");
+  assert_eq!(CALLS.lock()[5], "init dependent dependent@1");
+}
+
+#[test_case]
+fn devices_beyond_the_capacity_of_the_registry_are_ignored() {
+  static DRIVER: Recorder = Recorder {
+    name:       "many",
+    compatible: &["test,many"],
+    ready:      None,
+    bound:      None,
+  };
+
+  let names: Vec<String> = (0..=MAXIMUM_DEVICES)
+    .map(|index| alloc::format!("device@{index}"))
+    .collect();
+  let devices: Vec<(&str, &[&str])> = names
+    .iter()
+    .map(|name| (name.as_str(), &["test,many"][..]))
+    .collect();
+  let registry = spin::Mutex::new(Registry::new());
+  probe_nodes(&registry, nodes(&devices), &[&DRIVER]);
+
+  assert_eq!(states(&registry).len(), MAXIMUM_DEVICES);
+  assert!(registry.lock().full);
+  assert!(states(&registry)
+    .iter()
+    .all(|&(name, _, state)| name != "device@32" && state == State::Bound));
+}
+
+#[test_case]
+fn devices_are_shut_down_in_the_reverse_order_of_binding() {
+  static READY: AtomicBool = AtomicBool::new(false);
+  static FIRST: Recorder = Recorder {
+    name:       "first",
+    compatible: &["test,first"],
+    ready:      None,
+    bound:      Some(&READY),
+  };
+  static SECOND: Recorder = Recorder {
+    name:       "second",
+    compatible: &["test,second"],
+    ready:      Some(&READY),
+    bound:      None,
+  };
+
+  let registry = spin::Mutex::new(Registry::new());
+  probe_nodes(
+    &registry,
+    nodes(&[
+      ("second@1", &["test,second"]),
+      ("first@2", &["test,first"]),
+      ("third@3", &["test,first"]),
+    ]),
+    &[&FIRST, &SECOND],
+  );
+
+  CALLS.lock().clear();
+  // A lock that is never released (e.g. by code that panicked) does not stop shutdown
+  core::mem::forget(registry.lock());
+  shutdown_devices(&registry);
+  assert_eq!(
+    *CALLS.lock(),
+    ["shutdown second@1", "shutdown third@3", "shutdown first@2"]
+  );
+}
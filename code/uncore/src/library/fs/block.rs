@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains the interface of block devices (e.g. disks), on which file
+//! systems like [`super::Fat`] store their data, and the registry of all block devices.
+//!
+//! Drivers are initialized before the heap is available. Therefore, block devices live in
+//! `static` memory of their driver, which hands them to [`register`].
+
+use super::{
+  Error,
+  Result,
+};
+
+/// The maximum number of block devices the registry keeps track of.
+const MAXIMUM_DEVICES: usize = 8;
+
+/// The interface of a device that stores data in blocks of a fixed size.
+pub trait BlockDevice: Send + Sync {
+  /// Returns the size of a block in bytes, which is a power of two.
+  fn block_size(&self) -> usize { 512 }
+
+  /// Returns the number of blocks of the device.
+  fn block_count(&self) -> u64;
+
+  /// Reads consecutive blocks, starting with the block `block`, into `buffer`, whose
+  /// length is a multiple of the block size.
+  ///
+  /// #### Errors
+  ///
+  /// If the blocks lie outside of the device or cannot be read, an error is returned.
+  fn read_blocks(&self, block: u64, buffer: &mut [u8]) -> Result<()>;
+
+  /// Writes `buffer`, whose length is a multiple of the block size, to consecutive
+  /// blocks, starting with the block `block`.
+  ///
+  /// #### Errors
+  ///
+  /// If the device is read-only, [`Error::ReadOnly`] is returned. If the blocks lie
+  /// outside of the device or cannot be written, another error is returned.
+  fn write_blocks(&self, block: u64, buffer: &[u8]) -> Result<()>;
+
+  /// Makes sure that all data written so far reached the storage.
+  ///
+  /// #### Errors
+  ///
+  /// If the data cannot be written, an error is returned.
+  fn flush(&self) -> Result<()> { Ok(()) }
+}
+
+/// Checks that the request for blocks starting at `block` with the buffer length `length`
+/// fits to `device`.
+///
+/// #### Errors
+///
+/// If the length is not a multiple of the block size or the blocks lie outside of the
+/// device, [`Error::InvalidArgument`] is returned.
+pub fn check_request(device: &dyn BlockDevice, block: u64, length: usize) -> Result<()> {
+  let block_size = device.block_size();
+  if length & (block_size - 1) != 0 {
+    return Err(Error::InvalidArgument);
+  }
+
+  let blocks = (length / block_size) as u64;
+  match block.checked_add(blocks) {
+    Some(end) if end <= device.block_count() => Ok(()),
+    _ => Err(Error::InvalidArgument),
+  }
+}
+
+/// Converts a position into an index.
+fn index(value: u64) -> Result<usize> { usize::try_from(value).map_err(|_| Error::InvalidArgument) }
+
+/// Reads `buffer.len()` bytes at the byte `position` of `device`, which does not need to
+/// be aligned to blocks.
+///
+/// #### Errors
+///
+/// If the device cannot be read, an error is returned.
+pub fn read(device: &dyn BlockDevice, position: u64, buffer: &mut [u8]) -> Result<()> {
+  let block_size = device.block_size();
+  let mut block = alloc::vec![0; block_size];
+  let mut done = 0;
+
+  while done < buffer.len() {
+    let current = position + done as u64;
+    let number = current / block_size as u64;
+    let offset = index(current % block_size as u64)?;
+    let remaining = buffer.len() - done;
+
+    if offset == 0 && remaining >= block_size {
+      let length = remaining - remaining % block_size;
+      device.read_blocks(number, &mut buffer[done..done + length])?;
+      done += length;
+    } else {
+      let length = remaining.min(block_size - offset);
+      device.read_blocks(number, &mut block)?;
+      buffer[done..done + length].copy_from_slice(&block[offset..offset + length]);
+      done += length;
+    }
+  }
+  Ok(())
+}
+
+/// Writes `buffer` at the byte `position` of `device`. Blocks that are written only
+/// partially are read first.
+///
+/// #### Errors
+///
+/// If the device cannot be read or written, an error is returned.
+pub fn write(device: &dyn BlockDevice, position: u64, buffer: &[u8]) -> Result<()> {
+  let block_size = device.block_size();
+  let mut block = alloc::vec![0; block_size];
+  let mut done = 0;
+
+  while done < buffer.len() {
+    let current = position + done as u64;
+    let number = current / block_size as u64;
+    let offset = index(current % block_size as u64)?;
+    let remaining = buffer.len() - done;
+
+    if offset == 0 && remaining >= block_size {
+      let length = remaining - remaining % block_size;
+      device.write_blocks(number, &buffer[done..done + length])?;
+      done += length;
+    } else {
+      let length = remaining.min(block_size - offset);
+      device.read_blocks(number, &mut block)?;
+      block[offset..offset + length].copy_from_slice(&buffer[done..done + length]);
+      device.write_blocks(number, &block)?;
+      done += length;
+    }
+  }
+  Ok(())
+}
+
+/// A block device known to the registry.
+#[derive(Copy, Clone)]
+pub struct Registration {
+  /// The name of the device (e.g. `vda`)
+  pub name:   &'static str,
+  /// The device
+  pub device: &'static dyn BlockDevice,
+}
+
+impl core::fmt::Debug for Registration {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Registration")
+      .field("name", &self.name)
+      .field("blocks", &self.device.block_count())
+      .finish_non_exhaustive()
+  }
+}
+
+/// All registered block devices.
+static DEVICES: spin::Mutex<[Option<Registration>; MAXIMUM_DEVICES]> =
+  spin::Mutex::new([None; MAXIMUM_DEVICES]);
+
+/// Makes `device` known under the name `name`.
+///
+/// #### Errors
+///
+/// If the name is taken already, [`Error::AlreadyExists`] is returned. If the registry
+/// is full, [`Error::NoSpace`] is returned.
+pub fn register(name: &'static str, device: &'static dyn BlockDevice) -> Result<()> {
+  let mut devices = DEVICES.lock();
+  if devices
+    .iter()
+    .flatten()
+    .any(|registration| registration.name == name)
+  {
+    return Err(Error::AlreadyExists);
+  }
+
+  let slot = devices
+    .iter_mut()
+    .find(|slot| slot.is_none())
+    .ok_or(Error::NoSpace)?;
+  *slot = Some(Registration { name, device });
+  Ok(())
+}
+
+/// Returns all registered block devices in the order of their registration.
+pub fn devices() -> impl Iterator<Item = Registration> {
+  let devices = *DEVICES.lock();
+  devices.into_iter().flatten()
+}
+
+/// Returns the block device named `name`, if there is one.
+#[must_use]
+pub fn get(name: &str) -> Option<&'static dyn BlockDevice> {
+  devices()
+    .find(|registration| registration.name == name)
+    .map(|registration| registration.device)
+}
@@ -0,0 +1,246 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains the page cache, which holds the pages of files that are mapped
+//! into memory.
+//!
+//! [`get`] returns the [`CachedFile`] of an inode, which is the memory object that
+//! mappings of the file share. Its pages are read from the file on first use, and pages
+//! that were changed through shared mappings are written back by
+//! [`CachedFile::write_back`] (e.g. on `msync`) and when the last mapping is gone. Pages
+//! beyond the end of the file read as zeros; changes to them are not written back.
+//!
+//! Reading and writing a file through [`super::File`] keeps the cache coherent: writes
+//! update the cached pages, reads write back changed pages first, and truncating the file
+//! zeroes the cached data beyond its new end.
+//!
+//! When the kernel heap is exhausted, [`reclaim`] frees the pages that can be read from
+//! their files again.
+
+use alloc::{
+  collections::BTreeMap,
+  sync::{
+    Arc,
+    Weak,
+  },
+};
+
+use super::{
+  Inode,
+  Result,
+};
+use crate::library::mem::{
+  self,
+  object::{
+    Page,
+    VmObject,
+  },
+  PAGE_SIZE,
+};
+
+/// The cached pages of a file.
+pub struct CachedFile {
+  /// The inode of the file
+  inode: Arc<dyn Inode>,
+  /// The pages that have been read, indexed by their number
+  pages: spin::Mutex<BTreeMap<u64, Arc<Page>>>,
+}
+
+impl CachedFile {
+  /// Writes all pages that were changed through shared mappings back to the file.
+  ///
+  /// #### Errors
+  ///
+  /// If the file cannot be written, an error is returned.
+  pub fn write_back(&self) -> Result<()> {
+    let size = self.inode.metadata()?.size;
+    let dirty: alloc::vec::Vec<(u64, Arc<Page>)> = self
+      .pages
+      .lock()
+      .iter()
+      .filter(|(_, page)| page.take_dirty())
+      .map(|(&index, page)| (index, page.clone()))
+      .collect();
+
+    let mut buffer = [0; PAGE_SIZE];
+    for (index, page) in dirty {
+      let offset = index * PAGE_SIZE as u64;
+      let Ok(length) = usize::try_from(size.saturating_sub(offset).min(PAGE_SIZE as u64)) else {
+        continue;
+      };
+      if length == 0 {
+        continue;
+      }
+      page
+        .read(0, &mut buffer[..length])
+        .map_err(|_| super::Error::Io)?;
+      self.inode.write_at(offset, &buffer[..length])?;
+    }
+    Ok(())
+  }
+
+  /// Copies `data`, which was written to the file at `offset`, into the cached pages.
+  fn update(&self, offset: u64, data: &[u8]) {
+    let pages = self.pages.lock();
+    let mut done = 0;
+    while done < data.len() {
+      let position = offset + done as u64;
+      #[allow(clippy::cast_possible_truncation)]
+      let in_page = (position % PAGE_SIZE as u64) as usize;
+      let chunk = (PAGE_SIZE - in_page).min(data.len() - done);
+      if let Some(page) = pages.get(&(position / PAGE_SIZE as u64)) {
+        // Changes made through mappings elsewhere in the page must still be written back
+        let was_dirty = page.is_dirty();
+        // Writing within the page cannot fail; the data is in the file already
+        let _ = page.write(in_page, &data[done..done + chunk]);
+        if !was_dirty {
+          page.take_dirty();
+        }
+      }
+      done += chunk;
+    }
+  }
+
+  /// Zeroes the cached data beyond `size`, the new size of the file, and drops the pages
+  /// that are entirely beyond it and not mapped.
+  fn truncate(&self, size: u64) {
+    let mut pages = self.pages.lock();
+    let zeros = [0; PAGE_SIZE];
+    for (&index, page) in pages.range(size / PAGE_SIZE as u64..) {
+      let start = index * PAGE_SIZE as u64;
+      #[allow(clippy::cast_possible_truncation)]
+      let in_page = size.saturating_sub(start) as usize;
+      let was_dirty = page.is_dirty();
+      // Writing within the page cannot fail
+      let _ = page.write(in_page, &zeros[in_page..]);
+      // Only the page containing the new end may still have changes to write back
+      if !was_dirty || in_page == 0 {
+        page.take_dirty();
+      }
+    }
+    pages.retain(|&index, page| index * (PAGE_SIZE as u64) < size || Arc::strong_count(page) > 1);
+  }
+}
+
+impl VmObject for CachedFile {
+  fn page(&self, index: u64) -> mem::Result<Arc<Page>> {
+    let mut pages = self.pages.lock();
+    if let Some(page) = pages.get(&index) {
+      return Ok(page.clone());
+    }
+
+    let page = Page::new()?;
+    let mut buffer = [0; PAGE_SIZE];
+    let offset = index
+      .checked_mul(PAGE_SIZE as u64)
+      .ok_or(mem::Error::InvalidArgument)?;
+    let mut read = 0;
+    while read < PAGE_SIZE {
+      match self.inode.read_at(offset + read as u64, &mut buffer[read..])? {
+        0 => break,
+        length => read += length,
+      }
+    }
+    page.write(0, &buffer)?;
+    page.take_dirty();
+
+    pages.insert(index, page.clone());
+    Ok(page)
+  }
+
+  fn write_back(&self) -> mem::Result<()> { Ok(Self::write_back(self)?) }
+}
+
+impl Drop for CachedFile {
+  fn drop(&mut self) {
+    if let Err(error) = self.write_back() {
+      log::warn!("Could not write back a mapped file: {error}");
+    }
+  }
+}
+
+impl core::fmt::Debug for CachedFile {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("CachedFile")
+      .field("pages", &self.pages.lock().len())
+      .finish_non_exhaustive()
+  }
+}
+
+/// The cached files, indexed by the address of their inode. A file stays cached as long
+/// as it is mapped.
+static CACHE: spin::Mutex<BTreeMap<usize, Weak<CachedFile>>> = spin::Mutex::new(BTreeMap::new());
+
+/// Returns the key of `inode` in [`CACHE`].
+fn key(inode: &Arc<dyn Inode>) -> usize { Arc::as_ptr(inode).cast::<()>().addr() }
+
+/// Returns the cached pages of `inode`, creating an empty cache if the file is not
+/// cached yet.
+#[must_use]
+pub fn get(inode: &Arc<dyn Inode>) -> Arc<CachedFile> {
+  let mut cache = CACHE.lock();
+  cache.retain(|_, file| file.strong_count() != 0);
+  if let Some(file) = cache.get(&key(inode)).and_then(Weak::upgrade) {
+    return file;
+  }
+
+  let file = Arc::new(CachedFile {
+    inode: inode.clone(),
+    pages: spin::Mutex::new(BTreeMap::new()),
+  });
+  cache.insert(key(inode), Arc::downgrade(&file));
+  file
+}
+
+/// Returns the cached pages of `inode` if the file is cached.
+fn lookup(inode: &Arc<dyn Inode>) -> Option<Arc<CachedFile>> {
+  CACHE.lock().get(&key(inode)).and_then(Weak::upgrade)
+}
+
+/// Frees the cached pages that are unchanged and not in use.
+///
+/// Returns the number of bytes freed. This is the reclaimer of the page cache for the
+/// kernel heap (see [`crate::library::mem::heap::register_reclaimer`]), so it does not
+/// allocate and skips files whose pages are locked.
+pub fn reclaim() -> usize {
+  let Some(cache) = CACHE.try_lock() else {
+    return 0;
+  };
+
+  let mut freed = 0;
+  for file in cache.values().filter_map(Weak::upgrade) {
+    let Some(mut pages) = file.pages.try_lock() else {
+      continue;
+    };
+    pages.retain(|_, page| {
+      let in_use = Arc::strong_count(page) > 1 || page.is_dirty();
+      if !in_use {
+        freed += PAGE_SIZE;
+      }
+      in_use
+    });
+  }
+  freed
+}
+
+/// Updates the cached pages of `inode` after `data` was written to the file at `offset`.
+pub(super) fn update(inode: &Arc<dyn Inode>, offset: u64, data: &[u8]) {
+  if let Some(file) = lookup(inode) {
+    file.update(offset, data);
+  }
+}
+
+/// Updates the cached pages of `inode` after the file was truncated to `size`.
+pub(super) fn truncate(inode: &Arc<dyn Inode>, size: u64) {
+  if let Some(file) = lookup(inode) {
+    file.truncate(size);
+  }
+}
+
+/// Writes the changed pages of `inode` back to the file, so that it can be read.
+///
+/// #### Errors
+///
+/// If the file cannot be written, an error is returned.
+pub(super) fn write_back(inode: &Arc<dyn Inode>) -> Result<()> {
+  lookup(inode).map_or(Ok(()), |file| file.write_back())
+}
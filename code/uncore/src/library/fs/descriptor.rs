@@ -0,0 +1,346 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains file descriptors, the small integers through which programs
+//! refer to open files, pipes and the console.
+//!
+//! Everything a file descriptor can refer to implements [`FileDescription`]. A
+//! [`FileDescriptorTable`] maps file descriptors to descriptions; several descriptors
+//! (e.g. after [`FileDescriptorTable::duplicate`]) may refer to the same description and
+//! share its offset. Every process has its own table. There are no processes yet, so
+//! system calls use the table returned by [`current`], in which [`initialize`] opens the
+//! console as the standard input, output and error.
+
+use alloc::{
+  sync::Arc,
+  vec::Vec,
+};
+
+use super::{
+  Dentry,
+  Error,
+  File,
+  Result,
+};
+
+/// The maximum number of file descriptors a table can hold.
+pub const MAXIMUM_DESCRIPTORS: usize = 1024;
+/// The file descriptor of the standard input.
+pub const STANDARD_INPUT: usize = 0;
+/// The file descriptor of the standard output.
+pub const STANDARD_OUTPUT: usize = 1;
+/// The file descriptor of the standard error.
+pub const STANDARD_ERROR: usize = 2;
+
+/// An open file description, i.e. something a file descriptor can refer to.
+pub trait FileDescription: Send + Sync {
+  /// Reads into `buffer` and returns the number of bytes read, which is zero at the end
+  /// of the stream.
+  ///
+  /// #### Errors
+  ///
+  /// If the description cannot be read, [`Error::PermissionDenied`] is returned.
+  fn read(&self, _buffer: &mut [u8]) -> Result<usize> { Err(Error::PermissionDenied) }
+
+  /// Writes `buffer` and returns the number of bytes written.
+  ///
+  /// #### Errors
+  ///
+  /// If the description cannot be written, [`Error::PermissionDenied`] is returned.
+  fn write(&self, _buffer: &[u8]) -> Result<usize> { Err(Error::PermissionDenied) }
+
+  /// Returns the directory entry if the description is a file of the VFS, so that paths
+  /// can be resolved relative to it.
+  fn dentry(&self) -> Option<&Arc<Dentry>> { None }
+
+  /// Returns the open file if the description is one, e.g. so that it can be mapped
+  /// into memory.
+  fn file(&self) -> Option<&File> { None }
+}
+
+impl FileDescription for File {
+  fn read(&self, buffer: &mut [u8]) -> Result<usize> { Self::read(self, buffer) }
+
+  fn write(&self, buffer: &[u8]) -> Result<usize> { Self::write(self, buffer) }
+
+  fn dentry(&self) -> Option<&Arc<Dentry>> { Some(Self::dentry(self)) }
+
+  fn file(&self) -> Option<&File> { Some(self) }
+}
+
+/// The UART that serves as the console. Without a UART, reading returns the end of the
+/// stream and written data is discarded.
+#[derive(Debug)]
+struct Console;
+
+impl FileDescription for Console {
+  /// Waits for the first byte and returns it together with all bytes that have been
+  /// received in the meantime.
+  fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+    let Some(uart) = crate::arch::console() else {
+      return Ok(0);
+    };
+    if buffer.is_empty() {
+      return Ok(0);
+    }
+
+    buffer[0] = loop {
+      if let Some(byte) = uart.get() {
+        break byte;
+      }
+      core::hint::spin_loop();
+    };
+
+    let mut read = 1;
+    while read < buffer.len() {
+      let Some(byte) = uart.get() else {
+        break;
+      };
+      buffer[read] = byte;
+      read += 1;
+    }
+    Ok(read)
+  }
+
+  fn write(&self, buffer: &[u8]) -> Result<usize> {
+    if let Some(uart) = crate::arch::console() {
+      for &byte in buffer {
+        uart.put(byte);
+      }
+    }
+    Ok(buffer.len())
+  }
+}
+
+/// An entry of a [`FileDescriptorTable`].
+#[derive(Clone)]
+struct Entry {
+  /// The description the file descriptor refers to
+  description:   Arc<dyn FileDescription>,
+  /// Whether the file descriptor is closed when a new program is executed
+  close_on_exec: bool,
+}
+
+/// A table that maps file descriptors to the descriptions they refer to.
+pub struct FileDescriptorTable {
+  /// The entries, indexed by file descriptor
+  entries: spin::Mutex<Vec<Option<Entry>>>,
+}
+
+impl FileDescriptorTable {
+  /// Creates an empty table.
+  #[must_use]
+  pub const fn new() -> Self {
+    Self {
+      entries: spin::Mutex::new(Vec::new()),
+    }
+  }
+
+  /// Makes the console the standard input, output and error of the table.
+  pub fn open_console(&self) {
+    let console: Arc<dyn FileDescription> = Arc::new(Console);
+    for descriptor in [STANDARD_INPUT, STANDARD_OUTPUT, STANDARD_ERROR] {
+      // Inserting cannot fail, as the descriptors are below the maximum
+      let _ = self.insert_at(descriptor, console.clone(), false);
+    }
+  }
+
+  /// Adds `entry` with the lowest free file descriptor that is at least `minimum` and
+  /// returns this file descriptor.
+  fn insert_entry(&self, minimum: usize, entry: Entry) -> Result<usize> {
+    let mut entries = self.entries.lock();
+    let descriptor = (minimum..MAXIMUM_DESCRIPTORS)
+      .find(|&descriptor| entries.get(descriptor).is_none_or(Option::is_none))
+      .ok_or(Error::TooManyOpenFiles)?;
+
+    if entries.len() <= descriptor {
+      entries.resize(descriptor + 1, None);
+    }
+    entries[descriptor] = Some(entry);
+    Ok(descriptor)
+  }
+
+  /// Adds `description` with the lowest free file descriptor and returns this file
+  /// descriptor.
+  ///
+  /// #### Errors
+  ///
+  /// If the table is full, [`Error::TooManyOpenFiles`] is returned.
+  pub fn insert(&self, description: Arc<dyn FileDescription>, close_on_exec: bool) -> Result<usize> {
+    self.insert_entry(
+      0,
+      Entry {
+        description,
+        close_on_exec,
+      },
+    )
+  }
+
+  /// Makes `descriptor` refer to `description`. If `descriptor` was open, it is closed
+  /// first.
+  ///
+  /// #### Errors
+  ///
+  /// If `descriptor` is too large, [`Error::BadDescriptor`] is returned.
+  pub fn insert_at(
+    &self,
+    descriptor: usize,
+    description: Arc<dyn FileDescription>,
+    close_on_exec: bool,
+  ) -> Result<()> {
+    if descriptor >= MAXIMUM_DESCRIPTORS {
+      return Err(Error::BadDescriptor);
+    }
+
+    let mut entries = self.entries.lock();
+    if entries.len() <= descriptor {
+      entries.resize(descriptor + 1, None);
+    }
+    let previous = entries[descriptor].replace(Entry {
+      description,
+      close_on_exec,
+    });
+    // The previous description may be released here, which must not happen under the lock
+    drop(entries);
+    drop(previous);
+    Ok(())
+  }
+
+  /// Returns the entry of `descriptor`.
+  fn entry(&self, descriptor: usize) -> Result<Entry> {
+    self
+      .entries
+      .lock()
+      .get(descriptor)
+      .cloned()
+      .flatten()
+      .ok_or(Error::BadDescriptor)
+  }
+
+  /// Returns the description `descriptor` refers to.
+  ///
+  /// #### Errors
+  ///
+  /// If `descriptor` is not open, [`Error::BadDescriptor`] is returned.
+  pub fn get(&self, descriptor: usize) -> Result<Arc<dyn FileDescription>> {
+    Ok(self.entry(descriptor)?.description)
+  }
+
+  /// Closes `descriptor`. The description is released when no descriptor refers to it
+  /// anymore.
+  ///
+  /// #### Errors
+  ///
+  /// If `descriptor` is not open, [`Error::BadDescriptor`] is returned.
+  pub fn close(&self, descriptor: usize) -> Result<()> {
+    let entry = self
+      .entries
+      .lock()
+      .get_mut(descriptor)
+      .and_then(Option::take)
+      .ok_or(Error::BadDescriptor)?;
+    drop(entry);
+    Ok(())
+  }
+
+  /// Creates a new file descriptor that refers to the same description as `descriptor`
+  /// and returns it. The new file descriptor is the lowest free one that is at least
+  /// `minimum`.
+  ///
+  /// #### Errors
+  ///
+  /// If `descriptor` is not open, [`Error::BadDescriptor`] is returned. If there is no
+  /// free file descriptor, [`Error::TooManyOpenFiles`] is returned.
+  pub fn duplicate(&self, descriptor: usize, minimum: usize, close_on_exec: bool) -> Result<usize> {
+    let Entry { description, .. } = self.entry(descriptor)?;
+    self.insert_entry(
+      minimum,
+      Entry {
+        description,
+        close_on_exec,
+      },
+    )
+  }
+
+  /// Makes `target` refer to the same description as `descriptor`, closing `target`
+  /// first if it was open (like `dup2`). If both are the same, nothing happens.
+  ///
+  /// #### Errors
+  ///
+  /// If `descriptor` is not open or `target` is too large, [`Error::BadDescriptor`] is
+  /// returned.
+  pub fn duplicate_to(&self, descriptor: usize, target: usize, close_on_exec: bool) -> Result<()> {
+    let description = self.get(descriptor)?;
+    if descriptor == target {
+      return Ok(());
+    }
+    self.insert_at(target, description, close_on_exec)
+  }
+
+  /// Returns whether `descriptor` is closed when a new program is executed.
+  ///
+  /// #### Errors
+  ///
+  /// If `descriptor` is not open, [`Error::BadDescriptor`] is returned.
+  pub fn close_on_exec(&self, descriptor: usize) -> Result<bool> { Ok(self.entry(descriptor)?.close_on_exec) }
+
+  /// Sets whether `descriptor` is closed when a new program is executed.
+  ///
+  /// #### Errors
+  ///
+  /// If `descriptor` is not open, [`Error::BadDescriptor`] is returned.
+  pub fn set_close_on_exec(&self, descriptor: usize, close_on_exec: bool) -> Result<()> {
+    let mut entries = self.entries.lock();
+    let entry = entries
+      .get_mut(descriptor)
+      .and_then(Option::as_mut)
+      .ok_or(Error::BadDescriptor)?;
+    entry.close_on_exec = close_on_exec;
+    Ok(())
+  }
+
+  /// Closes all file descriptors that are to be closed when a new program is executed.
+  /// This function is called when a process executes a new program.
+  pub fn close_all_on_exec(&self) {
+    let mut entries = self.entries.lock();
+    let closed: Vec<Entry> = entries
+      .iter_mut()
+      .filter_map(|entry| entry.take_if(|entry| entry.close_on_exec))
+      .collect();
+    drop(entries);
+    drop(closed);
+  }
+}
+
+impl Clone for FileDescriptorTable {
+  /// Creates a table whose file descriptors refer to the same descriptions, as done for a
+  /// new process.
+  fn clone(&self) -> Self {
+    Self {
+      entries: spin::Mutex::new(self.entries.lock().clone()),
+    }
+  }
+}
+
+impl Default for FileDescriptorTable {
+  fn default() -> Self { Self::new() }
+}
+
+impl core::fmt::Debug for FileDescriptorTable {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    let open = self.entries.lock().iter().flatten().count();
+    f.debug_struct("FileDescriptorTable")
+      .field("open", &open)
+      .finish()
+  }
+}
+
+/// The file descriptor table the kernel uses until there are processes.
+static KERNEL_TABLE: FileDescriptorTable = FileDescriptorTable::new();
+
+/// Returns the file descriptor table of the running program. There are no processes yet,
+/// so this is always the table of the kernel.
+#[must_use]
+pub fn current() -> &'static FileDescriptorTable { &KERNEL_TABLE }
+
+/// Opens the console as the standard input, output and error in the table of the kernel.
+pub fn initialize() { KERNEL_TABLE.open_console(); }
@@ -0,0 +1,1482 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains a driver for the second extended file system (ext2). Unlike FAT,
+//! ext2 stores permissions, has inodes and knows hard and symbolic links, so it matches
+//! the model of the VFS directly. Images are created on the host with `mke2fs -d` and
+//! checked with `e2fsck`.
+//!
+//! The blocks of the file system are divided into groups. Every group has a bitmap of
+//! its used blocks, a bitmap of its used inodes and a part of the inode table; the group
+//! descriptors that follow the superblock tell where they are. An inode addresses its
+//! data with 12 direct block pointers and a single, a double and a triple indirect block.
+//! Blocks that are not allocated (holes) read as zeros. Directories are files that
+//! contain a list of entries of variable length.
+//!
+//! The driver supports the revisions 0 and 1 of the format with the features
+//! `filetype`, `sparse_super` and `large_file`; file systems with other incompatible
+//! features (e.g. the extents of ext4) are rejected. Indexed directories (`dir_index`)
+//! are read as the plain lists they also are, and lose their index when they are
+//! changed. All changes are written to the device immediately, but the backup copies of
+//! the superblock and the group descriptors are not updated.
+//!
+//! The format is described in "The Second Extended File System" by Dave Poirier.
+
+use alloc::{
+  collections::BTreeMap,
+  string::String,
+  sync::{
+    Arc,
+    Weak,
+  },
+  vec,
+  vec::Vec,
+};
+
+use super::{
+  block::BlockDevice,
+  DirectoryEntry,
+  Error,
+  FileType,
+  Inode,
+  Metadata,
+  Result,
+};
+
+/// The position of the superblock in bytes.
+const SUPERBLOCK_POSITION: u64 = 1024;
+/// The size of the superblock in bytes.
+const SUPERBLOCK_SIZE: usize = 1024;
+/// The magic number in the superblock.
+const MAGIC: u16 = 0xEF53;
+/// The size of a group descriptor in bytes.
+const GROUP_DESCRIPTOR_SIZE: usize = 32;
+/// The size of an inode in revision 0, which larger inodes start with.
+const GOOD_OLD_INODE_SIZE: usize = 128;
+/// The first inode that is not reserved in revision 0.
+const GOOD_OLD_FIRST_INODE: u32 = 11;
+/// The number of the root directory's inode.
+const ROOT_INODE: u32 = 2;
+
+/// The number of block pointers in an inode.
+const BLOCK_POINTERS: usize = 15;
+/// The number of direct block pointers in an inode, which are followed by the pointers
+/// to the single, double and triple indirect block.
+const DIRECT_BLOCKS: usize = 12;
+/// The size of the unit in which an inode counts its blocks.
+const SECTOR_SIZE: u64 = 512;
+/// Symbolic links with shorter targets store them in the block pointers.
+const FAST_SYMBOLIC_LINK_LENGTH: usize = 60;
+
+/// The size of a directory entry without its name.
+const ENTRY_HEADER_SIZE: usize = 8;
+/// The maximum length of a name in bytes.
+const MAXIMUM_NAME_LENGTH: usize = 255;
+/// The maximum number of directory entries that refer to one inode.
+const MAXIMUM_LINKS: u16 = 32_000;
+/// The inode flag that marks a directory with an index (`dir_index`).
+const INDEX_FLAG: u32 = 0x1000;
+
+/// The features of the file system that the driver knows about.
+mod feature {
+  /// Directory entries contain the type of the file (incompatible).
+  pub const FILE_TYPE: u32 = 0x0002;
+  /// Only some groups contain backups of the superblock (read-only compatible).
+  pub const SPARSE_SUPERBLOCK: u32 = 0x0001;
+  /// Regular files may be larger than 2 GiB (read-only compatible).
+  pub const LARGE_FILE: u32 = 0x0002;
+}
+
+/// The bits of an inode's mode.
+mod mode {
+  /// The bits that hold the type of the file.
+  pub const TYPE_MASK: u16 = 0xF000;
+  /// A regular file
+  pub const REGULAR: u16 = 0x8000;
+  /// A directory
+  pub const DIRECTORY: u16 = 0x4000;
+  /// A symbolic link
+  pub const SYMBOLIC_LINK: u16 = 0xA000;
+  /// The bits that hold the permissions.
+  pub const PERMISSIONS: u16 = 0o7777;
+}
+
+/// The types of files stored in directory entries.
+mod entry_type {
+  /// A regular file
+  pub const REGULAR: u8 = 1;
+  /// A directory
+  pub const DIRECTORY: u8 = 2;
+  /// A symbolic link
+  pub const SYMBOLIC_LINK: u8 = 7;
+}
+
+/// Reads the little-endian `u16` at `offset` of `bytes`.
+fn read_u16(bytes: &[u8], offset: usize) -> u16 { u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) }
+
+/// Reads the little-endian `u32` at `offset` of `bytes`.
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+  u32::from_le_bytes([
+    bytes[offset],
+    bytes[offset + 1],
+    bytes[offset + 2],
+    bytes[offset + 3],
+  ])
+}
+
+/// Converts a position or size into an index.
+fn index(value: u64) -> Result<usize> { usize::try_from(value).map_err(|_| Error::InvalidArgument) }
+
+/// Returns the size a directory entry with a name of `name_length` bytes needs.
+const fn entry_size(name_length: usize) -> usize { (ENTRY_HEADER_SIZE + name_length + 3) & !3 }
+
+/// Checks that `name` can be stored in a directory entry.
+fn check_name(name: &str) -> Result<()> {
+  if name.len() > MAXIMUM_NAME_LENGTH || name.contains(['/', '\0']) {
+    return Err(Error::InvalidPath);
+  }
+  Ok(())
+}
+
+/// Returns the type of the file with the mode `mode`.
+const fn file_type(mode: u16) -> Result<FileType> {
+  match mode & mode::TYPE_MASK {
+    mode::REGULAR => Ok(FileType::Regular),
+    mode::DIRECTORY => Ok(FileType::Directory),
+    mode::SYMBOLIC_LINK => Ok(FileType::SymbolicLink),
+    // Device files, FIFOs and sockets have no representation in the VFS
+    _ => Err(Error::Unsupported),
+  }
+}
+
+/// The first 128 bytes of an inode as they are stored on the device. The bytes of larger
+/// inodes are not touched.
+#[derive(Clone, Copy)]
+struct RawInode([u8; GOOD_OLD_INODE_SIZE]);
+
+impl RawInode {
+  /// Returns the `u16` at `offset`.
+  fn u16(&self, offset: usize) -> u16 { read_u16(&self.0, offset) }
+
+  /// Returns the `u32` at `offset`.
+  fn u32(&self, offset: usize) -> u32 { read_u32(&self.0, offset) }
+
+  /// Sets the `u16` at `offset` to `value`.
+  fn set_u16(&mut self, offset: usize, value: u16) {
+    self.0[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+  }
+
+  /// Sets the `u32` at `offset` to `value`.
+  fn set_u32(&mut self, offset: usize, value: u32) {
+    self.0[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+  }
+
+  /// Returns the type and permissions of the file.
+  fn mode(&self) -> u16 { self.u16(0) }
+
+  /// Returns whether the inode is a directory.
+  fn is_directory(&self) -> bool { self.mode() & mode::TYPE_MASK == mode::DIRECTORY }
+
+  /// Returns the size of the file in bytes. Only regular files use the upper half.
+  fn size(&self) -> u64 {
+    let upper = if self.mode() & mode::TYPE_MASK == mode::REGULAR {
+      self.u32(108)
+    } else {
+      0
+    };
+    (u64::from(upper) << 32) | u64::from(self.u32(4))
+  }
+
+  /// Sets the size of the file to `size` bytes.
+  #[allow(clippy::cast_possible_truncation)]
+  fn set_size(&mut self, size: u64) {
+    self.set_u32(4, size as u32);
+    if self.mode() & mode::TYPE_MASK == mode::REGULAR {
+      self.set_u32(108, (size >> 32) as u32);
+    }
+  }
+
+  /// Returns the number of directory entries that refer to the inode.
+  fn links(&self) -> u16 { self.u16(26) }
+
+  /// Sets the number of directory entries that refer to the inode.
+  fn set_links(&mut self, links: u16) { self.set_u16(26, links); }
+
+  /// Returns the number of 512-byte sectors the inode occupies, including indirect
+  /// blocks and the block of extended attributes.
+  fn sectors(&self) -> u32 { self.u32(28) }
+
+  /// Adds `sectors` to the number of sectors the inode occupies.
+  fn add_sectors(&mut self, sectors: u64, subtract: bool) {
+    let sectors = u32::try_from(sectors).unwrap_or(u32::MAX);
+    let value = if subtract {
+      self.sectors().saturating_sub(sectors)
+    } else {
+      self.sectors().saturating_add(sectors)
+    };
+    self.set_u32(28, value);
+  }
+
+  /// Returns the block pointer with the index `index`.
+  fn block(&self, index: usize) -> u32 { self.u32(40 + 4 * index) }
+
+  /// Sets the block pointer with the index `index`.
+  fn set_block(&mut self, index: usize, block: u32) { self.set_u32(40 + 4 * index, block); }
+
+  /// Returns the block that holds the extended attributes, or 0.
+  fn attribute_block(&self) -> u32 { self.u32(104) }
+
+  /// Records that the file was changed at `now`; `data` tells whether its contents
+  /// changed.
+  fn touch(&mut self, now: u32, data: bool) {
+    self.set_u32(12, now);
+    if data {
+      self.set_u32(16, now);
+    }
+  }
+}
+
+/// A group descriptor, which tells where the bitmaps and the inode table of a group
+/// are.
+#[derive(Clone, Copy)]
+struct Group {
+  /// The block that holds the bitmap of used blocks
+  block_bitmap: u32,
+  /// The block that holds the bitmap of used inodes
+  inode_bitmap: u32,
+  /// The first block of the inode table
+  inode_table:  u32,
+  /// The number of free blocks
+  free_blocks:  u16,
+  /// The number of free inodes
+  free_inodes:  u16,
+  /// The number of directories
+  directories:  u16,
+}
+
+/// The state of the block and inode allocators.
+struct Allocation {
+  /// The descriptors of all groups
+  groups:      Vec<Group>,
+  /// The number of free blocks of the file system
+  free_blocks: u32,
+  /// The number of free inodes of the file system
+  free_inodes: u32,
+}
+
+/// The layout of an ext2 file system on a device, and the state shared by all its
+/// inodes.
+struct Volume {
+  /// The device the file system is stored on
+  device:           &'static dyn BlockDevice,
+  /// The size of a block in bytes
+  block_size:       u64,
+  /// The number of blocks of the file system
+  block_count:      u32,
+  /// The block that holds the superblock, which is the first block of group 0
+  first_data_block: u32,
+  /// The number of blocks in a group
+  blocks_per_group: u32,
+  /// The number of inodes of the file system
+  inode_count:      u32,
+  /// The number of inodes in a group
+  inodes_per_group: u32,
+  /// The size of an inode in bytes
+  inode_size:       u64,
+  /// The first inode that is not reserved
+  first_inode:      u32,
+  /// Whether directory entries contain the type of the file
+  has_file_types:   bool,
+  /// Whether regular files may be larger than 2 GiB
+  has_large_files:  bool,
+  /// The time the file system was last written, which is used for timestamps if the
+  /// wall-clock time is unknown
+  written:          u32,
+  /// The state of the allocators
+  allocation:       spin::Mutex<Allocation>,
+  /// Serializes all operations that access the device
+  lock:             spin::Mutex<()>,
+  /// All inodes that exist, so that every file is represented by exactly one inode
+  nodes:            spin::Mutex<BTreeMap<u32, Weak<Node>>>,
+}
+
+impl Volume {
+  /// Returns the current time in seconds since the Unix epoch.
+  fn now(&self) -> u32 {
+    // Small deletion times have a special meaning, so the epoch cannot be used instead
+    crate::library::time::realtime().map_or(self.written, |now| {
+      u32::try_from(now.as_secs()).unwrap_or(u32::MAX)
+    })
+  }
+
+  /// Reads `buffer.len()` bytes at the byte `position` of the device.
+  fn read(&self, position: u64, buffer: &mut [u8]) -> Result<()> {
+    super::block::read(self.device, position, buffer)
+  }
+
+  /// Writes `buffer` at the byte `position` of the device.
+  fn write(&self, position: u64, buffer: &[u8]) -> Result<()> {
+    super::block::write(self.device, position, buffer)
+  }
+
+  /// Returns the position of the block `block` in bytes.
+  fn block_position(&self, block: u32) -> u64 { u64::from(block) * self.block_size }
+
+  /// Reads the block `block` completely.
+  fn read_block(&self, block: u32) -> Result<Vec<u8>> {
+    if block >= self.block_count {
+      return Err(Error::Corrupted);
+    }
+    let mut data = vec![0; index(self.block_size)?];
+    self.read(self.block_position(block), &mut data)?;
+    Ok(data)
+  }
+
+  /// Returns the number of block pointers an indirect block holds.
+  const fn pointers_per_block(&self) -> u64 { self.block_size / 4 }
+
+  /// Returns the number of sectors a block occupies.
+  const fn sectors_per_block(&self) -> u64 { self.block_size / SECTOR_SIZE }
+
+  /// Returns the number of the group the inode `number` belongs to.
+  const fn inode_group(&self, number: u32) -> usize { ((number - 1) / self.inodes_per_group) as usize }
+
+  /// Returns the position of the inode `number` in bytes.
+  fn inode_position(&self, number: u32) -> Result<u64> {
+    if number == 0 || number > self.inode_count {
+      return Err(Error::Corrupted);
+    }
+
+    let table = self.allocation.lock().groups[self.inode_group(number)].inode_table;
+    let within = u64::from((number - 1) % self.inodes_per_group);
+    Ok(self.block_position(table) + within * self.inode_size)
+  }
+
+  /// Reads the inode `number`.
+  fn read_inode(&self, number: u32) -> Result<RawInode> {
+    let mut inode = RawInode([0; GOOD_OLD_INODE_SIZE]);
+    self.read(self.inode_position(number)?, &mut inode.0)?;
+    Ok(inode)
+  }
+
+  /// Writes the inode `number`.
+  fn write_inode(&self, number: u32, inode: &RawInode) -> Result<()> {
+    self.write(self.inode_position(number)?, &inode.0)
+  }
+
+  /// Writes the descriptor of the group `group` and the numbers of free blocks and
+  /// inodes in the superblock.
+  fn write_group(&self, allocation: &Allocation, group: usize) -> Result<()> {
+    let descriptor = &allocation.groups[group];
+    let mut bytes = [0; 6];
+    bytes[..2].copy_from_slice(&descriptor.free_blocks.to_le_bytes());
+    bytes[2..4].copy_from_slice(&descriptor.free_inodes.to_le_bytes());
+    bytes[4..].copy_from_slice(&descriptor.directories.to_le_bytes());
+    let table = self.block_position(self.first_data_block + 1);
+    self.write(table + (group * GROUP_DESCRIPTOR_SIZE) as u64 + 12, &bytes)?;
+
+    let mut counts = [0; 8];
+    counts[..4].copy_from_slice(&allocation.free_blocks.to_le_bytes());
+    counts[4..].copy_from_slice(&allocation.free_inodes.to_le_bytes());
+    self.write(SUPERBLOCK_POSITION + 12, &counts)
+  }
+
+  /// Returns the number of blocks of the group `group`; the last group may be smaller.
+  fn blocks_in_group(&self, group: usize) -> u32 {
+    let first = self.first_data_block + self.blocks_per_group * u32::try_from(group).unwrap_or(u32::MAX);
+    (self.block_count - first).min(self.blocks_per_group)
+  }
+
+  /// Finds a bit that is not set in the first `count` bits of the bitmap stored in the
+  /// block `bitmap`, sets it and returns its index.
+  fn take_bit(&self, bitmap: u32, count: u32) -> Result<Option<u32>> {
+    let mut data = self.read_block(bitmap)?;
+    let Some(bit) = (0..count).find(|bit| data[(bit / 8) as usize] & (1 << (bit % 8)) == 0) else {
+      return Ok(None);
+    };
+
+    data[(bit / 8) as usize] |= 1 << (bit % 8);
+    self.write(
+      self.block_position(bitmap) + u64::from(bit / 8),
+      &data[(bit / 8) as usize..=(bit / 8) as usize],
+    )?;
+    Ok(Some(bit))
+  }
+
+  /// Clears the bit `bit` in the bitmap stored in the block `bitmap`.
+  fn clear_bit(&self, bitmap: u32, bit: u32) -> Result<()> {
+    let position = self.block_position(bitmap) + u64::from(bit / 8);
+    let mut byte = [0];
+    self.read(position, &mut byte)?;
+    if byte[0] & (1 << (bit % 8)) == 0 {
+      return Err(Error::Corrupted);
+    }
+    byte[0] &= !(1 << (bit % 8));
+    self.write(position, &byte)
+  }
+
+  /// Allocates a block, preferably in the group `preferred`, and fills it with zeros.
+  fn allocate_block(&self, preferred: usize) -> Result<u32> {
+    let mut allocation = self.allocation.lock();
+    let count = allocation.groups.len();
+    for offset in 0..count {
+      let group = (preferred + offset) % count;
+      let descriptor = allocation.groups[group];
+      if descriptor.free_blocks == 0 {
+        continue;
+      }
+      let Some(bit) = self.take_bit(descriptor.block_bitmap, self.blocks_in_group(group))? else {
+        continue;
+      };
+
+      allocation.groups[group].free_blocks -= 1;
+      allocation.free_blocks = allocation.free_blocks.saturating_sub(1);
+      self.write_group(&allocation, group)?;
+      drop(allocation);
+
+      let block =
+        self.first_data_block + self.blocks_per_group * u32::try_from(group).unwrap_or(u32::MAX) + bit;
+      self.write(self.block_position(block), &vec![0; index(self.block_size)?])?;
+      return Ok(block);
+    }
+
+    Err(Error::NoSpace)
+  }
+
+  /// Frees the block `block`.
+  fn free_block(&self, block: u32) -> Result<()> {
+    if block < self.first_data_block || block >= self.block_count {
+      return Err(Error::Corrupted);
+    }
+
+    let mut allocation = self.allocation.lock();
+    let group = ((block - self.first_data_block) / self.blocks_per_group) as usize;
+    let bit = (block - self.first_data_block) % self.blocks_per_group;
+    self.clear_bit(allocation.groups[group].block_bitmap, bit)?;
+    allocation.groups[group].free_blocks += 1;
+    allocation.free_blocks += 1;
+    self.write_group(&allocation, group)
+  }
+
+  /// Allocates an inode, preferably in the group `preferred`, and returns its number.
+  fn allocate_inode(&self, preferred: usize, directory: bool) -> Result<u32> {
+    let mut allocation = self.allocation.lock();
+    let count = allocation.groups.len();
+    for offset in 0..count {
+      let group = (preferred + offset) % count;
+      let descriptor = allocation.groups[group];
+      if descriptor.free_inodes == 0 {
+        continue;
+      }
+      let Some(bit) = self.take_bit(descriptor.inode_bitmap, self.inodes_per_group)? else {
+        continue;
+      };
+
+      let number = self.inodes_per_group * u32::try_from(group).unwrap_or(u32::MAX) + bit + 1;
+      // Reserved inodes are marked as used, so this only happens on damaged file systems
+      if number < self.first_inode || number > self.inode_count {
+        return Err(Error::Corrupted);
+      }
+
+      let descriptor = &mut allocation.groups[group];
+      descriptor.free_inodes -= 1;
+      if directory {
+        descriptor.directories += 1;
+      }
+      allocation.free_inodes = allocation.free_inodes.saturating_sub(1);
+      self.write_group(&allocation, group)?;
+      return Ok(number);
+    }
+
+    Err(Error::NoSpace)
+  }
+
+  /// Frees the inode `number`.
+  fn free_inode(&self, number: u32, directory: bool) -> Result<()> {
+    let mut allocation = self.allocation.lock();
+    let group = self.inode_group(number);
+    let bit = (number - 1) % self.inodes_per_group;
+    self.clear_bit(allocation.groups[group].inode_bitmap, bit)?;
+
+    let descriptor = &mut allocation.groups[group];
+    descriptor.free_inodes += 1;
+    if directory {
+      descriptor.directories = descriptor.directories.saturating_sub(1);
+    }
+    allocation.free_inodes += 1;
+    self.write_group(&allocation, group)
+  }
+
+  /// Returns the index of the block pointer in the inode and the indices in the indirect
+  /// blocks that lead to the block with the index `logical` of a file.
+  fn path(&self, logical: u64) -> Result<(usize, Vec<u64>)> {
+    if logical < DIRECT_BLOCKS as u64 {
+      return Ok((index(logical)?, Vec::new()));
+    }
+
+    let per_block = self.pointers_per_block();
+    let mut remaining = logical - DIRECT_BLOCKS as u64;
+    let mut span = per_block;
+    for level in 1..=3 {
+      if remaining < span {
+        let mut indices = vec![0; level];
+        for position in indices.iter_mut().rev() {
+          *position = remaining % per_block;
+          remaining /= per_block;
+        }
+        return Ok((DIRECT_BLOCKS + level - 1, indices));
+      }
+      remaining -= span;
+      span *= per_block;
+    }
+
+    // The file would be larger than the block pointers can address
+    Err(Error::InvalidArgument)
+  }
+
+  /// Returns the block that stores the block with the index `logical` of a file, or
+  /// `None` if the block is not allocated.
+  fn map(&self, inode: &RawInode, logical: u64) -> Result<Option<u32>> {
+    let (pointer, indices) = self.path(logical)?;
+    let mut block = inode.block(pointer);
+    for position in indices {
+      if block == 0 {
+        return Ok(None);
+      }
+      let mut bytes = [0; 4];
+      self.read(self.block_position(block) + position * 4, &mut bytes)?;
+      block = u32::from_le_bytes(bytes);
+    }
+    Ok((block != 0).then_some(block))
+  }
+
+  /// Returns the block that stores the block with the index `logical` of a file,
+  /// allocating it and the indirect blocks that lead to it if required.
+  fn map_or_allocate(&self, inode: &mut RawInode, logical: u64, group: usize) -> Result<u32> {
+    let (pointer, indices) = self.path(logical)?;
+    let mut block = inode.block(pointer);
+    if block == 0 {
+      block = self.allocate_block(group)?;
+      inode.set_block(pointer, block);
+      inode.add_sectors(self.sectors_per_block(), false);
+    }
+
+    for position in indices {
+      let position = self.block_position(block) + position * 4;
+      let mut bytes = [0; 4];
+      self.read(position, &mut bytes)?;
+      block = u32::from_le_bytes(bytes);
+      if block == 0 {
+        block = self.allocate_block(group)?;
+        self.write(position, &block.to_le_bytes())?;
+        inode.add_sectors(self.sectors_per_block(), false);
+      }
+    }
+    Ok(block)
+  }
+
+  /// Frees the blocks of the tree with `level` levels of indirect blocks below the block
+  /// `block` that store the blocks from the index `start` (relative to the tree) onwards,
+  /// and returns the number of freed blocks. If `start` is 0, `block` itself is freed.
+  fn free_tree(&self, block: u32, level: u32, start: u64) -> Result<u64> {
+    if block == 0 {
+      return Ok(0);
+    }
+
+    let mut freed = 0;
+    if level > 0 {
+      let span = self.pointers_per_block().pow(level - 1);
+      let mut data = self.read_block(block)?;
+      let mut changed = false;
+      for child in start / span..self.pointers_per_block() {
+        let offset = index(child * 4)?;
+        let child_start = start.saturating_sub(child * span);
+        freed += self.free_tree(read_u32(&data, offset), level - 1, child_start)?;
+        if child_start == 0 && read_u32(&data, offset) != 0 {
+          data[offset..offset + 4].fill(0);
+          changed = true;
+        }
+      }
+      if start > 0 && changed {
+        self.write(self.block_position(block), &data)?;
+      }
+    }
+
+    if start == 0 {
+      self.free_block(block)?;
+      freed += 1;
+    }
+    Ok(freed)
+  }
+
+  /// Frees all blocks of a file after the first `count` ones.
+  fn truncate_blocks(&self, inode: &mut RawInode, count: u64) -> Result<()> {
+    let mut first = 0;
+    let mut freed = 0;
+    for pointer in 0..BLOCK_POINTERS {
+      let level = u32::try_from((pointer + 1).saturating_sub(DIRECT_BLOCKS)).unwrap_or_default();
+      let span = self.pointers_per_block().pow(level);
+      if count < first + span {
+        let start = count.saturating_sub(first);
+        let result = self.free_tree(inode.block(pointer), level, start);
+        if start == 0 {
+          inode.set_block(pointer, 0);
+        }
+        match result {
+          Ok(count) => freed += count,
+          Err(error) => {
+            inode.add_sectors(freed * self.sectors_per_block(), true);
+            return Err(error);
+          },
+        }
+      }
+      first += span;
+    }
+
+    inode.add_sectors(freed * self.sectors_per_block(), true);
+    Ok(())
+  }
+
+  /// Releases the block of extended attributes of an inode that is destroyed; the block
+  /// may be shared by several inodes.
+  fn release_attributes(&self, inode: &mut RawInode) -> Result<()> {
+    let block = inode.attribute_block();
+    if block == 0 {
+      return Ok(());
+    }
+
+    let position = self.block_position(block) + 4;
+    let mut references = [0; 4];
+    self.read(position, &mut references)?;
+    match u32::from_le_bytes(references) {
+      0 | 1 => self.free_block(block)?,
+      count => self.write(position, &(count - 1).to_le_bytes())?,
+    }
+    inode.set_u32(104, 0);
+    inode.add_sectors(self.sectors_per_block(), true);
+    Ok(())
+  }
+
+  /// Returns whether `inode` is a symbolic link that stores its target in the block
+  /// pointers.
+  fn is_fast_symbolic_link(&self, inode: &RawInode) -> bool {
+    let attribute_sectors = if inode.attribute_block() == 0 {
+      0
+    } else {
+      self.sectors_per_block()
+    };
+    inode.mode() & mode::TYPE_MASK == mode::SYMBOLIC_LINK && u64::from(inode.sectors()) == attribute_sectors
+  }
+
+  /// Reads `buffer.len()` bytes at `offset` of the file `inode`.
+  fn read_data(&self, inode: &RawInode, offset: u64, buffer: &mut [u8]) -> Result<()> {
+    let mut done = 0;
+    while done < buffer.len() {
+      let current = offset + done as u64;
+      let within = current % self.block_size;
+      let length = (buffer.len() - done).min(index(self.block_size - within)?);
+      let part = &mut buffer[done..done + length];
+      match self.map(inode, current / self.block_size)? {
+        Some(block) => self.read(self.block_position(block) + within, part)?,
+        None => part.fill(0),
+      }
+      done += length;
+    }
+    Ok(())
+  }
+
+  /// Writes `buffer` at `offset` of the file `inode`, allocating blocks in the group
+  /// `group` as required, and returns the number of bytes written, which is smaller than
+  /// the length of `buffer` only if an error occurred.
+  fn write_data(
+    &self,
+    inode: &mut RawInode,
+    offset: u64,
+    buffer: &[u8],
+    group: usize,
+  ) -> (usize, Result<()>) {
+    let mut done = 0;
+    while done < buffer.len() {
+      let current = offset + done as u64;
+      let within = current % self.block_size;
+      let length = (buffer.len() - done).min(usize::try_from(self.block_size - within).unwrap_or(usize::MAX));
+      let result = self
+        .map_or_allocate(inode, current / self.block_size, group)
+        .and_then(|block| self.write(self.block_position(block) + within, &buffer[done..done + length]));
+      if let Err(error) = result {
+        return (done, Err(error));
+      }
+      done += length;
+    }
+    (done, Ok(()))
+  }
+
+  /// Reads the entries of the directory `inode`, including `.` and `..`.
+  fn entries(&self, inode: &RawInode) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for logical in 0..inode.size().div_ceil(self.block_size) {
+      let block = self.map(inode, logical)?.ok_or(Error::Corrupted)?;
+      let data = self.read_block(block)?;
+
+      let mut offset = 0;
+      let mut previous = None;
+      while offset < data.len() {
+        let record_length = usize::from(read_u16(&data, offset + 4));
+        let name_length = usize::from(data[offset + 6]);
+        if record_length < ENTRY_HEADER_SIZE
+          || record_length & 3 != 0
+          || offset + record_length > data.len()
+          || ENTRY_HEADER_SIZE + name_length > record_length
+        {
+          return Err(Error::Corrupted);
+        }
+
+        let number = read_u32(&data, offset);
+        if number != 0 {
+          let name = &data[offset + ENTRY_HEADER_SIZE..offset + ENTRY_HEADER_SIZE + name_length];
+          entries.push(Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            inode: number,
+            kind: if self.has_file_types { data[offset + 7] } else { 0 },
+            block,
+            offset,
+            previous,
+          });
+        }
+        previous = Some(offset);
+        offset += record_length;
+      }
+    }
+    Ok(entries)
+  }
+
+  /// Returns the inode `number`, reading it from the device if it is not in use yet.
+  fn node(self: &Arc<Self>, number: u32) -> Result<Arc<Node>> {
+    let mut nodes = self.nodes.lock();
+    if let Some(node) = nodes.get(&number).and_then(Weak::upgrade) {
+      return Ok(node);
+    }
+
+    let inode = self.read_inode(number)?;
+    file_type(inode.mode())?;
+    let node = Arc::new(Node {
+      volume: self.clone(),
+      number,
+      state: spin::Mutex::new(State {
+        inode,
+        deleted: false,
+      }),
+    });
+    nodes.insert(number, Arc::downgrade(&node));
+    Ok(node)
+  }
+}
+
+/// An entry of a directory as it is stored on the device.
+struct Entry {
+  /// The name of the file
+  name:     String,
+  /// The number of the inode the entry refers to
+  inode:    u32,
+  /// The type of the file, or 0 if the file system does not store it
+  kind:     u8,
+  /// The block that holds the entry
+  block:    u32,
+  /// The offset of the entry in the block
+  offset:   usize,
+  /// The offset of the previous entry in the same block, if there is one
+  previous: Option<usize>,
+}
+
+/// The part of an inode that changes.
+struct State {
+  /// The inode as it is stored on the device
+  inode:   RawInode,
+  /// Whether the last directory entry was removed; the inode is destroyed when it is
+  /// dropped
+  deleted: bool,
+}
+
+/// A file, directory or symbolic link of an ext2 file system.
+struct Node {
+  /// The file system the inode belongs to
+  volume: Arc<Volume>,
+  /// The number of the inode
+  number: u32,
+  /// The part of the inode that changes
+  state:  spin::Mutex<State>,
+}
+
+impl Node {
+  /// Returns the group the inode belongs to, in which its blocks are allocated.
+  fn group(&self) -> usize { self.volume.inode_group(self.number) }
+
+  /// Reads the entries of this directory, including `.` and `..`.
+  fn entries(&self) -> Result<Vec<Entry>> {
+    let state = self.state.lock();
+    if !state.inode.is_directory() {
+      return Err(Error::NotADirectory);
+    }
+    self.volume.entries(&state.inode)
+  }
+
+  /// Returns the entry `name` of this directory.
+  fn find(&self, name: &str) -> Result<Entry> {
+    self
+      .entries()?
+      .into_iter()
+      .find(|entry| entry.name == name)
+      .ok_or(Error::NotFound)
+  }
+
+  /// Writes `state` to the device.
+  fn write(&self, state: &State) -> Result<()> { self.volume.write_inode(self.number, &state.inode) }
+
+  /// Adds the entry `name` that refers to the inode `number` of the type `kind` to this
+  /// directory.
+  fn add_entry(&self, name: &str, number: u32, kind: u8) -> Result<()> {
+    let mut state = self.state.lock();
+    let needed = entry_size(name.len());
+    let mut new_entry = vec![0; needed];
+    new_entry[..4].copy_from_slice(&number.to_le_bytes());
+    new_entry[6] = u8::try_from(name.len()).map_err(|_| Error::InvalidPath)?;
+    new_entry[7] = if self.volume.has_file_types { kind } else { 0 };
+    new_entry[ENTRY_HEADER_SIZE..ENTRY_HEADER_SIZE + name.len()].copy_from_slice(name.as_bytes());
+
+    // Look for an entry whose record has enough unused space after its name
+    let blocks = state.inode.size().div_ceil(self.volume.block_size);
+    for logical in 0..blocks {
+      let block = self.volume.map(&state.inode, logical)?.ok_or(Error::Corrupted)?;
+      let mut data = self.volume.read_block(block)?;
+      let mut offset = 0;
+      while offset < data.len() {
+        let record_length = usize::from(read_u16(&data, offset + 4));
+        if record_length < ENTRY_HEADER_SIZE || offset + record_length > data.len() {
+          return Err(Error::Corrupted);
+        }
+        let used = if read_u32(&data, offset) == 0 {
+          0
+        } else {
+          entry_size(usize::from(data[offset + 6]))
+        };
+
+        if record_length - used >= needed {
+          let start = offset + used;
+          if used > 0 {
+            data[offset + 4..offset + 6]
+              .copy_from_slice(&u16::try_from(used).unwrap_or_default().to_le_bytes());
+          }
+          data[start..start + needed].copy_from_slice(&new_entry);
+          data[start + 4..start + 6].copy_from_slice(
+            &u16::try_from(record_length - used)
+              .unwrap_or_default()
+              .to_le_bytes(),
+          );
+          self.volume.write(self.volume.block_position(block), &data)?;
+          return self.changed_directory(&mut state);
+        }
+        offset += record_length;
+      }
+    }
+
+    // Append a block whose only entry is the new one
+    let group = self.group();
+    let block = self.volume.map_or_allocate(&mut state.inode, blocks, group);
+    let result = block.and_then(|block| {
+      let mut data = vec![0; index(self.volume.block_size)?];
+      data[..needed].copy_from_slice(&new_entry);
+      let length = u16::try_from(data.len()).unwrap_or_default();
+      data[4..6].copy_from_slice(&length.to_le_bytes());
+      self.volume.write(self.volume.block_position(block), &data)
+    });
+    if result.is_ok() {
+      let size = state.inode.size() + self.volume.block_size;
+      state.inode.set_size(size);
+    }
+    // The inode is written in any case, as blocks may have been allocated
+    self.changed_directory(&mut state)?;
+    result
+  }
+
+  /// Removes `entry` from this directory.
+  fn remove_entry(&self, entry: &Entry) -> Result<()> {
+    let mut state = self.state.lock();
+    let position = self.volume.block_position(entry.block);
+    let mut header = [0; ENTRY_HEADER_SIZE];
+    self.volume.read(position + entry.offset as u64, &mut header)?;
+
+    match entry.previous {
+      // The previous entry takes over the space of the removed one
+      Some(previous) => {
+        let mut length = [0; 2];
+        self.volume.read(position + previous as u64 + 4, &mut length)?;
+        let length = u16::from_le_bytes(length) + read_u16(&header, 4);
+        self
+          .volume
+          .write(position + previous as u64 + 4, &length.to_le_bytes())?;
+      },
+      // The first entry of a block is marked as unused instead
+      None => self.volume.write(position + entry.offset as u64, &[0; 4])?,
+    }
+    self.changed_directory(&mut state)
+  }
+
+  /// Records that this directory was changed and writes its inode. An index of the
+  /// directory would be outdated, so it is dropped.
+  fn changed_directory(&self, state: &mut State) -> Result<()> {
+    let flags = state.inode.u32(32) & !INDEX_FLAG;
+    state.inode.set_u32(32, flags);
+    state.inode.touch(self.volume.now(), true);
+    self.write(state)
+  }
+
+  /// Changes the number of links of this inode by one and writes it.
+  fn change_links(&self, increment: bool) -> Result<()> {
+    let mut state = self.state.lock();
+    let links = if increment {
+      state
+        .inode
+        .links()
+        .checked_add(1)
+        .filter(|links| *links <= MAXIMUM_LINKS)
+        .ok_or(Error::NoSpace)?
+    } else {
+      state.inode.links().saturating_sub(1)
+    };
+    state.inode.set_links(links);
+    state.inode.touch(self.volume.now(), false);
+    self.write(&state)
+  }
+
+  /// Allocates and writes a new inode with the mode `mode`, stores `data` in it, adds it
+  /// to this directory as `name` and returns it. If the new inode is a directory, `data`
+  /// must start with the entry `.`, which is set to refer to the new inode.
+  fn create_inode(&self, name: &str, mode: u16, data: &[u8]) -> Result<Arc<dyn Inode>> {
+    check_name(name)?;
+    if !self.state.lock().inode.is_directory() {
+      return Err(Error::NotADirectory);
+    }
+    if self.find(name).is_ok() {
+      return Err(Error::AlreadyExists);
+    }
+
+    let directory = mode & mode::TYPE_MASK == mode::DIRECTORY;
+    let group = self.group();
+    let number = self.volume.allocate_inode(group, directory)?;
+    let mut data = data.to_vec();
+    if directory {
+      data[..4].copy_from_slice(&number.to_le_bytes());
+    }
+
+    // Inodes that are larger than the original 128 bytes are initialized completely
+    let mut record = vec![0; index(self.volume.inode_size)?];
+    if record.len() > GOOD_OLD_INODE_SIZE {
+      let extra = u16::try_from(record.len() - GOOD_OLD_INODE_SIZE)
+        .unwrap_or_default()
+        .min(32);
+      record[GOOD_OLD_INODE_SIZE..GOOD_OLD_INODE_SIZE + 2].copy_from_slice(&extra.to_le_bytes());
+    }
+
+    let mut inode = RawInode([0; GOOD_OLD_INODE_SIZE]);
+    inode.set_u16(0, mode);
+    let now = self.volume.now();
+    inode.set_u32(8, now);
+    inode.touch(now, true);
+    inode.set_links(if directory { 2 } else { 1 });
+
+    let mut result = self.volume.write(self.volume.inode_position(number)?, &record);
+    if result.is_ok() {
+      result = if mode & mode::TYPE_MASK == mode::SYMBOLIC_LINK && data.len() < FAST_SYMBOLIC_LINK_LENGTH {
+        inode.0[40..40 + data.len()].copy_from_slice(&data);
+        Ok(())
+      } else {
+        self.volume.write_data(&mut inode, 0, &data, group).1
+      };
+    }
+    inode.set_size(data.len() as u64);
+    let kind = match mode & mode::TYPE_MASK {
+      mode::DIRECTORY => entry_type::DIRECTORY,
+      mode::SYMBOLIC_LINK => entry_type::SYMBOLIC_LINK,
+      _ => entry_type::REGULAR,
+    };
+    // The entry `..` of a new directory refers to this one
+    let result = result
+      .and_then(|()| self.volume.write_inode(number, &inode))
+      .and_then(|()| {
+        if directory {
+          self.change_links(true)
+        } else {
+          Ok(())
+        }
+      })
+      .and_then(|()| {
+        let result = self.add_entry(name, number, kind);
+        if result.is_err() && directory {
+          let _ = self.change_links(false);
+        }
+        result
+      });
+
+    if let Err(error) = result {
+      // The blocks and the inode are released again, the directory entry was not added
+      let _ = self.volume.truncate_blocks(&mut inode, 0);
+      let _ = self.volume.free_inode(number, directory);
+      return Err(error);
+    }
+    Ok(self.volume.node(number)?)
+  }
+}
+
+impl Drop for Node {
+  fn drop(&mut self) {
+    let mut nodes = self.volume.nodes.lock();
+    if nodes
+      .get(&self.number)
+      .is_some_and(|node| node.strong_count() == 0)
+    {
+      nodes.remove(&self.number);
+    }
+    drop(nodes);
+
+    // The last entry was removed, and the inode is not in use anymore
+    let state = self.state.get_mut();
+    if !state.deleted {
+      return;
+    }
+
+    let _guard = self.volume.lock.lock();
+    let inode = &mut state.inode;
+    let directory = inode.is_directory();
+    let result = if self.volume.is_fast_symbolic_link(inode) {
+      Ok(())
+    } else {
+      self.volume.truncate_blocks(inode, 0)
+    }
+    .and_then(|()| self.volume.release_attributes(inode))
+    .and_then(|()| {
+      inode.set_links(0);
+      inode.set_size(0);
+      inode.set_u32(20, self.volume.now());
+      self.volume.write_inode(self.number, inode)
+    })
+    .and_then(|()| self.volume.free_inode(self.number, directory));
+    if let Err(error) = result {
+      log::warn!("Could not free the inode of a removed file: {error}");
+    }
+  }
+}
+
+impl Inode for Node {
+  fn metadata(&self) -> Result<Metadata> {
+    let inode = self.state.lock().inode;
+    Ok(Metadata {
+      inode:     u64::from(self.number),
+      file_type: file_type(inode.mode())?,
+      size:      inode.size(),
+      mode:      inode.mode() & mode::PERMISSIONS,
+      links:     u32::from(inode.links()),
+    })
+  }
+
+  fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+    let _guard = self.volume.lock.lock();
+    let state = self.state.lock();
+    match file_type(state.inode.mode())? {
+      FileType::Regular => {},
+      FileType::Directory => return Err(Error::IsADirectory),
+      FileType::SymbolicLink => return Err(Error::InvalidArgument),
+    }
+
+    let size = state.inode.size();
+    if offset >= size {
+      return Ok(0);
+    }
+    let length = index((size - offset).min(buffer.len() as u64))?;
+    self
+      .volume
+      .read_data(&state.inode, offset, &mut buffer[..length])?;
+    Ok(length)
+  }
+
+  fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize> {
+    let maximum_size = if self.volume.has_large_files {
+      u64::MAX
+    } else {
+      i32::MAX as u64
+    };
+    offset
+      .checked_add(buffer.len() as u64)
+      .filter(|end| *end <= maximum_size)
+      .ok_or(Error::InvalidArgument)?;
+
+    let _guard = self.volume.lock.lock();
+    let mut state = self.state.lock();
+    match file_type(state.inode.mode())? {
+      FileType::Regular => {},
+      FileType::Directory => return Err(Error::IsADirectory),
+      FileType::SymbolicLink => return Err(Error::InvalidArgument),
+    }
+
+    // The inode is written in any case, as blocks may have been allocated
+    let (written, result) = self
+      .volume
+      .write_data(&mut state.inode, offset, buffer, self.group());
+    let size = state.inode.size().max(offset + written as u64);
+    state.inode.set_size(size);
+    state.inode.touch(self.volume.now(), true);
+    self.write(&state)?;
+    result.map(|()| written)
+  }
+
+  fn truncate(&self, size: u64) -> Result<()> {
+    let _guard = self.volume.lock.lock();
+    let mut state = self.state.lock();
+    match file_type(state.inode.mode())? {
+      FileType::Regular => {},
+      FileType::Directory => return Err(Error::IsADirectory),
+      FileType::SymbolicLink => return Err(Error::InvalidArgument),
+    }
+    if !self.volume.has_large_files && size > i32::MAX as u64 {
+      return Err(Error::InvalidArgument);
+    }
+
+    if size < state.inode.size() {
+      let block_size = self.volume.block_size;
+      let result = self
+        .volume
+        .truncate_blocks(&mut state.inode, size.div_ceil(block_size))
+        .and_then(|()| {
+          // The rest of the last block must read as zeros if the file grows again
+          let within = size % block_size;
+          match self.volume.map(&state.inode, size / block_size)? {
+            Some(block) if within != 0 => self.volume.write(
+              self.volume.block_position(block) + within,
+              &vec![0; index(block_size - within)?],
+            ),
+            _ => Ok(()),
+          }
+        });
+      if let Err(error) = result {
+        self.write(&state)?;
+        return Err(error);
+      }
+    }
+
+    // Growing the file only changes its size, the new part is a hole
+    state.inode.set_size(size);
+    state.inode.touch(self.volume.now(), true);
+    self.write(&state)
+  }
+
+  fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+    let _guard = self.volume.lock.lock();
+    let entry = self.find(name)?;
+    Ok(self.volume.node(entry.inode)?)
+  }
+
+  fn create(&self, name: &str, file_type: FileType, mode: u16) -> Result<Arc<dyn Inode>> {
+    let _guard = self.volume.lock.lock();
+    let permissions = mode & mode::PERMISSIONS;
+    match file_type {
+      FileType::Regular => self.create_inode(name, mode::REGULAR | permissions, &[]),
+      FileType::Directory => {
+        // The entries `.` and `..` are the only ones in the first block; `create_inode`
+        // fills in the number of `.`
+        let block_size = index(self.volume.block_size)?;
+        let mut data = vec![0; block_size];
+        data[4..6].copy_from_slice(&12_u16.to_le_bytes());
+        data[6] = 1;
+        data[7] = if self.volume.has_file_types {
+          entry_type::DIRECTORY
+        } else {
+          0
+        };
+        data[8] = b'.';
+        data[12..16].copy_from_slice(&self.number.to_le_bytes());
+        data[16..18].copy_from_slice(&u16::try_from(block_size - 12).unwrap_or_default().to_le_bytes());
+        data[18] = 2;
+        data[19] = data[7];
+        data[20..22].copy_from_slice(b"..");
+
+        self.create_inode(name, mode::DIRECTORY | permissions, &data)
+      },
+      FileType::SymbolicLink => Err(Error::InvalidArgument),
+    }
+  }
+
+  fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>> {
+    if target.is_empty() || target.len() >= index(self.volume.block_size)? {
+      return Err(Error::InvalidArgument);
+    }
+    let _guard = self.volume.lock.lock();
+    self.create_inode(name, mode::SYMBOLIC_LINK | 0o777, target.as_bytes())
+  }
+
+  fn link(&self, name: &str, inode: &Arc<dyn Inode>) -> Result<()> {
+    check_name(name)?;
+    let _guard = self.volume.lock.lock();
+    let number = u32::try_from(inode.metadata()?.inode).map_err(|_| Error::CrossesDevices)?;
+    let node = self
+      .volume
+      .nodes
+      .lock()
+      .get(&number)
+      .and_then(Weak::upgrade)
+      .ok_or(Error::CrossesDevices)?;
+
+    // The number alone does not identify the inode, as it may belong to another volume
+    if !core::ptr::addr_eq(Arc::as_ptr(&node), Arc::as_ptr(inode)) {
+      return Err(Error::CrossesDevices);
+    }
+    let (links, mode) = {
+      let state = node.state.lock();
+      (state.inode.links(), state.inode.mode())
+    };
+    // A file whose last entry was removed cannot get a new one
+    if links == 0 {
+      return Err(Error::NotFound);
+    }
+    if self.find(name).is_ok() {
+      return Err(Error::AlreadyExists);
+    }
+
+    let kind = match file_type(mode)? {
+      FileType::Regular => entry_type::REGULAR,
+      FileType::Directory => return Err(Error::IsADirectory),
+      FileType::SymbolicLink => entry_type::SYMBOLIC_LINK,
+    };
+    node.change_links(true)?;
+    if let Err(error) = self.add_entry(name, number, kind) {
+      let _ = node.change_links(false);
+      return Err(error);
+    }
+    Ok(())
+  }
+
+  fn unlink(&self, name: &str) -> Result<()> {
+    // Declared before the guard, so that the inode is dropped after the lock is released
+    let node;
+    let _guard = self.volume.lock.lock();
+
+    let entry = self.find(name)?;
+    node = self.volume.node(entry.inode)?;
+    let directory = node.state.lock().inode.is_directory();
+    if directory
+      && node
+        .entries()?
+        .iter()
+        .any(|entry| entry.name != "." && entry.name != "..")
+    {
+      return Err(Error::NotEmpty);
+    }
+
+    self.remove_entry(&entry)?;
+    let mut state = node.state.lock();
+    if directory {
+      // The entry `..` of the removed directory referred to this one
+      state.inode.set_links(0);
+      self.change_links(false)?;
+    } else {
+      let links = state.inode.links().saturating_sub(1);
+      state.inode.set_links(links);
+    }
+    state.inode.touch(self.volume.now(), false);
+    state.deleted = state.inode.links() == 0;
+    node.write(&state)
+  }
+
+  fn read_dir(&self) -> Result<Vec<DirectoryEntry>> {
+    let _guard = self.volume.lock.lock();
+    let mut entries = Vec::new();
+    for entry in self.entries()? {
+      let file_type = match entry.kind {
+        _ if entry.name == "." || entry.name == ".." => continue,
+        entry_type::REGULAR => FileType::Regular,
+        entry_type::DIRECTORY => FileType::Directory,
+        entry_type::SYMBOLIC_LINK => FileType::SymbolicLink,
+        // The type is only stored in the inode
+        0 => match file_type(self.volume.read_inode(entry.inode)?.mode()) {
+          Ok(file_type) => file_type,
+          Err(_) => continue,
+        },
+        // Device files, FIFOs and sockets are not shown
+        _ => continue,
+      };
+      entries.push(DirectoryEntry {
+        name: entry.name,
+        inode: u64::from(entry.inode),
+        file_type,
+      });
+    }
+    Ok(entries)
+  }
+
+  fn read_link(&self) -> Result<String> {
+    let _guard = self.volume.lock.lock();
+    let state = self.state.lock();
+    if state.inode.mode() & mode::TYPE_MASK != mode::SYMBOLIC_LINK {
+      return Err(Error::InvalidArgument);
+    }
+
+    let length = index(state.inode.size())?;
+    let target = if self.volume.is_fast_symbolic_link(&state.inode) {
+      state
+        .inode
+        .0
+        .get(40..40 + length)
+        .ok_or(Error::Corrupted)?
+        .to_vec()
+    } else {
+      let mut target = vec![0; length];
+      self.volume.read_data(&state.inode, 0, &mut target)?;
+      target
+    };
+    String::from_utf8(target).map_err(|_| Error::Corrupted)
+  }
+}
+
+/// An ext2 file system on a block device.
+pub struct Ext2 {
+  /// The layout and the shared state of the file system
+  volume: Arc<Volume>,
+  /// The root directory
+  root:   Arc<Node>,
+}
+
+impl core::fmt::Debug for Ext2 {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Ext2")
+      .field("block_size", &self.volume.block_size)
+      .field("blocks", &self.volume.block_count)
+      .field("inodes", &self.volume.inode_count)
+      .finish_non_exhaustive()
+  }
+}
+
+impl Ext2 {
+  /// Reads the superblock and the group descriptors of the ext2 file system on `device`
+  /// and returns the file system.
+  ///
+  /// #### Errors
+  ///
+  /// If there is no ext2 file system on the device, [`Error::InvalidArgument`] is
+  /// returned. If the file system uses features the driver does not support,
+  /// [`Error::Unsupported`] is returned.
+  pub fn new(device: &'static dyn BlockDevice) -> Result<Arc<Self>> {
+    let mut superblock = vec![0; SUPERBLOCK_SIZE];
+    super::block::read(device, SUPERBLOCK_POSITION, &mut superblock)?;
+    let log_block_size = read_u32(&superblock, 24);
+    if read_u16(&superblock, 56) != MAGIC || log_block_size > 6 {
+      return Err(Error::InvalidArgument);
+    }
+
+    let revision = read_u32(&superblock, 76);
+    let (first_inode, inode_size, incompatible, read_only_compatible) = if revision == 0 {
+      (GOOD_OLD_FIRST_INODE, GOOD_OLD_INODE_SIZE as u64, 0, 0)
+    } else {
+      (
+        read_u32(&superblock, 84),
+        u64::from(read_u16(&superblock, 88)),
+        read_u32(&superblock, 96),
+        read_u32(&superblock, 100),
+      )
+    };
+    if incompatible & !feature::FILE_TYPE != 0
+      || read_only_compatible & !(feature::SPARSE_SUPERBLOCK | feature::LARGE_FILE) != 0
+    {
+      return Err(Error::Unsupported);
+    }
+
+    let block_size = 1024_u64 << log_block_size;
+    let block_count = read_u32(&superblock, 4);
+    let first_data_block = read_u32(&superblock, 20);
+    let blocks_per_group = read_u32(&superblock, 32);
+    let inode_count = read_u32(&superblock, 0);
+    let inodes_per_group = read_u32(&superblock, 40);
+    let bits_per_block = block_size * 8;
+    let is_valid = first_data_block < block_count
+      && (1..=bits_per_block).contains(&u64::from(blocks_per_group))
+      && (1..=bits_per_block).contains(&u64::from(inodes_per_group))
+      && inode_size.is_power_of_two()
+      && (GOOD_OLD_INODE_SIZE as u64..=block_size).contains(&inode_size)
+      && first_inode > ROOT_INODE
+      && u64::from(block_count) * block_size <= device.block_count() * device.block_size() as u64;
+    if !is_valid {
+      return Err(Error::Corrupted);
+    }
+
+    let group_count = index(u64::from(
+      (block_count - first_data_block).div_ceil(blocks_per_group),
+    ))?;
+    if u64::from(inode_count) > group_count as u64 * u64::from(inodes_per_group) {
+      return Err(Error::Corrupted);
+    }
+
+    // The group descriptors start in the block after the superblock
+    let mut descriptors = vec![0; group_count * GROUP_DESCRIPTOR_SIZE];
+    super::block::read(
+      device,
+      u64::from(first_data_block + 1) * block_size,
+      &mut descriptors,
+    )?;
+    let groups = descriptors
+      .chunks_exact(GROUP_DESCRIPTOR_SIZE)
+      .map(|descriptor| {
+        let group = Group {
+          block_bitmap: read_u32(descriptor, 0),
+          inode_bitmap: read_u32(descriptor, 4),
+          inode_table:  read_u32(descriptor, 8),
+          free_blocks:  read_u16(descriptor, 12),
+          free_inodes:  read_u16(descriptor, 14),
+          directories:  read_u16(descriptor, 16),
+        };
+        let table_blocks = (u64::from(inodes_per_group) * inode_size).div_ceil(block_size);
+        let is_valid = group.block_bitmap < block_count
+          && group.inode_bitmap < block_count
+          && u64::from(group.inode_table) + table_blocks <= u64::from(block_count);
+        is_valid.then_some(group).ok_or(Error::Corrupted)
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    // The counts in the superblock are updated lazily by other implementations
+    let free_blocks = groups.iter().map(|group| u32::from(group.free_blocks)).sum();
+    let free_inodes = groups.iter().map(|group| u32::from(group.free_inodes)).sum();
+    let volume = Arc::new(Volume {
+      device,
+      block_size,
+      block_count,
+      first_data_block,
+      blocks_per_group,
+      inode_count,
+      inodes_per_group,
+      inode_size,
+      first_inode,
+      has_file_types: incompatible & feature::FILE_TYPE != 0,
+      has_large_files: read_only_compatible & feature::LARGE_FILE != 0,
+      written: read_u32(&superblock, 48),
+      allocation: spin::Mutex::new(Allocation {
+        groups,
+        free_blocks,
+        free_inodes,
+      }),
+      lock: spin::Mutex::new(()),
+      nodes: spin::Mutex::new(BTreeMap::new()),
+    });
+
+    let root = volume.node(ROOT_INODE)?;
+    if !root.state.lock().inode.is_directory() {
+      return Err(Error::Corrupted);
+    }
+    Ok(Arc::new(Self { volume, root }))
+  }
+}
+
+impl super::FileSystem for Ext2 {
+  fn name(&self) -> &'static str { "ext2" }
+
+  fn root(&self) -> Arc<dyn Inode> { self.root.clone() }
+
+  fn sync(&self) -> Result<()> {
+    let _guard = self.volume.lock.lock();
+    self.volume.device.flush()
+  }
+}
@@ -0,0 +1,1237 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains a driver for the FAT32 file system, so that the kernel can
+//! exchange files with the host via disk images that tools like `mkfs.vfat` and `mtools`
+//! create and inspect.
+//!
+//! The driver reads and writes files, creates and removes files and directories, and
+//! understands long file names (VFAT). Names are compared case-insensitively, and every
+//! file created gets a short name (e.g. `LONGFI~1.TXT`) in addition to its long name.
+//! FAT knows neither symbolic links nor hard links, and it does not store permissions;
+//! the read-only attribute is reported as missing write permissions.
+//!
+//! FAT has no inode numbers. The driver uses the position of a file's directory entry on
+//! the device instead, which stays the same as long as the file exists.
+//!
+//! The format is described in Microsoft's "FAT32 File System Specification", version
+//! 1.03.
+
+use alloc::{
+  collections::BTreeMap,
+  string::String,
+  sync::{
+    Arc,
+    Weak,
+  },
+  vec,
+  vec::Vec,
+};
+
+use super::{
+  block::BlockDevice,
+  DirectoryEntry,
+  Error,
+  FileType,
+  Inode,
+  Metadata,
+  Result,
+};
+use crate::library::mem::heap;
+
+/// The size of a directory entry in bytes.
+const ENTRY_SIZE: usize = 32;
+/// The maximum number of entries of a directory.
+const MAXIMUM_ENTRIES: usize = 65_536;
+/// The inode number of the root directory, which has no directory entry.
+const ROOT_INODE: u64 = 1;
+
+/// The bits of a FAT entry that hold the cluster number.
+const CLUSTER_MASK: u32 = 0x0FFF_FFFF;
+/// FAT entries from this value onwards mark the end of a cluster chain.
+const END_OF_CHAIN: u32 = 0x0FFF_FFF8;
+/// The value of a FAT entry that marks a free cluster.
+const FREE: u32 = 0;
+/// The value the free cluster count has in the FS information sector if it is unknown.
+const UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// The first byte of a directory entry that is not used anymore.
+const DELETED: u8 = 0xE5;
+/// The flag in the sequence number of a long name entry that marks the last entry.
+const LAST_LONG_ENTRY: u8 = 0x40;
+/// The maximum length of a long name in UTF-16 code units.
+const MAXIMUM_NAME_LENGTH: usize = 255;
+/// The offsets of the 13 UTF-16 code units that a long name entry holds.
+const LONG_NAME_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// The date 1980-01-01, which is used if the wall-clock time is unknown.
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+
+/// The attributes of a directory entry.
+mod attribute {
+  /// The file must not be written.
+  pub const READ_ONLY: u8 = 0x01;
+  /// The entry holds the label of the volume.
+  pub const VOLUME_ID: u8 = 0x08;
+  /// The entry is a directory.
+  pub const DIRECTORY: u8 = 0x10;
+  /// The file was changed since the last backup.
+  pub const ARCHIVE: u8 = 0x20;
+  /// The combination of attributes that marks a long name entry.
+  pub const LONG_NAME: u8 = 0x0F;
+  /// The attributes that are checked to detect a long name entry.
+  pub const LONG_NAME_MASK: u8 = 0x3F;
+}
+
+/// The flags that mark the parts of a short name that are displayed in lowercase.
+mod case {
+  /// The base name is lowercase.
+  pub const BASE: u8 = 0x08;
+  /// The extension is lowercase.
+  pub const EXTENSION: u8 = 0x10;
+}
+
+/// Reads the little-endian `u16` at `offset` of `bytes`.
+fn read_u16(bytes: &[u8], offset: usize) -> u16 { u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) }
+
+/// Reads the little-endian `u32` at `offset` of `bytes`.
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+  u32::from_le_bytes([
+    bytes[offset],
+    bytes[offset + 1],
+    bytes[offset + 2],
+    bytes[offset + 3],
+  ])
+}
+
+/// Stores `cluster` as the first cluster of the short entry `entry`, whose high and low
+/// halves are stored separately.
+fn set_first_cluster(entry: &mut [u8], cluster: u32) {
+  entry[20..22].copy_from_slice(&cluster.to_le_bytes()[2..]);
+  entry[26..28].copy_from_slice(&cluster.to_le_bytes()[..2]);
+}
+
+/// Converts a position or size into an index.
+fn index(value: u64) -> Result<usize> { usize::try_from(value).map_err(|_| Error::InvalidArgument) }
+
+/// Returns the date and time in the format of directory entries.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn timestamp() -> (u16, u16) {
+  let Some(now) = crate::library::time::realtime() else {
+    return (DEFAULT_DATE, 0);
+  };
+
+  let now = crate::library::time::DateTime::from(now);
+  let year = now.year.clamp(1980, 2107) - 1980;
+  let date = ((year as u16) << 9) | (u16::from(now.month) << 5) | u16::from(now.day);
+  let time = (u16::from(now.hour) << 11) | (u16::from(now.minute) << 5) | u16::from(now.second / 2);
+  (date, time)
+}
+
+/// Calculates the checksum of a short name that long name entries refer to.
+fn checksum(short_name: &[u8]) -> u8 {
+  short_name
+    .iter()
+    .fold(0_u8, |sum, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/// Returns whether `character` may appear in a short name.
+fn is_short_name_character(character: char) -> bool {
+  character.is_ascii_alphanumeric() || "$%'-_@~`!(){}^#&".contains(character)
+}
+
+/// Checks whether `name` can be the name of a file.
+fn check_name(name: &str) -> Result<()> {
+  let is_invalid = |character: char| character < ' ' || "\"*/:<>?\\|".contains(character);
+  if name.is_empty() || name.ends_with(['.', ' ']) || name.contains(is_invalid) {
+    return Err(Error::InvalidPath);
+  }
+  if name.encode_utf16().count() > MAXIMUM_NAME_LENGTH {
+    return Err(Error::InvalidPath);
+  }
+  Ok(())
+}
+
+/// Splits `name` into the base name and the extension (after the last dot).
+fn split_name(name: &str) -> (&str, &str) {
+  match name.rfind('.') {
+    Some(position) if position > 0 => (&name[..position], &name[position + 1..]),
+    _ => (name, ""),
+  }
+}
+
+/// Returns the short name and the case flags if `name` can be stored as a short name
+/// without losing information.
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+  let (base, extension) = split_name(name);
+  let fits = |part: &str, length: usize| part.len() <= length && part.chars().all(is_short_name_character);
+  if base.is_empty() || !fits(base, 8) || !fits(extension, 3) {
+    return None;
+  }
+
+  // Each part must be either completely uppercase or completely lowercase
+  let case = |part: &str, flag: u8| {
+    if !part.chars().any(|character| character.is_ascii_lowercase()) {
+      Some(0)
+    } else if !part.chars().any(|character| character.is_ascii_uppercase()) {
+      Some(flag)
+    } else {
+      None
+    }
+  };
+  let flags = case(base, case::BASE)? | case(extension, case::EXTENSION)?;
+
+  let mut short_name = [b' '; 11];
+  for (target, byte) in short_name.iter_mut().zip(base.bytes()) {
+    *target = byte.to_ascii_uppercase();
+  }
+  for (target, byte) in short_name[8..].iter_mut().zip(extension.bytes()) {
+    *target = byte.to_ascii_uppercase();
+  }
+  Some((short_name, flags))
+}
+
+/// Creates a short name of the form `BASE~N.EXT` for `name` that no entry in `entries`
+/// uses yet.
+fn generated_short_name(name: &str, entries: &[Entry]) -> Result<[u8; 11]> {
+  let convert = |part: &str, length: usize| -> Vec<u8> {
+    part
+      .chars()
+      .filter(|character| *character != ' ' && *character != '.')
+      .map(|character| {
+        if is_short_name_character(character) {
+          character.to_ascii_uppercase() as u8
+        } else {
+          b'_'
+        }
+      })
+      .take(length)
+      .collect()
+  };
+
+  let (base, extension) = split_name(name.trim_start_matches('.'));
+  let base = convert(base, 8);
+  let extension = convert(extension, 3);
+
+  for number in 1..1_000_000_u32 {
+    let tail = alloc::format!("~{number}");
+    let mut short_name = [b' '; 11];
+    let length = base.len().min(8 - tail.len());
+    short_name[..length].copy_from_slice(&base[..length]);
+    short_name[length..length + tail.len()].copy_from_slice(tail.as_bytes());
+    short_name[8..8 + extension.len()].copy_from_slice(&extension);
+
+    if !entries.iter().any(|entry| entry.short_name == short_name) {
+      return Ok(short_name);
+    }
+  }
+  Err(Error::NoSpace)
+}
+
+/// Converts a short name into a string, taking the case flags into account.
+fn short_name_to_string(short_name: &[u8; 11], flags: u8) -> String {
+  let convert = |part: &[u8], lowercase: bool| {
+    part
+      .iter()
+      .map(|byte| {
+        if lowercase {
+          char::from(byte.to_ascii_lowercase())
+        } else {
+          char::from(*byte)
+        }
+      })
+      .collect::<String>()
+  };
+
+  let mut short_name = *short_name;
+  // A first byte of 0xE5 is stored as 0x05, as 0xE5 marks deleted entries
+  if short_name[0] == 0x05 {
+    short_name[0] = DELETED;
+  }
+
+  let base = short_name[..8].trim_ascii_end();
+  let extension = short_name[8..].trim_ascii_end();
+  let mut name = convert(base, flags & case::BASE != 0);
+  if !extension.is_empty() {
+    name.push('.');
+    name.push_str(&convert(extension, flags & case::EXTENSION != 0));
+  }
+  name
+}
+
+/// A file or directory as described by its directory entries.
+struct Entry {
+  /// The long name, or the short name if there is no long name
+  name:          String,
+  /// The short name as stored on the device
+  short_name:    [u8; 11],
+  /// The index of the first entry that belongs to the file (a long name entry or the
+  /// short entry)
+  first_slot:    usize,
+  /// The index of the short entry
+  slot:          usize,
+  /// The attributes
+  attributes:    u8,
+  /// The first cluster of the data
+  first_cluster: u32,
+  /// The size in bytes
+  size:          u32,
+}
+
+impl Entry {
+  /// Returns whether `name` refers to this entry, either by its long or its short name.
+  fn matches(&self, name: &str) -> bool {
+    self.name.eq_ignore_ascii_case(name)
+      || short_name_to_string(&self.short_name, 0).eq_ignore_ascii_case(name)
+  }
+
+  /// Returns whether the entry is a directory.
+  const fn is_directory(&self) -> bool { self.attributes & attribute::DIRECTORY != 0 }
+}
+
+/// A long name that is being assembled from its entries.
+struct LongName {
+  /// The checksum of the short name the long name belongs to
+  checksum:   u8,
+  /// The sequence number of the previous entry
+  sequence:   u8,
+  /// The index of the first entry
+  first_slot: usize,
+  /// The UTF-16 code units of the entries read so far, in the order of the entries
+  parts:      Vec<[u16; 13]>,
+}
+
+impl LongName {
+  /// Returns the name, which ends at the first NUL character.
+  fn decode(&self) -> String {
+    let units = self
+      .parts
+      .iter()
+      .rev()
+      .flatten()
+      .copied()
+      .take_while(|unit| *unit != 0);
+    char::decode_utf16(units)
+      .map(|character| character.unwrap_or(char::REPLACEMENT_CHARACTER))
+      .collect()
+  }
+}
+
+/// Parses the directory entries in `data`.
+fn parse_directory(data: &[u8]) -> Vec<Entry> {
+  let mut entries = Vec::new();
+  let mut long_name: Option<LongName> = None;
+
+  for (index, slot) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+    match slot[0] {
+      0 => break,
+      DELETED => {
+        long_name = None;
+        continue;
+      },
+      _ => {},
+    }
+
+    let attributes = slot[11];
+    if attributes & attribute::LONG_NAME_MASK == attribute::LONG_NAME {
+      let sequence = slot[0] & !LAST_LONG_ENTRY;
+      let mut units = [0; 13];
+      for (unit, offset) in units.iter_mut().zip(LONG_NAME_OFFSETS) {
+        *unit = read_u16(slot, offset);
+      }
+
+      if slot[0] & LAST_LONG_ENTRY != 0 {
+        long_name = Some(LongName {
+          checksum: slot[13],
+          sequence,
+          first_slot: index,
+          parts: vec![units],
+        });
+      } else if let Some(name) = long_name
+        .as_mut()
+        .filter(|name| name.sequence == sequence + 1 && name.checksum == slot[13])
+      {
+        name.sequence = sequence;
+        name.parts.push(units);
+      } else {
+        long_name = None;
+      }
+      continue;
+    }
+
+    let long_name = long_name.take();
+    let mut short_name = [0; 11];
+    short_name.copy_from_slice(&slot[..11]);
+    // The volume label as well as `.` and `..` are no files
+    if attributes & attribute::VOLUME_ID != 0 || short_name[0] == b'.' {
+      continue;
+    }
+
+    let (name, first_slot) = match long_name {
+      Some(long_name) if long_name.sequence == 1 && long_name.checksum == checksum(&short_name) => {
+        (long_name.decode(), long_name.first_slot)
+      },
+      _ => (short_name_to_string(&short_name, slot[12]), index),
+    };
+
+    entries.push(Entry {
+      name,
+      short_name,
+      first_slot,
+      slot: index,
+      attributes,
+      first_cluster: (u32::from(read_u16(slot, 20)) << 16) | u32::from(read_u16(slot, 26)),
+      size: read_u32(slot, 28),
+    });
+  }
+
+  entries
+}
+
+/// Builds the entries for a file with the name `name`, the short name `short_name` and
+/// the `attributes`. If `needs_long_name` is `true`, long name entries precede the short
+/// entry.
+fn build_entries(
+  name: &str,
+  short_name: &[u8; 11],
+  flags: u8,
+  attributes: u8,
+  first_cluster: u32,
+  needs_long_name: bool,
+) -> Vec<[u8; ENTRY_SIZE]> {
+  let mut entries = Vec::new();
+
+  if needs_long_name {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    // The name is terminated by NUL unless it fills the entries, and padded with 0xFFFF
+    let count = units.len().div_ceil(13);
+    if units.len() < count * 13 {
+      units.push(0);
+    }
+    units.resize(count * 13, 0xFFFF);
+
+    let checksum = checksum(short_name);
+    for number in (1..=count).rev() {
+      let mut entry = [0; ENTRY_SIZE];
+      #[allow(clippy::cast_possible_truncation)]
+      let sequence = number as u8;
+      entry[0] = if number == count {
+        sequence | LAST_LONG_ENTRY
+      } else {
+        sequence
+      };
+      entry[11] = attribute::LONG_NAME;
+      entry[13] = checksum;
+      for (unit, offset) in units[(number - 1) * 13..number * 13]
+        .iter()
+        .zip(LONG_NAME_OFFSETS)
+      {
+        entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+      }
+      entries.push(entry);
+    }
+  }
+
+  let (date, time) = timestamp();
+  let mut entry = [0; ENTRY_SIZE];
+  entry[..11].copy_from_slice(short_name);
+  entry[11] = attributes;
+  entry[12] = flags;
+  entry[14..16].copy_from_slice(&time.to_le_bytes());
+  entry[16..18].copy_from_slice(&date.to_le_bytes());
+  entry[18..20].copy_from_slice(&date.to_le_bytes());
+  entry[22..24].copy_from_slice(&time.to_le_bytes());
+  entry[24..26].copy_from_slice(&date.to_le_bytes());
+  set_first_cluster(&mut entry, first_cluster);
+  entries.push(entry);
+
+  entries
+}
+
+/// The state of the cluster allocator.
+struct Allocation {
+  /// The number of free clusters, or [`UNKNOWN`]
+  free: u32,
+  /// The cluster where the search for a free cluster starts
+  next: u32,
+}
+
+/// The layout of a FAT32 file system on a device, and the state shared by all its
+/// inodes.
+struct Volume {
+  /// The device the file system is stored on
+  device:       &'static dyn BlockDevice,
+  /// The size of a cluster in bytes
+  cluster_size: u64,
+  /// The position of the first FAT in bytes
+  fat_start:    u64,
+  /// The size of one FAT in bytes
+  fat_size:     u64,
+  /// The number of FATs, which are kept identical
+  fat_count:    u64,
+  /// The position of the first cluster (cluster 2) in bytes
+  data_start:   u64,
+  /// The number of the first cluster after the last cluster of the file system
+  end_cluster:  u32,
+  /// The first cluster of the root directory
+  root_cluster: u32,
+  /// The position of the FS information sector in bytes, if there is one
+  information:  Option<u64>,
+  /// The state of the cluster allocator
+  allocation:   spin::Mutex<Allocation>,
+  /// Serializes all operations that access the device
+  lock:         spin::Mutex<()>,
+  /// All inodes that exist, so that every file is represented by exactly one inode
+  nodes:        spin::Mutex<BTreeMap<u64, Weak<Node>>>,
+}
+
+impl Volume {
+  /// Reads `buffer.len()` bytes at the byte `position` of the device.
+  fn read(&self, position: u64, buffer: &mut [u8]) -> Result<()> {
+    super::block::read(self.device, position, buffer)
+  }
+
+  /// Writes `buffer` at the byte `position` of the device.
+  fn write(&self, position: u64, buffer: &[u8]) -> Result<()> {
+    super::block::write(self.device, position, buffer)
+  }
+
+  /// Returns whether `cluster` is a cluster of the file system.
+  fn is_valid(&self, cluster: u32) -> bool { (2..self.end_cluster).contains(&cluster) }
+
+  /// Returns the position of the cluster `cluster` in bytes.
+  fn cluster_position(&self, cluster: u32) -> u64 {
+    self.data_start + u64::from(cluster - 2) * self.cluster_size
+  }
+
+  /// Returns the FAT entry of `cluster`.
+  fn entry(&self, cluster: u32) -> Result<u32> {
+    if !self.is_valid(cluster) {
+      return Err(Error::Corrupted);
+    }
+
+    let mut bytes = [0; 4];
+    self.read(self.fat_start + u64::from(cluster) * 4, &mut bytes)?;
+    Ok(u32::from_le_bytes(bytes) & CLUSTER_MASK)
+  }
+
+  /// Sets the entry of `cluster` to `value` in all FATs.
+  fn set_entry(&self, cluster: u32, value: u32) -> Result<()> {
+    for copy in 0..self.fat_count {
+      let position = self.fat_start + copy * self.fat_size + u64::from(cluster) * 4;
+      let mut bytes = [0; 4];
+      self.read(position, &mut bytes)?;
+      // The upper four bits are reserved and must be preserved
+      let entry = (u32::from_le_bytes(bytes) & !CLUSTER_MASK) | (value & CLUSTER_MASK);
+      self.write(position, &entry.to_le_bytes())?;
+    }
+    Ok(())
+  }
+
+  /// Returns the clusters of the chain that starts with `first`, which is empty for the
+  /// cluster 0.
+  fn chain(&self, first: u32) -> Result<Vec<u32>> {
+    let mut chain = Vec::new();
+    let mut cluster = first;
+    while cluster != FREE {
+      // A chain that is longer than the file system has clusters contains a loop
+      if !self.is_valid(cluster) || chain.len() >= self.end_cluster as usize {
+        return Err(Error::Corrupted);
+      }
+      chain.push(cluster);
+
+      cluster = self.entry(cluster)?;
+      if cluster >= END_OF_CHAIN {
+        break;
+      }
+    }
+    Ok(chain)
+  }
+
+  /// Writes the allocator state to the FS information sector.
+  fn write_information(&self, allocation: &Allocation) -> Result<()> {
+    let Some(position) = self.information else {
+      return Ok(());
+    };
+
+    let mut bytes = [0; 8];
+    bytes[..4].copy_from_slice(&allocation.free.to_le_bytes());
+    bytes[4..].copy_from_slice(&allocation.next.to_le_bytes());
+    self.write(position + 488, &bytes)
+  }
+
+  /// Allocates a cluster and appends it to the chain that ends with `previous`.
+  fn allocate(&self, previous: Option<u32>) -> Result<u32> {
+    let mut allocation = self.allocation.lock();
+    let block_size = self.device.block_size() as u64;
+    let count = self.end_cluster - 2;
+    let start = if self.is_valid(allocation.next) {
+      allocation.next
+    } else {
+      2
+    };
+
+    // The FAT is read block by block, as the search may need to look at many entries
+    let mut block = vec![0; index(block_size)?];
+    let mut loaded = None;
+    for offset in 0..count {
+      let cluster = 2 + (start - 2 + offset) % count;
+      let position = u64::from(cluster) * 4;
+      let number = position / block_size;
+      if loaded != Some(number) {
+        self.read(self.fat_start + number * block_size, &mut block)?;
+        loaded = Some(number);
+      }
+      if read_u32(&block, index(position % block_size)?) & CLUSTER_MASK != FREE {
+        continue;
+      }
+
+      self.set_entry(cluster, CLUSTER_MASK)?;
+      if let Some(previous) = previous {
+        self.set_entry(previous, cluster)?;
+      }
+      allocation.next = cluster + 1;
+      if allocation.free != UNKNOWN {
+        allocation.free = allocation.free.saturating_sub(1);
+      }
+      self.write_information(&allocation)?;
+      return Ok(cluster);
+    }
+
+    Err(Error::NoSpace)
+  }
+
+  /// Frees all clusters of the chain that starts with `first`.
+  fn free(&self, first: u32) -> Result<()> {
+    let chain = self.chain(first)?;
+    for cluster in &chain {
+      self.set_entry(*cluster, FREE)?;
+    }
+
+    let mut allocation = self.allocation.lock();
+    if allocation.free != UNKNOWN {
+      allocation.free = allocation
+        .free
+        .saturating_add(u32::try_from(chain.len()).unwrap_or(u32::MAX));
+    }
+    self.write_information(&allocation)
+  }
+
+  /// Fills the cluster `cluster` with zeros.
+  fn zero_cluster(&self, cluster: u32) -> Result<()> {
+    self.write(
+      self.cluster_position(cluster),
+      &vec![0; index(self.cluster_size)?],
+    )
+  }
+
+  /// Reads `buffer.len()` bytes at `offset` of the data stored in `chain`.
+  fn read_data(&self, chain: &[u32], offset: u64, buffer: &mut [u8]) -> Result<()> {
+    let mut done = 0;
+    while done < buffer.len() {
+      let current = offset + done as u64;
+      let cluster = chain
+        .get(index(current / self.cluster_size)?)
+        .ok_or(Error::Corrupted)?;
+      let within = current % self.cluster_size;
+      let length = (buffer.len() - done).min(index(self.cluster_size - within)?);
+      self.read(
+        self.cluster_position(*cluster) + within,
+        &mut buffer[done..done + length],
+      )?;
+      done += length;
+    }
+    Ok(())
+  }
+
+  /// Writes `buffer` at `offset` of the data stored in `chain`.
+  fn write_data(&self, chain: &[u32], offset: u64, buffer: &[u8]) -> Result<()> {
+    let mut done = 0;
+    while done < buffer.len() {
+      let current = offset + done as u64;
+      let cluster = chain
+        .get(index(current / self.cluster_size)?)
+        .ok_or(Error::Corrupted)?;
+      let within = current % self.cluster_size;
+      let length = (buffer.len() - done).min(index(self.cluster_size - within)?);
+      self.write(
+        self.cluster_position(*cluster) + within,
+        &buffer[done..done + length],
+      )?;
+      done += length;
+    }
+    Ok(())
+  }
+
+  /// Returns the position of the entry with the index `slot` of a directory that is
+  /// stored in `chain`.
+  fn slot_position(&self, chain: &[u32], slot: usize) -> Result<u64> {
+    let offset = (slot * ENTRY_SIZE) as u64;
+    let cluster = chain
+      .get(index(offset / self.cluster_size)?)
+      .ok_or(Error::Corrupted)?;
+    Ok(self.cluster_position(*cluster) + offset % self.cluster_size)
+  }
+
+  /// Reads the entries of the directory that starts with the cluster `first_cluster` and
+  /// returns them together with the clusters that store the directory.
+  fn directory(&self, first_cluster: u32) -> Result<(Vec<u32>, Vec<Entry>)> {
+    let chain = self.chain(first_cluster)?;
+    let mut data = heap::try_vec(0, chain.len() * index(self.cluster_size)?)?;
+    self.read_data(&chain, 0, &mut data)?;
+    Ok((chain, parse_directory(&data)))
+  }
+
+  /// Returns the inode for the file described by `entry`, whose short entry is located
+  /// at `position`.
+  fn node(self: &Arc<Self>, position: u64, entry: &Entry) -> Arc<Node> {
+    let mut nodes = self.nodes.lock();
+    if let Some(node) = nodes.get(&position).and_then(Weak::upgrade) {
+      return node;
+    }
+
+    let node = Arc::new(Node {
+      volume:    self.clone(),
+      number:    position,
+      directory: entry.is_directory(),
+      read_only: entry.attributes & attribute::READ_ONLY != 0,
+      state:     spin::Mutex::new(State {
+        first_cluster: entry.first_cluster,
+        size:          entry.size,
+        deleted:       false,
+      }),
+    });
+    nodes.insert(position, Arc::downgrade(&node));
+    node
+  }
+}
+
+/// The part of an inode that changes when the file is written.
+struct State {
+  /// The first cluster of the data, or 0 if the file is empty
+  first_cluster: u32,
+  /// The size of the file in bytes
+  size:          u32,
+  /// Whether the directory entry was removed; the clusters are freed when the inode is
+  /// dropped
+  deleted:       bool,
+}
+
+/// A file or directory of a FAT32 file system.
+struct Node {
+  /// The file system the inode belongs to
+  volume:    Arc<Volume>,
+  /// The number of the inode, which is the position of its short entry
+  number:    u64,
+  /// Whether the inode is a directory
+  directory: bool,
+  /// Whether the file has the read-only attribute
+  read_only: bool,
+  /// The part of the inode that changes
+  state:     spin::Mutex<State>,
+}
+
+impl Node {
+  /// Reads the entries of this directory and returns them together with the clusters
+  /// that store the directory.
+  fn entries(&self) -> Result<(Vec<u32>, Vec<Entry>)> {
+    if !self.directory {
+      return Err(Error::NotADirectory);
+    }
+    self.volume.directory(self.state.lock().first_cluster)
+  }
+
+  /// Returns the entry `name` of this directory together with the clusters that store
+  /// the directory.
+  fn find(&self, name: &str) -> Result<(Vec<u32>, Entry)> {
+    let (chain, entries) = self.entries()?;
+    let entry = entries
+      .into_iter()
+      .find(|entry| entry.matches(name))
+      .ok_or(Error::NotFound)?;
+    Ok((chain, entry))
+  }
+
+  /// Writes the first cluster, the size and the modification time of this file to its
+  /// directory entry.
+  fn write_entry(&self, state: &State) -> Result<()> {
+    if self.number == ROOT_INODE || state.deleted {
+      return Ok(());
+    }
+
+    let mut entry = [0; ENTRY_SIZE];
+    self.volume.read(self.number, &mut entry)?;
+    let (date, time) = timestamp();
+    entry[11] |= attribute::ARCHIVE;
+    entry[18..20].copy_from_slice(&date.to_le_bytes());
+    entry[22..24].copy_from_slice(&time.to_le_bytes());
+    entry[24..26].copy_from_slice(&date.to_le_bytes());
+    set_first_cluster(&mut entry, state.first_cluster);
+    entry[28..32].copy_from_slice(&state.size.to_le_bytes());
+    self.volume.write(self.number, &entry)
+  }
+
+  /// Makes the file consist of at least `count` clusters and returns its chain.
+  fn grow(&self, state: &mut State, count: usize) -> Result<Vec<u32>> {
+    let mut chain = self.volume.chain(state.first_cluster)?;
+    while chain.len() < count {
+      let cluster = self.volume.allocate(chain.last().copied())?;
+      if chain.is_empty() {
+        state.first_cluster = cluster;
+      }
+      chain.push(cluster);
+    }
+    Ok(chain)
+  }
+
+  /// Frees all clusters of the file after the first `count` ones.
+  fn shrink(&self, state: &mut State, count: usize) -> Result<()> {
+    let chain = self.volume.chain(state.first_cluster)?;
+    if chain.len() <= count {
+      return Ok(());
+    }
+
+    if count == 0 {
+      state.first_cluster = FREE;
+    } else {
+      self.volume.set_entry(chain[count - 1], CLUSTER_MASK)?;
+    }
+    self.volume.free(chain[count])
+  }
+
+  /// Returns the number of clusters that hold `size` bytes.
+  fn clusters_for(&self, size: u64) -> Result<usize> { index(size.div_ceil(self.volume.cluster_size)) }
+
+  /// Fills the file with zeros from its end up to `end`, allocating clusters as
+  /// required.
+  fn zero_fill(&self, state: &mut State, end: u64) -> Result<Vec<u32>> {
+    let chain = self.grow(state, self.clusters_for(end)?)?;
+    let zeros = vec![0; index(self.volume.cluster_size)?];
+    let mut position = u64::from(state.size);
+    while position < end {
+      let length = (end - position).min(self.volume.cluster_size - position % self.volume.cluster_size);
+      self
+        .volume
+        .write_data(&chain, position, &zeros[..index(length)?])?;
+      position += length;
+    }
+    Ok(chain)
+  }
+
+  /// Adds an entry for `name` with the given `attributes` and first cluster to this
+  /// directory and returns the inode of the new file.
+  fn add_entry(&self, name: &str, attributes: u8, first_cluster: u32) -> Result<Arc<dyn Inode>> {
+    let (mut chain, entries) = self.entries()?;
+    if entries.iter().any(|entry| entry.matches(name)) {
+      return Err(Error::AlreadyExists);
+    }
+
+    let (short_name, flags, needs_long_name) = match exact_short_name(name) {
+      Some((short_name, flags)) => (short_name, flags, false),
+      None => (generated_short_name(name, &entries)?, 0, true),
+    };
+    let new_entries = build_entries(
+      name,
+      &short_name,
+      flags,
+      attributes,
+      first_cluster,
+      needs_long_name,
+    );
+
+    // Look for enough consecutive unused entries; the run may continue after the end of
+    // the directory, which is then extended
+    let mut data = heap::try_vec(0, chain.len() * index(self.volume.cluster_size)?)?;
+    self.volume.read_data(&chain, 0, &mut data)?;
+    let mut run = 0;
+    let mut first_slot = None;
+    for (slot, bytes) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+      if bytes[0] != 0 && bytes[0] != DELETED {
+        run = 0;
+        continue;
+      }
+
+      run += 1;
+      if run == new_entries.len() {
+        first_slot = Some(slot + 1 - run);
+        break;
+      }
+    }
+    let first_slot = first_slot.unwrap_or(data.len() / ENTRY_SIZE - run);
+
+    let end = first_slot + new_entries.len();
+    if end > MAXIMUM_ENTRIES {
+      return Err(Error::NoSpace);
+    }
+    while chain.len() * index(self.volume.cluster_size)? < end * ENTRY_SIZE {
+      let cluster = self.volume.allocate(chain.last().copied())?;
+      self.volume.zero_cluster(cluster)?;
+      chain.push(cluster);
+    }
+
+    for (offset, entry) in new_entries.iter().enumerate() {
+      let position = self.volume.slot_position(&chain, first_slot + offset)?;
+      self.volume.write(position, entry)?;
+    }
+
+    let position = self.volume.slot_position(&chain, end - 1)?;
+    let entry = Entry {
+      name: String::from(name),
+      short_name,
+      first_slot,
+      slot: end - 1,
+      attributes,
+      first_cluster,
+      size: 0,
+    };
+    Ok(self.volume.node(position, &entry))
+  }
+
+  /// Creates a directory `name` in this directory.
+  fn create_directory(&self, name: &str, attributes: u8) -> Result<Arc<dyn Inode>> {
+    let cluster = self.volume.allocate(None)?;
+    let result = self.volume.zero_cluster(cluster).and_then(|()| {
+      // `..` refers to the root directory with the cluster 0
+      let parent = if self.number == ROOT_INODE {
+        FREE
+      } else {
+        self.state.lock().first_cluster
+      };
+      let dot = build_entries(".", b".          ", 0, attribute::DIRECTORY, cluster, false)[0];
+      let dot_dot = build_entries("..", b"..         ", 0, attribute::DIRECTORY, parent, false)[0];
+      let mut entries = [0; 2 * ENTRY_SIZE];
+      entries[..ENTRY_SIZE].copy_from_slice(&dot);
+      entries[ENTRY_SIZE..].copy_from_slice(&dot_dot);
+      self
+        .volume
+        .write(self.volume.cluster_position(cluster), &entries)?;
+      self.add_entry(name, attributes, cluster)
+    });
+
+    if result.is_err() {
+      let _ = self.volume.free(cluster);
+    }
+    result
+  }
+}
+
+impl Drop for Node {
+  fn drop(&mut self) {
+    let mut nodes = self.volume.nodes.lock();
+    if nodes
+      .get(&self.number)
+      .is_some_and(|node| node.strong_count() == 0)
+    {
+      nodes.remove(&self.number);
+    }
+    drop(nodes);
+
+    // The file was removed while it was still open
+    let state = self.state.get_mut();
+    if state.deleted && state.first_cluster != FREE {
+      let _guard = self.volume.lock.lock();
+      if let Err(error) = self.volume.free(state.first_cluster) {
+        log::warn!("Could not free the clusters of a removed file: {error}");
+      }
+    }
+  }
+}
+
+impl Inode for Node {
+  fn metadata(&self) -> Result<Metadata> {
+    let (file_type, mode, size) = if self.directory {
+      let _guard = self.volume.lock.lock();
+      let clusters = self.volume.chain(self.state.lock().first_cluster)?.len() as u64;
+      (FileType::Directory, 0o755, clusters * self.volume.cluster_size)
+    } else {
+      (FileType::Regular, 0o644, u64::from(self.state.lock().size))
+    };
+
+    Ok(Metadata {
+      inode: self.number,
+      file_type,
+      size,
+      mode: if self.read_only { mode & !0o222 } else { mode },
+      // FAT does not count links; every file has exactly one entry
+      links: if self.directory { 2 } else { 1 },
+    })
+  }
+
+  fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+    if self.directory {
+      return Err(Error::IsADirectory);
+    }
+
+    let _guard = self.volume.lock.lock();
+    let state = self.state.lock();
+    let size = u64::from(state.size);
+    if offset >= size {
+      return Ok(0);
+    }
+
+    let length = index((size - offset).min(buffer.len() as u64))?;
+    let chain = self.volume.chain(state.first_cluster)?;
+    self.volume.read_data(&chain, offset, &mut buffer[..length])?;
+    Ok(length)
+  }
+
+  fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize> {
+    if self.directory {
+      return Err(Error::IsADirectory);
+    }
+
+    let end = offset
+      .checked_add(buffer.len() as u64)
+      .filter(|end| u32::try_from(*end).is_ok())
+      .ok_or(Error::InvalidArgument)?;
+
+    let _guard = self.volume.lock.lock();
+    let mut state = self.state.lock();
+    if offset > u64::from(state.size) {
+      self.zero_fill(&mut state, offset)?;
+    }
+    let chain = self.grow(&mut state, self.clusters_for(end)?)?;
+
+    // The entry is written in any case, as clusters may have been allocated
+    let result = self.volume.write_data(&chain, offset, buffer);
+    if result.is_ok() {
+      state.size = state
+        .size
+        .max(u32::try_from(end).map_err(|_| Error::InvalidArgument)?);
+    }
+    self.write_entry(&state)?;
+    result.map(|()| buffer.len())
+  }
+
+  fn truncate(&self, size: u64) -> Result<()> {
+    if self.directory {
+      return Err(Error::IsADirectory);
+    }
+    let new_size = u32::try_from(size).map_err(|_| Error::InvalidArgument)?;
+
+    let _guard = self.volume.lock.lock();
+    let mut state = self.state.lock();
+    if new_size < state.size {
+      self.shrink(&mut state, self.clusters_for(size)?)?;
+    } else if new_size > state.size {
+      self.zero_fill(&mut state, size)?;
+    }
+    state.size = new_size;
+    self.write_entry(&state)
+  }
+
+  fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+    let _guard = self.volume.lock.lock();
+    let (chain, entry) = self.find(name)?;
+    let position = self.volume.slot_position(&chain, entry.slot)?;
+    Ok(self.volume.node(position, &entry))
+  }
+
+  fn create(&self, name: &str, file_type: FileType, mode: u16) -> Result<Arc<dyn Inode>> {
+    if !self.directory {
+      return Err(Error::NotADirectory);
+    }
+    check_name(name)?;
+
+    let attributes = if mode & 0o222 == 0 {
+      attribute::READ_ONLY
+    } else {
+      0
+    };
+    let _guard = self.volume.lock.lock();
+    match file_type {
+      FileType::Regular => self.add_entry(name, attributes | attribute::ARCHIVE, FREE),
+      FileType::Directory => self.create_directory(name, attributes | attribute::DIRECTORY),
+      FileType::SymbolicLink => Err(Error::Unsupported),
+    }
+  }
+
+  fn unlink(&self, name: &str) -> Result<()> {
+    // Declared before the guard, so that the inode is dropped after the lock is released
+    let node;
+    let _guard = self.volume.lock.lock();
+
+    let (chain, entry) = self.find(name)?;
+    if entry.is_directory() && !self.volume.directory(entry.first_cluster)?.1.is_empty() {
+      return Err(Error::NotEmpty);
+    }
+
+    for slot in entry.first_slot..=entry.slot {
+      let position = self.volume.slot_position(&chain, slot)?;
+      self.volume.write(position, &[DELETED])?;
+    }
+
+    // An inode that is still in use keeps its clusters until it is dropped
+    let position = self.volume.slot_position(&chain, entry.slot)?;
+    node = self
+      .volume
+      .nodes
+      .lock()
+      .remove(&position)
+      .as_ref()
+      .and_then(Weak::upgrade);
+    match &node {
+      Some(node) => node.state.lock().deleted = true,
+      None if entry.first_cluster != FREE => self.volume.free(entry.first_cluster)?,
+      None => {},
+    }
+    Ok(())
+  }
+
+  fn read_dir(&self) -> Result<Vec<DirectoryEntry>> {
+    let _guard = self.volume.lock.lock();
+    let (chain, entries) = self.entries()?;
+    entries
+      .into_iter()
+      .map(|entry| {
+        Ok(DirectoryEntry {
+          inode:     self.volume.slot_position(&chain, entry.slot)?,
+          file_type: if entry.is_directory() {
+            FileType::Directory
+          } else {
+            FileType::Regular
+          },
+          name:      entry.name,
+        })
+      })
+      .collect()
+  }
+}
+
+/// A FAT32 file system on a block device.
+#[allow(clippy::module_name_repetitions)]
+pub struct Fat {
+  /// The layout and the shared state of the file system
+  volume: Arc<Volume>,
+  /// The root directory
+  root:   Arc<Node>,
+}
+
+impl core::fmt::Debug for Fat {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Fat")
+      .field("cluster_size", &self.volume.cluster_size)
+      .field("clusters", &(self.volume.end_cluster - 2))
+      .finish_non_exhaustive()
+  }
+}
+
+impl Fat {
+  /// Reads the boot sector of the FAT32 file system on `device` and returns the file
+  /// system.
+  ///
+  /// #### Errors
+  ///
+  /// If there is no FAT file system on the device, [`Error::InvalidArgument`] is
+  /// returned. If it is a FAT12 or FAT16 file system, [`Error::Unsupported`] is
+  /// returned.
+  pub fn new(device: &'static dyn BlockDevice) -> Result<Arc<Self>> {
+    let block_size = device.block_size();
+    if block_size < 512 {
+      return Err(Error::Unsupported);
+    }
+    let mut boot = vec![0; block_size];
+    device.read_blocks(0, &mut boot)?;
+
+    let bytes_per_sector = u64::from(read_u16(&boot, 11));
+    let sectors_per_cluster = u64::from(boot[13]);
+    let reserved_sectors = u64::from(read_u16(&boot, 14));
+    let fat_count = u64::from(boot[16]);
+    let total_sectors = match read_u16(&boot, 19) {
+      0 => u64::from(read_u32(&boot, 32)),
+      sectors => u64::from(sectors),
+    };
+    let is_valid = boot[510..512] == [0x55, 0xAA]
+      && bytes_per_sector.is_power_of_two()
+      && (512..=4096).contains(&bytes_per_sector)
+      && bytes_per_sector >= block_size as u64
+      && sectors_per_cluster.is_power_of_two()
+      && reserved_sectors > 0
+      && fat_count > 0;
+    if !is_valid {
+      return Err(Error::InvalidArgument);
+    }
+    // FAT12 and FAT16 have a fixed root directory and a 16-bit FAT size
+    if read_u16(&boot, 17) != 0 || read_u16(&boot, 22) != 0 {
+      return Err(Error::Unsupported);
+    }
+
+    let fat_sectors = u64::from(read_u32(&boot, 36));
+    let data_sector = reserved_sectors + fat_count * fat_sectors;
+    let fat_capacity = fat_sectors * bytes_per_sector / 4;
+    let clusters =
+      (total_sectors.saturating_sub(data_sector) / sectors_per_cluster).min(fat_capacity.saturating_sub(2));
+    let end_cluster = u32::try_from(clusters + 2)
+      .ok()
+      .filter(|end| *end <= END_OF_CHAIN)
+      .ok_or(Error::Corrupted)?;
+    if total_sectors * bytes_per_sector > device.block_count() * block_size as u64 || clusters == 0 {
+      return Err(Error::Corrupted);
+    }
+
+    let information_sector = u64::from(read_u16(&boot, 48));
+    let mut volume = Volume {
+      device,
+      cluster_size: sectors_per_cluster * bytes_per_sector,
+      fat_start: reserved_sectors * bytes_per_sector,
+      fat_size: fat_sectors * bytes_per_sector,
+      fat_count,
+      data_start: data_sector * bytes_per_sector,
+      end_cluster,
+      root_cluster: read_u32(&boot, 44),
+      information: None,
+      allocation: spin::Mutex::new(Allocation {
+        free: UNKNOWN,
+        next: 2,
+      }),
+      lock: spin::Mutex::new(()),
+      nodes: spin::Mutex::new(BTreeMap::new()),
+    };
+    if !volume.is_valid(volume.root_cluster) {
+      return Err(Error::Corrupted);
+    }
+
+    // The FS information sector caches the number of free clusters
+    if (1..reserved_sectors).contains(&information_sector) {
+      let position = information_sector * bytes_per_sector;
+      let mut information = vec![0; 512];
+      volume.read(position, &mut information)?;
+      let has_signatures = read_u32(&information, 0) == 0x4161_5252
+        && read_u32(&information, 484) == 0x6141_7272
+        && read_u32(&information, 508) == 0xAA55_0000;
+      if has_signatures {
+        let allocation = volume.allocation.get_mut();
+        let free = read_u32(&information, 488);
+        if free <= end_cluster - 2 {
+          allocation.free = free;
+        }
+        allocation.next = read_u32(&information, 492);
+        volume.information = Some(position);
+      }
+    }
+
+    let volume = Arc::new(volume);
+    let root = Arc::new(Node {
+      volume:    volume.clone(),
+      number:    ROOT_INODE,
+      directory: true,
+      read_only: false,
+      state:     spin::Mutex::new(State {
+        first_cluster: volume.root_cluster,
+        size:          0,
+        deleted:       false,
+      }),
+    });
+    Ok(Arc::new(Self { volume, root }))
+  }
+}
+
+impl super::FileSystem for Fat {
+  fn name(&self) -> &'static str { "vfat" }
+
+  fn root(&self) -> Arc<dyn Inode> { self.root.clone() }
+
+  fn sync(&self) -> Result<()> {
+    let _guard = self.volume.lock.lock();
+    self.volume.write_information(&self.volume.allocation.lock())?;
+    self.volume.device.flush()
+  }
+}
@@ -0,0 +1,211 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains open files, which remember the offset at which the next read or
+//! write happens.
+
+use alloc::sync::Arc;
+
+use super::{
+  Dentry,
+  DirectoryEntry,
+  Error,
+  Metadata,
+  Result,
+};
+
+/// The flags a file is opened with. The values match those of Linux (`O_RDONLY` etc.),
+/// so that they can be passed through from system calls unchanged.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OpenFlags(u32);
+
+impl OpenFlags {
+  /// The bits that encode the access mode
+  const ACCESS_MODE: u32 = 0o3;
+  /// Write at the end of the file
+  pub const APPEND: Self = Self(0o2000);
+  /// Close the file descriptor when a new program is executed
+  pub const CLOSE_ON_EXEC: Self = Self(0o200_0000);
+  /// Create the file if it does not exist
+  pub const CREATE: Self = Self(0o100);
+  /// Fail if the file is not a directory
+  pub const DIRECTORY: Self = Self(0o20_0000);
+  /// Together with [`Self::CREATE`], fail if the file exists
+  pub const EXCLUSIVE: Self = Self(0o200);
+  /// Return [`Error::WouldBlock`] instead of blocking
+  pub const NON_BLOCKING: Self = Self(0o4000);
+  /// Fail if the last component of the path is a symbolic link
+  pub const NO_FOLLOW: Self = Self(0o40_0000);
+  /// Open for reading only
+  pub const READ: Self = Self(0);
+  /// Open for reading and writing
+  pub const READ_WRITE: Self = Self(0o2);
+  /// Truncate the file to length zero
+  pub const TRUNCATE: Self = Self(0o1000);
+  /// Open for writing only
+  pub const WRITE: Self = Self(0o1);
+
+  /// Creates flags from their Linux representation.
+  #[must_use]
+  pub const fn from_bits(bits: u32) -> Self { Self(bits) }
+
+  /// Returns the Linux representation of the flags.
+  #[must_use]
+  pub const fn bits(self) -> u32 { self.0 }
+
+  /// Returns whether all flags in `other` are set. The access mode is not a flag and
+  /// cannot be checked with this function.
+  #[must_use]
+  pub const fn contains(self, other: Self) -> bool { self.0 & other.0 == other.0 }
+
+  /// Returns whether the file may be read.
+  #[must_use]
+  pub const fn is_readable(self) -> bool { self.0 & Self::ACCESS_MODE != Self::WRITE.0 }
+
+  /// Returns whether the file may be written.
+  #[must_use]
+  pub const fn is_writable(self) -> bool { self.0 & Self::ACCESS_MODE != Self::READ.0 }
+}
+
+impl core::ops::BitOr for OpenFlags {
+  type Output = Self;
+
+  fn bitor(self, other: Self) -> Self { Self(self.0 | other.0) }
+}
+
+/// The position [`File::seek`] moves to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SeekFrom {
+  /// The given number of bytes from the start of the file
+  Start(u64),
+  /// The given number of bytes from the current offset
+  Current(i64),
+  /// The given number of bytes from the end of the file
+  End(i64),
+}
+
+/// An open file. For directories, the offset is the index of the next entry returned by
+/// [`File::read_dir`].
+#[derive(Debug)]
+pub struct File {
+  /// The directory entry the file was opened through
+  dentry: Arc<Dentry>,
+  /// The flags the file was opened with
+  flags:  OpenFlags,
+  /// The offset of the next read or write
+  offset: spin::Mutex<u64>,
+}
+
+impl File {
+  /// Creates an open file at offset zero.
+  pub(super) const fn new(dentry: Arc<Dentry>, flags: OpenFlags) -> Self {
+    Self {
+      dentry,
+      flags,
+      offset: spin::Mutex::new(0),
+    }
+  }
+
+  /// Returns the directory entry the file was opened through.
+  #[must_use]
+  pub const fn dentry(&self) -> &Arc<Dentry> { &self.dentry }
+
+  /// Returns the flags the file was opened with.
+  #[must_use]
+  pub const fn flags(&self) -> OpenFlags { self.flags }
+
+  /// Returns information about the file.
+  ///
+  /// #### Errors
+  ///
+  /// If the information cannot be read, an error is returned.
+  pub fn metadata(&self) -> Result<Metadata> { self.dentry.metadata() }
+
+  /// Reads into `buffer` from the current offset and advances the offset by the number of
+  /// bytes read, which is returned.
+  ///
+  /// #### Errors
+  ///
+  /// If the file was not opened for reading or cannot be read, an error is returned.
+  pub fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+    if !self.flags.is_readable() {
+      return Err(Error::PermissionDenied);
+    }
+
+    let mut offset = self.offset.lock();
+    super::cache::write_back(self.dentry.inode())?;
+    let read = self.dentry.inode().read_at(*offset, buffer)?;
+    *offset += read as u64;
+    Ok(read)
+  }
+
+  /// Writes `buffer` at the current offset (or at the end of the file if the file was
+  /// opened with [`OpenFlags::APPEND`]) and advances the offset by the number of bytes
+  /// written, which is returned.
+  ///
+  /// #### Errors
+  ///
+  /// If the file was not opened for writing or cannot be written, an error is returned.
+  pub fn write(&self, buffer: &[u8]) -> Result<usize> {
+    if !self.flags.is_writable() {
+      return Err(Error::PermissionDenied);
+    }
+
+    let mut offset = self.offset.lock();
+    if self.flags.contains(OpenFlags::APPEND) {
+      *offset = self.metadata()?.size;
+    }
+    let written = self.dentry.inode().write_at(*offset, buffer)?;
+    super::cache::update(self.dentry.inode(), *offset, &buffer[..written]);
+    *offset += written as u64;
+    Ok(written)
+  }
+
+  /// Moves the offset to `position` and returns the new offset.
+  ///
+  /// #### Errors
+  ///
+  /// If the new offset would be negative, [`Error::InvalidArgument`] is returned.
+  pub fn seek(&self, position: SeekFrom) -> Result<u64> {
+    let mut offset = self.offset.lock();
+    let new_offset = match position {
+      SeekFrom::Start(start) => Some(start),
+      SeekFrom::Current(delta) => offset.checked_add_signed(delta),
+      SeekFrom::End(delta) => self.metadata()?.size.checked_add_signed(delta),
+    }
+    .ok_or(Error::InvalidArgument)?;
+
+    *offset = new_offset;
+    Ok(new_offset)
+  }
+
+  /// Sets the size of the file to `size`. The offset is not changed.
+  ///
+  /// #### Errors
+  ///
+  /// If the file was not opened for writing or cannot be resized, an error is returned.
+  pub fn truncate(&self, size: u64) -> Result<()> {
+    if !self.flags.is_writable() {
+      return Err(Error::PermissionDenied);
+    }
+    self.dentry.inode().truncate(size)?;
+    super::cache::truncate(self.dentry.inode(), size);
+    Ok(())
+  }
+
+  /// Returns the next entry of the directory, or `None` after the last entry.
+  ///
+  /// #### Errors
+  ///
+  /// If the file is not a directory or cannot be read, an error is returned.
+  pub fn read_dir(&self) -> Result<Option<DirectoryEntry>> {
+    let mut offset = self.offset.lock();
+    let entries = self.dentry.inode().read_dir()?;
+    let entry = usize::try_from(*offset)
+      .ok()
+      .and_then(|index| entries.into_iter().nth(index));
+    if entry.is_some() {
+      *offset += 1;
+    }
+    Ok(entry)
+  }
+}
@@ -0,0 +1,317 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module unpacks the initial RAM file system (initramfs), a `cpio` archive in the
+//! "newc" format, into the VFS.
+//!
+//! The archive can reach the kernel in two ways: it is linked into the kernel image when
+//! the environment variable `INITRAMFS` names an archive at build time (see `build.rs`),
+//! or the boot loader (e.g. QEMU with `-initrd`) places it in memory and announces its
+//! location with the properties `linux,initrd-start` and `linux,initrd-end` of the
+//! device tree's `/chosen` node. If both exist, the linked archive is unpacked first.
+//!
+//! The initramfs provides the program that is started first, [`INIT`] (`/init` unless
+//! the kernel command line sets `init`). There are no processes yet that could run it,
+//! so the kernel only checks that it exists after unpacking the archives.
+//!
+//! The format is described in <https://www.kernel.org/doc/Documentation/early-userspace/buffer-format.txt>.
+
+use alloc::{
+  collections::BTreeMap,
+  string::String,
+};
+
+use super::{
+  FileType,
+  OpenFlags,
+  Vfs,
+};
+
+crate::library::cmdline::kernel_param! {
+  /// The path of the program that is started first.
+  pub INIT: &'static str = "/init", "init", validate = validate_init
+}
+
+/// Checks that `path`, the path of the program that is started first, is absolute.
+fn validate_init(path: &'static str) -> Result<(), &'static str> {
+  if path.starts_with('/') {
+    Ok(())
+  } else {
+    Err("not an absolute path")
+  }
+}
+
+/// The magic number at the start of every header.
+const MAGIC: &[u8] = b"070701";
+/// The size of a header in bytes: the magic number and 13 fields of 8 hexadecimal digits.
+const HEADER_SIZE: usize = 110;
+/// The name of the entry that marks the end of the archive.
+const TRAILER: &str = "TRAILER!!!";
+
+/// The bits of the mode that encode the type of an entry.
+const TYPE_MASK: u32 = 0o170_000;
+/// The type of a directory.
+const TYPE_DIRECTORY: u32 = 0o040_000;
+/// The type of a regular file.
+const TYPE_REGULAR: u32 = 0o100_000;
+/// The type of a symbolic link.
+const TYPE_SYMBOLIC_LINK: u32 = 0o120_000;
+
+/// The archive that is linked into the kernel image.
+#[cfg(initramfs)]
+static LINKED_ARCHIVE: &[u8] = include_bytes!(env!("INITRAMFS"));
+
+/// Errors that can occur while unpacking an archive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+  /// A header does not start with the magic number.
+  InvalidMagic,
+  /// A header contains a field that is not a hexadecimal number or a name that is not
+  /// valid UTF-8.
+  InvalidHeader,
+  /// The archive ends in the middle of an entry.
+  Truncated,
+  /// An entry could not be created.
+  FileSystem(super::Error),
+}
+
+impl core::fmt::Display for Error {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::InvalidMagic => write!(f, "invalid magic number"),
+      Self::InvalidHeader => write!(f, "invalid header"),
+      Self::Truncated => write!(f, "archive is truncated"),
+      Self::FileSystem(error) => write!(f, "{error}"),
+    }
+  }
+}
+
+impl From<super::Error> for Error {
+  fn from(error: super::Error) -> Self { Self::FileSystem(error) }
+}
+
+/// An entry of an archive.
+#[derive(Debug, Copy, Clone)]
+pub struct Entry<'a> {
+  /// The path of the entry, relative to the root of the archive
+  pub name:  &'a str,
+  /// The type and permission bits
+  pub mode:  u32,
+  /// The number of the inode; entries with the same number are hard links
+  pub inode: u32,
+  /// The number of entries that refer to the inode
+  pub links: u32,
+  /// The data of a regular file or the target of a symbolic link
+  pub data:  &'a [u8],
+}
+
+/// An iterator over the entries of an archive.
+#[derive(Debug, Clone)]
+pub struct Entries<'a> {
+  /// The part of the archive that has not been read yet
+  archive: &'a [u8],
+}
+
+impl<'a> Entries<'a> {
+  /// Creates an iterator over the entries of `archive`.
+  #[must_use]
+  pub const fn new(archive: &'a [u8]) -> Self { Self { archive } }
+
+  /// Reads the entry at the start of the remaining archive.
+  fn read_entry(&mut self) -> Result<Option<Entry<'a>>, Error> {
+    let header = self.archive.get(..HEADER_SIZE).ok_or(Error::Truncated)?;
+    if &header[..MAGIC.len()] != MAGIC {
+      return Err(Error::InvalidMagic);
+    }
+
+    // The fields after the magic number are (in this order) the inode number, mode, user
+    // ID, group ID, number of links, modification time, file size, device major and minor
+    // number, rdev major and minor number, name size and checksum
+    let field = |index: usize| {
+      let start = MAGIC.len() + 8 * index;
+      core::str::from_utf8(&header[start..start + 8])
+        .ok()
+        .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+        .ok_or(Error::InvalidHeader)
+    };
+    let inode = field(0)?;
+    let mode = field(1)?;
+    let links = field(4)?;
+    let file_size = field(6)? as usize;
+    let name_size = field(11)? as usize;
+
+    // The name is terminated by a NUL byte; the name and the data are padded to 4 bytes
+    let name_end = HEADER_SIZE + name_size;
+    let name = self
+      .archive
+      .get(HEADER_SIZE..name_end.saturating_sub(1))
+      .ok_or(Error::Truncated)?;
+    let name = core::str::from_utf8(name).map_err(|_| Error::InvalidHeader)?;
+    let data_start = align(name_end);
+    let data_end = data_start + file_size;
+    let data = self.archive.get(data_start..data_end).ok_or(Error::Truncated)?;
+
+    if name == TRAILER {
+      self.archive = &[];
+      return Ok(None);
+    }
+
+    self.archive = self.archive.get(align(data_end)..).unwrap_or_default();
+    Ok(Some(Entry {
+      name,
+      mode,
+      inode,
+      links,
+      data,
+    }))
+  }
+}
+
+impl<'a> Iterator for Entries<'a> {
+  type Item = Result<Entry<'a>, Error>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.archive.is_empty() {
+      return None;
+    }
+
+    let entry = self.read_entry();
+    if entry.is_err() {
+      self.archive = &[];
+    }
+    entry.transpose()
+  }
+}
+
+/// Rounds `offset` up to the next multiple of 4.
+const fn align(offset: usize) -> usize { (offset + 3) & !3 }
+
+/// Unpacks `archive` into the root directory of `vfs` and returns the number of entries
+/// that were created. Existing files are overwritten.
+///
+/// #### Errors
+///
+/// If the archive is malformed or an entry cannot be created, an error is returned.
+/// Entries before the erroneous one have been created.
+pub fn unpack(vfs: &Vfs, archive: &[u8]) -> Result<usize, Error> {
+  // The first path under which each inode with multiple links was created
+  let mut hard_links: BTreeMap<u32, String> = BTreeMap::new();
+  let mut count = 0;
+
+  for entry in Entries::new(archive) {
+    let entry = entry?;
+    let name = entry.name.trim_start_matches("./").trim_start_matches('/');
+    if name.is_empty() || name == "." {
+      continue;
+    }
+
+    let mut path = String::from("/");
+    path.push_str(name);
+    let permissions = u16::try_from(entry.mode & 0o7777).unwrap_or_default();
+
+    match entry.mode & TYPE_MASK {
+      TYPE_DIRECTORY => match vfs.create_directory(&path, permissions) {
+        Ok(()) | Err(super::Error::AlreadyExists) => {},
+        Err(error) => return Err(error.into()),
+      },
+      TYPE_REGULAR => {
+        if entry.links > 1 {
+          if let Some(first) = hard_links.get(&entry.inode) {
+            if vfs.hard_link(first, &path) == Err(super::Error::AlreadyExists) {
+              vfs.remove_file(&path)?;
+              vfs.hard_link(first, &path)?;
+            }
+            if entry.data.is_empty() {
+              count += 1;
+              continue;
+            }
+          } else {
+            hard_links.insert(entry.inode, path.clone());
+          }
+        }
+
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        let file = vfs.open(&path, flags, permissions)?;
+        let mut data = entry.data;
+        while !data.is_empty() {
+          let written = file.write(data)?;
+          data = &data[written..];
+        }
+      },
+      TYPE_SYMBOLIC_LINK => {
+        let target = core::str::from_utf8(entry.data).map_err(|_| Error::InvalidHeader)?;
+        if vfs.symlink(target, &path) == Err(super::Error::AlreadyExists) {
+          vfs.remove_file(&path)?;
+          vfs.symlink(target, &path)?;
+        }
+      },
+      // Device files, FIFOs and sockets are not supported
+      _ => {
+        log::debug!("Skipping initramfs entry '{}' of unsupported type", entry.name);
+        continue;
+      },
+    }
+    count += 1;
+  }
+
+  Ok(count)
+}
+
+/// Returns the archive the boot loader placed in memory, if any.
+fn archive_from_device_tree() -> Option<&'static [u8]> {
+  let chosen = crate::library::device_tree::get()?.find_node("/chosen")?;
+  let start = usize::try_from(chosen.property("linux,initrd-start")?.as_u64()?).ok()?;
+  let end = usize::try_from(chosen.property("linux,initrd-end")?.as_u64()?).ok()?;
+  if end <= start {
+    return None;
+  }
+
+  // The boot loader reserved this memory for the archive
+  Some(unsafe { core::slice::from_raw_parts(start as *const u8, end - start) })
+}
+
+/// Returns the archive that is linked into the kernel image, if any.
+#[allow(clippy::missing_const_for_fn, clippy::unnecessary_wraps)]
+fn linked_archive() -> Option<&'static [u8]> {
+  #[cfg(initramfs)]
+  return Some(LINKED_ARCHIVE);
+  #[cfg(not(initramfs))]
+  None
+}
+
+/// Unpacks all available archives (see the module documentation) into `vfs`. Errors are
+/// logged, but do not stop the kernel.
+pub fn load(vfs: &Vfs) {
+  let archives = [
+    ("linked", linked_archive()),
+    ("initrd", archive_from_device_tree()),
+  ];
+
+  let mut unpacked = false;
+  for (source, archive) in archives {
+    let Some(archive) = archive else {
+      continue;
+    };
+
+    match unpack(vfs, archive) {
+      Ok(count) => log::info!("Unpacked {count} entries from the {source} initramfs"),
+      Err(error) => log::warn!("Could not unpack the {source} initramfs: {error}"),
+    }
+    unpacked = true;
+  }
+
+  if unpacked {
+    check_init(vfs);
+  }
+}
+
+/// Reports whether [`INIT`] is an executable file in `vfs`.
+fn check_init(vfs: &Vfs) {
+  let path = INIT.get();
+  match vfs.metadata(path) {
+    Ok(metadata) if metadata.file_type == FileType::Regular && metadata.mode & 0o111 != 0 => {
+      log::info!("Init program is '{path}'");
+    },
+    Ok(_) => log::warn!("Init program '{path}' is not an executable file"),
+    Err(error) => log::warn!("Init program '{path}' not found: {error}"),
+  }
+}
@@ -0,0 +1,379 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains the virtual file system (VFS), which provides a uniform view onto
+//! all file systems the kernel knows about.
+//!
+//! Concrete file systems implement [`FileSystem`] and [`Inode`]. An inode is a file, a
+//! directory or a symbolic link; it does not know its own name. Names live in directory
+//! entries ([`Dentry`]), which the VFS creates while resolving paths and which form the
+//! tree that is visible to the rest of the kernel. File systems are attached to this tree
+//! with [`Vfs::mount`]; the first file system is mounted at `/`.
+//!
+//! Files are opened with [`Vfs::open`], which returns a [`File`] that remembers the
+//! current offset, so that consecutive reads and writes continue where the previous one
+//! stopped. Programs refer to open files, [`pipe`]s and the console through file
+//! descriptors (see [`descriptor`]).
+//!
+//! During boot, [`initialize`] mounts a [`Tmpfs`] at `/` and unpacks the initial RAM
+//! file system into it (see [`initramfs`]). Afterwards, a [`Procfs`], which exposes the
+//! state of the kernel, is mounted at `/proc`, a [`Tmpfs`] for named shared memory is
+//! mounted at `/dev/shm`, and the [`Fat`] and [`Ext2`] file systems
+//! found on block devices (see [`block`]) are mounted at `/mnt/<DEVICE NAME>`.
+
+use alloc::{
+  string::String,
+  sync::Arc,
+  vec::Vec,
+};
+
+pub mod block;
+pub mod cache;
+pub mod descriptor;
+mod ext2;
+mod fat;
+mod file;
+pub mod initramfs;
+pub mod pipe;
+mod procfs;
+mod tmpfs;
+mod vfs;
+
+#[cfg(test)]
+mod tests;
+
+pub use ext2::Ext2;
+pub use fat::Fat;
+pub use file::{
+  File,
+  OpenFlags,
+  SeekFrom,
+};
+pub use procfs::Procfs;
+pub use tmpfs::Tmpfs;
+pub use vfs::{
+  Dentry,
+  Mount,
+  Vfs,
+};
+
+/// Errors that file system operations may return.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+  /// A component of the path does not exist.
+  NotFound,
+  /// A component of the path is used as a directory but is not one.
+  NotADirectory,
+  /// The operation is not possible on a directory.
+  IsADirectory,
+  /// The file already exists.
+  AlreadyExists,
+  /// The directory is not empty.
+  NotEmpty,
+  /// The path is empty or ends in a component that cannot be used here.
+  InvalidPath,
+  /// Resolving the path required following too many symbolic links.
+  TooManySymbolicLinks,
+  /// The file or file system is in use.
+  Busy,
+  /// The operation would link files on different file systems.
+  CrossesDevices,
+  /// The file was not opened with the required access mode.
+  PermissionDenied,
+  /// An argument (e.g. an offset) is out of range.
+  InvalidArgument,
+  /// The file system does not support the operation.
+  Unsupported,
+  /// The underlying device reported an error.
+  Io,
+  /// There is no space left on the device.
+  NoSpace,
+  /// The file system or device is read-only.
+  ReadOnly,
+  /// The data structures of the file system are damaged.
+  Corrupted,
+  /// The file descriptor is not open.
+  BadDescriptor,
+  /// The file descriptor table is full.
+  TooManyOpenFiles,
+  /// The operation would block, but the file is non-blocking.
+  WouldBlock,
+  /// The pipe has no reader anymore.
+  BrokenPipe,
+  /// The kernel heap is exhausted.
+  OutOfMemory,
+}
+
+impl core::fmt::Display for Error {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::NotFound => write!(f, "no such file or directory"),
+      Self::NotADirectory => write!(f, "not a directory"),
+      Self::IsADirectory => write!(f, "is a directory"),
+      Self::AlreadyExists => write!(f, "file exists"),
+      Self::NotEmpty => write!(f, "directory not empty"),
+      Self::InvalidPath => write!(f, "invalid path"),
+      Self::TooManySymbolicLinks => write!(f, "too many levels of symbolic links"),
+      Self::Busy => write!(f, "resource busy"),
+      Self::CrossesDevices => write!(f, "invalid cross-device link"),
+      Self::PermissionDenied => write!(f, "permission denied"),
+      Self::InvalidArgument => write!(f, "invalid argument"),
+      Self::Unsupported => write!(f, "operation not supported"),
+      Self::Io => write!(f, "input/output error"),
+      Self::NoSpace => write!(f, "no space left on device"),
+      Self::ReadOnly => write!(f, "read-only file system"),
+      Self::Corrupted => write!(f, "structure needs cleaning"),
+      Self::BadDescriptor => write!(f, "bad file descriptor"),
+      Self::TooManyOpenFiles => write!(f, "too many open files"),
+      Self::WouldBlock => write!(f, "resource temporarily unavailable"),
+      Self::BrokenPipe => write!(f, "broken pipe"),
+      Self::OutOfMemory => write!(f, "cannot allocate memory"),
+    }
+  }
+}
+
+impl From<alloc::collections::TryReserveError> for Error {
+  fn from(_: alloc::collections::TryReserveError) -> Self { Self::OutOfMemory }
+}
+
+impl From<crate::library::mem::Error> for Error {
+  fn from(error: crate::library::mem::Error) -> Self {
+    use crate::library::mem::Error;
+
+    match error {
+      Error::OutOfMemory => Self::OutOfMemory,
+      Error::File(error) => error,
+      Error::InvalidArgument | Error::NotMapped | Error::OutOfAddressSpace | Error::AccessDenied => {
+        Self::InvalidArgument
+      },
+    }
+  }
+}
+
+/// The result type of all file system operations.
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// The type of an inode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileType {
+  /// A regular file
+  Regular,
+  /// A directory
+  Directory,
+  /// A symbolic link
+  SymbolicLink,
+}
+
+/// Information about an inode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Metadata {
+  /// The number of the inode, which is unique within its file system
+  pub inode:     u64,
+  /// The type of the inode
+  pub file_type: FileType,
+  /// The size in bytes
+  pub size:      u64,
+  /// The permission bits (e.g. `0o755`)
+  pub mode:      u16,
+  /// The number of directory entries that refer to the inode
+  pub links:     u32,
+}
+
+/// An entry of a directory as returned by [`Inode::read_dir`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryEntry {
+  /// The name of the entry
+  pub name:      String,
+  /// The number of the inode the entry refers to
+  pub inode:     u64,
+  /// The type of the inode the entry refers to
+  pub file_type: FileType,
+}
+
+/// The interface of a file system. A file system is mounted with [`Vfs::mount`].
+pub trait FileSystem: Send + Sync {
+  /// Returns the name of the file system type (e.g. `tmpfs`).
+  fn name(&self) -> &'static str;
+
+  /// Returns the root directory of the file system.
+  fn root(&self) -> Arc<dyn Inode>;
+
+  /// Writes all cached changes to the underlying storage.
+  ///
+  /// #### Errors
+  ///
+  /// If the changes cannot be written, an error is returned.
+  fn sync(&self) -> Result<()> { Ok(()) }
+}
+
+/// The interface of a file, directory or symbolic link in a file system.
+///
+/// All operations that do not apply to every type of inode have default implementations
+/// that return an error. Names passed to directory operations are single path
+/// components; they are never empty, `.` or `..`.
+pub trait Inode: Send + Sync {
+  /// Returns information about the inode.
+  ///
+  /// #### Errors
+  ///
+  /// If the information cannot be read, an error is returned.
+  fn metadata(&self) -> Result<Metadata>;
+
+  /// Reads from the file at `offset` into `buffer` and returns the number of bytes read,
+  /// which is zero at the end of the file.
+  ///
+  /// #### Errors
+  ///
+  /// If the inode is not a regular file or cannot be read, an error is returned.
+  fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize> { Err(Error::Unsupported) }
+
+  /// Writes `buffer` to the file at `offset`, growing the file if required, and returns
+  /// the number of bytes written.
+  ///
+  /// #### Errors
+  ///
+  /// If the inode is not a regular file or cannot be written, an error is returned.
+  fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<usize> { Err(Error::Unsupported) }
+
+  /// Sets the size of the file to `size`, discarding or zero-filling data.
+  ///
+  /// #### Errors
+  ///
+  /// If the inode is not a regular file or cannot be resized, an error is returned.
+  fn truncate(&self, _size: u64) -> Result<()> { Err(Error::Unsupported) }
+
+  /// Returns the inode of the directory entry `name`.
+  ///
+  /// #### Errors
+  ///
+  /// If there is no such entry, [`Error::NotFound`] is returned.
+  fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>> { Err(Error::NotADirectory) }
+
+  /// Creates an empty file or directory `name` with the permission bits `mode` and
+  /// returns its inode.
+  ///
+  /// #### Errors
+  ///
+  /// If the entry exists already, [`Error::AlreadyExists`] is returned.
+  fn create(&self, _name: &str, _file_type: FileType, _mode: u16) -> Result<Arc<dyn Inode>> {
+    Err(Error::NotADirectory)
+  }
+
+  /// Creates the symbolic link `name` that points to `target` and returns its inode.
+  ///
+  /// #### Errors
+  ///
+  /// If the entry exists already, [`Error::AlreadyExists`] is returned.
+  fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>> { Err(Error::Unsupported) }
+
+  /// Adds the entry `name` that refers to `inode`, which belongs to the same file system.
+  ///
+  /// #### Errors
+  ///
+  /// If the entry exists already, [`Error::AlreadyExists`] is returned.
+  fn link(&self, _name: &str, _inode: &Arc<dyn Inode>) -> Result<()> { Err(Error::Unsupported) }
+
+  /// Removes the entry `name`. The inode is destroyed when no entry refers to it anymore.
+  ///
+  /// #### Errors
+  ///
+  /// If the entry is a directory that is not empty, [`Error::NotEmpty`] is returned.
+  fn unlink(&self, _name: &str) -> Result<()> { Err(Error::NotADirectory) }
+
+  /// Returns all entries of the directory, except for `.` and `..`.
+  ///
+  /// #### Errors
+  ///
+  /// If the inode is not a directory or cannot be read, an error is returned.
+  fn read_dir(&self) -> Result<Vec<DirectoryEntry>> { Err(Error::NotADirectory) }
+
+  /// Returns the target of the symbolic link.
+  ///
+  /// #### Errors
+  ///
+  /// If the inode is not a symbolic link, [`Error::InvalidArgument`] is returned.
+  fn read_link(&self) -> Result<String> { Err(Error::InvalidArgument) }
+}
+
+/// The VFS of the kernel.
+static VFS: Vfs = Vfs::new();
+
+/// Returns the VFS of the kernel.
+#[must_use]
+pub fn get() -> &'static Vfs { &VFS }
+
+/// Mounts a [`Tmpfs`] at `/` of the kernel's VFS and unpacks the initial RAM file system
+/// into it.
+///
+/// Afterwards, a [`Procfs`] is mounted at `/proc` and the file systems on block devices
+/// are mounted. This function must be called after the heap has been initialized.
+pub fn initialize() {
+  if let Err(error) = VFS.mount("/", Tmpfs::new(0o755)) {
+    log::error!("Could not mount the root file system: {error}");
+    return;
+  }
+
+  descriptor::initialize();
+  crate::library::mem::heap::register_reclaimer(cache::reclaim);
+  initramfs::load(&VFS);
+  mount_procfs();
+  mount_shared_memory();
+  mount_block_devices();
+}
+
+/// Mounts a [`Procfs`] at `/proc`.
+fn mount_procfs() {
+  let result = match VFS.create_directory("/proc", 0o555) {
+    Ok(()) | Err(Error::AlreadyExists) => VFS.mount("/proc", Procfs::new()),
+    Err(error) => Err(error),
+  };
+
+  if let Err(error) = result {
+    log::warn!("Could not mount the process file system at '/proc': {error}");
+  }
+}
+
+/// Mounts a [`Tmpfs`] at `/dev/shm`. Programs share memory by mapping the same file in
+/// it, which is how C libraries implement `shm_open`.
+fn mount_shared_memory() {
+  let result = match VFS.create_directory("/dev", 0o755) {
+    Ok(()) | Err(Error::AlreadyExists) => VFS.create_directory("/dev/shm", 0o1777),
+    Err(error) => Err(error),
+  };
+  let result = match result {
+    Ok(()) | Err(Error::AlreadyExists) => VFS.mount("/dev/shm", Tmpfs::new(0o1777)),
+    Err(error) => Err(error),
+  };
+
+  if let Err(error) = result {
+    log::warn!("Could not mount the shared memory file system at '/dev/shm': {error}");
+  }
+}
+
+/// A function that returns the file system on a block device if it is of a certain type.
+type Probe = fn(&'static dyn block::BlockDevice) -> Result<Arc<dyn FileSystem>>;
+
+/// The file systems that are looked for on block devices, in this order.
+const BLOCK_FILE_SYSTEMS: [Probe; 2] = [|device| Ok(Fat::new(device)?), |device| Ok(Ext2::new(device)?)];
+
+/// Mounts the file system of every block device that contains a supported file system
+/// at `/mnt/<DEVICE NAME>`.
+fn mount_block_devices() {
+  for block::Registration { name, device } in block::devices() {
+    let Some(file_system) = BLOCK_FILE_SYSTEMS.iter().find_map(|probe| probe(device).ok()) else {
+      log::debug!("Block device '{name}' contains no supported file system");
+      continue;
+    };
+
+    let path = alloc::format!("/mnt/{name}");
+    let result = match VFS.create_directory("/mnt", 0o755) {
+      Ok(()) | Err(Error::AlreadyExists) => VFS.create_directory(&path, 0o755),
+      Err(error) => Err(error),
+    }
+    .and_then(|()| VFS.mount(&path, file_system));
+
+    match result {
+      Ok(()) => log::info!("Mounted block device '{name}' at '{path}'"),
+      Err(error) => log::warn!("Could not mount block device '{name}': {error}"),
+    }
+  }
+}
@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains pipes, which pass a stream of bytes from writers to readers.
+//!
+//! A pipe is created with [`new`], which returns its reading and its writing end. Both
+//! are [`FileDescription`]s, so that file descriptors can refer to them. Reading blocks
+//! while the pipe is empty and returns zero (the end of the stream) once all writing
+//! ends have been closed. Writing blocks while the pipe is full and fails with
+//! [`Error::BrokenPipe`] once all reading ends have been closed.
+
+use alloc::{
+  collections::VecDeque,
+  sync::Arc,
+};
+
+use super::{
+  descriptor::FileDescription,
+  Error,
+  Result,
+};
+use crate::library::sync::WaitQueue;
+
+/// The number of bytes a pipe buffers.
+pub const CAPACITY: usize = 4096;
+
+/// The state of a pipe that is protected by its lock.
+struct State {
+  /// The bytes that have been written, but not read yet
+  data:    VecDeque<u8>,
+  /// The number of reading ends that exist
+  readers: usize,
+  /// The number of writing ends that exist
+  writers: usize,
+}
+
+/// A pipe, which both of its ends refer to.
+struct Pipe {
+  /// The buffered data and the number of ends
+  state:    spin::Mutex<State>,
+  /// The readers waiting for data
+  readable: WaitQueue,
+  /// The writers waiting for space
+  writable: WaitQueue,
+}
+
+impl Pipe {
+  /// Waits on `queue` until `attempt` returns a result, which is then returned. If
+  /// `non_blocking` is set, [`Error::WouldBlock`] is returned instead of waiting.
+  fn wait<T>(
+    &self,
+    queue: &WaitQueue,
+    non_blocking: bool,
+    mut attempt: impl FnMut(&mut State) -> Option<Result<T>>,
+  ) -> Result<T> {
+    queue.wait_until(|| {
+      attempt(&mut self.state.lock()).or_else(|| non_blocking.then_some(Err(Error::WouldBlock)))
+    })
+  }
+}
+
+/// The reading end of a pipe.
+pub struct Reader {
+  /// The pipe
+  pipe:         Arc<Pipe>,
+  /// Whether reading an empty pipe returns [`Error::WouldBlock`] instead of blocking
+  non_blocking: bool,
+}
+
+/// The writing end of a pipe.
+pub struct Writer {
+  /// The pipe
+  pipe:         Arc<Pipe>,
+  /// Whether writing a full pipe returns [`Error::WouldBlock`] instead of blocking
+  non_blocking: bool,
+}
+
+/// Creates a pipe and returns its reading and its writing end. If `non_blocking` is set,
+/// operations on both ends return [`Error::WouldBlock`] instead of blocking.
+#[must_use]
+pub fn new(non_blocking: bool) -> (Arc<Reader>, Arc<Writer>) {
+  let pipe = Arc::new(Pipe {
+    state:    spin::Mutex::new(State {
+      data:    VecDeque::new(),
+      readers: 1,
+      writers: 1,
+    }),
+    readable: WaitQueue::new(),
+    writable: WaitQueue::new(),
+  });
+
+  (
+    Arc::new(Reader {
+      pipe: pipe.clone(),
+      non_blocking,
+    }),
+    Arc::new(Writer { pipe, non_blocking }),
+  )
+}
+
+impl FileDescription for Reader {
+  fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+    if buffer.is_empty() {
+      return Ok(0);
+    }
+
+    let read = self.pipe.wait(&self.pipe.readable, self.non_blocking, |state| {
+      if state.data.is_empty() {
+        return (state.writers == 0).then_some(Ok(0));
+      }
+
+      let length = state.data.len().min(buffer.len());
+      for (target, byte) in buffer.iter_mut().zip(state.data.drain(..length)) {
+        *target = byte;
+      }
+      Some(Ok(length))
+    })?;
+
+    self.pipe.writable.wake_all();
+    Ok(read)
+  }
+}
+
+impl FileDescription for Writer {
+  fn write(&self, buffer: &[u8]) -> Result<usize> {
+    let mut written = 0;
+    while written < buffer.len() {
+      let result = self.pipe.wait(&self.pipe.writable, self.non_blocking, |state| {
+        if state.readers == 0 {
+          return Some(Err(Error::BrokenPipe));
+        }
+
+        let length = (CAPACITY - state.data.len()).min(buffer.len() - written);
+        (length != 0).then(|| {
+          state.data.extend(&buffer[written..written + length]);
+          Ok(length)
+        })
+      });
+
+      match result {
+        Ok(length) => {
+          written += length;
+          self.pipe.readable.wake_all();
+        },
+        // What has been written so far is reported; the error is returned by the next write
+        Err(_) if written != 0 => break,
+        Err(error) => return Err(error),
+      }
+    }
+    Ok(written)
+  }
+}
+
+impl Drop for Reader {
+  fn drop(&mut self) {
+    self.pipe.state.lock().readers -= 1;
+    self.pipe.writable.wake_all();
+  }
+}
+
+impl Drop for Writer {
+  fn drop(&mut self) {
+    self.pipe.state.lock().writers -= 1;
+    self.pipe.readable.wake_all();
+  }
+}
+
+impl core::fmt::Debug for Reader {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Reader")
+      .field("buffered", &self.pipe.state.lock().data.len())
+      .finish_non_exhaustive()
+  }
+}
+
+impl core::fmt::Debug for Writer {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Writer")
+      .field("buffered", &self.pipe.state.lock().data.len())
+      .finish_non_exhaustive()
+  }
+}
@@ -0,0 +1,355 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains `procfs`, a file system whose files expose the live state of the
+//! kernel. The contents of a file are generated whenever it is read, so they are always
+//! up to date; reading a file in several parts may therefore return parts of different
+//! versions. A few files can be written to change the state of the kernel; a write must
+//! contain the whole new contents.
+//!
+//! | File         | Contents                                                      |
+//! | ------------ | ------------------------------------------------------------- |
+//! | `version`    | the kernel version and the toolchain the kernel was built with |
+//! | `cmdline`    | the kernel command line                                       |
+//! | `uptime`     | the time since the machine started in seconds                 |
+//! | `meminfo`    | the usage of the kernel heap                                  |
+//! | `slabinfo`   | the usage of the caches of the slab allocator                 |
+//! | `tasks`      | what every hart executes                                      |
+//! | `interrupts` | the number of interrupts and system calls per hart            |
+//! | `mounts`     | the mounted file systems                                      |
+//! | `devices`    | the devices known to the driver framework and their state     |
+//! | `block`      | the registered block devices                                  |
+//! | `kmsg`       | the records of the kernel log that are still in memory        |
+//! | `logfilter`  | the directives of the log filter (writable)                   |
+//! | `logsinks`   | the sinks of the kernel log, their level and format (writable) |
+
+use alloc::{
+  string::String,
+  sync::Arc,
+  vec::Vec,
+};
+use core::fmt::Write;
+
+use super::{
+  DirectoryEntry,
+  Error,
+  FileType,
+  Inode,
+  Metadata,
+  Result,
+};
+
+/// A function that writes the contents of a file.
+type Generator = fn(&mut String) -> core::fmt::Result;
+/// A function that applies new contents of a writable file.
+type Updater = fn(&str) -> Result<()>;
+
+/// The files of `procfs` as triples of name, generator and updater (for writable files).
+/// The file at index `i` has the inode number `i + 2`; the root directory has the inode
+/// number 1.
+const FILES: [(&str, Generator, Option<Updater>); 13] = [
+  ("version", version, None),
+  ("cmdline", crate::library::cmdline::write, None),
+  ("uptime", uptime, None),
+  ("meminfo", meminfo, None),
+  ("slabinfo", slabinfo, None),
+  ("tasks", tasks, None),
+  ("interrupts", interrupts, None),
+  ("mounts", mounts, None),
+  ("devices", devices, None),
+  ("block", block_devices, None),
+  ("kmsg", kmsg, None),
+  (
+    "logfilter",
+    crate::library::log::filter::write,
+    Some(set_log_filter),
+  ),
+  (
+    "logsinks",
+    crate::library::log::sink::write_sinks,
+    Some(configure_log_sinks),
+  ),
+];
+
+/// The inode number of the root directory.
+const ROOT_INODE: u64 = 1;
+
+/// Writes the version of the kernel and of the toolchain it was built with.
+fn version(contents: &mut String) -> core::fmt::Result {
+  use crate::library::log::KernelInformation;
+
+  writeln!(contents, "version:   {}", KernelInformation::get_kernel_version())?;
+  writeln!(
+    contents,
+    "compiled:  {}",
+    KernelInformation::get_compilation_date_and_time()
+  )?;
+  writeln!(contents, "rustc:     {}", KernelInformation::get_rustc_version())?;
+  writeln!(contents, "toolchain: {}", KernelInformation::get_rust_toolchain())?;
+  writeln!(contents, "log level: {}", KernelInformation::get_log_level())
+}
+
+/// Writes the time since the machine started.
+fn uptime(contents: &mut String) -> core::fmt::Result {
+  let uptime = crate::library::time::uptime();
+  writeln!(contents, "{}.{:03}", uptime.as_secs(), uptime.subsec_millis())
+}
+
+/// Writes the usage of the kernel heap.
+fn meminfo(contents: &mut String) -> core::fmt::Result {
+  let heap = crate::library::mem::heap::Heap::statistics();
+  writeln!(contents, "HeapSize: {:>12} bytes", heap.size)?;
+  writeln!(contents, "HeapUsed: {:>12} bytes", heap.used)?;
+  writeln!(contents, "HeapFree: {:>12} bytes", heap.free)?;
+  writeln!(contents, "HeapAllocated: {:>7} bytes", heap.allocated)?;
+  writeln!(contents, "HeapLargestFree: {:>5} bytes", heap.largest_free)?;
+  writeln!(contents, "HeapFragmentation: {:>3} %", heap.fragmentation())
+}
+
+/// Writes the number of slabs and allocated objects of every cache of the slab allocator.
+fn slabinfo(contents: &mut String) -> core::fmt::Result {
+  writeln!(contents, "SIZE        SLABS    ALLOCATED")?;
+  for cache in crate::library::mem::heap::Heap::statistics().caches {
+    writeln!(
+      contents,
+      "{:<8} {:>8} {:>12}",
+      cache.size, cache.slabs, cache.allocated
+    )?;
+  }
+  Ok(())
+}
+
+/// Writes what every hart executes. There is no scheduler yet, so every hart that was
+/// started runs the kernel itself.
+fn tasks(contents: &mut String) -> core::fmt::Result {
+  writeln!(contents, "HART  STATE    TASK")?;
+  for hart in crate::arch::online_harts() {
+    writeln!(contents, "{hart:<4}  running  kernel")?;
+  }
+  Ok(())
+}
+
+/// Writes the number of traps per cause for every hart that was started.
+fn interrupts(contents: &mut String) -> core::fmt::Result {
+  use crate::arch::{
+    trap_counts,
+    MAXIMUM_HARTS,
+  };
+
+  let harts: Vec<usize> = crate::arch::online_harts()
+    .filter(|&hart| hart < MAXIMUM_HARTS)
+    .collect();
+
+  write!(contents, "{:<20}", "CAUSE")?;
+  for hart in &harts {
+    write!(contents, "{:>12}", alloc::format!("HART{hart}"))?;
+  }
+  writeln!(contents)?;
+
+  for (cause, counts) in trap_counts() {
+    write!(contents, "{:<20}", alloc::format!("{cause}"))?;
+    for &hart in &harts {
+      write!(contents, "{:>12}", counts[hart])?;
+    }
+    writeln!(contents)?;
+  }
+  Ok(())
+}
+
+/// Writes the mounted file systems as pairs of type and mount point.
+fn mounts(contents: &mut String) -> core::fmt::Result {
+  for mount in super::get().mounts() {
+    writeln!(contents, "{} {}", mount.file_system().name(), mount.path())?;
+  }
+  Ok(())
+}
+
+/// Writes the devices known to the driver framework with their driver and state.
+fn devices(contents: &mut String) -> core::fmt::Result {
+  use crate::library::drivers::State;
+
+  writeln!(contents, "{:<32} {:<16} STATE", "DEVICE", "DRIVER")?;
+  for device in crate::library::drivers::devices() {
+    write!(
+      contents,
+      "{:<32} {:<16} ",
+      device.node().name(),
+      device.driver().name()
+    )?;
+    match device.state() {
+      State::Pending => writeln!(contents, "pending")?,
+      State::Bound => writeln!(contents, "bound")?,
+      State::Failed(error) => writeln!(contents, "failed: {error}")?,
+    }
+  }
+  Ok(())
+}
+
+/// Writes the registered block devices with their block size and number of blocks.
+fn block_devices(contents: &mut String) -> core::fmt::Result {
+  writeln!(contents, "{:<16} {:>10} {:>12}", "NAME", "BLOCK SIZE", "BLOCKS")?;
+  for super::block::Registration { name, device } in super::block::devices() {
+    writeln!(
+      contents,
+      "{name:<16} {:>10} {:>12}",
+      device.block_size(),
+      device.block_count()
+    )?;
+  }
+  Ok(())
+}
+
+/// Writes the records of the kernel log that are still in the ring buffer, like `dmesg`.
+fn kmsg(contents: &mut String) -> core::fmt::Result {
+  let end = crate::library::log::next_sequence();
+  for entry in crate::library::log::records(0, end) {
+    writeln!(contents, "{entry}")?;
+  }
+  Ok(())
+}
+
+/// Replaces the directives of the log filter.
+fn set_log_filter(directives: &str) -> Result<()> {
+  crate::library::log::filter::set(directives).map_err(|_| Error::InvalidArgument)
+}
+
+/// Changes the level (and optionally the format) of sinks of the kernel log, e.g.
+/// `uart=warn,framebuffer=info:plain`.
+fn configure_log_sinks(settings: &str) -> Result<()> {
+  for setting in settings
+    .split([',', '\n'])
+    .map(str::trim)
+    .filter(|setting| !setting.is_empty())
+  {
+    let (name, setting) = setting.split_once('=').ok_or(Error::InvalidArgument)?;
+    let (level, format) = setting
+      .split_once(':')
+      .map_or((setting, None), |(level, format)| (level, Some(format)));
+    let level = level.parse().map_err(|_| Error::InvalidArgument)?;
+    let format = format
+      .map(str::parse)
+      .transpose()
+      .map_err(|_| Error::InvalidArgument)?;
+    crate::library::log::sink::configure(name, level, format).map_err(|_| Error::InvalidArgument)?;
+  }
+  Ok(())
+}
+
+/// A file of `procfs`.
+struct Node {
+  /// The number of the inode
+  number:   u64,
+  /// The function that writes the contents
+  generate: Generator,
+  /// The function that applies new contents, if the file is writable
+  update:   Option<Updater>,
+}
+
+impl Node {
+  /// Returns the current contents of the file.
+  fn contents(&self) -> Result<String> {
+    let mut contents = String::new();
+    (self.generate)(&mut contents).map_err(|_| Error::Io)?;
+    Ok(contents)
+  }
+}
+
+impl Inode for Node {
+  fn metadata(&self) -> Result<Metadata> {
+    Ok(Metadata {
+      inode:     self.number,
+      file_type: FileType::Regular,
+      size:      self.contents()?.len() as u64,
+      mode:      if self.update.is_some() { 0o644 } else { 0o444 },
+      links:     1,
+    })
+  }
+
+  fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+    let contents = self.contents()?;
+    let offset = usize::try_from(offset).map_err(|_| Error::InvalidArgument)?;
+    let data = contents.as_bytes().get(offset..).unwrap_or_default();
+    let length = data.len().min(buffer.len());
+    buffer[..length].copy_from_slice(&data[..length]);
+    Ok(length)
+  }
+
+  fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize> {
+    let update = self.update.ok_or(Error::ReadOnly)?;
+    if offset != 0 {
+      return Err(Error::InvalidArgument);
+    }
+    update(core::str::from_utf8(buffer).map_err(|_| Error::InvalidArgument)?)?;
+    Ok(buffer.len())
+  }
+
+  // Truncating a writable file (e.g. when a shell redirects output to it) has no effect,
+  // as a write replaces its whole contents anyway
+  fn truncate(&self, _size: u64) -> Result<()> { self.update.map(|_| ()).ok_or(Error::ReadOnly) }
+}
+
+/// The root directory of `procfs`, which contains the files in [`FILES`].
+struct Root;
+
+impl Inode for Root {
+  fn metadata(&self) -> Result<Metadata> {
+    Ok(Metadata {
+      inode:     ROOT_INODE,
+      file_type: FileType::Directory,
+      size:      FILES.len() as u64,
+      mode:      0o555,
+      links:     2,
+    })
+  }
+
+  fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+    let (number, (_, generate, update)) = (ROOT_INODE + 1..)
+      .zip(FILES)
+      .find(|(_, (file, ..))| *file == name)
+      .ok_or(Error::NotFound)?;
+    Ok(Arc::new(Node {
+      number,
+      generate,
+      update,
+    }))
+  }
+
+  fn create(&self, _name: &str, _file_type: FileType, _mode: u16) -> Result<Arc<dyn Inode>> {
+    Err(Error::ReadOnly)
+  }
+
+  fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>> { Err(Error::ReadOnly) }
+
+  fn link(&self, _name: &str, _inode: &Arc<dyn Inode>) -> Result<()> { Err(Error::ReadOnly) }
+
+  fn unlink(&self, _name: &str) -> Result<()> { Err(Error::ReadOnly) }
+
+  fn read_dir(&self) -> Result<Vec<DirectoryEntry>> {
+    Ok(
+      (ROOT_INODE + 1..)
+        .zip(FILES)
+        .map(|(inode, (name, ..))| DirectoryEntry {
+          name: name.into(),
+          inode,
+          file_type: FileType::Regular,
+        })
+        .collect(),
+    )
+  }
+}
+
+/// A file system that exposes the live state of the kernel.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug)]
+pub struct Procfs;
+
+impl Procfs {
+  /// Creates the file system.
+  #[must_use]
+  pub fn new() -> Arc<Self> { Arc::new(Self) }
+}
+
+impl super::FileSystem for Procfs {
+  fn name(&self) -> &'static str { "proc" }
+
+  fn root(&self) -> Arc<dyn Inode> { Arc::new(Root) }
+}
@@ -0,0 +1,433 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unit tests of the VFS, `tmpfs`, `procfs`, pipes, file descriptors, the page cache and
+//! the initramfs loader.
+
+use alloc::{
+  format,
+  string::ToString,
+  vec::Vec,
+};
+
+use super::{
+  cache,
+  descriptor::{
+    FileDescription,
+    FileDescriptorTable,
+  },
+  initramfs,
+  pipe,
+  Error,
+  FileType,
+  OpenFlags,
+  Procfs,
+  SeekFrom,
+  Tmpfs,
+  Vfs,
+};
+
+/// Creates a VFS with an empty `tmpfs` mounted at `/`.
+fn vfs() -> Vfs {
+  let vfs = Vfs::new();
+  vfs.mount("/", Tmpfs::new(0o755)).unwrap();
+  vfs
+}
+
+#[test_case]
+fn files_are_read_and_written_at_the_offset() {
+  let vfs = vfs();
+  let flags = OpenFlags::READ_WRITE | OpenFlags::CREATE;
+  let file = vfs.open("/file", flags, 0o644).unwrap();
+  assert_eq!(file.write(b"hello world").unwrap(), 11);
+  assert_eq!(file.seek(SeekFrom::Start(6)).unwrap(), 6);
+
+  let mut buffer = [0; 16];
+  assert_eq!(file.read(&mut buffer).unwrap(), 5);
+  assert_eq!(&buffer[..5], b"world");
+  assert_eq!(file.read(&mut buffer).unwrap(), 0);
+  assert_eq!(file.seek(SeekFrom::End(-5)).unwrap(), 6);
+  assert_eq!(file.seek(SeekFrom::Current(-7)), Err(Error::InvalidArgument));
+
+  let appending = vfs.open("file", OpenFlags::WRITE | OpenFlags::APPEND, 0).unwrap();
+  appending.write(b"!").unwrap();
+  assert_eq!(vfs.metadata("/file").unwrap().size, 12);
+  assert_eq!(appending.read(&mut buffer), Err(Error::PermissionDenied));
+
+  vfs
+    .open("/file", OpenFlags::WRITE | OpenFlags::TRUNCATE, 0)
+    .unwrap();
+  assert_eq!(vfs.metadata("/file").unwrap().size, 0);
+  let exclusive = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE;
+  assert_eq!(vfs.open("/file", exclusive, 0).unwrap_err(), Error::AlreadyExists);
+  assert_eq!(
+    vfs.open("/missing", OpenFlags::READ, 0).unwrap_err(),
+    Error::NotFound
+  );
+}
+
+#[test_case]
+fn paths_with_dots_are_resolved() {
+  let vfs = vfs();
+  vfs.create_directory("/a", 0o755).unwrap();
+  vfs.create_directory("/a/b", 0o755).unwrap();
+  vfs
+    .open("/a/b/file", OpenFlags::WRITE | OpenFlags::CREATE, 0o644)
+    .unwrap();
+
+  assert_eq!(vfs.lookup("/a/./b/../b//file").unwrap().path(), "/a/b/file");
+  assert_eq!(vfs.lookup("/../../a").unwrap().path(), "/a");
+  assert_eq!(vfs.lookup("/a/b/file/..").unwrap_err(), Error::NotADirectory);
+  assert_eq!(vfs.lookup("/a/b/file/").unwrap_err(), Error::NotADirectory);
+  assert_eq!(vfs.create_directory("/a", 0o755), Err(Error::AlreadyExists));
+
+  let directory = vfs
+    .open("/a/b", OpenFlags::READ | OpenFlags::DIRECTORY, 0)
+    .unwrap();
+  assert_eq!(directory.read_dir().unwrap().unwrap().name, "file");
+  assert_eq!(directory.read_dir().unwrap(), None);
+  assert_eq!(
+    vfs.open("/a", OpenFlags::WRITE, 0).unwrap_err(),
+    Error::IsADirectory
+  );
+
+  assert_eq!(vfs.remove_directory("/a"), Err(Error::NotEmpty));
+  assert_eq!(vfs.remove_file("/a/b"), Err(Error::IsADirectory));
+  vfs.remove_file("/a/b/file").unwrap();
+  vfs.remove_directory("/a/b").unwrap();
+  assert_eq!(vfs.lookup("/a/b").unwrap_err(), Error::NotFound);
+}
+
+#[test_case]
+fn symbolic_links_are_followed() {
+  let vfs = vfs();
+  vfs.create_directory("/directory", 0o755).unwrap();
+  vfs
+    .open("/directory/file", OpenFlags::WRITE | OpenFlags::CREATE, 0o644)
+    .unwrap();
+  vfs.symlink("directory", "/relative").unwrap();
+  vfs.symlink("/directory/file", "/absolute").unwrap();
+  vfs.symlink("../relative/file", "/directory/up").unwrap();
+
+  assert_eq!(vfs.lookup("/relative/file").unwrap().path(), "/directory/file");
+  assert_eq!(vfs.lookup("/absolute").unwrap().path(), "/directory/file");
+  assert_eq!(vfs.lookup("/directory/up").unwrap().path(), "/directory/file");
+  assert_eq!(vfs.read_link("/absolute").unwrap(), "/directory/file");
+  assert_eq!(
+    vfs.symlink_metadata("/absolute").unwrap().file_type,
+    FileType::SymbolicLink
+  );
+  assert_eq!(
+    vfs
+      .open("/absolute", OpenFlags::READ | OpenFlags::NO_FOLLOW, 0)
+      .unwrap_err(),
+    Error::TooManySymbolicLinks
+  );
+
+  vfs.symlink("/loop", "/loop").unwrap();
+  assert_eq!(vfs.lookup("/loop").unwrap_err(), Error::TooManySymbolicLinks);
+  vfs.remove_file("/loop").unwrap();
+}
+
+#[test_case]
+fn file_systems_are_mounted_and_unmounted() {
+  let vfs = vfs();
+  vfs.create_directory("/mnt", 0o755).unwrap();
+  vfs
+    .open("/file", OpenFlags::WRITE | OpenFlags::CREATE, 0o644)
+    .unwrap();
+  vfs.mount("/mnt", Tmpfs::new(0o755)).unwrap();
+  assert_eq!(vfs.mount("/mnt", Tmpfs::new(0o755)), Err(Error::Busy));
+
+  vfs.create_directory("/mnt/inner", 0o755).unwrap();
+  assert_eq!(vfs.lookup("/mnt/inner/../..").unwrap().path(), "/");
+  assert_eq!(vfs.lookup("/mnt/../file").unwrap().path(), "/file");
+  assert_eq!(vfs.hard_link("/file", "/mnt/link"), Err(Error::CrossesDevices));
+  vfs.hard_link("/file", "/link").unwrap();
+  assert_eq!(vfs.metadata("/file").unwrap().links, 2);
+
+  vfs.mount("/mnt/inner", Tmpfs::new(0o755)).unwrap();
+  let paths: Vec<_> = vfs
+    .mounts()
+    .iter()
+    .map(|mount| mount.path().to_string())
+    .collect();
+  assert_eq!(paths, ["/", "/mnt", "/mnt/inner"]);
+  assert_eq!(vfs.unmount("/mnt"), Err(Error::Busy));
+  assert_eq!(vfs.remove_directory("/mnt/inner"), Err(Error::Busy));
+
+  vfs.unmount("/mnt/inner").unwrap();
+  vfs.unmount("/mnt").unwrap();
+  assert_eq!(vfs.lookup("/mnt/inner").unwrap_err(), Error::NotFound);
+  assert_eq!(vfs.mounts().len(), 1);
+}
+
+/// Appends an entry to the `cpio` archive `archive`.
+fn append_to_archive(archive: &mut Vec<u8>, name: &str, mode: u32, inode: u32, links: u32, data: &[u8]) {
+  let header = format!(
+    "070701{inode:08X}{mode:08X}{:08X}{:08X}{links:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}",
+    0,
+    0,
+    0,
+    data.len(),
+    0,
+    0,
+    0,
+    0,
+    name.len() + 1,
+    0
+  );
+  archive.extend_from_slice(header.as_bytes());
+  archive.extend_from_slice(name.as_bytes());
+  archive.push(0);
+  archive.resize(archive.len().next_multiple_of(4), 0);
+  archive.extend_from_slice(data);
+  archive.resize(archive.len().next_multiple_of(4), 0);
+}
+
+#[test_case]
+fn initramfs_archives_are_unpacked() {
+  let mut archive = Vec::new();
+  append_to_archive(&mut archive, ".", 0o040_755, 1, 2, &[]);
+  append_to_archive(&mut archive, "bin", 0o040_755, 2, 2, &[]);
+  append_to_archive(&mut archive, "bin/hello", 0o100_644, 3, 2, &[]);
+  append_to_archive(&mut archive, "bin/hi", 0o100_644, 3, 2, b"hello");
+  append_to_archive(&mut archive, "hello", 0o120_777, 4, 1, b"bin/hello");
+  append_to_archive(&mut archive, "TRAILER!!!", 0, 0, 1, &[]);
+
+  let vfs = vfs();
+  assert_eq!(initramfs::unpack(&vfs, &archive), Ok(4));
+  let file = vfs.open("/hello", OpenFlags::READ, 0).unwrap();
+  let mut buffer = [0; 8];
+  assert_eq!(file.read(&mut buffer).unwrap(), 5);
+  assert_eq!(&buffer[..5], b"hello");
+  assert_eq!(vfs.metadata("/bin/hi").unwrap().links, 2);
+  assert_eq!(vfs.metadata("/bin/hi").unwrap().mode, 0o644);
+
+  archive.truncate(archive.len() - 8);
+  assert_eq!(
+    initramfs::unpack(&vfs, &archive),
+    Err(initramfs::Error::Truncated)
+  );
+  assert_eq!(
+    initramfs::unpack(&vfs, b"not an archive at all"),
+    Err(initramfs::Error::Truncated)
+  );
+}
+
+#[test_case]
+fn procfs_files_show_the_kernel_state() {
+  let vfs = vfs();
+  vfs.create_directory("/proc", 0o555).unwrap();
+  vfs.mount("/proc", Procfs::new()).unwrap();
+
+  let directory = vfs.open("/proc", OpenFlags::READ, 0).unwrap();
+  let mut names = Vec::new();
+  while let Some(entry) = directory.read_dir().unwrap() {
+    names.push(entry.name);
+  }
+  assert!(names.iter().any(|name| name == "meminfo"));
+  assert!(names.iter().any(|name| name == "interrupts"));
+
+  let file = vfs.open("/proc/meminfo", OpenFlags::READ, 0).unwrap();
+  let mut buffer = [0; 256];
+  let length = file.read(&mut buffer).unwrap();
+  let meminfo = core::str::from_utf8(&buffer[..length]).unwrap();
+  assert!(meminfo.starts_with("HeapSize:"));
+  assert!(meminfo.lines().any(|line| line.starts_with("HeapUsed:")));
+
+  assert_eq!(vfs.metadata("/proc/version").unwrap().mode, 0o444);
+  let flags = OpenFlags::WRITE | OpenFlags::CREATE;
+  assert_eq!(
+    vfs.open("/proc/uptime", OpenFlags::WRITE, 0).unwrap().write(b"0"),
+    Err(Error::ReadOnly)
+  );
+  assert_eq!(vfs.open("/proc/new", flags, 0o644).unwrap_err(), Error::ReadOnly);
+  assert_eq!(vfs.remove_file("/proc/version"), Err(Error::ReadOnly));
+}
+
+#[test_case]
+fn pipes_pass_data_until_an_end_is_closed() {
+  let (reader, writer) = pipe::new(true);
+  let table = FileDescriptorTable::new();
+  let input = table.insert(reader, false).unwrap();
+  let output = table.insert(writer, true).unwrap();
+  assert_eq!((input, output), (0, 1));
+
+  let mut buffer = [0; pipe::CAPACITY];
+  assert_eq!(
+    table.get(input).unwrap().read(&mut buffer),
+    Err(Error::WouldBlock)
+  );
+  assert_eq!(table.get(output).unwrap().write(b"hello").unwrap(), 5);
+  assert_eq!(
+    table.get(output).unwrap().read(&mut buffer),
+    Err(Error::PermissionDenied)
+  );
+  assert_eq!(table.get(input).unwrap().read(&mut buffer[..3]).unwrap(), 3);
+  assert_eq!(&buffer[..3], b"hel");
+
+  let large = [0; pipe::CAPACITY];
+  assert_eq!(
+    table.get(output).unwrap().write(&large).unwrap(),
+    pipe::CAPACITY - 2
+  );
+  assert_eq!(table.get(output).unwrap().write(b"!"), Err(Error::WouldBlock));
+
+  // The duplicate keeps the pipe open after the original is closed
+  assert_eq!(table.duplicate(output, 5, false).unwrap(), 5);
+  assert_eq!(table.close_on_exec(output), Ok(true));
+  table.close_all_on_exec();
+  assert_eq!(table.close(output), Err(Error::BadDescriptor));
+  assert_eq!(
+    table.get(input).unwrap().read(&mut buffer).unwrap(),
+    pipe::CAPACITY
+  );
+  assert_eq!(&buffer[..2], b"lo");
+  assert_eq!(
+    table.get(input).unwrap().read(&mut buffer),
+    Err(Error::WouldBlock)
+  );
+  table.close(5).unwrap();
+  assert_eq!(table.get(input).unwrap().read(&mut buffer).unwrap(), 0);
+
+  table.duplicate_to(input, 3, false).unwrap();
+  table.close(input).unwrap();
+  assert_eq!(table.insert(table.get(3).unwrap(), false).unwrap(), 0);
+  table.close(0).unwrap();
+  table.close(3).unwrap();
+  let (reader, writer) = pipe::new(false);
+  drop(reader);
+  assert_eq!(writer.write(b"lost"), Err(Error::BrokenPipe));
+}
+
+#[test_case]
+fn file_mappings_share_pages_through_the_page_cache() {
+  use crate::library::mem::address_space::{
+    AddressSpace,
+    Mapping,
+    Placement,
+    Protection,
+  };
+
+  let vfs = vfs();
+  let file = vfs
+    .open("/shared", OpenFlags::CREATE | OpenFlags::READ_WRITE, 0o600)
+    .unwrap();
+  file.write(b"original").unwrap();
+
+  let (first, second) = (AddressSpace::new(), AddressSpace::new());
+  let mapping = |shared| Mapping {
+    object: cache::get(file.dentry().inode()),
+    offset: 0,
+    protection: Protection::READ | Protection::WRITE,
+    maximum: Protection::READ | Protection::WRITE,
+    shared,
+  };
+  let shared = first.map(Placement::Anywhere, 8, mapping(true)).unwrap();
+  let also_shared = second.map(Placement::Anywhere, 8, mapping(true)).unwrap();
+  let private = second.map(Placement::Anywhere, 8, mapping(false)).unwrap();
+
+  let mut buffer = [0; 8];
+  first.write(shared, b"mapped").unwrap();
+  second.read(also_shared, &mut buffer).unwrap();
+  assert_eq!(&buffer, b"mappedal");
+  second.write(private, b"private").unwrap();
+  first.read(shared, &mut buffer).unwrap();
+  assert_eq!(&buffer, b"mappedal");
+
+  // Reading the file writes the changes back first; writing it updates the mappings
+  file.seek(SeekFrom::Start(0)).unwrap();
+  assert_eq!(file.read(&mut buffer).unwrap(), 8);
+  assert_eq!(&buffer, b"mappedal");
+  file.seek(SeekFrom::Start(6)).unwrap();
+  file.write(b"!!").unwrap();
+  second.read(also_shared, &mut buffer).unwrap();
+  assert_eq!(&buffer, b"mapped!!");
+  second.read(private, &mut buffer).unwrap();
+  assert_eq!(&buffer, b"privatel");
+
+  first.write(shared, b"synced").unwrap();
+  first.sync(shared, 8).unwrap();
+  drop((first, second));
+  assert_eq!(
+    vfs
+      .open("/shared", OpenFlags::READ, 0)
+      .unwrap()
+      .read(&mut buffer)
+      .unwrap(),
+    8
+  );
+  assert_eq!(&buffer, b"synced!!");
+}
+
+#[test_case]
+fn writes_keep_the_changes_of_shared_mappings_in_the_same_page() {
+  use crate::library::mem::address_space::{
+    AddressSpace,
+    Mapping,
+    Placement,
+    Protection,
+  };
+
+  let vfs = vfs();
+  let file = vfs
+    .open("/merged", OpenFlags::CREATE | OpenFlags::READ_WRITE, 0o600)
+    .unwrap();
+  file.write(b"0123456789").unwrap();
+
+  let space = AddressSpace::new();
+  let mapping = Mapping {
+    object:     cache::get(file.dentry().inode()),
+    offset:     0,
+    protection: Protection::READ | Protection::WRITE,
+    maximum:    Protection::READ | Protection::WRITE,
+    shared:     true,
+  };
+  let address = space.map(Placement::Anywhere, 10, mapping).unwrap();
+  space.write(address, b"ab").unwrap();
+  file.seek(SeekFrom::Start(8)).unwrap();
+  file.write(b"yz").unwrap();
+  space.unmap(address, 10).unwrap();
+  drop(space);
+
+  let mut buffer = [0; 16];
+  file.seek(SeekFrom::Start(0)).unwrap();
+  assert_eq!(file.read(&mut buffer).unwrap(), 10);
+  assert_eq!(&buffer[..10], b"ab234567yz");
+}
+
+#[test_case]
+fn truncating_a_file_zeroes_its_cached_pages() {
+  use crate::library::mem::address_space::{
+    AddressSpace,
+    Mapping,
+    Placement,
+    Protection,
+  };
+
+  let vfs = vfs();
+  let file = vfs
+    .open("/shrunk", OpenFlags::CREATE | OpenFlags::READ_WRITE, 0o600)
+    .unwrap();
+  file.write(b"0123456789").unwrap();
+
+  let space = AddressSpace::new();
+  let mapping = Mapping {
+    object:     cache::get(file.dentry().inode()),
+    offset:     0,
+    protection: Protection::READ,
+    maximum:    Protection::READ,
+    shared:     true,
+  };
+  let address = space.map(Placement::Anywhere, 10, mapping).unwrap();
+  let mut buffer = [0; 10];
+  space.read(address, &mut buffer).unwrap();
+  file.truncate(4).unwrap();
+  file.truncate(10).unwrap();
+
+  space.read(address, &mut buffer).unwrap();
+  assert_eq!(&buffer, b"0123\0\0\0\0\0\0");
+  file.seek(SeekFrom::Start(0)).unwrap();
+  assert_eq!(file.read(&mut buffer).unwrap(), 10);
+  assert_eq!(&buffer, b"0123\0\0\0\0\0\0");
+}
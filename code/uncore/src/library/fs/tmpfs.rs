@@ -0,0 +1,306 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains `tmpfs`, a file system that keeps all files in memory (on the
+//! kernel heap). Its contents are lost when the kernel exits.
+
+use alloc::{
+  collections::BTreeMap,
+  string::{
+    String,
+    ToString,
+  },
+  sync::{
+    Arc,
+    Weak,
+  },
+  vec::Vec,
+};
+use core::sync::atomic::{
+  AtomicU32,
+  AtomicU64,
+  Ordering,
+};
+
+use super::{
+  DirectoryEntry,
+  Error,
+  FileType,
+  Inode,
+  Metadata,
+  Result,
+};
+
+/// The state shared by all inodes of one `tmpfs` instance.
+struct State {
+  /// The number of the next inode that is created
+  next_inode: AtomicU64,
+  /// All inodes that exist, so that hard links can find the inode they refer to
+  inodes:     spin::Mutex<BTreeMap<u64, Weak<Node>>>,
+}
+
+/// The contents of an inode, which depend on its type.
+enum Contents {
+  /// The data of a regular file
+  File(Vec<u8>),
+  /// The entries of a directory
+  Directory(BTreeMap<String, Arc<Node>>),
+  /// The target of a symbolic link
+  SymbolicLink(String),
+}
+
+/// An inode of `tmpfs`.
+struct Node {
+  /// The number of the inode
+  number:   u64,
+  /// The permission bits
+  mode:     u16,
+  /// The number of directory entries that refer to the inode
+  links:    AtomicU32,
+  /// The file system the inode belongs to
+  state:    Arc<State>,
+  /// The contents of the inode
+  contents: spin::Mutex<Contents>,
+}
+
+impl Node {
+  /// Creates an inode in the file system with the state `state` and registers it there.
+  fn new(state: &Arc<State>, contents: Contents, mode: u16) -> Arc<Self> {
+    let number = state.next_inode.fetch_add(1, Ordering::Relaxed);
+    let node = Arc::new(Self {
+      number,
+      mode,
+      links: AtomicU32::new(0),
+      state: state.clone(),
+      contents: spin::Mutex::new(contents),
+    });
+    state.inodes.lock().insert(number, Arc::downgrade(&node));
+    node
+  }
+
+  /// Returns the type of the inode.
+  fn file_type(&self) -> FileType {
+    match *self.contents.lock() {
+      Contents::File(_) => FileType::Regular,
+      Contents::Directory(_) => FileType::Directory,
+      Contents::SymbolicLink(_) => FileType::SymbolicLink,
+    }
+  }
+
+  /// Adds the entry `name` that refers to `node` to this directory.
+  fn insert(&self, name: &str, node: Arc<Self>) -> Result<Arc<dyn Inode>> {
+    let mut contents = self.contents.lock();
+    let Contents::Directory(entries) = &mut *contents else {
+      return Err(Error::NotADirectory);
+    };
+    if entries.contains_key(name) {
+      return Err(Error::AlreadyExists);
+    }
+
+    node.links.fetch_add(1, Ordering::Relaxed);
+    entries.insert(name.to_string(), node.clone());
+    Ok(node)
+  }
+
+  /// Creates an inode with `contents` in the same file system as this directory and adds
+  /// it as the entry `name`.
+  fn insert_new(&self, name: &str, contents: Contents, mode: u16) -> Result<Arc<dyn Inode>> {
+    self.insert(name, Self::new(&self.state, contents, mode))
+  }
+}
+
+impl Drop for Node {
+  fn drop(&mut self) { self.state.inodes.lock().remove(&self.number); }
+}
+
+/// Converts an offset into an index into the data of a file.
+fn index(offset: u64) -> Result<usize> { usize::try_from(offset).map_err(|_| Error::InvalidArgument) }
+
+impl Inode for Node {
+  fn metadata(&self) -> Result<Metadata> {
+    let contents = self.contents.lock();
+    let (file_type, size, links) = match &*contents {
+      Contents::File(data) => (FileType::Regular, data.len(), self.links.load(Ordering::Relaxed)),
+      Contents::Directory(entries) => {
+        let directories = entries
+          .values()
+          .filter(|node| node.file_type() == FileType::Directory)
+          .count();
+        (
+          FileType::Directory,
+          entries.len(),
+          2 + u32::try_from(directories).unwrap_or(u32::MAX),
+        )
+      },
+      Contents::SymbolicLink(target) => (
+        FileType::SymbolicLink,
+        target.len(),
+        self.links.load(Ordering::Relaxed),
+      ),
+    };
+
+    Ok(Metadata {
+      inode: self.number,
+      file_type,
+      size: size as u64,
+      mode: self.mode,
+      links,
+    })
+  }
+
+  fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+    let contents = self.contents.lock();
+    let Contents::File(data) = &*contents else {
+      return Err(Error::IsADirectory);
+    };
+
+    let data = data.get(index(offset)?..).unwrap_or_default();
+    let length = data.len().min(buffer.len());
+    buffer[..length].copy_from_slice(&data[..length]);
+    Ok(length)
+  }
+
+  fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize> {
+    let mut contents = self.contents.lock();
+    let Contents::File(data) = &mut *contents else {
+      return Err(Error::IsADirectory);
+    };
+
+    let start = index(offset)?;
+    let end = start.checked_add(buffer.len()).ok_or(Error::InvalidArgument)?;
+    if data.len() < end {
+      data.try_reserve(end - data.len())?;
+      data.resize(end, 0);
+    }
+    data[start..end].copy_from_slice(buffer);
+    Ok(buffer.len())
+  }
+
+  fn truncate(&self, size: u64) -> Result<()> {
+    let mut contents = self.contents.lock();
+    let Contents::File(data) = &mut *contents else {
+      return Err(Error::IsADirectory);
+    };
+
+    let size = index(size)?;
+    data.try_reserve(size.saturating_sub(data.len()))?;
+    data.resize(size, 0);
+    Ok(())
+  }
+
+  fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+    let contents = self.contents.lock();
+    let Contents::Directory(entries) = &*contents else {
+      return Err(Error::NotADirectory);
+    };
+
+    let node = entries.get(name).ok_or(Error::NotFound)?;
+    Ok(node.clone())
+  }
+
+  fn create(&self, name: &str, file_type: FileType, mode: u16) -> Result<Arc<dyn Inode>> {
+    let contents = match file_type {
+      FileType::Regular => Contents::File(Vec::new()),
+      FileType::Directory => Contents::Directory(BTreeMap::new()),
+      FileType::SymbolicLink => return Err(Error::InvalidArgument),
+    };
+    self.insert_new(name, contents, mode)
+  }
+
+  fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>> {
+    self.insert_new(name, Contents::SymbolicLink(target.to_string()), 0o777)
+  }
+
+  fn link(&self, name: &str, inode: &Arc<dyn Inode>) -> Result<()> {
+    let number = inode.metadata()?.inode;
+    let node = self
+      .state
+      .inodes
+      .lock()
+      .get(&number)
+      .and_then(Weak::upgrade)
+      .ok_or(Error::CrossesDevices)?;
+
+    // The number alone does not identify the inode, as it may belong to another instance
+    if !core::ptr::addr_eq(Arc::as_ptr(&node), Arc::as_ptr(inode)) {
+      return Err(Error::CrossesDevices);
+    }
+    self.insert(name, node).map(|_| ())
+  }
+
+  fn unlink(&self, name: &str) -> Result<()> {
+    let mut contents = self.contents.lock();
+    let Contents::Directory(entries) = &mut *contents else {
+      return Err(Error::NotADirectory);
+    };
+
+    let node = entries.get(name).ok_or(Error::NotFound)?;
+    if matches!(&*node.contents.lock(), Contents::Directory(entries) if !entries.is_empty()) {
+      return Err(Error::NotEmpty);
+    }
+
+    node.links.fetch_sub(1, Ordering::Relaxed);
+    entries.remove(name);
+    Ok(())
+  }
+
+  fn read_dir(&self) -> Result<Vec<DirectoryEntry>> {
+    let contents = self.contents.lock();
+    let Contents::Directory(entries) = &*contents else {
+      return Err(Error::NotADirectory);
+    };
+
+    Ok(
+      entries
+        .iter()
+        .map(|(name, node)| DirectoryEntry {
+          name:      name.clone(),
+          inode:     node.number,
+          file_type: node.file_type(),
+        })
+        .collect(),
+    )
+  }
+
+  fn read_link(&self) -> Result<String> {
+    match &*self.contents.lock() {
+      Contents::SymbolicLink(target) => Ok(target.clone()),
+      _ => Err(Error::InvalidArgument),
+    }
+  }
+}
+
+/// A file system that keeps all files in memory.
+#[allow(clippy::module_name_repetitions)]
+pub struct Tmpfs {
+  /// The state shared by all inodes
+  state: Arc<State>,
+  /// The root directory
+  root:  Arc<Node>,
+}
+
+impl core::fmt::Debug for Tmpfs {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Tmpfs")
+      .field("inodes", &self.state.inodes.lock().len())
+      .finish_non_exhaustive()
+  }
+}
+
+impl Tmpfs {
+  /// Creates an empty file system whose root directory has the permission bits `mode`.
+  #[must_use]
+  pub fn new(mode: u16) -> Arc<Self> {
+    let state = Arc::new(State {
+      next_inode: AtomicU64::new(1),
+      inodes:     spin::Mutex::new(BTreeMap::new()),
+    });
+    let root = Node::new(&state, Contents::Directory(BTreeMap::new()), mode);
+    Arc::new(Self { state, root })
+  }
+}
+
+impl super::FileSystem for Tmpfs {
+  fn name(&self) -> &'static str { "tmpfs" }
+
+  fn root(&self) -> Arc<dyn Inode> { self.root.clone() }
+}
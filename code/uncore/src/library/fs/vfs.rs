@@ -0,0 +1,614 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains the directory entry cache, the mount table and path resolution.
+
+use alloc::{
+  collections::BTreeMap,
+  string::{
+    String,
+    ToString,
+  },
+  sync::{
+    Arc,
+    Weak,
+  },
+  vec::Vec,
+};
+
+use super::{
+  Error,
+  File,
+  FileSystem,
+  FileType,
+  Inode,
+  Metadata,
+  OpenFlags,
+  Result,
+};
+
+/// The maximum number of symbolic links that are followed while resolving one path.
+const MAXIMUM_SYMBOLIC_LINKS: usize = 40;
+
+/// A directory entry, i.e. an inode together with the name under which it was found.
+/// Directory entries are cached as long as they are used.
+pub struct Dentry {
+  /// The name of the entry; empty for the root directory of a file system
+  name:     String,
+  /// The inode the entry refers to
+  inode:    Arc<dyn Inode>,
+  /// The directory that contains the entry; `None` for the root of a file system
+  parent:   Option<Arc<Self>>,
+  /// The directory the file system is mounted on, if this is the root of a file system
+  /// that is not mounted at `/`
+  covered:  Option<Arc<Self>>,
+  /// The cached entries of this directory
+  children: spin::Mutex<BTreeMap<String, Weak<Self>>>,
+  /// The root of the file system that is mounted on this directory
+  mounted:  spin::Mutex<Option<Arc<Self>>>,
+}
+
+impl core::fmt::Debug for Dentry {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Dentry")
+      .field("path", &self.path())
+      .finish_non_exhaustive()
+  }
+}
+
+impl Dentry {
+  /// Creates the root entry of a file system that is mounted on `covered`.
+  fn new_root(inode: Arc<dyn Inode>, covered: Option<Arc<Self>>) -> Arc<Self> {
+    Arc::new(Self {
+      name: String::new(),
+      inode,
+      parent: None,
+      covered,
+      children: spin::Mutex::new(BTreeMap::new()),
+      mounted: spin::Mutex::new(None),
+    })
+  }
+
+  /// Returns the name of the entry.
+  #[must_use]
+  pub fn name(&self) -> &str { &self.name }
+
+  /// Returns the inode the entry refers to.
+  #[must_use]
+  pub fn inode(&self) -> &Arc<dyn Inode> { &self.inode }
+
+  /// Returns information about the inode the entry refers to.
+  ///
+  /// #### Errors
+  ///
+  /// If the information cannot be read, an error is returned.
+  pub fn metadata(&self) -> Result<Metadata> { self.inode.metadata() }
+
+  /// Returns the absolute path of the entry.
+  #[must_use]
+  pub fn path(&self) -> String {
+    let mut components = Vec::new();
+    let mut current = self;
+    loop {
+      if let Some(covered) = &current.covered {
+        current = covered;
+      } else if let Some(parent) = &current.parent {
+        components.push(current.name.as_str());
+        current = parent;
+      } else {
+        break;
+      }
+    }
+
+    if components.is_empty() {
+      return String::from("/");
+    }
+
+    let mut path = String::new();
+    for component in components.iter().rev() {
+      path.push('/');
+      path.push_str(component);
+    }
+    path
+  }
+
+  /// Returns the root entry of the file system the entry belongs to.
+  fn file_system_root(&self) -> &Self {
+    let mut current = self;
+    while let Some(parent) = &current.parent {
+      current = parent;
+    }
+    current
+  }
+
+  /// Returns the directory that contains this entry. `..` of the root of a mounted file
+  /// system is the parent of the directory it is mounted on; `..` of `/` is `/`.
+  fn parent(self: &Arc<Self>) -> Arc<Self> {
+    let mut current = self.clone();
+    while let Some(covered) = current.covered.clone() {
+      current = covered;
+    }
+    current.parent.clone().unwrap_or(current)
+  }
+
+  /// Returns the root of the file system that is mounted on this entry (repeatedly, if
+  /// file systems are stacked), or the entry itself.
+  fn follow_mounts(self: Arc<Self>) -> Arc<Self> {
+    let mut current = self;
+    loop {
+      let mounted = current.mounted.lock().clone();
+      match mounted {
+        Some(root) => current = root,
+        None => return current,
+      }
+    }
+  }
+
+  /// Returns the entry `name` of this directory, looking it up in the file system if it
+  /// is not cached.
+  fn child(self: &Arc<Self>, name: &str) -> Result<Arc<Self>> {
+    if let Some(child) = self.children.lock().get(name).and_then(Weak::upgrade) {
+      return Ok(child.follow_mounts());
+    }
+
+    let inode = self.inode.lookup(name)?;
+    let child = Arc::new(Self {
+      name: name.to_string(),
+      inode,
+      parent: Some(self.clone()),
+      covered: None,
+      children: spin::Mutex::new(BTreeMap::new()),
+      mounted: spin::Mutex::new(None),
+    });
+
+    let mut children = self.children.lock();
+    children.retain(|_, child| child.strong_count() > 0);
+    children.insert(name.to_string(), Arc::downgrade(&child));
+    Ok(child)
+  }
+
+  /// Removes the entry `name` from the cache.
+  fn forget(&self, name: &str) { self.children.lock().remove(name); }
+
+  /// Returns whether this entry is a directory.
+  fn is_directory(&self) -> Result<bool> { Ok(self.metadata()?.file_type == FileType::Directory) }
+}
+
+/// A file system attached to the directory tree.
+#[derive(Clone)]
+pub struct Mount {
+  /// The absolute path of the mount point
+  path:        String,
+  /// The mounted file system
+  file_system: Arc<dyn FileSystem>,
+  /// The root entry of the mounted file system
+  root:        Arc<Dentry>,
+  /// The directory the file system is mounted on; `None` for `/`
+  covered:     Option<Arc<Dentry>>,
+}
+
+impl core::fmt::Debug for Mount {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Mount")
+      .field("path", &self.path)
+      .field("file_system", &self.file_system.name())
+      .finish_non_exhaustive()
+  }
+}
+
+impl Mount {
+  /// Returns the absolute path the file system is mounted at.
+  #[must_use]
+  pub fn path(&self) -> &str { &self.path }
+
+  /// Returns the mounted file system.
+  #[must_use]
+  pub fn file_system(&self) -> &Arc<dyn FileSystem> { &self.file_system }
+}
+
+/// The virtual file system: a directory tree assembled from mounted file systems.
+/// Relative paths are resolved from `/`.
+#[derive(Debug)]
+pub struct Vfs {
+  /// The root directory; `None` until a file system is mounted at `/`
+  root:   spin::Mutex<Option<Arc<Dentry>>>,
+  /// All mounted file systems in the order they were mounted
+  mounts: spin::Mutex<Vec<Mount>>,
+}
+
+impl Default for Vfs {
+  fn default() -> Self { Self::new() }
+}
+
+impl Vfs {
+  /// Creates a VFS without any file system.
+  #[must_use]
+  pub const fn new() -> Self {
+    Self {
+      root:   spin::Mutex::new(None),
+      mounts: spin::Mutex::new(Vec::new()),
+    }
+  }
+
+  /// Returns the root directory.
+  ///
+  /// #### Errors
+  ///
+  /// If no file system is mounted at `/`, [`Error::NotFound`] is returned.
+  pub fn root(&self) -> Result<Arc<Dentry>> { self.root.lock().clone().ok_or(Error::NotFound) }
+
+  /// Returns all mounted file systems in the order they were mounted.
+  #[must_use]
+  pub fn mounts(&self) -> Vec<Mount> { self.mounts.lock().clone() }
+
+  /// Mounts `file_system` on the directory `path`. The first file system must be mounted
+  /// at `/`.
+  ///
+  /// #### Errors
+  ///
+  /// If `path` is not a directory or another file system is mounted there already, an
+  /// error is returned.
+  pub fn mount(&self, path: &str, file_system: Arc<dyn FileSystem>) -> Result<()> {
+    let root_is_mounted = self.root.lock().is_some();
+    if !root_is_mounted {
+      if components(path).next().is_some() || !path.starts_with('/') {
+        return Err(Error::NotFound);
+      }
+
+      let root = Dentry::new_root(file_system.root(), None);
+      let mut current_root = self.root.lock();
+      if current_root.is_some() {
+        return Err(Error::Busy);
+      }
+      *current_root = Some(root.clone());
+      self.mounts.lock().push(Mount {
+        path: String::from("/"),
+        file_system,
+        root,
+        covered: None,
+      });
+      return Ok(());
+    }
+
+    let mount_point = self.lookup(path)?;
+    if !mount_point.is_directory()? {
+      return Err(Error::NotADirectory);
+    }
+    if mount_point.parent.is_none() {
+      return Err(Error::Busy);
+    }
+
+    let root = Dentry::new_root(file_system.root(), Some(mount_point.clone()));
+    let mut mounted = mount_point.mounted.lock();
+    if mounted.is_some() {
+      return Err(Error::Busy);
+    }
+    *mounted = Some(root.clone());
+    drop(mounted);
+
+    self.mounts.lock().push(Mount {
+      path: mount_point.path(),
+      file_system,
+      root,
+      covered: Some(mount_point),
+    });
+    Ok(())
+  }
+
+  /// Unmounts the file system mounted at `path` after writing its cached changes.
+  ///
+  /// #### Errors
+  ///
+  /// If no file system is mounted at `path` or other file systems are mounted below it,
+  /// an error is returned.
+  pub fn unmount(&self, path: &str) -> Result<()> {
+    let root = self.lookup(path)?;
+    let mut mounts = self.mounts.lock();
+    let index = mounts
+      .iter()
+      .position(|mount| Arc::ptr_eq(&mount.root, &root))
+      .ok_or(Error::InvalidArgument)?;
+
+    let mount_path = mounts[index].path.clone();
+    let is_below = |other: &Mount| {
+      other.path != mount_path
+        && (mount_path == "/"
+          || other
+            .path
+            .strip_prefix(mount_path.as_str())
+            .is_some_and(|rest| rest.starts_with('/')))
+    };
+    if mounts.iter().any(is_below) {
+      return Err(Error::Busy);
+    }
+
+    mounts[index].file_system.sync()?;
+    let mount = mounts.remove(index);
+    match mount.covered {
+      Some(mount_point) => *mount_point.mounted.lock() = None,
+      None => *self.root.lock() = None,
+    }
+    Ok(())
+  }
+
+  /// Resolves `path` and returns its directory entry. Symbolic links are followed.
+  ///
+  /// #### Errors
+  ///
+  /// If a component of the path does not exist, an error is returned.
+  pub fn lookup(&self, path: &str) -> Result<Arc<Dentry>> { self.lookup_at(&self.root()?, path, true) }
+
+  /// Resolves `path` relative to the directory `base` (unless `path` is absolute) and
+  /// returns its directory entry. Symbolic links are followed, except for the last
+  /// component if `follow` is false.
+  ///
+  /// #### Errors
+  ///
+  /// If a component of the path does not exist, an error is returned.
+  pub fn lookup_at(&self, base: &Arc<Dentry>, path: &str, follow: bool) -> Result<Arc<Dentry>> {
+    let mut symbolic_links = 0;
+    self.resolve(base, path, follow, &mut symbolic_links)
+  }
+
+  /// Resolves `path` relative to `base`. `symbolic_links` counts the symbolic links that
+  /// have been followed so far.
+  fn resolve(
+    &self,
+    base: &Arc<Dentry>,
+    path: &str,
+    follow: bool,
+    symbolic_links: &mut usize,
+  ) -> Result<Arc<Dentry>> {
+    if path.is_empty() {
+      return Err(Error::InvalidPath);
+    }
+
+    let mut current = if path.starts_with('/') {
+      self.root()?
+    } else {
+      base.clone()
+    };
+
+    let mut components = components(path).peekable();
+    while let Some(component) = components.next() {
+      if !current.is_directory()? {
+        return Err(Error::NotADirectory);
+      }
+
+      if component == "." {
+        continue;
+      }
+      if component == ".." {
+        current = current.parent();
+        continue;
+      }
+
+      let child = current.child(component)?;
+      let is_last = components.peek().is_none();
+      current = if child.metadata()?.file_type == FileType::SymbolicLink && (follow || !is_last) {
+        *symbolic_links += 1;
+        if *symbolic_links > MAXIMUM_SYMBOLIC_LINKS {
+          return Err(Error::TooManySymbolicLinks);
+        }
+        let target = child.inode.read_link()?;
+        self.resolve(&current, &target, true, symbolic_links)?
+      } else {
+        child
+      };
+    }
+
+    // A trailing slash requires a directory
+    if path.ends_with('/') && !current.is_directory()? {
+      return Err(Error::NotADirectory);
+    }
+    Ok(current)
+  }
+
+  /// Resolves all components of `path` except for the last one, which is returned
+  /// separately. The last component is `None` if it is `.` or `..` or if there is none.
+  fn resolve_parent<'path>(
+    &self,
+    base: &Arc<Dentry>,
+    path: &'path str,
+  ) -> Result<(Arc<Dentry>, Option<&'path str>)> {
+    let trimmed = path.trim_end_matches('/');
+    let (directory, name) = match trimmed.rfind('/') {
+      Some(0) => ("/", &trimmed[1..]),
+      Some(index) => (&trimmed[..index], &trimmed[index + 1..]),
+      None if trimmed.is_empty() => return Ok((self.lookup_at(base, path, true)?, None)),
+      None => (".", trimmed),
+    };
+
+    let parent = self.lookup_at(base, directory, true)?;
+    if !parent.is_directory()? {
+      return Err(Error::NotADirectory);
+    }
+
+    match name {
+      "." | ".." => Ok((parent, None)),
+      name => Ok((parent, Some(name))),
+    }
+  }
+
+  /// Opens the file `path` with `flags`. If [`OpenFlags::CREATE`] is set and the file
+  /// does not exist, it is created with the permission bits `mode`.
+  ///
+  /// #### Errors
+  ///
+  /// If the file does not exist (and is not created) or the flags do not fit the type of
+  /// the file, an error is returned.
+  pub fn open(&self, path: &str, flags: OpenFlags, mode: u16) -> Result<Arc<File>> {
+    self.open_at(&self.root()?, path, flags, mode)
+  }
+
+  /// Opens the file `path` relative to the directory `base`, see [`Self::open`].
+  ///
+  /// #### Errors
+  ///
+  /// If the file does not exist (and is not created) or the flags do not fit the type of
+  /// the file, an error is returned.
+  pub fn open_at(&self, base: &Arc<Dentry>, path: &str, flags: OpenFlags, mode: u16) -> Result<Arc<File>> {
+    let follow = !flags.contains(OpenFlags::NO_FOLLOW);
+    let dentry = if flags.contains(OpenFlags::CREATE) {
+      let (parent, name) = self.resolve_parent(base, path)?;
+      let Some(name) = name else {
+        return Err(Error::IsADirectory);
+      };
+
+      match self.lookup_at(&parent, name, follow) {
+        Ok(_) if flags.contains(OpenFlags::EXCLUSIVE) => return Err(Error::AlreadyExists),
+        Ok(dentry) => dentry,
+        Err(Error::NotFound) => {
+          parent.inode.create(name, FileType::Regular, mode)?;
+          parent.forget(name);
+          parent.child(name)?
+        },
+        Err(error) => return Err(error),
+      }
+    } else {
+      self.lookup_at(base, path, follow)?
+    };
+
+    let metadata = dentry.metadata()?;
+    match metadata.file_type {
+      FileType::Directory if flags.is_writable() => return Err(Error::IsADirectory),
+      FileType::Regular if flags.contains(OpenFlags::DIRECTORY) => return Err(Error::NotADirectory),
+      // Only reachable with `OpenFlags::NO_FOLLOW`
+      FileType::SymbolicLink => return Err(Error::TooManySymbolicLinks),
+      _ => {},
+    }
+
+    if flags.contains(OpenFlags::TRUNCATE) && flags.is_writable() && metadata.size != 0 {
+      dentry.inode.truncate(0)?;
+      super::cache::truncate(&dentry.inode, 0);
+    }
+
+    Ok(Arc::new(File::new(dentry, flags)))
+  }
+
+  /// Returns information about the file `path`, following symbolic links.
+  ///
+  /// #### Errors
+  ///
+  /// If the file does not exist, an error is returned.
+  pub fn metadata(&self, path: &str) -> Result<Metadata> { self.lookup(path)?.metadata() }
+
+  /// Returns information about the file `path` without following a symbolic link in the
+  /// last component.
+  ///
+  /// #### Errors
+  ///
+  /// If the file does not exist, an error is returned.
+  pub fn symlink_metadata(&self, path: &str) -> Result<Metadata> {
+    self.lookup_at(&self.root()?, path, false)?.metadata()
+  }
+
+  /// Creates the directory `path` with the permission bits `mode`.
+  ///
+  /// #### Errors
+  ///
+  /// If the parent directory does not exist or `path` exists already, an error is
+  /// returned.
+  pub fn create_directory(&self, path: &str, mode: u16) -> Result<()> {
+    let (parent, name) = self.resolve_parent(&self.root()?, path)?;
+    let name = name.ok_or(Error::AlreadyExists)?;
+    parent.inode.create(name, FileType::Directory, mode)?;
+    parent.forget(name);
+    Ok(())
+  }
+
+  /// Creates the symbolic link `path` that points to `target`.
+  ///
+  /// #### Errors
+  ///
+  /// If the parent directory does not exist or `path` exists already, an error is
+  /// returned.
+  pub fn symlink(&self, target: &str, path: &str) -> Result<()> {
+    let (parent, name) = self.resolve_parent(&self.root()?, path)?;
+    let name = name.ok_or(Error::AlreadyExists)?;
+    parent.inode.symlink(name, target)?;
+    parent.forget(name);
+    Ok(())
+  }
+
+  /// Creates the entry `path` that refers to the same inode as `existing` (a hard link).
+  ///
+  /// #### Errors
+  ///
+  /// If `existing` is a directory or the two paths are on different file systems, an
+  /// error is returned.
+  pub fn hard_link(&self, existing: &str, path: &str) -> Result<()> {
+    let root = self.root()?;
+    let existing = self.lookup_at(&root, existing, false)?;
+    if existing.is_directory()? {
+      return Err(Error::IsADirectory);
+    }
+
+    let (parent, name) = self.resolve_parent(&root, path)?;
+    let name = name.ok_or(Error::AlreadyExists)?;
+    if !core::ptr::eq(existing.file_system_root(), parent.file_system_root()) {
+      return Err(Error::CrossesDevices);
+    }
+
+    parent.inode.link(name, &existing.inode)?;
+    parent.forget(name);
+    Ok(())
+  }
+
+  /// Returns the target of the symbolic link `path`.
+  ///
+  /// #### Errors
+  ///
+  /// If `path` is not a symbolic link, an error is returned.
+  pub fn read_link(&self, path: &str) -> Result<String> {
+    self.lookup_at(&self.root()?, path, false)?.inode.read_link()
+  }
+
+  /// Removes the file or symbolic link `path`.
+  ///
+  /// #### Errors
+  ///
+  /// If `path` does not exist or is a directory, an error is returned.
+  pub fn remove_file(&self, path: &str) -> Result<()> { self.remove(path, false) }
+
+  /// Removes the empty directory `path`.
+  ///
+  /// #### Errors
+  ///
+  /// If `path` does not exist, is not an empty directory or is a mount point, an error is
+  /// returned.
+  pub fn remove_directory(&self, path: &str) -> Result<()> { self.remove(path, true) }
+
+  /// Removes the entry `path`, which must be a directory if and only if `directory` is
+  /// true.
+  fn remove(&self, path: &str, directory: bool) -> Result<()> {
+    let (parent, name) = self.resolve_parent(&self.root()?, path)?;
+    let Some(name) = name else {
+      return Err(
+        if directory {
+          Error::Busy
+        } else {
+          Error::IsADirectory
+        },
+      );
+    };
+
+    let child = parent.child(name)?;
+    match (child.is_directory()?, directory) {
+      (true, false) => return Err(Error::IsADirectory),
+      (false, true) => return Err(Error::NotADirectory),
+      _ => {},
+    }
+    if child.parent.is_none() || child.mounted.lock().is_some() {
+      return Err(Error::Busy);
+    }
+
+    parent.inode.unlink(name)?;
+    parent.forget(name);
+    Ok(())
+  }
+}
+
+/// Returns the components of `path`, skipping empty ones.
+fn components(path: &str) -> impl Iterator<Item = &str> {
+  path.split('/').filter(|component| !component.is_empty())
+}
@@ -0,0 +1,248 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains capabilities, the kernel objects they refer to, and the tables
+//! that hold them.
+
+use alloc::{
+  sync::Arc,
+  vec::Vec,
+};
+
+use super::{
+  Endpoint,
+  Error,
+  Result,
+};
+use crate::library::mem::PAGE_SIZE;
+
+/// The maximum number of capabilities a table can hold.
+pub const MAXIMUM_CAPABILITIES: usize = 256;
+
+/// The operations a capability permits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rights(u8);
+
+impl Rights {
+  /// All rights
+  pub const ALL: Self = Self(0b111);
+  /// Transfer capabilities through an endpoint
+  pub const GRANT: Self = Self(0b100);
+  /// Receive from an endpoint, or read a memory object
+  pub const READ: Self = Self(0b001);
+  /// Send to an endpoint, or write a memory object
+  pub const WRITE: Self = Self(0b010);
+
+  /// Creates rights from their bit representation, ignoring unknown bits.
+  #[must_use]
+  pub const fn from_bits(bits: usize) -> Self {
+    #[allow(clippy::cast_possible_truncation)]
+    Self(bits as u8 & Self::ALL.0)
+  }
+
+  /// Returns whether all rights in `other` are included.
+  #[must_use]
+  pub const fn contains(self, other: Self) -> bool { self.0 & other.0 == other.0 }
+}
+
+impl core::ops::BitOr for Rights {
+  type Output = Self;
+
+  fn bitor(self, other: Self) -> Self { Self(self.0 | other.0) }
+}
+
+impl core::ops::BitAnd for Rights {
+  type Output = Self;
+
+  fn bitand(self, other: Self) -> Self { Self(self.0 & other.0) }
+}
+
+/// A range of whole pages that can be shared between tasks. The pages are freed when no
+/// capability refers to the object anymore.
+#[derive(Debug)]
+pub struct MemoryObject {
+  /// The address of the first page
+  address: usize,
+  /// The number of pages
+  pages:   usize,
+}
+
+impl MemoryObject {
+  /// Returns the layout of `pages` pages on the heap.
+  fn layout(pages: usize) -> Result<core::alloc::Layout> {
+    let size = pages.checked_mul(PAGE_SIZE).ok_or(Error::OutOfMemory)?;
+    core::alloc::Layout::from_size_align(size, PAGE_SIZE).map_err(|_| Error::OutOfMemory)
+  }
+
+  /// Allocates `pages` zeroed pages.
+  ///
+  /// #### Errors
+  ///
+  /// If `pages` is zero or the pages cannot be allocated, [`Error::OutOfMemory`] is
+  /// returned.
+  pub fn allocate(pages: usize) -> Result<Arc<Self>> {
+    if pages == 0 {
+      return Err(Error::OutOfMemory);
+    }
+
+    let address = unsafe { alloc::alloc::alloc_zeroed(Self::layout(pages)?) };
+    if address.is_null() {
+      return Err(Error::OutOfMemory);
+    }
+    Ok(Arc::new(Self {
+      address: address as usize,
+      pages,
+    }))
+  }
+
+  /// Returns the address of the first page.
+  #[must_use]
+  pub const fn address(&self) -> usize { self.address }
+
+  /// Returns the number of pages.
+  #[must_use]
+  pub const fn pages(&self) -> usize { self.pages }
+}
+
+impl Drop for MemoryObject {
+  fn drop(&mut self) {
+    if let Ok(layout) = Self::layout(self.pages) {
+      unsafe { alloc::alloc::dealloc(self.address as *mut u8, layout) };
+    }
+  }
+}
+
+/// The kernel object a capability refers to.
+#[derive(Debug, Clone)]
+pub enum Object {
+  /// An endpoint, through which messages are sent
+  Endpoint(Arc<Endpoint>),
+  /// A range of pages
+  Memory(Arc<MemoryObject>),
+}
+
+/// A reference to a kernel object together with the operations it permits.
+#[derive(Debug, Clone)]
+pub struct Capability {
+  /// The object
+  pub object: Object,
+  /// The operations that are permitted on the object
+  pub rights: Rights,
+}
+
+impl Capability {
+  /// Returns the endpoint if the capability refers to one and permits `rights`.
+  pub(super) const fn endpoint(&self, rights: Rights) -> Result<&Arc<Endpoint>> {
+    let Object::Endpoint(endpoint) = &self.object else {
+      return Err(Error::WrongType);
+    };
+    if !self.rights.contains(rights) {
+      return Err(Error::InsufficientRights);
+    }
+    Ok(endpoint)
+  }
+}
+
+/// A table of capabilities, which are referred to by the index of their slot. Every
+/// task has its own table; it can only use the kernel objects its table refers to.
+pub struct CapabilityTable {
+  /// The slots, indexed by their number
+  slots:            spin::Mutex<Vec<Option<Capability>>>,
+  /// The call that was received last and has not been replied to yet
+  pub(super) reply: spin::Mutex<Option<Arc<super::Transfer>>>,
+}
+
+impl CapabilityTable {
+  /// Creates an empty table.
+  #[must_use]
+  pub const fn new() -> Self {
+    Self {
+      slots: spin::Mutex::new(Vec::new()),
+      reply: spin::Mutex::new(None),
+    }
+  }
+
+  /// Adds `capability` in the lowest free slot and returns the number of the slot.
+  ///
+  /// #### Errors
+  ///
+  /// If the table is full, [`Error::TableFull`] is returned.
+  pub fn insert(&self, capability: Capability) -> Result<usize> {
+    let mut slots = self.slots.lock();
+    let slot = (0..MAXIMUM_CAPABILITIES)
+      .find(|&slot| slots.get(slot).is_none_or(Option::is_none))
+      .ok_or(Error::TableFull)?;
+
+    if slots.len() <= slot {
+      slots.resize(slot + 1, None);
+    }
+    slots[slot] = Some(capability);
+    Ok(slot)
+  }
+
+  /// Returns the capability in `slot`.
+  ///
+  /// #### Errors
+  ///
+  /// If the slot is empty, [`Error::InvalidCapability`] is returned.
+  pub fn get(&self, slot: usize) -> Result<Capability> {
+    self
+      .slots
+      .lock()
+      .get(slot)
+      .cloned()
+      .flatten()
+      .ok_or(Error::InvalidCapability)
+  }
+
+  /// Copies the capability in `slot` to a free slot, keeping only the rights in
+  /// `rights`, and returns the number of the new slot.
+  ///
+  /// #### Errors
+  ///
+  /// If the slot is empty, [`Error::InvalidCapability`] is returned. If the table is
+  /// full, [`Error::TableFull`] is returned.
+  pub fn copy(&self, slot: usize, rights: Rights) -> Result<usize> {
+    let mut capability = self.get(slot)?;
+    capability.rights = capability.rights & rights;
+    self.insert(capability)
+  }
+
+  /// Removes the capability in `slot`. The object is destroyed when no capability
+  /// refers to it anymore.
+  ///
+  /// #### Errors
+  ///
+  /// If the slot is empty, [`Error::InvalidCapability`] is returned.
+  pub fn remove(&self, slot: usize) -> Result<()> {
+    let capability = self
+      .slots
+      .lock()
+      .get_mut(slot)
+      .and_then(Option::take)
+      .ok_or(Error::InvalidCapability)?;
+    drop(capability);
+    Ok(())
+  }
+}
+
+impl core::fmt::Debug for CapabilityTable {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    let used = self.slots.lock().iter().flatten().count();
+    f.debug_struct("CapabilityTable")
+      .field("used", &used)
+      .field("replying", &self.reply.lock().is_some())
+      .finish()
+  }
+}
+
+impl Default for CapabilityTable {
+  fn default() -> Self { Self::new() }
+}
+
+impl Drop for CapabilityTable {
+  fn drop(&mut self) {
+    if let Some(transfer) = self.reply.get_mut().take() {
+      transfer.abandon();
+    }
+  }
+}
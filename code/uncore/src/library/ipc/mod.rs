@@ -0,0 +1,283 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains synchronous inter-process communication (IPC) in the style of
+//! microkernels like L4 and seL4.
+//!
+//! Tasks exchange short [`Message`]s through [`Endpoint`]s. A message consists of a label
+//! and a few words, which fit into registers, and can carry one capability (e.g. for a
+//! [`MemoryObject`]), which is copied into the table of the receiver. This is how pages
+//! are shared: the sender grants a capability for them.
+//!
+//! Communication is synchronous: [`CapabilityTable::send`] blocks until a receiver has
+//! taken the message, and [`CapabilityTable::receive`] blocks until a message arrives.
+//! [`CapabilityTable::call`] sends a message and waits for the answer, which the receiver
+//! sends with [`CapabilityTable::reply`]. A server, e.g. a driver outside of the kernel,
+//! receives requests in a loop and replies to each of them.
+//!
+//! Tasks refer to endpoints and memory objects through capabilities in their
+//! [`CapabilityTable`], which also record the permitted operations ([`Rights`]). There
+//! are no tasks yet, so system calls use the table returned by [`current`].
+
+mod capability;
+
+#[cfg(test)]
+mod tests;
+
+use alloc::{
+  collections::VecDeque,
+  sync::Arc,
+};
+
+pub use capability::{
+  Capability,
+  CapabilityTable,
+  MemoryObject,
+  Object,
+  Rights,
+};
+
+use crate::library::sync::WaitQueue;
+
+/// The number of words of a message besides its label.
+pub const MESSAGE_WORDS: usize = 3;
+
+/// Errors that IPC operations may return.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+  /// The slot does not contain a capability.
+  InvalidCapability,
+  /// The capability refers to a different kind of object.
+  WrongType,
+  /// The capability does not permit the operation.
+  InsufficientRights,
+  /// The capability table is full.
+  TableFull,
+  /// There is no call to reply to, or the receiver of a call never replied.
+  NoReply,
+  /// Memory for the object could not be allocated.
+  OutOfMemory,
+}
+
+impl core::fmt::Display for Error {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::InvalidCapability => write!(f, "invalid capability"),
+      Self::WrongType => write!(f, "capability refers to the wrong kind of object"),
+      Self::InsufficientRights => write!(f, "insufficient rights"),
+      Self::TableFull => write!(f, "capability table is full"),
+      Self::NoReply => write!(f, "no reply"),
+      Self::OutOfMemory => write!(f, "out of memory"),
+    }
+  }
+}
+
+/// The result type of all IPC operations.
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// A message. When sending, `capability` is the slot of a capability the sender grants;
+/// when receiving, it is the slot the granted capability was put into.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Message {
+  /// The label, which usually identifies the request
+  pub label:      usize,
+  /// The payload
+  pub words:      [usize; MESSAGE_WORDS],
+  /// The slot of the capability that is transferred, if any
+  pub capability: Option<usize>,
+}
+
+/// The state of a [`Transfer`].
+enum State {
+  /// The message waits for a receiver.
+  Sent(Message, Option<Capability>),
+  /// A receiver took the message.
+  Received,
+  /// The receiver replied to the call.
+  Replied(Message, Option<Capability>),
+  /// The receiver will never reply to the call.
+  Abandoned,
+}
+
+/// A message on its way from a sender to a receiver, and possibly back.
+struct Transfer {
+  /// Where the message is
+  state:         spin::Mutex<State>,
+  /// Whether the sender waits for a reply
+  expects_reply: bool,
+  /// The sender waiting for the receiver
+  changed:       WaitQueue,
+}
+
+impl Transfer {
+  /// Tells the sender of a call that it will not get a reply.
+  fn abandon(&self) {
+    *self.state.lock() = State::Abandoned;
+    self.changed.wake_all();
+  }
+}
+
+/// An endpoint, through which tasks send messages to each other.
+pub struct Endpoint {
+  /// The messages that wait for a receiver, in the order they were sent
+  queue:     spin::Mutex<VecDeque<Arc<Transfer>>>,
+  /// The receivers waiting for a message
+  receivers: WaitQueue,
+}
+
+impl Endpoint {
+  /// Creates an endpoint without waiting senders.
+  #[must_use]
+  pub fn new() -> Arc<Self> {
+    Arc::new(Self {
+      queue:     spin::Mutex::new(VecDeque::new()),
+      receivers: WaitQueue::new(),
+    })
+  }
+}
+
+impl core::fmt::Debug for Endpoint {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Endpoint")
+      .field("waiting", &self.queue.lock().len())
+      .finish_non_exhaustive()
+  }
+}
+
+impl CapabilityTable {
+  /// Returns the capability the sender grants with `message`. Granting requires
+  /// [`Rights::GRANT`] on the endpoint.
+  fn granted(&self, message: &Message, endpoint: &Capability) -> Result<Option<Capability>> {
+    let Some(slot) = message.capability else {
+      return Ok(None);
+    };
+    if !endpoint.rights.contains(Rights::GRANT) {
+      return Err(Error::InsufficientRights);
+    }
+    self.get(slot).map(Some)
+  }
+
+  /// Returns `message` with the slot `granted` was put into.
+  fn accept(&self, mut message: Message, granted: Option<Capability>) -> Result<Message> {
+    message.capability = granted.map(|capability| self.insert(capability)).transpose()?;
+    Ok(message)
+  }
+
+  /// Puts `message` into the queue of the endpoint in `slot` and returns the transfer.
+  fn enqueue(&self, slot: usize, message: Message, expects_reply: bool) -> Result<Arc<Transfer>> {
+    let capability = self.get(slot)?;
+    let endpoint = capability.endpoint(Rights::WRITE)?;
+    let granted = self.granted(&message, &capability)?;
+
+    let transfer = Arc::new(Transfer {
+      state: spin::Mutex::new(State::Sent(message, granted)),
+      expects_reply,
+      changed: WaitQueue::new(),
+    });
+    endpoint.queue.lock().push_back(transfer.clone());
+    endpoint.receivers.wake_all();
+    Ok(transfer)
+  }
+
+  /// Sends `message` to the endpoint in `slot` and waits until a receiver took it.
+  ///
+  /// #### Errors
+  ///
+  /// If the slot does not contain an endpoint with [`Rights::WRITE`], or the message
+  /// grants a capability without [`Rights::GRANT`] on the endpoint, an error is returned.
+  pub fn send(&self, slot: usize, message: Message) -> Result<()> {
+    let transfer = self.enqueue(slot, message, false)?;
+    transfer
+      .changed
+      .wait_until(|| (!matches!(*transfer.state.lock(), State::Sent(..))).then_some(()));
+    Ok(())
+  }
+
+  /// Sends `message` to the endpoint in `slot` and waits for the reply, which is
+  /// returned.
+  ///
+  /// #### Errors
+  ///
+  /// The errors of [`Self::send`] are returned. If the receiver does not reply,
+  /// [`Error::NoReply`] is returned.
+  pub fn call(&self, slot: usize, message: Message) -> Result<Message> {
+    let transfer = self.enqueue(slot, message, true)?;
+    let (reply, granted) = transfer.changed.wait_until(|| {
+      let mut state = transfer.state.lock();
+      match core::mem::replace(&mut *state, State::Received) {
+        State::Replied(reply, granted) => Some(Ok((reply, granted))),
+        State::Abandoned => Some(Err(Error::NoReply)),
+        waiting => {
+          *state = waiting;
+          None
+        },
+      }
+    })?;
+    self.accept(reply, granted)
+  }
+
+  /// Waits for a message on the endpoint in `slot` and returns it. If the message was
+  /// sent with [`Self::call`], the next [`Self::reply`] answers it.
+  ///
+  /// #### Errors
+  ///
+  /// If the slot does not contain an endpoint with [`Rights::READ`], or the message
+  /// grants a capability and the table is full, an error is returned.
+  pub fn receive(&self, slot: usize) -> Result<Message> {
+    let capability = self.get(slot)?;
+    let endpoint = capability.endpoint(Rights::READ)?;
+
+    let transfer = endpoint
+      .receivers
+      .wait_until(|| endpoint.queue.lock().pop_front());
+
+    let mut state = transfer.state.lock();
+    // Only transfers whose message waits for a receiver are queued
+    let State::Sent(message, granted) = core::mem::replace(&mut *state, State::Received) else {
+      unreachable!("a message in the queue of an endpoint was received before");
+    };
+
+    let message = match self.accept(message, granted.clone()) {
+      Ok(message) => message,
+      Err(error) => {
+        *state = State::Sent(message, granted);
+        drop(state);
+        endpoint.queue.lock().push_front(transfer);
+        return Err(error);
+      },
+    };
+    drop(state);
+
+    let previous = if transfer.expects_reply {
+      self.reply.lock().replace(transfer.clone())
+    } else {
+      None
+    };
+    if let Some(previous) = previous {
+      previous.abandon();
+    }
+    transfer.changed.wake_all();
+    Ok(message)
+  }
+
+  /// Answers the call that was received last with `message`.
+  ///
+  /// #### Errors
+  ///
+  /// If there is no call to answer, [`Error::NoReply`] is returned. If the message
+  /// grants a capability that does not exist, [`Error::InvalidCapability`] is returned.
+  pub fn reply(&self, message: Message) -> Result<()> {
+    let granted = message.capability.map(|slot| self.get(slot)).transpose()?;
+    let transfer = self.reply.lock().take().ok_or(Error::NoReply)?;
+    *transfer.state.lock() = State::Replied(message, granted);
+    transfer.changed.wake_all();
+    Ok(())
+  }
+}
+
+/// The capability table the kernel uses until there are tasks.
+static KERNEL_TABLE: CapabilityTable = CapabilityTable::new();
+
+/// Returns the capability table of the running task. There are no tasks yet, so this is
+/// always the table of the kernel.
+#[must_use]
+pub fn current() -> &'static CapabilityTable { &KERNEL_TABLE }
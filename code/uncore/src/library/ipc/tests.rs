@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unit tests of capability tables and IPC.
+
+use super::{
+  Capability,
+  CapabilityTable,
+  Endpoint,
+  Error,
+  MemoryObject,
+  Message,
+  Object,
+  Rights,
+};
+
+#[test_case]
+fn capabilities_are_copied_with_fewer_rights() {
+  let table = CapabilityTable::new();
+  let endpoint = table
+    .insert(Capability {
+      object: Object::Endpoint(Endpoint::new()),
+      rights: Rights::ALL,
+    })
+    .unwrap();
+  let send_only = table.copy(endpoint, Rights::WRITE).unwrap();
+  assert_eq!(table.get(send_only).unwrap().rights, Rights::WRITE);
+  assert_eq!(table.receive(send_only), Err(Error::InsufficientRights));
+
+  let memory = MemoryObject::allocate(2).unwrap();
+  assert_eq!(memory.address() & (crate::library::mem::PAGE_SIZE - 1), 0);
+  let memory = table
+    .insert(Capability {
+      object: Object::Memory(memory),
+      rights: Rights::READ | Rights::WRITE,
+    })
+    .unwrap();
+  assert_eq!(table.receive(memory), Err(Error::WrongType));
+
+  let message = Message {
+    capability: Some(memory),
+    ..Message::default()
+  };
+  assert_eq!(table.send(send_only, message), Err(Error::InsufficientRights));
+  assert_eq!(table.reply(Message::default()), Err(Error::NoReply));
+
+  table.remove(send_only).unwrap();
+  assert_eq!(table.remove(send_only), Err(Error::InvalidCapability));
+  assert_eq!(table.copy(endpoint, Rights::READ).unwrap(), send_only);
+}
@@ -34,6 +34,18 @@
 /// `LOG_LEVEL` environment variable was set.
 const LOG_LEVEL: Option<&str> = option_env!("LOG_LEVEL");
 
+/// ## The Kernel Log Filter from the Environment
+///
+/// This variable has a value if the `LOG_FILTER` environment variable was set when the
+/// kernel was built. It holds directives that set the log level per module or hart.
+const LOG_FILTER: Option<&str> = option_env!("LOG_FILTER");
+
+/// ## The Kernel Log Format from the Environment
+///
+/// This variable has a value if the `LOG_FORMAT` environment variable was set when the
+/// kernel was built. It holds the format of the records on the console, e.g. `json`.
+const LOG_FORMAT: Option<&str> = option_env!("LOG_FORMAT");
+
 /// ### Static Kernel Information
 ///
 /// This struct exists to call non-member ("static") function on it to
@@ -89,4 +101,14 @@ pub fn get_log_level() -> log::Level {
       },
     )
   }
+
+  /// Returns the log filter directives defined by the environment variable `LOG_FILTER`
+  /// that was supplied at build-time, if any.
+  #[must_use]
+  pub const fn get_log_filter() -> Option<&'static str> { LOG_FILTER }
+
+  /// Returns the format of the records on the console defined by the environment
+  /// variable `LOG_FORMAT` that was supplied at build-time, if any.
+  #[must_use]
+  pub const fn get_log_format() -> Option<&'static str> { LOG_FORMAT }
 }
@@ -0,0 +1,273 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module decides which records are written to the kernel log. The decision is
+//! made by directives in the syntax of `env_logger`, separated by commas:
+//!
+//! | Directive            | Effect                                                     |
+//! | -------------------- | ---------------------------------------------------------- |
+//! | `warn`               | records of all modules are logged up to level `warn`       |
+//! | `uncore::mem=trace`  | records of `uncore::mem` and its submodules up to `trace`  |
+//! | `uncore::fs`         | all records of `uncore::fs` and its submodules             |
+//! | `uncore::fs@1=debug` | records of `uncore::fs` logged on hart 1 up to `debug`     |
+//! | `@2=off`             | no records that are logged on hart 2                       |
+//!
+//! Module paths may omit `library::`, i.e. `uncore::mem` matches records of
+//! `uncore::library::mem`. If several directives match a record, the one with the
+//! longest module path wins; a directive with a hart wins over one without, and a later
+//! directive wins over an earlier one. Records that no directive matches are logged up
+//! to the default level, which is `LOG_LEVEL` unless a directive without module path and
+//! hart sets it; the `log.level` argument of the kernel command line overrides
+//! `LOG_LEVEL`.
+//!
+//! The directives are taken from `LOG_FILTER` at build time and from the `log` argument
+//! of the kernel command line (see [`crate::library::cmdline`]) when the kernel starts.
+//! They can be replaced at runtime by writing to `/proc/logfilter`.
+
+/// The maximum number of directives (except the default level).
+const MAXIMUM_DIRECTIVES: usize = 16;
+/// The maximum length of the module path of a directive.
+const MAXIMUM_MODULE_LENGTH: usize = 64;
+
+crate::library::cmdline::kernel_param! {
+  /// The level of records that no directive matches, `LOG_LEVEL` by default.
+  pub LEVEL: log::LevelFilter = super::env::KernelInformation::get_log_level().to_level_filter(), "log.level"
+}
+
+crate::library::cmdline::kernel_param! {
+  /// The directives from the kernel command line.
+  pub DIRECTIVES: &'static str = "", "log", validate = validate
+}
+
+/// The directives that are in effect.
+static FILTER: spin::RwLock<Filter> = spin::RwLock::new(Filter::new(log::LevelFilter::Info));
+
+/// Errors that may occur when parsing directives.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+  /// A directive names a level that does not exist.
+  InvalidLevel,
+  /// A directive names a hart that is not a number.
+  InvalidHart,
+  /// The module path of a directive is longer than [`MAXIMUM_MODULE_LENGTH`].
+  ModuleTooLong,
+  /// There are more than [`MAXIMUM_DIRECTIVES`] directives.
+  TooManyDirectives,
+}
+
+impl core::fmt::Display for Error {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::InvalidLevel => write!(f, "invalid log level"),
+      Self::InvalidHart => write!(f, "invalid hart"),
+      Self::ModuleTooLong => write!(f, "module path longer than {MAXIMUM_MODULE_LENGTH} bytes"),
+      Self::TooManyDirectives => write!(f, "more than {MAXIMUM_DIRECTIVES} directives"),
+    }
+  }
+}
+
+/// A directive that sets the level of the records of a module or a hart.
+#[derive(Debug, Copy, Clone)]
+struct Directive {
+  /// The module path
+  module:        [u8; MAXIMUM_MODULE_LENGTH],
+  /// The length of the module path (zero if the directive applies to all modules)
+  module_length: usize,
+  /// The hart the directive applies to, or [`None`] if it applies to all harts
+  hart:          Option<usize>,
+  /// The maximum level of records that are logged
+  level:         log::LevelFilter,
+}
+
+impl Directive {
+  /// An empty directive.
+  const EMPTY: Self = Self {
+    module:        [0; MAXIMUM_MODULE_LENGTH],
+    module_length: 0,
+    hart:          None,
+    level:         log::LevelFilter::Off,
+  };
+
+  /// Returns the module path of the directive.
+  fn module(&self) -> &str { core::str::from_utf8(&self.module[..self.module_length]).unwrap_or_default() }
+
+  /// Returns whether the directive applies to records of the module `target` that are
+  /// logged on `hart`.
+  fn matches(&self, target: &str, hart: usize) -> bool {
+    /// Returns whether the module path `target` is `module` or one of its submodules.
+    fn within(target: &str, module: &str) -> bool {
+      target
+        .strip_prefix(module)
+        .is_some_and(|rest| module.is_empty() || rest.is_empty() || rest.starts_with("::"))
+    }
+
+    let module = self.module();
+    self.hart.is_none_or(|directive_hart| directive_hart == hart)
+      && (within(target, module)
+        || target.strip_prefix("uncore::library").is_some_and(|rest| {
+          module
+            .strip_prefix("uncore")
+            .is_some_and(|module| within(rest, module))
+        }))
+  }
+}
+
+/// A set of directives.
+#[derive(Debug, Clone)]
+pub(super) struct Filter {
+  /// The level of records that no directive matches
+  default:    log::LevelFilter,
+  /// The directives; only the first `count` are valid
+  directives: [Directive; MAXIMUM_DIRECTIVES],
+  /// The number of directives
+  count:      usize,
+}
+
+impl Filter {
+  /// Creates a filter without directives.
+  pub(super) const fn new(default: log::LevelFilter) -> Self {
+    Self {
+      default,
+      directives: [Directive::EMPTY; MAXIMUM_DIRECTIVES],
+      count: 0,
+    }
+  }
+
+  /// Adds `directives` to the filter.
+  pub(super) fn extend(&mut self, directives: &str) -> Result<(), Error> {
+    for directive in directives
+      .split(',')
+      .map(str::trim)
+      .filter(|directive| !directive.is_empty())
+    {
+      let (specification, level) = match directive.split_once('=') {
+        Some((specification, level)) => (specification, level.parse().map_err(|_| Error::InvalidLevel)?),
+        // A directive without level is either a level or a module of which all records
+        // are logged
+        None => directive
+          .parse()
+          .map_or((directive, log::LevelFilter::Trace), |level| ("", level)),
+      };
+      let (module, hart) = match specification.split_once('@') {
+        Some((module, hart)) => (module, Some(hart.parse().map_err(|_| Error::InvalidHart)?)),
+        None => (specification, None),
+      };
+
+      if module.is_empty() && hart.is_none() {
+        self.default = level;
+        continue;
+      }
+      if module.len() > MAXIMUM_MODULE_LENGTH {
+        return Err(Error::ModuleTooLong);
+      }
+      let entry = self
+        .directives
+        .get_mut(self.count)
+        .ok_or(Error::TooManyDirectives)?;
+      entry.module[..module.len()].copy_from_slice(module.as_bytes());
+      entry.module_length = module.len();
+      entry.hart = hart;
+      entry.level = level;
+      self.count += 1;
+    }
+    Ok(())
+  }
+
+  /// Returns the maximum level of records of the module `target` that are logged on
+  /// `hart`.
+  pub(super) fn level(&self, target: &str, hart: usize) -> log::LevelFilter {
+    self.directives[..self.count]
+      .iter()
+      .filter(|directive| directive.matches(target, hart))
+      .max_by_key(|directive| (directive.module_length, directive.hart.is_some()))
+      .map_or(self.default, |directive| directive.level)
+  }
+
+  /// Returns the maximum level of any record that is logged.
+  pub(super) fn maximum_level(&self) -> log::LevelFilter {
+    self.directives[..self.count]
+      .iter()
+      .map(|directive| directive.level)
+      .fold(self.default, core::cmp::Ord::max)
+  }
+}
+
+impl core::fmt::Display for Filter {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "{}", self.default)?;
+    for directive in &self.directives[..self.count] {
+      write!(f, ",{}", directive.module())?;
+      if let Some(hart) = directive.hart {
+        write!(f, "@{hart}")?;
+      }
+      write!(f, "={}", directive.level)?;
+    }
+    Ok(())
+  }
+}
+
+/// Checks that `directives` can be parsed.
+fn validate(directives: &'static str) -> Result<(), &'static str> {
+  Filter::new(log::LevelFilter::Off)
+    .extend(directives)
+    .map_err(|_| "invalid log filter directives")
+}
+
+/// Puts `filter` into effect.
+fn install(filter: Filter) {
+  log::set_max_level(filter.maximum_level());
+  crate::arch::without_interrupts(|| *FILTER.write() = filter);
+}
+
+/// Replaces the directives in effect with `directives`. Records that no directive
+/// matches are logged up to the level from `LOG_LEVEL` (or `log.level`) unless a
+/// directive sets the default level.
+///
+/// #### Errors
+///
+/// If `directives` cannot be parsed, an error is returned and the directives in effect
+/// are kept.
+pub fn set(directives: &str) -> Result<(), Error> {
+  let mut filter = Filter::new(LEVEL.get());
+  filter.extend(directives)?;
+  install(filter);
+  Ok(())
+}
+
+/// Returns whether a record of the module `target` with `level` that is logged on `hart`
+/// passes the directives in effect.
+pub(super) fn enabled(target: &str, level: log::Level, hart: usize) -> bool {
+  level <= FILTER.read().level(target, hart)
+}
+
+/// Writes the directives in effect in the syntax [`set`] accepts.
+pub fn write(out: &mut impl core::fmt::Write) -> core::fmt::Result { writeln!(out, "{}", *FILTER.read()) }
+
+/// Puts the default level into effect, until [`initialize`] applies the directives.
+pub(super) fn install_default() { install(Filter::new(LEVEL.get())); }
+
+/// Puts the directives from `LOG_FILTER` at build time and from the `log` argument of
+/// the kernel command line into effect. Directives that cannot be parsed are reported
+/// and ignored.
+pub(super) fn initialize() {
+  install_default();
+
+  for (source, directives) in [
+    (
+      "the build environment",
+      super::env::KernelInformation::get_log_filter(),
+    ),
+    (
+      "the kernel command line",
+      Some(DIRECTIVES.get()).filter(|directives| !directives.is_empty()),
+    ),
+  ] {
+    let Some(directives) = directives else {
+      continue;
+    };
+    let mut filter = FILTER.read().clone();
+    match filter.extend(directives) {
+      Ok(()) => install(filter),
+      Err(error) => log::warn!("Ignoring invalid log filter '{directives}' from {source}: {error}"),
+    }
+  }
+}
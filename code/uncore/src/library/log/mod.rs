@@ -1,12 +1,30 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 //! This module implements kernel-wide logging. It uses the [`::log`] crate.
+//!
+//! Which records are logged is decided per module and hart by the directives in
+//! [`filter`]. Every record that is logged is written to the registered sinks (see
+//! [`sink`]), e.g. the console and a ring buffer in memory (see [`records`]).
 
+#[cfg(test)]
+mod tests;
+
+pub mod filter;
 mod print;
+pub mod sink;
 mod env;
+mod ring;
 
+pub use env::KernelInformation;
 pub use print::{
   initialize,
+  configure,
   display_initial_information,
+  enter_panic_mode,
   KernelLogger,
 };
+pub use ring::{
+  dropped,
+  next_sequence,
+  records,
+};
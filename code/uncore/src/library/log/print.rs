@@ -8,59 +8,77 @@
 /// logging kernel-wide.
 static LOGGER: KernelLogger = KernelLogger::new();
 
+/// Whether the kernel panics. Records are then written even if the sinks are in use (see
+/// [`super::sink`]), and errors are written regardless of the log filter.
+static PANICKING: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
 /// ### The Main Kernel Logger
 ///
 /// This structure holds associated function that provide logging. The
-/// [`log::Log`] trait is implemented for this structure.
+/// [`log::Log`] trait is implemented for this structure. Records are handed to the
+/// registered sinks (see [`super::sink`]).
 #[derive(Debug)]
-pub struct KernelLogger {
-  /// This field represents a UART provides by QEMU
-  qemu_uart: qemu_uart::Logger,
-}
+pub struct KernelLogger;
 
 impl KernelLogger {
   /// ### Create a New Global Logger for the Kernel
   ///
   /// Creates a new instance of the kernel-wide logger.
-  const fn new() -> Self {
-    Self {
-      qemu_uart: qemu_uart::Logger,
-    }
-  }
-
-  /// This function takes care of setting the correct log level.
-  fn set_log_level(log_level: log::Level) { log::set_max_level(log_level.to_level_filter()); }
+  const fn new() -> Self { Self }
 
-  /// As the QEMU UART logger is disabled in the beginning, we need to enable it
-  /// explicitly after the UART has been initialed.
-  pub fn enable_qemu_logger() { qemu_uart::Logger::enable(); }
+  /// As the UART sink is disabled in the beginning, we need to enable it explicitly
+  /// after the UART that serves as the console has been initialized.
+  pub fn enable_uart_logger(uart: crate::arch::Console) { super::sink::uart::Uart::enable(uart); }
 }
 
 impl log::Log for KernelLogger {
-  fn enabled(&self, metadata: &log::Metadata) -> bool { metadata.level() <= log::max_level() }
+  fn enabled(&self, metadata: &log::Metadata) -> bool {
+    (panicking() && metadata.level() == log::Level::Error)
+      || (metadata.level() <= log::max_level()
+        && super::filter::enabled(metadata.target(), metadata.level(), crate::arch::hart()))
+  }
 
   fn log(&self, record: &log::Record) {
     if !self.enabled(record.metadata()) {
       return;
     }
 
-    self.qemu_uart.log(record);
+    super::sink::write(record, crate::arch::hart());
   }
 
-  fn flush(&self) {}
+  fn flush(&self) { super::sink::flush(); }
 }
 
-/// Initializes the log by setting the global kernel logger and the correct log level.
+/// Switches the log into panic mode: from now on, records are written even if the sinks
+/// are in use, so that the reason for the panic always gets out. This function is called
+/// by the panic handler before anything is logged.
+pub fn enter_panic_mode() { PANICKING.store(true, core::sync::atomic::Ordering::Relaxed); }
+
+/// Returns whether the log is in panic mode (see [`enter_panic_mode`]).
+pub(super) fn panicking() -> bool { PANICKING.load(core::sync::atomic::Ordering::Relaxed) }
+
+/// Initializes the log by registering the sinks (see [`super::sink`]) and setting the
+/// global kernel logger with the default log level. This function is called by
+/// [`crate::arch::initialize`] before devices are probed, so that the records of the
+/// drivers are kept in the ring buffer and replayed when the console comes up.
 ///
 /// #### Panics
 ///
 /// If this function is called twice, the kernel panics, because we want to avoid code
 /// that initializes the logger twice.
 pub fn initialize() {
+  super::sink::initialize();
   crate::panic_on_error!(log::set_logger, &LOGGER);
-  KernelLogger::set_log_level(super::env::KernelInformation::get_log_level());
+  super::filter::install_default();
 
   log::debug!("Kernel logging enabled");
+}
+
+/// Puts the log filter from the build environment and the kernel command line into
+/// effect (see [`super::filter`]). This function is called after the command line has
+/// been parsed.
+pub fn configure() {
+  super::filter::initialize();
 
   log::debug!(
     "Log level set to '{}'",
@@ -87,71 +105,3 @@ pub fn display_initial_information() {
     super::env::KernelInformation::get_rust_toolchain()
   );
 }
-
-/// This module contains code to work with the UART provided by the underlying
-/// architecture, see [`crate::arch::drivers::qemu_uart::Uart`].
-mod qemu_uart {
-  /// This lock ensure that simultaneous writers will be serialized when calling [`log`].
-  /// The logger is initialized in a way that logging has to be enabled explicitly later
-  /// (when the UART itself has been initialized), as logging before that is undefined
-  /// behavior.
-  ///
-  /// The first field indicates whether the logger is enabled or not. We have to store
-  /// this information in the same [`spin::Mutex`] to avoid race-conditions later when
-  /// locking the Mutex and checking whether the logger is enabled.
-  ///
-  /// We also not introduce a new filed on [`Logger`] because we would need to mutate
-  /// through a `&self` reference (to disable the logger is required) in
-  /// [`log::Log::log`], which is not allowed.
-  static LOCK: spin::Mutex<(bool, crate::arch::drivers::qemu_uart::Uart)> =
-    spin::Mutex::new((false, crate::arch::drivers::qemu_uart::UART));
-
-  /// An opaque type used to implement [`log::Log`] on.
-  #[derive(Debug)]
-  pub struct Logger;
-
-  impl Logger {
-    /// Enables this logger.
-    pub(super) fn enable() { LOCK.lock().0 = true; }
-  }
-
-  impl log::Log for Logger {
-    // This function is not used because the global logger instance already checks whether the
-    // log is enabled or not.
-    fn enabled(&self, _: &log::Metadata) -> bool { true }
-
-    fn flush(&self) {}
-
-    fn log(&self, record: &log::Record) {
-      let mut lock = LOCK.lock();
-
-      if lock.0 {
-        use core::fmt::Write;
-        use owo_colors::OwoColorize;
-
-        /// Shortens the log sequence (writing via `println!`).
-        macro_rules! log_with_color {
-          ($string:expr, $r:expr, $g:expr, $b:expr) => {{
-            if let Err(_) = writeln!(
-              lock.1,
-              "{} {}",
-              $string.fg_rgb::<$r, $g, $b>(),
-              record.args()
-            ) {
-              lock.0 = false;
-            }
-          }};
-        }
-
-        // https://coolors.co/fb4934-fabd2f-458588-83a598-8f8f8f
-        match record.level() {
-          log::Level::Error => log_with_color!("ERROR", 251, 73, 52),
-          log::Level::Warn => log_with_color!("WARN ", 250, 189, 47),
-          log::Level::Info => log_with_color!("INFO ", 69, 133, 136),
-          log::Level::Debug => log_with_color!("DEBUG", 131, 165, 152),
-          log::Level::Trace => log_with_color!("TRACE", 143, 143, 143),
-        };
-      }
-    }
-  }
-}
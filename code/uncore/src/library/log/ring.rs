@@ -0,0 +1,235 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module keeps the most recent records of the kernel log in memory, so that they
+//! can be read later, e.g. by `dmesg`-like tools (see `/proc/kmsg` and the `syslog`
+//! system call) or in crash dumps.
+//!
+//! The ring buffer consists of [`SLOTS`] slots, and record number `n` is stored in slot
+//! `n % SLOTS`. Writers never wait: a writer reserves a record number and claims its slot
+//! by marking it as being written; if another writer still writes to the slot, the record
+//! is dropped. Readers check the state of a slot before and after copying it, and skip
+//! records that were overwritten in the meantime. The contents of the slots are atomics,
+//! so concurrent reads and writes are well-defined.
+
+use core::sync::atomic::{
+  AtomicU64,
+  Ordering,
+};
+
+/// The number of records the ring buffer holds.
+pub const SLOTS: usize = 256;
+/// The maximum number of bytes of the module path and the message of a record.
+const TEXT_SIZE: usize = 224;
+/// The number of words in which the text of a record is stored.
+const TEXT_WORDS: usize = TEXT_SIZE / 8;
+
+/// A slot of the ring buffer.
+struct Slot {
+  /// `2 * (n + 1)` if the slot holds record number `n`, plus one while it is written
+  state:     AtomicU64,
+  /// The level (bits 0 to 7), hart (bits 8 to 15), length of the module path (bits 16 to
+  /// 31) and length of the message (bits 32 to 47)
+  header:    AtomicU64,
+  /// The time since the machine started in nanoseconds
+  timestamp: AtomicU64,
+  /// The module path followed by the message
+  text:      [AtomicU64; TEXT_WORDS],
+}
+
+impl Slot {
+  /// Creates an empty slot.
+  const fn new() -> Self {
+    Self {
+      state:     AtomicU64::new(0),
+      header:    AtomicU64::new(0),
+      timestamp: AtomicU64::new(0),
+      text:      [const { AtomicU64::new(0) }; TEXT_WORDS],
+    }
+  }
+}
+
+/// The slots of the ring buffer.
+static RING: [Slot; SLOTS] = [const { Slot::new() }; SLOTS];
+/// The number of the next record.
+static NEXT: AtomicU64 = AtomicU64::new(0);
+/// The number of records that were dropped because their slot was in use.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the slot that holds the record with `sequence`.
+fn slot(sequence: u64) -> &'static Slot {
+  &RING[usize::try_from(sequence % SLOTS as u64).unwrap_or_default()]
+}
+
+/// Returns the state of a slot that holds the record with `sequence`.
+const fn committed(sequence: u64) -> u64 { (sequence + 1) << 1 }
+
+/// Collects text up to [`TEXT_SIZE`] bytes and drops the rest without splitting
+/// characters.
+struct Text {
+  /// The text collected so far
+  bytes:  [u8; TEXT_SIZE],
+  /// The number of bytes collected so far
+  length: usize,
+}
+
+impl core::fmt::Write for Text {
+  fn write_str(&mut self, out: &str) -> core::fmt::Result {
+    let mut end = out.len().min(TEXT_SIZE - self.length);
+    while !out.is_char_boundary(end) {
+      end -= 1;
+    }
+    self.bytes[self.length..self.length + end].copy_from_slice(&out.as_bytes()[..end]);
+    self.length += end;
+    Ok(())
+  }
+}
+
+/// Appends `record` to the ring buffer.
+pub(super) fn push(record: &log::Record) {
+  use core::fmt::Write;
+
+  let mut text = Text {
+    bytes:  [0; TEXT_SIZE],
+    length: 0,
+  };
+  let _ = text.write_str(record.module_path().unwrap_or_else(|| record.target()));
+  let module_length = text.length;
+  let _ = write!(text, "{}", record.args());
+  let message_length = text.length - module_length;
+
+  let timestamp = u64::try_from(crate::library::time::uptime().as_nanos()).unwrap_or(u64::MAX);
+  let hart = u64::try_from(crate::arch::hart()).unwrap_or(u64::MAX).min(0xFF);
+  let header =
+    record.level() as u64 | hart << 8 | (module_length as u64) << 16 | (message_length as u64) << 32;
+
+  let sequence = NEXT.fetch_add(1, Ordering::Relaxed);
+  let slot = slot(sequence);
+  let previous = slot.state.load(Ordering::Relaxed);
+  if previous & 1 == 1
+    || previous >= committed(sequence)
+    || slot
+      .state
+      .compare_exchange(
+        previous,
+        committed(sequence) | 1,
+        Ordering::Acquire,
+        Ordering::Relaxed,
+      )
+      .is_err()
+  {
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+    return;
+  }
+
+  slot.header.store(header, Ordering::Relaxed);
+  slot.timestamp.store(timestamp, Ordering::Relaxed);
+  for (word, bytes) in slot.text.iter().zip(text.bytes.chunks_exact(8)) {
+    word.store(
+      u64::from_le_bytes(bytes.try_into().unwrap_or_default()),
+      Ordering::Relaxed,
+    );
+  }
+  slot.state.store(committed(sequence), Ordering::Release);
+}
+
+/// A record of the kernel log that was read from the ring buffer.
+#[derive(Debug, Clone)]
+pub struct Entry {
+  /// The time since the machine started when the record was logged
+  pub uptime:     core::time::Duration,
+  /// The level of the record
+  pub level:      log::Level,
+  /// The hart that logged the record
+  pub hart:       usize,
+  /// The length of the module path in `text`
+  module_length:  usize,
+  /// The length of the message in `text`
+  message_length: usize,
+  /// The module path followed by the message
+  text:           [u8; TEXT_SIZE],
+}
+
+impl Entry {
+  /// Reads the record with `sequence`, or returns [`None`] if it has been overwritten or
+  /// is being written.
+  fn read(sequence: u64) -> Option<Self> {
+    let slot = slot(sequence);
+    let state = slot.state.load(Ordering::Acquire);
+    if state != committed(sequence) {
+      return None;
+    }
+
+    let header = slot.header.load(Ordering::Relaxed);
+    let timestamp = slot.timestamp.load(Ordering::Relaxed);
+    let mut text = [0; TEXT_SIZE];
+    for (bytes, word) in text.chunks_exact_mut(8).zip(&slot.text) {
+      bytes.copy_from_slice(&word.load(Ordering::Relaxed).to_le_bytes());
+    }
+
+    core::sync::atomic::fence(Ordering::Acquire);
+    if slot.state.load(Ordering::Relaxed) != state {
+      return None;
+    }
+
+    let module_length = ((header >> 16) & 0xFFFF) as usize;
+    let message_length = ((header >> 32) & 0xFFFF) as usize;
+    if module_length + message_length > TEXT_SIZE {
+      return None;
+    }
+    Some(Self {
+      uptime: core::time::Duration::from_nanos(timestamp),
+      level: match header & 0xFF {
+        1 => log::Level::Error,
+        2 => log::Level::Warn,
+        3 => log::Level::Info,
+        4 => log::Level::Debug,
+        _ => log::Level::Trace,
+      },
+      hart: ((header >> 8) & 0xFF) as usize,
+      module_length,
+      message_length,
+      text,
+    })
+  }
+
+  /// Returns the path of the module that logged the record.
+  #[must_use]
+  pub fn module(&self) -> &str { core::str::from_utf8(&self.text[..self.module_length]).unwrap_or("?") }
+
+  /// Returns the message of the record, which is truncated if it was too long.
+  #[must_use]
+  pub fn message(&self) -> &str {
+    core::str::from_utf8(&self.text[self.module_length..self.module_length + self.message_length])
+      .unwrap_or("?")
+  }
+}
+
+impl core::fmt::Display for Entry {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(
+      f,
+      "[{:>5}.{:06}] {:<5} {} {}: {}",
+      self.uptime.as_secs(),
+      self.uptime.subsec_micros(),
+      self.level,
+      self.hart,
+      self.module(),
+      self.message()
+    )
+  }
+}
+
+/// Returns the number of the next record that will be logged.
+#[must_use]
+pub fn next_sequence() -> u64 { NEXT.load(Ordering::Acquire) }
+
+/// Returns the number of records that were dropped because their slot was in use.
+#[must_use]
+pub fn dropped() -> u64 { DROPPED.load(Ordering::Relaxed) }
+
+/// Returns the records with numbers from `first` up to (excluding) `end` that are still
+/// in the ring buffer, from the oldest to the newest one.
+pub fn records(first: u64, end: u64) -> impl Iterator<Item = Entry> {
+  let first = first.max(end.saturating_sub(SLOTS as u64));
+  (first..end).filter_map(Entry::read)
+}
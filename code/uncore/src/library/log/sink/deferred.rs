@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module holds records that could not be written right away because a trap
+//! interrupted the kernel while it held the lock of a sink. Waiting for the lock in the
+//! trap would deadlock the hart, so the record is rendered into a buffer of the hart
+//! instead and written by the next writer that holds the lock.
+//!
+//! Every hart has its own buffer, into which only the hart itself writes (while it
+//! handles a trap, with interrupts disabled), and from which only the holder of the lock
+//! of the sink reads. Therefore, the buffers need no lock.
+
+use core::sync::atomic::{
+  AtomicU64,
+  AtomicU8,
+  AtomicUsize,
+  Ordering,
+};
+
+use crate::arch::MAXIMUM_HARTS;
+
+/// The number of bytes of the buffer of a hart.
+const BUFFER_SIZE: usize = 4096;
+/// The maximum number of bytes of a record that is deferred; longer records are cut.
+const RECORD_SIZE: usize = 512;
+
+/// The buffer of a hart.
+struct Buffer {
+  /// The rendered records
+  bytes: [AtomicU8; BUFFER_SIZE],
+  /// The total number of bytes that were written (only changed by the hart)
+  head:  AtomicUsize,
+  /// The total number of bytes that were read (only changed by the reader)
+  tail:  AtomicUsize,
+}
+
+/// The buffers of all harts.
+static BUFFERS: [Buffer; MAXIMUM_HARTS] = [const {
+  Buffer {
+    bytes: [const { AtomicU8::new(0) }; BUFFER_SIZE],
+    head:  AtomicUsize::new(0),
+    tail:  AtomicUsize::new(0),
+  }
+}; MAXIMUM_HARTS];
+
+/// The number of records that were dropped because the buffer of their hart was full.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Collects up to [`RECORD_SIZE`] bytes of a rendered record.
+struct Record {
+  /// The bytes collected so far
+  bytes:  [u8; RECORD_SIZE],
+  /// The number of bytes collected so far
+  length: usize,
+}
+
+impl core::fmt::Write for Record {
+  fn write_str(&mut self, text: &str) -> core::fmt::Result {
+    let length = text.len().min(RECORD_SIZE - self.length);
+    self.bytes[self.length..self.length + length].copy_from_slice(&text.as_bytes()[..length]);
+    self.length += length;
+    Ok(())
+  }
+}
+
+/// Renders `record`, which was logged on `hart`, in `format` into the buffer of `hart`.
+/// If the buffer is full, the record is dropped.
+pub(in crate::library::log) fn defer(record: &log::Record, hart: usize, format: super::Format) {
+  let Some(buffer) = BUFFERS.get(hart) else {
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+    return;
+  };
+
+  let mut rendered = Record {
+    bytes:  [0; RECORD_SIZE],
+    length: 0,
+  };
+  let _ = super::write_record(&mut rendered, record, hart, format);
+  if rendered.length == RECORD_SIZE {
+    rendered.bytes[RECORD_SIZE - 1] = b'\n';
+  }
+
+  let head = buffer.head.load(Ordering::Relaxed);
+  if head - buffer.tail.load(Ordering::Acquire) + rendered.length > BUFFER_SIZE {
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+    return;
+  }
+  for (offset, &byte) in rendered.bytes[..rendered.length].iter().enumerate() {
+    buffer.bytes[(head + offset) % BUFFER_SIZE].store(byte, Ordering::Relaxed);
+  }
+  buffer.head.store(head + rendered.length, Ordering::Release);
+}
+
+/// Hands the bytes of all deferred records to `write`, oldest first per hart, and
+/// removes them from the buffers. This function must only be called while the lock of
+/// the sink is held, as there may only be one reader.
+pub(in crate::library::log) fn drain(mut write: impl FnMut(u8)) {
+  for buffer in &BUFFERS {
+    let head = buffer.head.load(Ordering::Acquire);
+    let tail = buffer.tail.load(Ordering::Relaxed);
+    for position in tail..head {
+      write(buffer.bytes[position % BUFFER_SIZE].load(Ordering::Relaxed));
+    }
+    buffer.tail.store(head, Ordering::Release);
+  }
+}
+
+/// Returns the number of records that were dropped because the buffer of their hart was
+/// full.
+pub(super) fn dropped() -> u64 { DROPPED.load(Ordering::Relaxed) }
@@ -0,0 +1,29 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains the sink that mirrors the kernel log onto the framebuffer text
+//! console, see [`crate::library::console`].
+
+/// The sink that writes records to the framebuffer text console.
+#[derive(Debug)]
+pub struct Framebuffer;
+
+impl super::Sink for Framebuffer {
+  fn name(&self) -> &'static str { "framebuffer" }
+
+  fn write(&self, record: &log::Record, hart: usize, format: super::Format) {
+    // The console is optional; if it is not available, the record is silently dropped.
+    // Traps that interrupted the kernel do not wait for the console, as the interrupted
+    // code may hold it; the record is still kept by the other sinks.
+    let write =
+      |console: &mut crate::library::console::TextConsole| super::write_record(console, record, hart, format);
+    let _ = if crate::arch::in_kernel_trap() {
+      crate::library::console::try_with(write)
+    } else {
+      crate::library::console::with(write)
+    };
+  }
+
+  fn write_in_panic(&self, record: &log::Record, hart: usize, format: super::Format) {
+    let _ = crate::library::console::try_with(|console| super::write_record(console, record, hart, format));
+  }
+}
@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module renders records as JSON objects, one per line, so that tools (e.g.
+//! `uncore-helper`) can parse the kernel log:
+//!
+//! ```json
+//! {"uptime":1.500000,"level":"INFO","hart":0,"module":"uncore","file":"src/lib.rs","line":7,"message":"Hi"}
+//! ```
+//!
+//! `uptime` is the time since the machine started in seconds. `file` and `line` are
+//! `null` if they are unknown, e.g. for records that are replayed from the ring buffer.
+
+/// Escapes the text written to it as the contents of a JSON string.
+struct Escaped<'writer, W: core::fmt::Write>(&'writer mut W);
+
+impl<W: core::fmt::Write> core::fmt::Write for Escaped<'_, W> {
+  fn write_str(&mut self, text: &str) -> core::fmt::Result {
+    for character in text.chars() {
+      match character {
+        '"' => self.0.write_str("\\\"")?,
+        '\\' => self.0.write_str("\\\\")?,
+        '\n' => self.0.write_str("\\n")?,
+        '\r' => self.0.write_str("\\r")?,
+        '\t' => self.0.write_str("\\t")?,
+        character if character.is_control() => write!(self.0, "\\u{:04x}", u32::from(character))?,
+        character => self.0.write_char(character)?,
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Writes `value` as a JSON string to `writer`.
+fn write_string(writer: &mut impl core::fmt::Write, value: core::fmt::Arguments) -> core::fmt::Result {
+  use core::fmt::Write;
+
+  writer.write_char('"')?;
+  Escaped(writer).write_fmt(value)?;
+  writer.write_char('"')
+}
+
+/// Writes `record`, which was logged on `hart`, as a JSON object followed by a newline to
+/// `writer`.
+pub fn write_record(
+  writer: &mut impl core::fmt::Write,
+  record: &log::Record,
+  hart: usize,
+) -> core::fmt::Result {
+  let uptime = crate::library::time::uptime();
+  write!(
+    writer,
+    "{{\"uptime\":{}.{:06},\"level\":\"{}\",\"hart\":{hart},\"module\":",
+    uptime.as_secs(),
+    uptime.subsec_micros(),
+    record.level()
+  )?;
+  write_string(writer, format_args!("{}", record.target()))?;
+  write!(writer, ",\"file\":")?;
+  match record.file() {
+    Some(file) => write_string(writer, format_args!("{file}"))?,
+    None => write!(writer, "null")?,
+  }
+  match record.line() {
+    Some(line) => write!(writer, ",\"line\":{line},\"message\":")?,
+    None => write!(writer, ",\"line\":null,\"message\":")?,
+  }
+  write_string(writer, *record.args())?;
+  writeln!(writer, "}}")
+}
@@ -0,0 +1,17 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains the sink that keeps records in the ring buffer in memory, see
+//! [`crate::library::log::records`].
+
+/// The sink that keeps records in the ring buffer in memory. Records are stored with
+/// their level, hart and module, so the format does not apply.
+#[derive(Debug)]
+pub struct Memory;
+
+impl super::Sink for Memory {
+  fn name(&self) -> &'static str { "memory" }
+
+  fn write(&self, record: &log::Record, _hart: usize, _format: super::Format) {
+    super::super::ring::push(record);
+  }
+}
@@ -0,0 +1,306 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains the sinks the kernel log writes records to. Every record that
+//! passes the log filter (see [`super::filter`]) is handed to all registered sinks; each
+//! sink has its own level and format on top of that.
+//!
+//! | Sink          | Destination                                       | Default format |
+//! | ------------- | ------------------------------------------------- | -------------- |
+//! | `memory`      | the ring buffer in memory (see [`super::records`]) | -              |
+//! | `uart`        | the UART that serves as the console               | colored        |
+//! | `framebuffer` | the framebuffer text console                      | colored        |
+//! | `semihosting` | the debugger or emulator via semihosting          | plain          |
+//!
+//! If `LOG_FORMAT` is set at build time (to `colored`, `plain` or `json`), the `uart` and
+//! `semihosting` sinks use that format instead, e.g. JSON lines that tools can parse (see
+//! [`json`]).
+//!
+//! The `semihosting` sink only exists if the kernel is built with the `semihosting`
+//! feature, as semihosting calls trap if no debugger or emulator handles them. Further
+//! sinks (e.g. of drivers) can be added with [`register`].
+//!
+//! Records may be logged from any context. Sinks take their locks with interrupts
+//! disabled, and traps that interrupted the kernel never wait for a lock: the `uart` sink
+//! defers their records to a buffer of the hart (see `deferred`) if its lock is held,
+//! and writes them with the next record or when the log is flushed. When the kernel
+//! panics, records are written with [`Sink::write_in_panic`], which breaks locks that
+//! are not released in time so that the panic message always gets out.
+
+pub(super) mod deferred;
+mod framebuffer;
+mod json;
+mod memory;
+#[cfg(feature = "semihosting")]
+mod semihosting;
+pub(super) mod uart;
+
+/// The maximum number of sinks.
+const MAXIMUM_SINKS: usize = 8;
+
+/// The registered sinks.
+static SINKS: spin::RwLock<[Option<Registration>; MAXIMUM_SINKS]> = spin::RwLock::new([None; MAXIMUM_SINKS]);
+
+/// A destination of the kernel log.
+pub trait Sink: core::fmt::Debug + Sync {
+  /// Returns the name of the sink, e.g. `uart`.
+  fn name(&self) -> &'static str;
+
+  /// Writes `record`, which was logged on `hart`, in `format`.
+  fn write(&self, record: &log::Record, hart: usize, format: Format);
+
+  /// Writes `record` while the kernel panics. Sinks whose [`Sink::write`] may wait for a
+  /// lock forever should not do so here. By default, the record is written with
+  /// [`Sink::write`].
+  fn write_in_panic(&self, record: &log::Record, hart: usize, format: Format) {
+    self.write(record, hart, format);
+  }
+
+  /// Writes records the sink has buffered. By default, nothing is done.
+  fn flush(&self) {}
+}
+
+/// The format of the records a sink writes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+  /// Records are colored with ANSI escape sequences.
+  Colored,
+  /// Records are plain text.
+  Plain,
+  /// Records are JSON objects, one per line.
+  Json,
+}
+
+impl core::str::FromStr for Format {
+  type Err = Error;
+
+  fn from_str(format: &str) -> Result<Self, Error> {
+    match format {
+      "colored" => Ok(Self::Colored),
+      "plain" => Ok(Self::Plain),
+      "json" => Ok(Self::Json),
+      _ => Err(Error::InvalidFormat),
+    }
+  }
+}
+
+impl core::fmt::Display for Format {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::Colored => write!(f, "colored"),
+      Self::Plain => write!(f, "plain"),
+      Self::Json => write!(f, "json"),
+    }
+  }
+}
+
+/// Errors that may occur when registering or configuring sinks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+  /// A sink with the same name is already registered.
+  AlreadyRegistered,
+  /// A format is neither `colored`, `plain` nor `json`.
+  InvalidFormat,
+  /// No sink with the name is registered.
+  NotFound,
+  /// There are already [`MAXIMUM_SINKS`] sinks.
+  TooManySinks,
+}
+
+impl core::fmt::Display for Error {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::AlreadyRegistered => write!(f, "log sink already registered"),
+      Self::InvalidFormat => write!(f, "invalid log format"),
+      Self::NotFound => write!(f, "log sink not found"),
+      Self::TooManySinks => write!(f, "more than {MAXIMUM_SINKS} log sinks"),
+    }
+  }
+}
+
+/// A registered sink with its level and format.
+#[derive(Debug, Copy, Clone)]
+struct Registration {
+  /// The sink
+  sink:   &'static dyn Sink,
+  /// The maximum level of records the sink writes
+  level:  log::LevelFilter,
+  /// The format of the records the sink writes
+  format: Format,
+}
+
+/// Registers `sink`, which from now on writes records up to `level` in `format`.
+///
+/// #### Errors
+///
+/// If a sink with the same name is already registered or there is no space left for
+/// another sink, an error is returned.
+pub fn register(sink: &'static dyn Sink, level: log::LevelFilter, format: Format) -> Result<(), Error> {
+  crate::arch::without_interrupts(|| {
+    let mut sinks = SINKS.write();
+    if sinks
+      .iter()
+      .flatten()
+      .any(|registration| registration.sink.name() == sink.name())
+    {
+      return Err(Error::AlreadyRegistered);
+    }
+    let slot = sinks
+      .iter_mut()
+      .find(|slot| slot.is_none())
+      .ok_or(Error::TooManySinks)?;
+    *slot = Some(Registration { sink, level, format });
+    Ok(())
+  })
+}
+
+/// Changes the level of the sink with `name`, and its format if `format` is not [`None`].
+///
+/// #### Errors
+///
+/// If no sink with `name` is registered, [`Error::NotFound`] is returned.
+pub fn configure(name: &str, level: log::LevelFilter, format: Option<Format>) -> Result<(), Error> {
+  crate::arch::without_interrupts(|| {
+    let mut sinks = SINKS.write();
+    let registration = sinks
+      .iter_mut()
+      .flatten()
+      .find(|registration| registration.sink.name() == name)
+      .ok_or(Error::NotFound)?;
+    registration.level = level;
+    registration.format = format.unwrap_or(registration.format);
+    Ok(())
+  })
+}
+
+/// Returns the level and format of the sink with `name`, if it is registered.
+fn configuration(name: &str) -> Option<(log::LevelFilter, Format)> {
+  SINKS
+    .read()
+    .iter()
+    .flatten()
+    .find(|registration| registration.sink.name() == name)
+    .map(|registration| (registration.level, registration.format))
+}
+
+/// Writes the registered sinks with their level and format.
+pub fn write_sinks(out: &mut impl core::fmt::Write) -> core::fmt::Result {
+  writeln!(out, "{:<16} {:<5} FORMAT", "SINK", "LEVEL")?;
+  for registration in SINKS.read().iter().flatten() {
+    writeln!(
+      out,
+      "{:<16} {:<5} {}",
+      registration.sink.name(),
+      registration.level,
+      registration.format
+    )?;
+  }
+  writeln!(out, "{} deferred records dropped", deferred::dropped())
+}
+
+/// Hands `record`, which was logged on `hart`, to the sinks whose level it passes. While
+/// the kernel panics, the records are written with [`Sink::write_in_panic`]; if the sinks
+/// cannot be read because they are being changed, the record is written to the UART.
+pub(super) fn write(record: &log::Record, hart: usize) {
+  if !super::print::panicking() {
+    for registration in SINKS.read().iter().flatten() {
+      if record.level() <= registration.level {
+        registration.sink.write(record, hart, registration.format);
+      }
+    }
+    return;
+  }
+
+  match SINKS.try_read() {
+    Some(sinks) => {
+      for registration in sinks.iter().flatten() {
+        if record.level() <= registration.level {
+          registration
+            .sink
+            .write_in_panic(record, hart, registration.format);
+        }
+      }
+    },
+    None => uart::Uart.write_in_panic(record, hart, Format::Plain),
+  }
+}
+
+/// Writes the records the sinks have buffered.
+pub(super) fn flush() {
+  if let Some(sinks) = SINKS.try_read() {
+    for registration in sinks.iter().flatten() {
+      registration.sink.flush();
+    }
+  }
+}
+
+/// Registers the sinks of the kernel.
+pub(super) fn initialize() {
+  let format = super::env::KernelInformation::get_log_format().and_then(|format| format.parse().ok());
+  let sinks: &[(&'static dyn Sink, Format)] = &[
+    (&memory::Memory, Format::Plain),
+    (&uart::Uart, format.unwrap_or(Format::Colored)),
+    (&framebuffer::Framebuffer, Format::Colored),
+    #[cfg(feature = "semihosting")]
+    (&semihosting::Semihosting, format.unwrap_or(Format::Plain)),
+  ];
+  for &(sink, format) in sinks {
+    if let Err(error) = register(sink, log::LevelFilter::Trace, format) {
+      log::warn!("Could not register log sink '{}': {error}", sink.name());
+    }
+  }
+  // In case the console came up before its sink was registered
+  uart::Uart::replay();
+}
+
+/// Writes `record`, which was logged on `hart`, to `writer` in the kernel log format: the
+/// level, the hart and the module followed by the message, or a JSON object if `format`
+/// is [`Format::Json`]. Sinks may use this function to render records.
+pub fn write_record(
+  writer: &mut impl core::fmt::Write,
+  record: &log::Record,
+  hart: usize,
+  format: Format,
+) -> core::fmt::Result {
+  use owo_colors::{
+    OwoColorize,
+    Rgb,
+  };
+
+  /// The color of the time and the module.
+  const GRAY: Rgb = Rgb(143, 143, 143);
+
+  if format == Format::Json {
+    return json::write_record(writer, record, hart);
+  }
+
+  // https://coolors.co/fb4934-fabd2f-458588-83a598-8f8f8f
+  let (level, color) = match record.level() {
+    log::Level::Error => ("ERROR", Rgb(251, 73, 52)),
+    log::Level::Warn => ("WARN ", Rgb(250, 189, 47)),
+    log::Level::Info => ("INFO ", Rgb(69, 133, 136)),
+    log::Level::Debug => ("DEBUG", Rgb(131, 165, 152)),
+    log::Level::Trace => ("TRACE", GRAY),
+  };
+
+  let time = crate::library::time::realtime().map(crate::library::time::DateTime::from);
+  match format {
+    Format::Colored => {
+      if let Some(time) = time {
+        write!(writer, "{} ", time.color(GRAY))?;
+      }
+      writeln!(
+        writer,
+        "{} {hart} {}: {}",
+        level.color(color),
+        record.target().color(GRAY),
+        record.args()
+      )
+    },
+    Format::Plain | Format::Json => {
+      if let Some(time) = time {
+        write!(writer, "{time} ")?;
+      }
+      writeln!(writer, "{level} {hart} {}: {}", record.target(), record.args())
+    },
+  }
+}
@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains the sink that writes records to the debugger or emulator via
+//! semihosting, which works even if no console is available. QEMU prints the records if
+//! it runs with `-semihosting-config enable=on,target=native`.
+
+/// The semihosting operation that writes a null-terminated string (`SYS_WRITE0`).
+const SYS_WRITE0: usize = 0x04;
+
+/// Collects text and writes it via semihosting in null-terminated chunks.
+struct Writer {
+  /// The text collected so far, followed by a null byte
+  buffer: [u8; 128],
+  /// The number of bytes collected so far
+  length: usize,
+}
+
+impl Writer {
+  /// Writes the text collected so far.
+  fn flush(&mut self) {
+    if self.length == 0 {
+      return;
+    }
+    self.buffer[self.length] = 0;
+    crate::arch::semihosting(SYS_WRITE0, self.buffer.as_ptr() as usize);
+    self.length = 0;
+  }
+}
+
+impl core::fmt::Write for Writer {
+  fn write_str(&mut self, text: &str) -> core::fmt::Result {
+    for &byte in text.as_bytes() {
+      // Null bytes would end the string early
+      if byte == 0 {
+        continue;
+      }
+      if self.length == self.buffer.len() - 1 {
+        self.flush();
+      }
+      self.buffer[self.length] = byte;
+      self.length += 1;
+    }
+    Ok(())
+  }
+}
+
+/// The sink that writes records via semihosting.
+#[derive(Debug)]
+pub struct Semihosting;
+
+impl super::Sink for Semihosting {
+  fn name(&self) -> &'static str { "semihosting" }
+
+  fn write(&self, record: &log::Record, hart: usize, format: super::Format) {
+    let mut writer = Writer {
+      buffer: [0; 128],
+      length: 0,
+    };
+    let _ = super::write_record(&mut writer, record, hart, format);
+    writer.flush();
+  }
+}
@@ -0,0 +1,146 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains the sink that writes to the UART that serves as the console, see
+//! [`crate::arch::Console`].
+
+/// This lock ensure that simultaneous writers will be serialized when calling
+/// [`Uart::write_locked`]. The sink is initialized in a way that it has to be enabled
+/// explicitly later (when the UART itself has been discovered and initialized), as
+/// writing before that is undefined behavior.
+///
+/// The sink is enabled when the lock holds a UART. We have to store this information in
+/// the same [`spin::Mutex`] to avoid race-conditions later when locking the Mutex and
+/// checking whether the sink is enabled.
+///
+/// We also not introduce a new filed on [`Uart`] because we would need to mutate
+/// through a `&self` reference (to disable the sink is required) in
+/// [`super::Sink::write`], which is not allowed.
+///
+/// The lock is only taken with interrupts disabled. Traps that interrupted the kernel do
+/// not wait for the lock, as the interrupted code may hold it; their records are deferred
+/// (see `deferred`) if the lock is not free.
+static LOCK: spin::Mutex<Option<crate::arch::Console>> = spin::Mutex::new(None);
+
+/// Whether the records that were logged before the sink was enabled have been replayed.
+static REPLAYED: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+/// How often the lock is tried while the kernel panics before it is broken.
+const PANIC_ATTEMPTS: usize = 1_000_000;
+
+/// The sink that writes records to the UART that serves as the console.
+#[derive(Debug)]
+pub struct Uart;
+
+impl Uart {
+  /// Enables this sink, which writes to `uart` from now on. The records that were logged
+  /// before (and are still in the ring buffer) are replayed, see [`Uart::replay`].
+  pub(in crate::library::log) fn enable(uart: crate::arch::Console) { Self::replay_with(Some(uart)); }
+
+  /// Writes the records that are in the ring buffer to the UART with the level and format
+  /// the sink is registered with. This happens once, as soon as the sink is both enabled
+  /// and registered.
+  pub(in crate::library::log) fn replay() { Self::replay_with(None); }
+
+  /// Enables the sink with `uart` if it is not [`None`], and replays the records if this
+  /// has not happened yet and the sink is enabled and registered.
+  fn replay_with(uart: Option<crate::arch::Console>) {
+    let configuration = super::configuration(super::Sink::name(&Self));
+    crate::arch::without_interrupts(|| {
+      let mut lock = LOCK.lock();
+      if uart.is_some() {
+        *lock = uart;
+      }
+      let (Some(uart), Some((level, format))) = (lock.as_mut(), configuration) else {
+        return;
+      };
+      if !REPLAYED.swap(true, core::sync::atomic::Ordering::Relaxed) {
+        let _ = write_records(uart, 0, level, format);
+      }
+    });
+  }
+
+  /// Writes the deferred records and, if it is not [`None`], `record` (with the hart it
+  /// was logged on and its format) to the UART in `lock`. If writing fails, the sink is
+  /// disabled.
+  fn write_locked(
+    lock: &mut Option<crate::arch::Console>,
+    record: Option<(&log::Record, usize, super::Format)>,
+  ) {
+    let Some(uart) = lock.as_mut() else {
+      return;
+    };
+    super::deferred::drain(|byte| uart.put(byte));
+    if let Some((record, hart, format)) = record {
+      if super::write_record(uart, record, hart, format).is_err() {
+        *lock = None;
+      }
+    }
+  }
+}
+
+/// Writes the records from the ring buffer with a sequence number of at least `first`
+/// and up to `level` to `writer` in `format`.
+pub(in crate::library::log) fn write_records(
+  writer: &mut impl core::fmt::Write,
+  first: u64,
+  level: log::LevelFilter,
+  format: super::Format,
+) -> core::fmt::Result {
+  for entry in crate::library::log::records(first, crate::library::log::next_sequence()) {
+    if entry.level > level {
+      continue;
+    }
+    super::write_record(
+      writer,
+      &log::Record::builder()
+        .level(entry.level)
+        .target(entry.module())
+        .args(format_args!("{}", entry.message()))
+        .build(),
+      entry.hart,
+      format,
+    )?;
+  }
+  Ok(())
+}
+
+impl super::Sink for Uart {
+  fn name(&self) -> &'static str { "uart" }
+
+  fn write(&self, record: &log::Record, hart: usize, format: super::Format) {
+    if crate::arch::in_kernel_trap() {
+      match LOCK.try_lock() {
+        Some(mut lock) => Self::write_locked(&mut lock, Some((record, hart, format))),
+        None => super::deferred::defer(record, hart, format),
+      }
+    } else {
+      crate::arch::without_interrupts(|| Self::write_locked(&mut LOCK.lock(), Some((record, hart, format))));
+    }
+  }
+
+  fn write_in_panic(&self, record: &log::Record, hart: usize, format: super::Format) {
+    // The lock may be held by the code that panicked, or by another hart that is stuck;
+    // in this case, the lock is broken after a while so that the record gets out
+    let mut lock = (0..PANIC_ATTEMPTS)
+      .find_map(|_| {
+        let lock = LOCK.try_lock();
+        if lock.is_none() {
+          core::hint::spin_loop();
+        }
+        lock
+      })
+      .unwrap_or_else(|| {
+        unsafe { LOCK.force_unlock() };
+        LOCK.lock()
+      });
+    Self::write_locked(&mut lock, Some((record, hart, format)));
+  }
+
+  fn flush(&self) {
+    crate::arch::without_interrupts(|| {
+      if let Some(mut lock) = LOCK.try_lock() {
+        Self::write_locked(&mut lock, None);
+      }
+    });
+  }
+}
@@ -0,0 +1,175 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unit tests of the ring buffer, the filter and the sinks of the kernel log.
+
+use alloc::{
+  format,
+  string::String,
+};
+
+use super::{
+  filter,
+  next_sequence,
+  records,
+  ring,
+  sink,
+};
+
+#[test_case]
+fn records_are_kept_in_the_ring_buffer() {
+  let first = next_sequence();
+  let long = "x".repeat(300);
+  for message in ["ring buffer test", long.as_str()] {
+    ring::push(
+      &log::Record::builder()
+        .level(log::Level::Warn)
+        .module_path(Some("uncore::test"))
+        .args(format_args!("{message}"))
+        .build(),
+    );
+  }
+
+  let entries: alloc::vec::Vec<_> = records(first, next_sequence()).collect();
+  assert_eq!(entries.len(), 2);
+  assert_eq!(entries[0].level, log::Level::Warn);
+  assert_eq!(entries[0].module(), "uncore::test");
+  assert_eq!(entries[0].message(), "ring buffer test");
+  assert!(format!("{}", entries[0]).ends_with("WARN  0 uncore::test: ring buffer test"));
+  // Long messages are truncated
+  assert_eq!(entries[1].message().len(), 224 - "uncore::test".len());
+
+  // Records that are not (or no longer) in their slot are skipped
+  let end = next_sequence();
+  assert_eq!(records(0, end + ring::SLOTS as u64).count(), 0);
+}
+
+#[test_case]
+fn filter_directives_select_the_most_specific_level() {
+  use log::LevelFilter::{
+    Debug,
+    Off,
+    Trace,
+    Warn,
+  };
+
+  let mut filter = filter::Filter::new(log::LevelFilter::Info);
+  filter
+    .extend("uncore::mem=trace, warn,uncore::mem::slab@1=off,@2=debug,uncore::fs")
+    .unwrap();
+  assert_eq!(
+    format!("{filter}"),
+    "WARN,uncore::mem=TRACE,uncore::mem::slab@1=OFF,@2=DEBUG,uncore::fs=TRACE"
+  );
+
+  assert_eq!(filter.level("uncore::library::mem", 0), Trace);
+  assert_eq!(filter.level("uncore::library::mem::slab", 0), Trace);
+  assert_eq!(filter.level("uncore::library::mem::slab", 1), Off);
+  assert_eq!(filter.level("uncore::library::memory", 0), Warn);
+  assert_eq!(filter.level("uncore::library::sync", 2), Debug);
+  assert_eq!(filter.level("uncore::fs", 2), Trace);
+  assert_eq!(filter.maximum_level(), Trace);
+
+  assert_eq!(filter.extend("uncore=loud"), Err(filter::Error::InvalidLevel));
+  assert_eq!(filter.extend("@first=info"), Err(filter::Error::InvalidHart));
+}
+
+/// A sink that collects the records it writes.
+#[derive(Debug)]
+struct Collector(spin::Mutex<String>);
+
+impl sink::Sink for Collector {
+  fn name(&self) -> &'static str { "test" }
+
+  fn write(&self, record: &log::Record, hart: usize, format: sink::Format) {
+    let _ = sink::write_record(&mut *self.0.lock(), record, hart, format);
+  }
+}
+
+#[test_case]
+fn sinks_apply_their_own_level_and_format() {
+  static COLLECTOR: Collector = Collector(spin::Mutex::new(String::new()));
+
+  sink::register(&COLLECTOR, log::LevelFilter::Info, sink::Format::Plain).unwrap();
+  assert_eq!(
+    sink::register(&COLLECTOR, log::LevelFilter::Info, sink::Format::Plain),
+    Err(sink::Error::AlreadyRegistered)
+  );
+
+  for level in [log::Level::Debug, log::Level::Warn] {
+    sink::write(
+      &log::Record::builder()
+        .level(level)
+        .target("uncore::test")
+        .args(format_args!("sink test"))
+        .build(),
+      3,
+    );
+  }
+  assert!(COLLECTOR.0.lock().ends_with("WARN  3 uncore::test: sink test\n"));
+  assert!(!COLLECTOR.0.lock().contains("DEBUG"));
+
+  sink::configure("test", log::LevelFilter::Off, Some(sink::Format::Colored)).unwrap();
+  assert_eq!(
+    sink::configure("missing", log::LevelFilter::Off, None),
+    Err(sink::Error::NotFound)
+  );
+}
+
+#[test_case]
+fn records_are_written_as_json_lines() {
+  let mut line = String::new();
+  sink::write_record(
+    &mut line,
+    &log::Record::builder()
+      .level(log::Level::Error)
+      .target("uncore::test")
+      .file(Some("test.rs"))
+      .line(Some(7))
+      .args(format_args!("a \"quoted\"\tmessage\n"))
+      .build(),
+    1,
+    sink::Format::Json,
+  )
+  .unwrap();
+
+  let (uptime, rest) = line.split_once(',').unwrap();
+  assert!(uptime.starts_with("{\"uptime\":"));
+  assert_eq!(
+    rest,
+    "\"level\":\"ERROR\",\"hart\":1,\"module\":\"uncore::test\",\"file\":\"test.rs\",\"line\":7,\"message\":\
+     \"a \\\"quoted\\\"\\tmessage\\n\"}\n"
+  );
+}
+
+#[test_case]
+fn deferred_records_are_written_by_the_next_writer() {
+  sink::deferred::defer(
+    &log::Record::builder()
+      .level(log::Level::Info)
+      .target("uncore::test")
+      .args(format_args!("deferred test"))
+      .build(),
+    2,
+    sink::Format::Plain,
+  );
+
+  let mut drained = String::new();
+  sink::deferred::drain(|byte| drained.push(char::from(byte)));
+  assert!(drained.ends_with("INFO  2 uncore::test: deferred test\n"));
+
+  drained.clear();
+  sink::deferred::drain(|byte| drained.push(char::from(byte)));
+  assert!(drained.is_empty());
+}
+
+#[test_case]
+fn records_logged_before_the_console_are_replayed() {
+  let first = next_sequence();
+  log::warn!("logged before the console came up");
+  log::trace!("too verbose to be replayed");
+
+  let mut console = String::new();
+  sink::uart::write_records(&mut console, first, log::LevelFilter::Info, sink::Format::Plain).unwrap();
+  assert!(console.ends_with("logged before the console came up\n"));
+  assert!(!console.contains("too verbose"));
+}
@@ -0,0 +1,479 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains address spaces, which record which memory objects are mapped at
+//! which addresses.
+//!
+//! Every mapping is a virtual memory area: a page-aligned range of addresses together
+//! with the memory object it maps, the offset into the object and the [`Protection`].
+//! The areas of an address space never overlap and are kept in a B-tree that is ordered
+//! by their start address, so that the area containing an address is found in
+//! logarithmic time. Mapping over, unmapping or protecting a part of an area splits it.
+//!
+//! Shared mappings access the pages of the object, so that changes are visible through
+//! all mappings of the object and, for files, are written back. Private mappings copy a
+//! page when it is written first (copy-on-write), so that changes stay private.
+//!
+//! Address spaces do not fill page tables (see [`crate::arch::PageTable`]) yet, so
+//! mappings are not visible to programs. The kernel accesses them with
+//! [`AddressSpace::read`] and [`AddressSpace::write`], which resolve every page with
+//! [`AddressSpace::resolve`] as the page fault handler will. There are no processes yet,
+//! so system calls use the address space returned by [`current`].
+
+use alloc::{
+  collections::BTreeMap,
+  sync::Arc,
+  vec::Vec,
+};
+
+use super::{
+  object::{
+    Page,
+    VmObject,
+  },
+  Error,
+  Result,
+  PAGE_SIZE,
+};
+
+/// The lowest address that can be mapped.
+///
+/// Without the MMU, lower addresses refer to the physical memory of the machine: the MMIO
+/// regions and the RAM, which holds the kernel image, its heap and its stacks. QEMU's
+/// `virt` machine places its RAM at 2 GiB, so the RAM must not exceed 126 GiB for the
+/// ranges to stay apart.
+pub const USER_START: usize = 0x20_0000_0000;
+/// The end of the addresses that can be mapped, which is the end of the lower half of
+/// an Sv39 address space.
+pub const USER_END: usize = 0x40_0000_0000;
+
+/// Returns whether all of the `length` bytes at `address` can be mapped.
+///
+/// Only addresses from [`USER_START`] to [`USER_END`] can be mapped. Addresses outside
+/// may refer to memory of the kernel and must never be accessed on behalf of a program.
+#[must_use]
+pub const fn is_user_range(address: usize, length: usize) -> bool {
+  address >= USER_START && address <= USER_END && length <= USER_END - address
+}
+
+/// The accesses a mapping permits. The values match those of Linux (`PROT_READ` etc.).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Protection(u8);
+
+impl Protection {
+  /// Instructions may be fetched
+  pub const EXECUTE: Self = Self(0b100);
+  /// Data may be read
+  pub const READ: Self = Self(0b001);
+  /// Data may be written
+  pub const WRITE: Self = Self(0b010);
+
+  /// Creates a protection from its Linux representation, or returns [`None`] if unknown
+  /// bits are set.
+  #[must_use]
+  pub const fn from_bits(bits: usize) -> Option<Self> {
+    if bits & !0b111 != 0 {
+      return None;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    Some(Self(bits as u8))
+  }
+
+  /// Returns whether all accesses in `other` are permitted.
+  #[must_use]
+  pub const fn contains(self, other: Self) -> bool { self.0 & other.0 == other.0 }
+}
+
+impl core::ops::BitOr for Protection {
+  type Output = Self;
+
+  fn bitor(self, other: Self) -> Self { Self(self.0 | other.0) }
+}
+
+/// What is mapped by [`AddressSpace::map`].
+#[derive(Clone)]
+pub struct Mapping {
+  /// The memory object
+  pub object:     Arc<dyn VmObject>,
+  /// The offset into the object in bytes, which must be page-aligned
+  pub offset:     u64,
+  /// The accesses that are permitted
+  pub protection: Protection,
+  /// The accesses that [`AddressSpace::protect`] may permit, e.g. no writes to a shared
+  /// mapping of a file that was opened for reading only
+  pub maximum:    Protection,
+  /// Whether changes are visible through other mappings of the object
+  pub shared:     bool,
+}
+
+impl core::fmt::Debug for Mapping {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Mapping")
+      .field("offset", &self.offset)
+      .field("protection", &self.protection)
+      .field("maximum", &self.maximum)
+      .field("shared", &self.shared)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Where [`AddressSpace::map`] puts a mapping.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Placement {
+  /// At any free address
+  Anywhere,
+  /// At the given address if it is free, otherwise anywhere
+  Hint(usize),
+  /// At exactly the given address, replacing what is mapped there
+  Fixed(usize),
+}
+
+/// A virtual memory area.
+#[derive(Debug)]
+struct Area {
+  /// The first address
+  start:   usize,
+  /// The address after the last one
+  end:     usize,
+  /// What is mapped
+  mapping: Mapping,
+  /// The pages of a private mapping that were copied on write, indexed by the number of
+  /// the page in the object
+  copies:  BTreeMap<u64, Arc<Page>>,
+}
+
+impl Area {
+  /// Returns the number of the page of the object that is mapped at `address`.
+  const fn page_index(&self, address: usize) -> u64 {
+    (self.mapping.offset + (address - self.start) as u64) / PAGE_SIZE as u64
+  }
+
+  /// Shrinks the area to end at `at` and returns the area of the remaining addresses.
+  fn split_off(&mut self, at: usize) -> Self {
+    let copies = self.copies.split_off(&self.page_index(at));
+    let mut mapping = self.mapping.clone();
+    mapping.offset += (at - self.start) as u64;
+    let tail = Self {
+      start: at,
+      end: self.end,
+      mapping,
+      copies,
+    };
+    self.end = at;
+    tail
+  }
+}
+
+/// Returns `length` rounded up to whole pages, or an error if it is zero or too large.
+const fn page_length(length: usize) -> Result<usize> {
+  if length == 0 || length > USER_END {
+    return Err(Error::InvalidArgument);
+  }
+  Ok(length.next_multiple_of(PAGE_SIZE))
+}
+
+/// Returns the end of the range of `length` bytes at `address`, which must be
+/// page-aligned, if the range lies within the addresses that can be mapped.
+const fn range_end(address: usize, length: usize) -> Result<usize> {
+  let Ok(length) = page_length(length) else {
+    return Err(Error::InvalidArgument);
+  };
+  if address & (PAGE_SIZE - 1) != 0 || address < USER_START || address > USER_END - length {
+    return Err(Error::InvalidArgument);
+  }
+  Ok(address + length)
+}
+
+/// An address space, i.e. the areas that are mapped into the memory of a program.
+pub struct AddressSpace {
+  /// The areas, indexed by their start address
+  areas: spin::Mutex<BTreeMap<usize, Area>>,
+}
+
+impl AddressSpace {
+  /// Creates an address space without mappings.
+  #[must_use]
+  pub const fn new() -> Self {
+    Self {
+      areas: spin::Mutex::new(BTreeMap::new()),
+    }
+  }
+
+  /// Splits the area that contains `address` such that an area starts at `address`.
+  fn split_at(areas: &mut BTreeMap<usize, Area>, address: usize) {
+    let Some((_, area)) = areas.range_mut(..address).next_back() else {
+      return;
+    };
+    if area.end > address {
+      let tail = area.split_off(address);
+      areas.insert(address, tail);
+    }
+  }
+
+  /// Removes the addresses from `start` to `end` from the areas and returns the removed
+  /// areas.
+  fn carve(areas: &mut BTreeMap<usize, Area>, start: usize, end: usize) -> Vec<Area> {
+    Self::split_at(areas, start);
+    Self::split_at(areas, end);
+    let mut removed = Vec::new();
+    while let Some(&first) = areas.range(start..end).next().map(|(first, _)| first) {
+      removed.extend(areas.remove(&first));
+    }
+    removed
+  }
+
+  /// Returns whether no area contains an address from `start` to `end`.
+  fn is_free(areas: &BTreeMap<usize, Area>, start: usize, end: usize) -> bool {
+    areas
+      .range(..end)
+      .next_back()
+      .is_none_or(|(_, area)| area.end <= start)
+  }
+
+  /// Returns the highest free address at which `length` bytes fit.
+  fn find_free(areas: &BTreeMap<usize, Area>, length: usize) -> Result<usize> {
+    let mut top = USER_END;
+    for area in areas.values().rev() {
+      if top - area.end >= length {
+        return Ok(top - length);
+      }
+      top = area.start;
+    }
+    if top - USER_START >= length {
+      return Ok(top - length);
+    }
+    Err(Error::OutOfAddressSpace)
+  }
+
+  /// Maps `length` bytes of `mapping` at the address chosen according to `placement` and
+  /// returns this address. The length is rounded up to whole pages.
+  ///
+  /// #### Errors
+  ///
+  /// If the length is zero, an address or the offset is not page-aligned, or the
+  /// protection exceeds the maximum, [`Error::InvalidArgument`] is returned. If there is
+  /// no free range that is large enough, [`Error::OutOfAddressSpace`] is returned.
+  pub fn map(&self, placement: Placement, length: usize, mapping: Mapping) -> Result<usize> {
+    let length = page_length(length)?;
+    if mapping.offset & (PAGE_SIZE as u64 - 1) != 0 || !mapping.maximum.contains(mapping.protection) {
+      return Err(Error::InvalidArgument);
+    }
+
+    let mut areas = self.areas.lock();
+    let mut replaced = Vec::new();
+    let start = match placement {
+      Placement::Fixed(address) => {
+        replaced = Self::carve(&mut areas, address, range_end(address, length)?);
+        address
+      },
+      Placement::Hint(address) => match range_end(address, length) {
+        Ok(end) if Self::is_free(&areas, address, end) => address,
+        _ => Self::find_free(&areas, length)?,
+      },
+      Placement::Anywhere => Self::find_free(&areas, length)?,
+    };
+
+    areas.insert(
+      start,
+      Area {
+        start,
+        end: start + length,
+        mapping,
+        copies: BTreeMap::new(),
+      },
+    );
+    // Releasing replaced objects may write back files, which must not happen under the lock
+    drop(areas);
+    drop(replaced);
+    Ok(start)
+  }
+
+  /// Unmaps the `length` bytes at `address`. Addresses that are not mapped are skipped.
+  ///
+  /// #### Errors
+  ///
+  /// If the length is zero or the address is not page-aligned, [`Error::InvalidArgument`]
+  /// is returned.
+  pub fn unmap(&self, address: usize, length: usize) -> Result<()> {
+    let end = range_end(address, length)?;
+    let removed = Self::carve(&mut self.areas.lock(), address, end);
+    drop(removed);
+    Ok(())
+  }
+
+  /// Sets the protection of the `length` bytes at `address`.
+  ///
+  /// #### Errors
+  ///
+  /// If not all of the addresses are mapped, [`Error::NotMapped`] is returned. If the
+  /// protection exceeds the maximum of a mapping, [`Error::AccessDenied`] is returned.
+  pub fn protect(&self, address: usize, length: usize, protection: Protection) -> Result<()> {
+    let end = range_end(address, length)?;
+    let mut areas = self.areas.lock();
+
+    let mut covered = address;
+    for area in areas
+      .values()
+      .filter(|area| area.end > address && area.start < end)
+    {
+      if area.start > covered {
+        return Err(Error::NotMapped);
+      }
+      if !area.mapping.maximum.contains(protection) {
+        return Err(Error::AccessDenied);
+      }
+      covered = area.end;
+    }
+    if covered < end {
+      return Err(Error::NotMapped);
+    }
+
+    Self::split_at(&mut areas, address);
+    Self::split_at(&mut areas, end);
+    for area in areas.range_mut(address..end).map(|(_, area)| area) {
+      area.mapping.protection = protection;
+    }
+    Ok(())
+  }
+
+  /// Returns whether `address` is mapped.
+  #[must_use]
+  pub fn is_mapped(&self, address: usize) -> bool {
+    self
+      .areas
+      .lock()
+      .range(..=address)
+      .next_back()
+      .is_some_and(|(_, area)| area.end > address)
+  }
+
+  /// Returns the page that is mapped at `address` for an `access`. For writes to a
+  /// private mapping, the page is copied first.
+  ///
+  /// #### Errors
+  ///
+  /// If the address is not mapped, [`Error::NotMapped`] is returned. If the mapping does
+  /// not permit the access, [`Error::AccessDenied`] is returned.
+  pub fn resolve(&self, address: usize, access: Protection) -> Result<Arc<Page>> {
+    let mut areas = self.areas.lock();
+    let area = areas
+      .range_mut(..=address)
+      .next_back()
+      .map(|(_, area)| area)
+      .filter(|area| area.end > address)
+      .ok_or(Error::NotMapped)?;
+    if !area.mapping.protection.contains(access) {
+      return Err(Error::AccessDenied);
+    }
+
+    let index = area.page_index(address);
+    if area.mapping.shared {
+      return area.mapping.object.page(index);
+    }
+    if let Some(copy) = area.copies.get(&index) {
+      return Ok(copy.clone());
+    }
+
+    let page = area.mapping.object.page(index)?;
+    if !access.contains(Protection::WRITE) {
+      return Ok(page);
+    }
+    let copy = page.duplicate()?;
+    area.copies.insert(index, copy.clone());
+    Ok(copy)
+  }
+
+  /// Calls `copy` with every page the `length` bytes at `address` touch, the offset into
+  /// the page and the range of the bytes.
+  fn for_each_page(
+    &self,
+    address: usize,
+    length: usize,
+    access: Protection,
+    mut copy: impl FnMut(&Page, usize, core::ops::Range<usize>) -> Result<()>,
+  ) -> Result<()> {
+    let mut done = 0;
+    while done < length {
+      let current = address.checked_add(done).ok_or(Error::NotMapped)?;
+      let offset = current % PAGE_SIZE;
+      let chunk = (PAGE_SIZE - offset).min(length - done);
+      copy(&*self.resolve(current, access)?, offset, done..done + chunk)?;
+      done += chunk;
+    }
+    Ok(())
+  }
+
+  /// Copies the memory at `address` into `buffer`.
+  ///
+  /// #### Errors
+  ///
+  /// If a byte is not mapped or not readable, an error is returned.
+  pub fn read(&self, address: usize, buffer: &mut [u8]) -> Result<()> {
+    self.for_each_page(address, buffer.len(), Protection::READ, |page, offset, range| {
+      page.read(offset, &mut buffer[range])
+    })
+  }
+
+  /// Copies `buffer` to the memory at `address`.
+  ///
+  /// #### Errors
+  ///
+  /// If a byte is not mapped or not writable, an error is returned.
+  pub fn write(&self, address: usize, buffer: &[u8]) -> Result<()> {
+    self.for_each_page(address, buffer.len(), Protection::WRITE, |page, offset, range| {
+      page.write(offset, &buffer[range])
+    })
+  }
+
+  /// Writes the changes to the shared mappings among the `length` bytes at `address` back
+  /// to their objects, e.g. to files.
+  ///
+  /// #### Errors
+  ///
+  /// If not all of the addresses are mapped, [`Error::NotMapped`] is returned. If the
+  /// changes cannot be written, an error is returned.
+  pub fn sync(&self, address: usize, length: usize) -> Result<()> {
+    let end = range_end(address, length)?;
+    let areas = self.areas.lock();
+
+    let mut covered = address;
+    let mut objects = Vec::new();
+    for area in areas
+      .values()
+      .filter(|area| area.end > address && area.start < end)
+    {
+      if area.start > covered {
+        return Err(Error::NotMapped);
+      }
+      if area.mapping.shared {
+        objects.push(area.mapping.object.clone());
+      }
+      covered = area.end;
+    }
+    drop(areas);
+    if covered < end {
+      return Err(Error::NotMapped);
+    }
+
+    objects.iter().try_for_each(|object| object.write_back())
+  }
+}
+
+impl Default for AddressSpace {
+  fn default() -> Self { Self::new() }
+}
+
+impl core::fmt::Debug for AddressSpace {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("AddressSpace")
+      .field("areas", &self.areas.lock().len())
+      .finish()
+  }
+}
+
+/// The address space the kernel uses until there are processes.
+static KERNEL_SPACE: AddressSpace = AddressSpace::new();
+
+/// Returns the address space of the running process. There are no processes yet, so
+/// this is always the address space of the kernel.
+#[must_use]
+pub fn current() -> &'static AddressSpace { &KERNEL_SPACE }
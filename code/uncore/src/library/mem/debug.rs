@@ -0,0 +1,438 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains the debugging allocator, which checks the kernel heap for
+//! corruption. It is used instead of the plain [`super::slab`] allocator when the
+//! `heap-debug` feature is enabled.
+//!
+//! Every allocation is preceded by a header and surrounded by red zones:
+//!
+//! ```text
+//! | header | red zone | data (filled with UNINITIALIZED) | red zone |
+//! ```
+//!
+//! The header records the layout and the caller of the allocation and links all live
+//! allocations, so that leaks can be reported. The caller is the first frame of the
+//! backtrace that belongs neither to the allocator, nor to the allocator shim of the
+//! compiler, nor to the `alloc` library (see [`caller`]). When an allocation is freed,
+//! the red zones are verified and the data is poisoned. Freed allocations are then kept
+//! in a quarantine for a while: a second free of the same allocation is detected as a
+//! double free, and writes to freed data are detected when it leaves the quarantine.
+
+use alloc::vec::Vec;
+use core::{
+  alloc::{
+    GlobalAlloc,
+    Layout,
+  },
+  ptr::NonNull,
+};
+
+/// The byte new allocations are filled with.
+const UNINITIALIZED: u8 = 0x5A;
+/// The byte freed allocations are filled with.
+const FREED: u8 = 0x6B;
+/// The byte red zones are filled with.
+const RED_ZONE: u8 = 0xBB;
+/// The minimum size of a red zone in bytes.
+const RED_ZONE_SIZE: usize = 16;
+/// The number of freed allocations that are kept before their memory is reused.
+const QUARANTINE_SIZE: usize = 64;
+
+/// The prefixes of the names of functions that are skipped to find the caller of an
+/// allocation: the allocator shim, the `alloc` library, this allocator and the fallible
+/// allocation functions of [`super::heap`].
+const ALLOCATOR_FUNCTIONS: [&str; 7] = [
+  "__rust",
+  "alloc::",
+  "<alloc::",
+  "core::alloc::",
+  "uncore::library::mem::debug::",
+  "<uncore::library::mem::debug::",
+  "uncore::library::mem::heap::try_",
+];
+
+/// The state of an allocation that is live.
+const STATE_ALLOCATED: u64 = 0xA110_CA7E_DA11_0CA7;
+/// The state of an allocation that was freed and is in the quarantine.
+const STATE_FREED: u64 = 0xF7EE_DF7E_EDF7_EEDF;
+
+/// The header in front of every allocation.
+#[repr(C)]
+struct Header {
+  /// Either [`STATE_ALLOCATED`] or [`STATE_FREED`]
+  state:    u64,
+  /// The size of the data
+  size:     usize,
+  /// The offset of the data from the header
+  offset:   usize,
+  /// The address the allocation was made from
+  caller:   usize,
+  /// The previous live allocation
+  previous: Option<NonNull<Self>>,
+  /// The next live allocation
+  next:     Option<NonNull<Self>>,
+}
+
+/// An allocation as reported by [`Allocator::live`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Allocation {
+  /// The address of the data
+  pub address: usize,
+  /// The size of the data in bytes
+  pub size:    usize,
+  /// The address the allocation was made from
+  pub caller:  usize,
+}
+
+/// The kinds of heap corruption the debugging allocator detects.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Corruption {
+  /// The allocation was freed before.
+  DoubleFree,
+  /// The pointer does not point to an allocation, or its header was overwritten.
+  InvalidPointer,
+  /// The allocation was freed with a different size than it was allocated with.
+  SizeMismatch,
+  /// The red zone in front of the data was overwritten.
+  RedZoneBefore,
+  /// The red zone behind the data was overwritten.
+  RedZoneAfter,
+  /// The data was written after the allocation had been freed.
+  UseAfterFree,
+}
+
+impl core::fmt::Display for Corruption {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::DoubleFree => write!(f, "double free"),
+      Self::InvalidPointer => write!(f, "pointer to no allocation"),
+      Self::SizeMismatch => write!(f, "freed with the wrong size"),
+      Self::RedZoneBefore => write!(f, "red zone before the data overwritten"),
+      Self::RedZoneAfter => write!(f, "red zone after the data overwritten"),
+      Self::UseAfterFree => write!(f, "written after it was freed"),
+    }
+  }
+}
+
+/// The placement of an allocation with a given layout within its block.
+#[derive(Copy, Clone)]
+struct Block {
+  /// The layout of the block in the underlying allocator
+  layout: Layout,
+  /// The offset of the data in the block
+  data:   usize,
+}
+
+impl Block {
+  /// Returns the block that holds an allocation with `layout`.
+  fn new(layout: Layout) -> Option<Self> {
+    let align = layout.align().max(core::mem::align_of::<Header>());
+    let data = (core::mem::size_of::<Header>() + RED_ZONE_SIZE).checked_next_multiple_of(align)?;
+    let size = data.checked_add(layout.size())?.checked_add(RED_ZONE_SIZE)?;
+    Some(Self {
+      layout: Layout::from_size_align(size, align).ok()?,
+      data,
+    })
+  }
+
+  /// Returns the ranges of the red zones relative to the start of the block.
+  const fn red_zones(self) -> [core::ops::Range<usize>; 2] {
+    let end = self.layout.size();
+    [
+      core::mem::size_of::<Header>()..self.data,
+      end - RED_ZONE_SIZE..end,
+    ]
+  }
+}
+
+/// Fills `range` of the block at `start` with `byte`.
+unsafe fn fill(start: NonNull<u8>, range: core::ops::Range<usize>, byte: u8) {
+  unsafe { core::ptr::write_bytes(start.as_ptr().add(range.start), byte, range.len()) };
+}
+
+/// Returns whether `range` of the block at `start` holds only `byte`.
+unsafe fn holds(start: NonNull<u8>, range: core::ops::Range<usize>, byte: u8) -> bool {
+  unsafe { core::slice::from_raw_parts(start.as_ptr().add(range.start), range.len()) }
+    .iter()
+    .all(|&value| value == byte)
+}
+
+/// The live allocations as a list through their headers.
+struct LiveList {
+  /// The allocation that was made last
+  head:   Option<NonNull<Header>>,
+  /// The number of allocations in the list
+  length: usize,
+}
+
+/// The allocations that were freed last, as a ring buffer of their blocks.
+struct Quarantine {
+  /// The blocks and their layouts
+  blocks: [Option<(NonNull<u8>, Layout)>; QUARANTINE_SIZE],
+  /// The index of the slot the next block goes to
+  next:   usize,
+}
+
+// The headers and blocks are only accessed behind the locks of the allocator
+unsafe impl Send for LiveList {}
+unsafe impl Send for Quarantine {}
+
+/// The debugging allocator. It serves allocations from the slab allocator and
+/// implements [`GlobalAlloc`].
+pub struct Allocator {
+  /// The allocator the blocks come from
+  inner:      super::slab::Allocator,
+  /// The live allocations
+  live:       spin::Mutex<LiveList>,
+  /// The allocations that were freed last
+  quarantine: spin::Mutex<Quarantine>,
+}
+
+impl Allocator {
+  /// Creates an allocator without memory. [`Allocator::initialize`] provides the memory.
+  #[must_use]
+  pub const fn empty() -> Self {
+    Self {
+      inner:      super::slab::Allocator::empty(),
+      live:       spin::Mutex::new(LiveList {
+        head:   None,
+        length: 0,
+      }),
+      quarantine: spin::Mutex::new(Quarantine {
+        blocks: [None; QUARANTINE_SIZE],
+        next:   0,
+      }),
+    }
+  }
+
+  /// Provides the allocator with the `size` bytes at `start`.
+  ///
+  /// #### Safety
+  ///
+  /// The memory must be valid, unused and must not be provided twice.
+  pub unsafe fn initialize(&self, start: *mut u8, size: usize) {
+    unsafe { self.inner.initialize(start, size) }
+  }
+
+  /// Returns the current usage statistics of the underlying allocator, which include
+  /// the headers, red zones and the quarantine.
+  #[must_use]
+  pub fn statistics(&self) -> super::slab::Statistics { self.inner.statistics() }
+
+  /// Like [`Allocator::statistics`], but returns [`None`] instead of waiting if the
+  /// underlying allocator is in use.
+  #[must_use]
+  pub fn try_statistics(&self) -> Option<super::slab::Statistics> { self.inner.try_statistics() }
+
+  /// Returns the live allocations, the one that was made last first.
+  #[must_use]
+  pub fn live(&self) -> Vec<Allocation> {
+    // The vector is allocated before the list is locked, as allocating locks it as well.
+    // If allocations were made in the meantime, the vector is allocated again.
+    let mut capacity = crate::arch::without_interrupts(|| self.live.lock().length);
+    loop {
+      let mut allocations = Vec::with_capacity(capacity + 1);
+      let complete = crate::arch::without_interrupts(|| {
+        let live = self.live.lock();
+        if live.length > allocations.capacity() {
+          capacity = live.length;
+          return false;
+        }
+        let mut header = live.head;
+        while let Some(current) = header {
+          let current = unsafe { current.as_ref() };
+          allocations.push(Allocation {
+            address: core::ptr::from_ref(current).addr() + current.offset,
+            size:    current.size,
+            caller:  current.caller,
+          });
+          header = current.next;
+        }
+        true
+      });
+      if complete {
+        return allocations;
+      }
+    }
+  }
+
+  /// Allocates `layout` on behalf of the code at `caller`.
+  fn allocate(&self, layout: Layout, caller: usize) -> Option<NonNull<u8>> {
+    let block = Block::new(layout)?;
+    let start = NonNull::new(unsafe { self.inner.alloc(block.layout) })?;
+
+    unsafe {
+      for range in block.red_zones() {
+        fill(start, range, RED_ZONE);
+      }
+      fill(start, block.data..block.data + layout.size(), UNINITIALIZED);
+    }
+
+    let header = start.cast::<Header>();
+    crate::arch::without_interrupts(|| {
+      let mut live = self.live.lock();
+      unsafe {
+        header.write(Header {
+          state: STATE_ALLOCATED,
+          size: layout.size(),
+          offset: block.data,
+          caller,
+          previous: None,
+          next: live.head,
+        });
+        if let Some(mut next) = live.head {
+          next.as_mut().previous = Some(header);
+        }
+      }
+      live.head = Some(header);
+      live.length += 1;
+    });
+    Some(unsafe { start.add(block.data) })
+  }
+
+  /// Checks and frees the allocation of `layout` at `data`. The allocation is moved into
+  /// the quarantine; the allocation that leaves the quarantine in turn is checked for
+  /// writes after it was freed and given back to the underlying allocator.
+  ///
+  /// #### Errors
+  ///
+  /// If the heap is corrupted, the kind of corruption and the address of the affected
+  /// allocation are returned. The allocation is not freed then.
+  pub(super) fn release(&self, data: NonNull<u8>, layout: Layout) -> Result<(), (Corruption, usize)> {
+    let address = data.as_ptr().addr();
+    let block = Block::new(layout).ok_or((Corruption::InvalidPointer, address))?;
+    let start =
+      NonNull::new(data.as_ptr().wrapping_sub(block.data)).ok_or((Corruption::InvalidPointer, address))?;
+    let mut header = start.cast::<Header>();
+
+    crate::arch::without_interrupts(|| {
+      let mut live = self.live.lock();
+      let header = unsafe { header.as_mut() };
+      match header.state {
+        STATE_ALLOCATED => (),
+        STATE_FREED => return Err((Corruption::DoubleFree, address)),
+        _ => return Err((Corruption::InvalidPointer, address)),
+      }
+      if header.size != layout.size() {
+        return Err((Corruption::SizeMismatch, address));
+      }
+      let [before, after] = block.red_zones();
+      if !unsafe { holds(start, before, RED_ZONE) } {
+        return Err((Corruption::RedZoneBefore, address));
+      }
+      if !unsafe { holds(start, after, RED_ZONE) } {
+        return Err((Corruption::RedZoneAfter, address));
+      }
+
+      match header.previous {
+        Some(mut previous) => unsafe { previous.as_mut().next = header.next },
+        None => live.head = header.next,
+      }
+      if let Some(mut next) = header.next {
+        unsafe { next.as_mut().previous = header.previous };
+      }
+      live.length -= 1;
+      header.state = STATE_FREED;
+      unsafe { fill(start, block.data..block.data + layout.size(), FREED) };
+      Ok(())
+    })?;
+
+    let evicted = crate::arch::without_interrupts(|| {
+      let mut quarantine = self.quarantine.lock();
+      let slot = quarantine.next;
+      quarantine.next = (slot + 1) % QUARANTINE_SIZE;
+      quarantine.blocks[slot].replace((start, block.layout))
+    });
+    let Some((start, layout)) = evicted else {
+      return Ok(());
+    };
+
+    let (offset, size) = {
+      let header = unsafe { start.cast::<Header>().as_ref() };
+      (header.offset, header.size)
+    };
+    let intact = unsafe {
+      holds(start, offset..offset + size, FREED)
+        && holds(start, offset + size..offset + size + RED_ZONE_SIZE, RED_ZONE)
+    };
+    unsafe { self.inner.dealloc(start.as_ptr(), layout) };
+    if intact {
+      Ok(())
+    } else {
+      Err((Corruption::UseAfterFree, start.as_ptr().addr() + offset))
+    }
+  }
+}
+
+impl core::fmt::Debug for Allocator {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Allocator")
+      .field("inner", &self.inner)
+      .field(
+        "live",
+        &crate::arch::without_interrupts(|| self.live.lock().length),
+      )
+      .finish_non_exhaustive()
+  }
+}
+
+/// Returns the address of the code that requested an allocation: the first return
+/// address of the backtrace whose function is not one of [`ALLOCATOR_FUNCTIONS`].
+///
+/// Without a symbol table, the functions cannot be told apart, and the address the
+/// allocator was called from is returned, which may be in the allocator shim.
+#[inline(never)]
+fn caller() -> usize {
+  let mut frames = crate::arch::backtrace();
+  let Some(table) = crate::library::backtrace::SymbolTable::kernel().filter(|table| !table.is_empty()) else {
+    return frames.nth(1).unwrap_or(0);
+  };
+  frames
+    .find(|&address| {
+      !table
+        .lookup(address - 1)
+        .is_some_and(|(name, _)| ALLOCATOR_FUNCTIONS.iter().any(|prefix| name.starts_with(prefix)))
+    })
+    .unwrap_or(0)
+}
+
+unsafe impl GlobalAlloc for Allocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    self
+      .allocate(layout, caller())
+      .map_or(core::ptr::null_mut(), NonNull::as_ptr)
+  }
+
+  unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+    let Some(data) = self.allocate(layout, caller()) else {
+      return core::ptr::null_mut();
+    };
+    unsafe { core::ptr::write_bytes(data.as_ptr(), 0, layout.size()) };
+    data.as_ptr()
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    let Some(data) = NonNull::new(ptr) else {
+      return;
+    };
+    if let Err((corruption, address)) = self.release(data, layout) {
+      panic!("heap corruption at {address:#x}: {corruption}");
+    }
+  }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    let caller = caller();
+    let Some(new) = Layout::from_size_align(new_size, layout.align())
+      .ok()
+      .and_then(|new_layout| self.allocate(new_layout, caller))
+    else {
+      return core::ptr::null_mut();
+    };
+
+    unsafe {
+      core::ptr::copy_nonoverlapping(ptr, new.as_ptr(), layout.size().min(new_size));
+      self.dealloc(ptr, layout);
+    }
+    new.as_ptr()
+  }
+}
@@ -1,20 +1,86 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 //! This module holds all functionality required for working with the kernel heap.
+//!
+//! When the heap is exhausted, the out-of-memory [`Policy`] decides whether a failed
+//! allocation is retried; the default policy frees memory that subsystems registered as
+//! reclaimable (see [`register_reclaimer`]) first. If the allocation fails for good,
+//! fallible allocations ([`try_box`], [`try_vec`] and `try_reserve`) return an error,
+//! while all other allocations end in the allocation error handler, which logs the state
+//! of the heap and panics.
+
+use alloc::{
+  boxed::Box,
+  vec::Vec,
+};
+use core::alloc::Layout;
+
+use super::{
+  Error,
+  Result,
+};
 
 /// This is the global kernel heap allocator. It implements
 /// [`core::alloc::GlobalAlloc`].
 ///
-/// To manage the heap, we use the [`linked_list_allocator`] crate. This is an easy yet
-/// performant allocator for `#![no_std]` binaries like our kernel.
+/// To manage the heap, we use the slab allocator in [`super::slab`]: small allocations
+/// are served from per-size caches and per-hart magazines, large allocations from the
+/// pages of the heap directly.
+///
+/// With the `heap-debug` feature, the debugging allocator in `super::debug` wraps the
+/// slab allocator and checks the heap for corruption.
+#[cfg(not(feature = "heap-debug"))]
 #[global_allocator]
-static ALLOCATOR: linked_list_allocator::LockedHeap = linked_list_allocator::LockedHeap::empty();
+static ALLOCATOR: super::slab::Allocator = super::slab::Allocator::empty();
+
+/// This is the global kernel heap allocator with the `heap-debug` feature.
+#[cfg(feature = "heap-debug")]
+#[global_allocator]
+static ALLOCATOR: super::debug::Allocator = super::debug::Allocator::empty();
+
+/// What to do about an allocation that failed because the heap is exhausted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Decision {
+  /// Try the allocation again, e.g. because memory was freed.
+  Retry,
+  /// Give up; the allocation fails.
+  Fail,
+}
+
+/// A policy that decides what to do when the heap is exhausted.
+///
+/// It is called with the layout of the failed allocation and the number of times the
+/// allocation was retried already. Before it decides to retry, it frees memory, e.g. by
+/// reclaiming caches or by terminating a program.
+///
+/// The policy runs in the middle of an allocation, so it must not allocate itself and
+/// must not wait for locks that may be held while allocating.
+pub type Policy = fn(Layout, usize) -> Decision;
+
+/// A function that frees memory a subsystem can do without (e.g. cached data that can be
+/// read again) and returns the number of bytes freed. The same restrictions as for a
+/// [`Policy`] apply.
+pub type Reclaimer = fn() -> usize;
+
+/// The number of reclaimers that can be registered.
+const MAXIMUM_RECLAIMERS: usize = 8;
+
+/// The current out-of-memory policy.
+static POLICY: spin::Mutex<Policy> = spin::Mutex::new(default_policy);
+
+/// The registered reclaimers.
+static RECLAIMERS: spin::Mutex<[Option<Reclaimer>; MAXIMUM_RECLAIMERS]> =
+  spin::Mutex::new([None; MAXIMUM_RECLAIMERS]);
 
 /// Checks whether [`Heap::initialize`] has been called before.
 static mut INIT_WAS_CALLED: bool = false;
 
+/// Usage statistics of the kernel heap.
+pub use super::slab::Statistics;
+
 /// This data structure represents the kernel heap.
 #[allow(clippy::module_name_repetitions)]
+#[derive(Debug)]
 pub struct Heap {
   /// The starting address of the heap
   start: *mut u8,
@@ -26,13 +92,17 @@ impl Heap {
   /// Constructs a new instance of [`Heap`].
   fn new() -> Self {
     Self {
-      start: crate::arch::heap::get_start(),
-      size:  crate::arch::heap::get_size(),
+      start: crate::arch::heap_start(),
+      size:  crate::arch::heap_size(),
     }
   }
 
-  /// Initialize the kernel heap by providing the allocator [`ALLOCATOR`] with a start
+  /// Initialize the kernel heap by providing the allocator `ALLOCATOR` with a start
   /// address and a size.
+  ///
+  /// #### Panics
+  ///
+  /// If the heap has been initialized before, this function panics.
   pub fn initialize() {
     assert!(
       unsafe { !INIT_WAS_CALLED },
@@ -47,7 +117,136 @@ pub fn initialize() {
     let heap = Self::new();
 
     unsafe {
-      ALLOCATOR.lock().init(heap.start, heap.size);
+      ALLOCATOR.initialize(heap.start, heap.size);
     }
   }
+
+  /// Returns the current usage statistics of the kernel heap.
+  #[must_use]
+  pub fn statistics() -> Statistics { ALLOCATOR.statistics() }
+
+  /// Like [`Heap::statistics`], but returns [`None`] instead of waiting if the heap is in
+  /// use, e.g. because the kernel crashed while allocating.
+  #[must_use]
+  pub fn try_statistics() -> Option<Statistics> { ALLOCATOR.try_statistics() }
+
+  /// Returns the live allocations of the kernel heap, the one that was made last first.
+  #[cfg(feature = "heap-debug")]
+  #[must_use]
+  pub fn live() -> Vec<super::debug::Allocation> { ALLOCATOR.live() }
+
+  /// Logs the allocations that are live, e.g. when the kernel exits, to find leaks.
+  #[cfg(feature = "heap-debug")]
+  pub fn report_leaks() {
+    let allocations = Self::live();
+    let bytes: usize = allocations.iter().map(|allocation| allocation.size).sum();
+    log::info!("{} live heap allocations with {bytes} bytes", allocations.len());
+    for allocation in allocations {
+      log::info!(
+        "  {:>8} bytes at {:#x}, allocated from {}",
+        allocation.size,
+        allocation.address,
+        crate::library::backtrace::CodeAddress(allocation.caller)
+      );
+    }
+  }
+}
+
+/// Replaces the out-of-memory policy with `policy` and returns the previous policy.
+pub fn set_policy(policy: Policy) -> Policy { core::mem::replace(&mut POLICY.lock(), policy) }
+
+/// Registers `reclaimer`, which the default out-of-memory policy calls when the heap is
+/// exhausted.
+pub fn register_reclaimer(reclaimer: Reclaimer) {
+  let mut reclaimers = RECLAIMERS.lock();
+  if let Some(slot) = reclaimers.iter_mut().find(|slot| slot.is_none()) {
+    *slot = Some(reclaimer);
+  } else {
+    log::warn!("Could not register a heap reclaimer: all {MAXIMUM_RECLAIMERS} slots are used");
+  }
+}
+
+/// Calls all registered reclaimers and returns the number of bytes they freed.
+pub fn reclaim() -> usize {
+  let reclaimers = *RECLAIMERS.lock();
+  reclaimers.iter().flatten().map(|reclaimer| reclaimer()).sum()
+}
+
+/// The default out-of-memory policy: as long as the registered reclaimers free memory,
+/// the allocation is retried, up to three times.
+#[must_use]
+pub fn default_policy(_layout: Layout, retries: usize) -> Decision {
+  if retries < 3 && reclaim() != 0 {
+    Decision::Retry
+  } else {
+    Decision::Fail
+  }
+}
+
+/// Asks the out-of-memory policy what to do about the allocation with `layout` that
+/// failed after `retries` retries.
+pub(super) fn out_of_memory(layout: Layout, retries: usize) -> Decision {
+  let policy = *POLICY.lock();
+  policy(layout, retries)
+}
+
+/// Moves `value` into a new [`Box`], like [`Box::new`], but returns an error instead of
+/// calling the allocation error handler if the heap is exhausted.
+///
+/// #### Errors
+///
+/// If the memory cannot be allocated, [`Error::OutOfMemory`] is returned.
+pub fn try_box<T>(value: T) -> Result<Box<T>> {
+  let layout = Layout::new::<T>();
+  if layout.size() == 0 {
+    return Ok(Box::new(value));
+  }
+
+  let pointer = unsafe { alloc::alloc::alloc(layout) }.cast::<T>();
+  if pointer.is_null() {
+    return Err(Error::OutOfMemory);
+  }
+  unsafe {
+    pointer.write(value);
+    Ok(Box::from_raw(pointer))
+  }
+}
+
+/// Returns a vector of `length` clones of `value`, like `vec![value; length]`, but
+/// returns an error instead of calling the allocation error handler if the heap is
+/// exhausted.
+///
+/// #### Errors
+///
+/// If the memory cannot be allocated, [`Error::OutOfMemory`] is returned.
+pub fn try_vec<T: Clone>(value: T, length: usize) -> Result<Vec<T>> {
+  let mut vector = Vec::new();
+  vector.try_reserve_exact(length)?;
+  vector.resize(length, value);
+  Ok(vector)
+}
+
+/// Handles an allocation that failed for good: the requested layout and the state of the
+/// heap are logged before the kernel panics.
+#[alloc_error_handler]
+fn allocation_failed(layout: Layout) -> ! {
+  let heap = Heap::statistics();
+  log::error!(
+    "Out of memory: could not allocate {} bytes aligned to {} bytes",
+    layout.size(),
+    layout.align()
+  );
+  log::error!(
+    "Heap: {} of {} bytes used ({} bytes allocated), {} bytes free",
+    heap.used,
+    heap.size,
+    heap.allocated,
+    heap.free
+  );
+  log::error!(
+    "Fragmentation: the largest free block has {} bytes ({}% of the free memory is in smaller blocks)",
+    heap.largest_free,
+    heap.fragmentation()
+  );
+  panic!("out of memory");
 }
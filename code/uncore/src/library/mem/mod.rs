@@ -1,5 +1,63 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 //! This is the module file for the memory subsystem of `unCORE`.
+//!
+//! Besides the kernel [`heap`], which the [`slab`] allocator serves (checked by the
+//! debugging allocator with the `heap-debug` feature), it contains the memory that
+//! programs map: pages are provided by memory objects ([`object`]), e.g. anonymous
+//! memory or files through the page cache ([`crate::library::fs::cache`]), and mapped
+//! into address spaces ([`address_space`]).
 
+pub mod address_space;
+#[cfg(feature = "heap-debug")]
+pub mod debug;
 pub mod heap;
+pub mod object;
+pub mod slab;
+
+#[cfg(test)]
+mod tests;
+
+/// The size of a page in bytes.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Errors that operations on memory objects and address spaces may return.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+  /// An argument (e.g. an address that is not page-aligned) is invalid.
+  InvalidArgument,
+  /// The address is not mapped.
+  NotMapped,
+  /// There is no free range of addresses that is large enough.
+  OutOfAddressSpace,
+  /// The protection of the mapping does not permit the access.
+  AccessDenied,
+  /// A page could not be allocated.
+  OutOfMemory,
+  /// The file that backs the memory could not be accessed.
+  File(crate::library::fs::Error),
+}
+
+impl core::fmt::Display for Error {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::InvalidArgument => write!(f, "invalid argument"),
+      Self::NotMapped => write!(f, "address not mapped"),
+      Self::OutOfAddressSpace => write!(f, "address space exhausted"),
+      Self::AccessDenied => write!(f, "access denied"),
+      Self::OutOfMemory => write!(f, "out of memory"),
+      Self::File(error) => write!(f, "file error: {error}"),
+    }
+  }
+}
+
+impl From<crate::library::fs::Error> for Error {
+  fn from(error: crate::library::fs::Error) -> Self { Self::File(error) }
+}
+
+impl From<alloc::collections::TryReserveError> for Error {
+  fn from(_: alloc::collections::TryReserveError) -> Self { Self::OutOfMemory }
+}
+
+/// The result type of operations on memory objects and address spaces.
+pub type Result<T> = core::result::Result<T, Error>;
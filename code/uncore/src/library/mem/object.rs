@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains memory objects, which provide the pages that are mapped into
+//! address spaces.
+//!
+//! A memory object implements [`VmObject`] and hands out its pages by index. Pages are
+//! created on first use, so that large mappings only cost memory for the pages that are
+//! accessed. [`Anonymous`] memory starts zeroed; files are memory objects through the
+//! page cache ([`crate::library::fs::cache`]).
+
+use alloc::{
+  collections::BTreeMap,
+  sync::Arc,
+};
+use core::{
+  ptr::NonNull,
+  sync::atomic::{
+    AtomicBool,
+    Ordering,
+  },
+};
+
+use super::{
+  Error,
+  Result,
+  PAGE_SIZE,
+};
+
+/// A page of memory that is allocated from the kernel heap. Mappings of the page may
+/// access it at any time, so its content is only read and written through copies.
+pub struct Page {
+  /// The first byte of the page
+  address: NonNull<u8>,
+  /// Whether the page was written since [`Page::take_dirty`] was called
+  dirty:   AtomicBool,
+}
+
+// The page is plain memory that is only accessed by copying bytes in and out
+unsafe impl Send for Page {}
+unsafe impl Sync for Page {}
+
+impl Page {
+  /// The layout of a page on the heap.
+  const LAYOUT: core::alloc::Layout = match core::alloc::Layout::from_size_align(PAGE_SIZE, PAGE_SIZE) {
+    Ok(layout) => layout,
+    Err(_) => panic!("the page size is not a power of two"),
+  };
+
+  /// Allocates a zeroed page.
+  ///
+  /// #### Errors
+  ///
+  /// If the page cannot be allocated, [`Error::OutOfMemory`] is returned.
+  pub fn new() -> Result<Arc<Self>> {
+    let address =
+      NonNull::new(unsafe { alloc::alloc::alloc_zeroed(Self::LAYOUT) }).ok_or(Error::OutOfMemory)?;
+    Ok(Arc::new(Self {
+      address,
+      dirty: AtomicBool::new(false),
+    }))
+  }
+
+  /// Returns the address of the first byte of the page.
+  #[must_use]
+  pub fn address(&self) -> usize { self.address.as_ptr().addr() }
+
+  /// Returns the range of the page that `length` bytes at `offset` cover.
+  fn range(offset: usize, length: usize) -> Result<core::ops::Range<usize>> {
+    let end = offset.checked_add(length).ok_or(Error::InvalidArgument)?;
+    if end > PAGE_SIZE {
+      return Err(Error::InvalidArgument);
+    }
+    Ok(offset..end)
+  }
+
+  /// Copies the bytes at `offset` into `buffer`.
+  ///
+  /// #### Errors
+  ///
+  /// If the bytes are not within the page, [`Error::InvalidArgument`] is returned.
+  pub fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<()> {
+    let range = Self::range(offset, buffer.len())?;
+    unsafe {
+      core::ptr::copy_nonoverlapping(
+        self.address.as_ptr().add(range.start),
+        buffer.as_mut_ptr(),
+        range.len(),
+      );
+    }
+    Ok(())
+  }
+
+  /// Copies `buffer` to `offset` and marks the page dirty.
+  ///
+  /// #### Errors
+  ///
+  /// If the bytes are not within the page, [`Error::InvalidArgument`] is returned.
+  pub fn write(&self, offset: usize, buffer: &[u8]) -> Result<()> {
+    let range = Self::range(offset, buffer.len())?;
+    unsafe {
+      core::ptr::copy_nonoverlapping(
+        buffer.as_ptr(),
+        self.address.as_ptr().add(range.start),
+        range.len(),
+      );
+    }
+    self.dirty.store(true, Ordering::Release);
+    Ok(())
+  }
+
+  /// Returns a new page with the same content, as done when a private mapping is written.
+  ///
+  /// #### Errors
+  ///
+  /// If the page cannot be allocated, [`Error::OutOfMemory`] is returned.
+  pub fn duplicate(&self) -> Result<Arc<Self>> {
+    let copy = Self::new()?;
+    unsafe { core::ptr::copy_nonoverlapping(self.address.as_ptr(), copy.address.as_ptr(), PAGE_SIZE) };
+    Ok(copy)
+  }
+
+  /// Returns whether the page was written since [`Page::take_dirty`] was called.
+  #[must_use]
+  pub fn is_dirty(&self) -> bool { self.dirty.load(Ordering::Acquire) }
+
+  /// Returns whether the page was written since the last call and clears the flag.
+  pub fn take_dirty(&self) -> bool { self.dirty.swap(false, Ordering::AcqRel) }
+}
+
+impl Drop for Page {
+  fn drop(&mut self) { unsafe { alloc::alloc::dealloc(self.address.as_ptr(), Self::LAYOUT) } }
+}
+
+impl core::fmt::Debug for Page {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Page")
+      .field("address", &format_args!("{:#x}", self.address()))
+      .field("dirty", &self.dirty.load(Ordering::Relaxed))
+      .finish()
+  }
+}
+
+/// The interface of memory objects.
+pub trait VmObject: Send + Sync {
+  /// Returns the page with `index`, creating it if it does not exist yet.
+  ///
+  /// #### Errors
+  ///
+  /// If the page cannot be allocated or its content cannot be read, an error is
+  /// returned.
+  fn page(&self, index: u64) -> Result<Arc<Page>>;
+
+  /// Writes the pages that were changed through shared mappings back to where their
+  /// content came from.
+  ///
+  /// #### Errors
+  ///
+  /// If the pages cannot be written, an error is returned.
+  fn write_back(&self) -> Result<()> { Ok(()) }
+}
+
+/// Anonymous memory, which is not backed by a file. Its pages start zeroed.
+#[derive(Debug, Default)]
+pub struct Anonymous {
+  /// The pages that have been used, indexed by their number
+  pages: spin::Mutex<BTreeMap<u64, Arc<Page>>>,
+}
+
+impl Anonymous {
+  /// Creates anonymous memory without pages.
+  #[must_use]
+  pub fn new() -> Arc<Self> { Arc::new(Self::default()) }
+}
+
+impl VmObject for Anonymous {
+  fn page(&self, index: u64) -> Result<Arc<Page>> {
+    let mut pages = self.pages.lock();
+    if let Some(page) = pages.get(&index) {
+      return Ok(page.clone());
+    }
+    let page = Page::new()?;
+    pages.insert(index, page.clone());
+    Ok(page)
+  }
+}
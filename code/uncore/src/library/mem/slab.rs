@@ -0,0 +1,415 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains the slab allocator, which serves all allocations on the kernel
+//! heap.
+//!
+//! Small allocations are served by caches of equally sized objects, one per size class
+//! ([`SIZE_CLASSES`]). A cache carves pages ("slabs") from the page allocator into
+//! objects and keeps freed objects on a free list, so that allocating and freeing an
+//! object takes constant time. Every hart additionally holds a magazine of a few free
+//! objects per cache; most allocations and deallocations are served from it without
+//! taking a lock. Allocations that do not fit into the largest size class go to the page
+//! allocator directly.
+//!
+//! Slabs are not returned to the page allocator when all their objects are free; the
+//! objects stay cached for later allocations of the same size class.
+
+use core::{
+  alloc::{
+    GlobalAlloc,
+    Layout,
+  },
+  cell::UnsafeCell,
+  ptr::NonNull,
+  sync::atomic::{
+    AtomicUsize,
+    Ordering,
+  },
+};
+
+use super::PAGE_SIZE;
+use crate::arch::MAXIMUM_HARTS;
+
+/// The sizes of the objects of the caches, in bytes. An object is aligned to its size,
+/// so an allocation is served by the smallest class that covers both its size and its
+/// alignment.
+pub const SIZE_CLASSES: [usize; 10] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+/// The number of size classes.
+const CLASSES: usize = SIZE_CLASSES.len();
+
+/// The number of free objects a magazine holds at most.
+const MAGAZINE_SIZE: usize = 16;
+
+/// Returns the index of the size class that serves `layout`, or [`None`] if the
+/// allocation is too large for a cache.
+fn class(layout: Layout) -> Option<usize> {
+  let size = layout.size().max(layout.align());
+  SIZE_CLASSES.iter().position(|&class| class >= size)
+}
+
+/// A free object, which holds the next free object of its cache.
+struct FreeObject {
+  /// The next free object
+  next: Option<NonNull<Self>>,
+}
+
+/// The free objects of a cache.
+struct FreeList {
+  /// The first free object
+  head: Option<NonNull<FreeObject>>,
+}
+
+// The free objects are owned by the list, which is only accessed behind a lock
+unsafe impl Send for FreeList {}
+
+impl FreeList {
+  /// Adds `object` to the list.
+  const fn push(&mut self, object: NonNull<u8>) {
+    let object = object.cast::<FreeObject>();
+    unsafe { object.write(FreeObject { next: self.head }) };
+    self.head = Some(object);
+  }
+
+  /// Removes an object from the list.
+  fn pop(&mut self) -> Option<NonNull<u8>> {
+    let object = self.head?;
+    self.head = unsafe { object.read().next };
+    Some(object.cast())
+  }
+}
+
+/// The cache of the objects of one size class.
+struct Cache {
+  /// The objects that are neither allocated nor in a magazine
+  free:      spin::Mutex<FreeList>,
+  /// The number of slabs the cache has carved into objects
+  slabs:     AtomicUsize,
+  /// The number of objects that are allocated
+  allocated: AtomicUsize,
+}
+
+impl Cache {
+  /// Creates a cache without slabs.
+  const fn new() -> Self {
+    Self {
+      free:      spin::Mutex::new(FreeList { head: None }),
+      slabs:     AtomicUsize::new(0),
+      allocated: AtomicUsize::new(0),
+    }
+  }
+}
+
+/// A few free objects of one size class that are reserved for one hart.
+struct Magazine {
+  /// The objects; the first `length` entries are valid
+  objects: [Option<NonNull<u8>>; MAGAZINE_SIZE],
+  /// The number of objects in the magazine
+  length:  usize,
+}
+
+impl Magazine {
+  /// Creates an empty magazine.
+  const fn new() -> Self {
+    Self {
+      objects: [None; MAGAZINE_SIZE],
+      length:  0,
+    }
+  }
+
+  /// Removes the object that was added last.
+  fn pop(&mut self) -> Option<NonNull<u8>> {
+    self.length = self.length.checked_sub(1)?;
+    self.objects[self.length].take()
+  }
+
+  /// Adds `object` unless the magazine is full, in which case `object` is returned.
+  const fn push(&mut self, object: NonNull<u8>) -> Option<NonNull<u8>> {
+    if self.length == MAGAZINE_SIZE {
+      return Some(object);
+    }
+    self.objects[self.length] = Some(object);
+    self.length += 1;
+    None
+  }
+}
+
+/// The magazines of one hart, one per size class.
+struct Magazines(UnsafeCell<[Magazine; CLASSES]>);
+
+// The magazines of a hart are only accessed by that hart with interrupts disabled
+unsafe impl Sync for Magazines {}
+
+/// Usage statistics of the cache of one size class.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStatistics {
+  /// The size of the objects in bytes
+  pub size:      usize,
+  /// The number of slabs the cache has carved into objects
+  pub slabs:     usize,
+  /// The number of objects that are allocated
+  pub allocated: usize,
+}
+
+/// Usage statistics of the slab allocator, in bytes unless noted otherwise.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Statistics {
+  /// The size of the memory the page allocator manages
+  pub size:         usize,
+  /// The number of bytes the page allocator has handed out, i.e. slabs and large
+  /// allocations
+  pub used:         usize,
+  /// The number of bytes the page allocator has left
+  pub free:         usize,
+  /// The size of the largest block of whole pages the page allocator can still hand
+  /// out at once
+  pub largest_free: usize,
+  /// The number of bytes that are allocated, rounded up to the size class or page
+  pub allocated:    usize,
+  /// The statistics of the caches, in the order of [`SIZE_CLASSES`]
+  pub caches:       [CacheStatistics; CLASSES],
+}
+
+impl Statistics {
+  /// Returns how fragmented the free memory is, as the percentage of it that is not part
+  /// of the largest free block.
+  #[must_use]
+  pub const fn fragmentation(&self) -> usize {
+    if self.free == 0 || self.largest_free >= self.free {
+      return 0;
+    }
+    100 - self.largest_free * 100 / self.free
+  }
+}
+
+/// Returns the size of the largest block of whole pages that `pages` can hand out at
+/// once. The page allocator cannot list its free blocks, so the size is found by trying
+/// allocations.
+fn largest_free_block(pages: &mut linked_list_allocator::Heap) -> usize {
+  let (mut fits, mut too_large) = (0, pages.free() / PAGE_SIZE + 1);
+  while too_large - fits > 1 {
+    let middle = fits + (too_large - fits) / 2;
+    let Ok(layout) = Layout::from_size_align(middle * PAGE_SIZE, PAGE_SIZE) else {
+      break;
+    };
+    if let Ok(block) = pages.allocate_first_fit(layout) {
+      unsafe { pages.deallocate(block, layout) };
+      fits = middle;
+    } else {
+      too_large = middle;
+    }
+  }
+  fits * PAGE_SIZE
+}
+
+/// The slab allocator. It implements [`GlobalAlloc`].
+pub struct Allocator {
+  /// The page allocator, which provides slabs and large allocations
+  pages:     spin::Mutex<linked_list_allocator::Heap>,
+  /// The caches, in the order of [`SIZE_CLASSES`]
+  caches:    [Cache; CLASSES],
+  /// The magazines, indexed by the hart ID
+  magazines: [Magazines; MAXIMUM_HARTS],
+  /// The number of bytes of large allocations, which bypass the caches
+  large:     AtomicUsize,
+}
+
+impl Allocator {
+  /// Creates an allocator without memory. [`Allocator::initialize`] provides the memory.
+  #[must_use]
+  pub const fn empty() -> Self {
+    Self {
+      pages:     spin::Mutex::new(linked_list_allocator::Heap::empty()),
+      caches:    [const { Cache::new() }; CLASSES],
+      magazines: [const { Magazines(UnsafeCell::new([const { Magazine::new() }; CLASSES])) }; MAXIMUM_HARTS],
+      large:     AtomicUsize::new(0),
+    }
+  }
+
+  /// Provides the allocator with the `size` bytes at `start`.
+  ///
+  /// #### Safety
+  ///
+  /// The memory must be valid, unused and must not be provided twice.
+  pub unsafe fn initialize(&self, start: *mut u8, size: usize) {
+    unsafe { self.pages.lock().init(start, size) };
+  }
+
+  /// Returns the current usage statistics.
+  #[must_use]
+  pub fn statistics(&self) -> Statistics { self.statistics_of(&mut self.pages.lock()) }
+
+  /// Like [`Allocator::statistics`], but returns [`None`] instead of waiting if the page
+  /// allocator is in use, e.g. because the kernel crashed while allocating.
+  #[must_use]
+  pub fn try_statistics(&self) -> Option<Statistics> {
+    Some(self.statistics_of(&mut *self.pages.try_lock()?))
+  }
+
+  /// Returns the current usage statistics, given the locked page allocator.
+  fn statistics_of(&self, pages: &mut linked_list_allocator::Heap) -> Statistics {
+    let largest_free = largest_free_block(pages);
+    let (size, used, free) = (pages.size(), pages.used(), pages.free());
+
+    let caches = core::array::from_fn(|class| CacheStatistics {
+      size:      SIZE_CLASSES[class],
+      slabs:     self.caches[class].slabs.load(Ordering::Relaxed),
+      allocated: self.caches[class].allocated.load(Ordering::Relaxed),
+    });
+    let allocated = caches
+      .iter()
+      .map(|cache: &CacheStatistics| cache.size * cache.allocated)
+      .sum::<usize>()
+      + self.large.load(Ordering::Relaxed);
+
+    Statistics {
+      size,
+      used,
+      free,
+      largest_free,
+      allocated,
+      caches,
+    }
+  }
+
+  /// Returns the magazines of the current hart, or [`None`] if the hart has none.
+  ///
+  /// #### Safety
+  ///
+  /// Interrupts must be disabled while the magazines are used, and the magazines must not
+  /// be requested again before they are no longer used.
+  #[allow(clippy::mut_from_ref)]
+  unsafe fn magazines(&self) -> Option<&mut [Magazine; CLASSES]> {
+    self
+      .magazines
+      .get(crate::arch::hart())
+      .map(|magazines| unsafe { &mut *magazines.0.get() })
+  }
+
+  /// Allocates an object of the size class `class`. Interrupts must be disabled.
+  fn allocate_object(&self, class: usize) -> Option<NonNull<u8>> {
+    let mut magazines = unsafe { self.magazines() };
+    if let Some(object) = magazines.as_mut().and_then(|magazines| magazines[class].pop()) {
+      return Some(object);
+    }
+
+    let mut free = self.caches[class].free.lock();
+    if free.head.is_none() {
+      self.grow(class, &mut free)?;
+    }
+    let object = free.pop()?;
+
+    // Refill half of the magazine, so that the next allocations do not take the lock
+    if let Some(magazine) = magazines.map(|magazines| &mut magazines[class]) {
+      while magazine.length < MAGAZINE_SIZE / 2 {
+        let Some(spare) = free.pop() else { break };
+        magazine.push(spare);
+      }
+    }
+    Some(object)
+  }
+
+  /// Frees an object of the size class `class`. Interrupts must be disabled.
+  fn deallocate_object(&self, class: usize, object: NonNull<u8>) {
+    let Some(magazine) = (unsafe { self.magazines() }).map(|magazines| &mut magazines[class]) else {
+      self.caches[class].free.lock().push(object);
+      return;
+    };
+
+    if let Some(object) = magazine.push(object) {
+      // The magazine is full, so half of it goes back to the cache
+      let mut free = self.caches[class].free.lock();
+      free.push(object);
+      while magazine.length > MAGAZINE_SIZE / 2 {
+        let Some(spare) = magazine.pop() else { break };
+        free.push(spare);
+      }
+    }
+  }
+
+  /// Carves a new slab into objects of the size class `class` and adds them to `free`.
+  fn grow(&self, class: usize, free: &mut FreeList) -> Option<()> {
+    let slab = Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).ok()?;
+    let start = self.pages.lock().allocate_first_fit(slab).ok()?;
+    // Objects are pushed from the end, so that they are handed out in ascending order
+    for offset in (0..PAGE_SIZE).step_by(SIZE_CLASSES[class]).rev() {
+      free.push(unsafe { start.add(offset) });
+    }
+    self.caches[class].slabs.fetch_add(1, Ordering::Relaxed);
+    Some(())
+  }
+
+  /// Returns the layout of a large allocation with `layout` in the page allocator.
+  fn page_layout(layout: Layout) -> Option<Layout> {
+    let size = layout.size().checked_next_multiple_of(PAGE_SIZE)?;
+    Layout::from_size_align(size, layout.align().max(PAGE_SIZE)).ok()
+  }
+}
+
+impl core::fmt::Debug for Allocator {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("Allocator")
+      .field("statistics", &self.statistics())
+      .finish_non_exhaustive()
+  }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    for retries in 0.. {
+      let allocation = crate::arch::without_interrupts(|| {
+        if let Some(class) = class(layout) {
+          let object = self.allocate_object(class)?;
+          self.caches[class].allocated.fetch_add(1, Ordering::Relaxed);
+          return Some(object);
+        }
+
+        let pages = Self::page_layout(layout)?;
+        let start = self.pages.lock().allocate_first_fit(pages).ok()?;
+        self.large.fetch_add(pages.size(), Ordering::Relaxed);
+        Some(start)
+      });
+
+      if let Some(allocation) = allocation {
+        return allocation.as_ptr();
+      }
+      if super::heap::out_of_memory(layout, retries) == super::heap::Decision::Fail {
+        break;
+      }
+    }
+    core::ptr::null_mut()
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    let Some(ptr) = NonNull::new(ptr) else {
+      return;
+    };
+
+    crate::arch::without_interrupts(|| {
+      if let Some(class) = class(layout) {
+        self.caches[class].allocated.fetch_sub(1, Ordering::Relaxed);
+        self.deallocate_object(class, ptr);
+      } else if let Some(pages) = Self::page_layout(layout) {
+        unsafe { self.pages.lock().deallocate(ptr, pages) };
+        self.large.fetch_sub(pages.size(), Ordering::Relaxed);
+      }
+    });
+  }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    // An object that stays in its size class does not move
+    let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+    if class(layout).is_some() && class(layout) == class(new_layout) {
+      return ptr;
+    }
+
+    let new = unsafe { self.alloc(new_layout) };
+    if !new.is_null() {
+      unsafe {
+        core::ptr::copy_nonoverlapping(ptr, new, layout.size().min(new_size));
+        self.dealloc(ptr, layout);
+      }
+    }
+    new
+  }
+}
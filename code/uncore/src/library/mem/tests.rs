@@ -0,0 +1,255 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unit tests of the slab allocator, the out-of-memory policy, memory objects and address
+//! spaces.
+
+use core::{
+  alloc::{
+    GlobalAlloc,
+    Layout,
+  },
+  sync::atomic::{
+    AtomicUsize,
+    Ordering,
+  },
+};
+
+use super::{
+  address_space::{
+    AddressSpace,
+    Mapping,
+    Placement,
+    is_user_range,
+    Protection,
+    USER_END,
+    USER_START,
+  },
+  heap::{
+    self,
+    Decision,
+  },
+  object::Anonymous,
+  slab::Allocator,
+  Error,
+  PAGE_SIZE,
+};
+
+#[test_case]
+fn areas_are_split_and_private_mappings_copied_on_write() {
+  let space = AddressSpace::new();
+  let object = Anonymous::new();
+  let mapping = |shared| Mapping {
+    object: object.clone(),
+    offset: 0,
+    protection: Protection::READ | Protection::WRITE,
+    maximum: Protection::READ | Protection::WRITE,
+    shared,
+  };
+
+  let shared = space
+    .map(Placement::Anywhere, 3 * PAGE_SIZE, mapping(true))
+    .unwrap();
+  assert_eq!(shared, USER_END - 3 * PAGE_SIZE);
+  let private = space.map(Placement::Hint(USER_START), 1, mapping(false)).unwrap();
+  assert_eq!(private, USER_START);
+  // Addresses of the physical memory, e.g. of the kernel image, cannot be mapped
+  let kernel = 0x8020_0000;
+  let hinted = space.map(Placement::Hint(kernel), 1, mapping(false)).unwrap();
+  assert!(is_user_range(hinted, PAGE_SIZE));
+  assert_eq!(
+    space.map(Placement::Fixed(kernel), 1, mapping(false)),
+    Err(Error::InvalidArgument)
+  );
+  space.unmap(hinted, PAGE_SIZE).unwrap();
+  assert!(!is_user_range(kernel, 1));
+  assert!(!is_user_range(USER_END - 1, 2));
+
+  space.write(shared + PAGE_SIZE - 2, b"shared").unwrap();
+  space.write(private, b"private").unwrap();
+  let mut buffer = [0; 6];
+  space.read(private, &mut buffer).unwrap();
+  assert_eq!(&buffer, b"privat");
+  space.read(shared, &mut buffer).unwrap();
+  assert_eq!(buffer, [0; 6]);
+
+  space.unmap(shared + PAGE_SIZE, PAGE_SIZE).unwrap();
+  assert_eq!(space.read(shared + PAGE_SIZE, &mut buffer), Err(Error::NotMapped));
+  space
+    .read(shared + 2 * PAGE_SIZE - 2, &mut buffer[..2])
+    .unwrap_err();
+  assert_eq!(
+    space.protect(shared, 3 * PAGE_SIZE, Protection::READ),
+    Err(Error::NotMapped)
+  );
+
+  space.protect(shared, PAGE_SIZE, Protection::READ).unwrap();
+  assert_eq!(space.write(shared, b"x"), Err(Error::AccessDenied));
+  assert_eq!(
+    space.protect(shared, PAGE_SIZE, Protection::EXECUTE),
+    Err(Error::AccessDenied)
+  );
+  space.read(shared + PAGE_SIZE - 2, &mut buffer[..2]).unwrap();
+  assert_eq!(&buffer[..2], b"sh");
+  space.write(shared + 2 * PAGE_SIZE, b"!").unwrap();
+}
+
+#[test_case]
+fn slab_caches_reuse_objects_and_large_allocations_use_pages() {
+  /// The memory of the allocator under test
+  #[repr(align(4096))]
+  struct Memory([u8; 16 * PAGE_SIZE]);
+  static mut MEMORY: Memory = Memory([0; 16 * PAGE_SIZE]);
+  static ALLOCATOR: Allocator = Allocator::empty();
+
+  unsafe { ALLOCATOR.initialize(core::ptr::addr_of_mut!(MEMORY.0).cast(), 16 * PAGE_SIZE) };
+  let small = Layout::from_size_align(24, 8).unwrap();
+  let object = unsafe { ALLOCATOR.alloc(small) };
+  assert_eq!(object.addr() % 32, 0);
+  let statistics = ALLOCATOR.statistics();
+  assert_eq!(
+    (statistics.caches[2].slabs, statistics.caches[2].allocated),
+    (1, 1)
+  );
+  assert_eq!(statistics.allocated, 32);
+  unsafe { ALLOCATOR.dealloc(object, small) };
+  assert_eq!(unsafe { ALLOCATOR.alloc(small) }, object);
+  assert_eq!(unsafe { ALLOCATOR.realloc(object, small, 30) }, object);
+  unsafe { ALLOCATOR.dealloc(object, small) };
+
+  let medium = Layout::from_size_align(64, 64).unwrap();
+  let objects: alloc::vec::Vec<*mut u8> = (0..200).map(|_| unsafe { ALLOCATOR.alloc(medium) }).collect();
+  assert!(objects
+    .iter()
+    .all(|object| !object.is_null() && object.addr() % 64 == 0));
+  assert_eq!(ALLOCATOR.statistics().caches[3].slabs, 4);
+  for &object in &objects {
+    unsafe { ALLOCATOR.dealloc(object, medium) };
+  }
+  let statistics = ALLOCATOR.statistics();
+  assert_eq!(
+    (statistics.caches[3].slabs, statistics.caches[3].allocated),
+    (4, 0)
+  );
+
+  let large = Layout::from_size_align(PAGE_SIZE + 1, 8).unwrap();
+  let pages = unsafe { ALLOCATOR.alloc(large) };
+  assert_eq!(pages.addr() % PAGE_SIZE, 0);
+  assert_eq!(ALLOCATOR.statistics().allocated, 2 * PAGE_SIZE);
+  assert!(unsafe { ALLOCATOR.alloc(Layout::from_size_align(16 * PAGE_SIZE, 8).unwrap()) }.is_null());
+  unsafe { ALLOCATOR.dealloc(pages, large) };
+  assert_eq!(ALLOCATOR.statistics().allocated, 0);
+}
+
+#[test_case]
+fn exhausted_heaps_consult_the_out_of_memory_policy() {
+  /// The memory of the allocator under test
+  #[repr(align(4096))]
+  struct Memory([u8; 4 * PAGE_SIZE]);
+  static mut MEMORY: Memory = Memory([0; 4 * PAGE_SIZE]);
+  static ALLOCATOR: Allocator = Allocator::empty();
+  static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+  fn retry_twice(_: Layout, retries: usize) -> Decision {
+    CALLS.fetch_add(1, Ordering::Relaxed);
+    if retries < 2 {
+      Decision::Retry
+    } else {
+      Decision::Fail
+    }
+  }
+
+  unsafe { ALLOCATOR.initialize(core::ptr::addr_of_mut!(MEMORY.0).cast(), 4 * PAGE_SIZE) };
+  let statistics = ALLOCATOR.statistics();
+  assert_eq!(
+    (statistics.largest_free, statistics.fragmentation()),
+    (4 * PAGE_SIZE, 0)
+  );
+
+  let previous = heap::set_policy(retry_twice);
+  let layout = Layout::from_size_align(8 * PAGE_SIZE, 8).unwrap();
+  assert!(unsafe { ALLOCATOR.alloc(layout) }.is_null());
+  heap::set_policy(previous);
+  assert_eq!(CALLS.load(Ordering::Relaxed), 3);
+
+  assert_eq!(heap::try_vec(0_u8, usize::MAX), Err(Error::OutOfMemory));
+  assert_eq!(*heap::try_box(7).unwrap(), 7);
+}
+
+#[cfg(feature = "heap-debug")]
+#[test_case]
+fn heap_debugging_detects_corruption_and_tracks_live_allocations() {
+  use super::debug::{
+    Allocator,
+    Corruption,
+  };
+
+  /// The memory of the allocator under test
+  #[repr(align(4096))]
+  struct Memory([u8; 16 * PAGE_SIZE]);
+  static mut MEMORY: Memory = Memory([0; 16 * PAGE_SIZE]);
+  static ALLOCATOR: Allocator = Allocator::empty();
+
+  unsafe { ALLOCATOR.initialize(core::ptr::addr_of_mut!(MEMORY.0).cast(), 16 * PAGE_SIZE) };
+  let layout = Layout::from_size_align(20, 4).unwrap();
+  let first = core::ptr::NonNull::new(unsafe { ALLOCATOR.alloc(layout) }).unwrap();
+  let second = core::ptr::NonNull::new(unsafe { ALLOCATOR.alloc(layout) }).unwrap();
+  assert_eq!(unsafe { first.read() }, 0x5A);
+  let live = ALLOCATOR.live();
+  assert_eq!(live.len(), 2);
+  assert_eq!((live[1].address, live[1].size), (first.as_ptr().addr(), 20));
+
+  ALLOCATOR.release(first, layout).unwrap();
+  assert_eq!(unsafe { first.add(19).read() }, 0x6B);
+  assert_eq!(
+    ALLOCATOR.release(first, layout),
+    Err((Corruption::DoubleFree, first.as_ptr().addr()))
+  );
+  assert_eq!(ALLOCATOR.live().len(), 1);
+
+  unsafe { second.add(20).write(0) };
+  assert_eq!(
+    ALLOCATOR.release(second, layout),
+    Err((Corruption::RedZoneAfter, second.as_ptr().addr()))
+  );
+  unsafe { second.add(20).write(0xBB) };
+  assert_eq!(
+    ALLOCATOR.release(second, Layout::from_size_align(24, 4).unwrap()),
+    Err((Corruption::SizeMismatch, second.as_ptr().addr()))
+  );
+  ALLOCATOR.release(second, layout).unwrap();
+  assert!(ALLOCATOR.live().is_empty());
+}
+
+#[cfg(feature = "heap-debug")]
+#[test_case]
+fn heap_debugging_records_the_code_that_allocated() {
+  use crate::library::backtrace::SymbolTable;
+
+  #[inline(never)]
+  fn first_site(layout: Layout) -> *mut u8 { unsafe { alloc::alloc::alloc(layout) } }
+
+  #[inline(never)]
+  fn second_site(layout: Layout) -> *mut u8 { unsafe { alloc::alloc::alloc(layout) } }
+
+  let layout = Layout::new::<u64>();
+  let (first, second) = (first_site(layout), second_site(layout));
+  let live = heap::Heap::live();
+  let caller = |pointer: *mut u8| {
+    live
+      .iter()
+      .find(|allocation| allocation.address == pointer.addr())
+      .map(|allocation| allocation.caller)
+  };
+
+  // Without a symbol table, the frames of the allocator shim cannot be skipped
+  if let Some(table) = SymbolTable::kernel().filter(|table| !table.is_empty()) {
+    let (first_caller, second_caller) = (caller(first).unwrap(), caller(second).unwrap());
+    assert_ne!(first_caller, second_caller);
+    assert!(table.lookup(first_caller).unwrap().0.ends_with("::first_site"));
+    assert!(table.lookup(second_caller).unwrap().0.ends_with("::second_site"));
+  }
+  unsafe {
+    alloc::alloc::dealloc(first, layout);
+    alloc::alloc::dealloc(second, layout);
+  }
+}
@@ -3,27 +3,43 @@
 //! `unCORE` library module file that contains all other modules.
 
 pub mod arch;
+pub mod backtrace;
+pub mod cmdline;
+pub mod console;
+pub mod crash;
+pub mod device_tree;
+pub mod drivers;
+pub mod fs;
+pub mod ipc;
 pub mod mem;
 pub mod log;
 pub mod prelude;
+pub mod signal;
+pub mod sync;
+pub mod syscall;
 pub mod test;
+pub mod time;
 
 /// `unCORE`'s panic handler. This panic handler does not implement stack-unwinding;
 /// instead, it terminates execution by exiting the kernel (with [`arch::exit_kernel`]). A
-/// log message is provided to indicate the reasons for the call to [`panic!`].
+/// log message is provided to indicate the reasons for the call to [`panic!`], followed
+/// by a backtrace (see [`backtrace::log`]).
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
+  log::enter_panic_mode();
   if let Some(location) = info.location() {
-    let message = info.message().as_str().unwrap_or("no message available");
     ::log::error!(
-      "Panicked in file {}, line {}, column {}: {message}",
+      "Panicked in file {}, line {}, column {}: {}",
       location.file(),
       location.line(),
-      location.column()
+      location.column(),
+      info.message()
     );
   } else {
     ::log::error!("Panic without location information - you are out of luck!");
   }
+  backtrace::log();
+  crash::dump(info);
 
   arch::exit_kernel(crate::UncoreResult::Err);
 }
@@ -0,0 +1,503 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains POSIX signals, which notify programs asynchronously.
+//!
+//! A signal is raised with [`SignalState::raise`], e.g. by the `kill` system call, and
+//! stays pending until it is delivered. Delivery happens whenever the trap handler
+//! returns to user mode; blocked signals (see [`SignalState::set_mask`]) stay pending
+//! until they are unblocked. A delivered signal either runs the handler installed with
+//! [`SignalState::set_action`] or has its [`DefaultAction`]. The architecture builds the
+//! frame a handler runs on and removes it again on `rt_sigreturn`.
+//!
+//! Signals the kernel raises because of a fault (e.g. [`Signal::SIGSEGV`]) are raised
+//! with [`SignalState::force`], as returning to the faulting instruction without running
+//! a handler would fault again.
+//!
+//! Every process has its own state. There are no processes yet, so the trap handler and
+//! the system calls use the state returned by [`current`].
+
+#[cfg(test)]
+mod tests;
+
+use core::sync::atomic::{
+  AtomicU64,
+  Ordering,
+};
+
+use crate::library::sync::WaitQueue;
+
+/// The number of signals, including the real-time signals.
+pub const SIGNALS: usize = 64;
+
+/// The names of the signals that are not real-time signals, indexed by their number
+/// minus one.
+const NAMES: [&str; 31] = [
+  "SIGHUP",
+  "SIGINT",
+  "SIGQUIT",
+  "SIGILL",
+  "SIGTRAP",
+  "SIGABRT",
+  "SIGBUS",
+  "SIGFPE",
+  "SIGKILL",
+  "SIGUSR1",
+  "SIGSEGV",
+  "SIGUSR2",
+  "SIGPIPE",
+  "SIGALRM",
+  "SIGTERM",
+  "SIGSTKFLT",
+  "SIGCHLD",
+  "SIGCONT",
+  "SIGSTOP",
+  "SIGTSTP",
+  "SIGTTIN",
+  "SIGTTOU",
+  "SIGURG",
+  "SIGXCPU",
+  "SIGXFSZ",
+  "SIGVTALRM",
+  "SIGPROF",
+  "SIGWINCH",
+  "SIGIO",
+  "SIGPWR",
+  "SIGSYS",
+];
+
+/// A signal. The numbers follow Linux.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Signal(u8);
+
+impl Signal {
+  /// Child stopped or terminated
+  pub const SIGCHLD: Self = Self(17);
+  /// Continue if stopped
+  pub const SIGCONT: Self = Self(18);
+  /// Illegal instruction
+  pub const SIGILL: Self = Self(4);
+  /// Kill, which can neither be handled nor blocked
+  pub const SIGKILL: Self = Self(9);
+  /// Write to a pipe without readers
+  pub const SIGPIPE: Self = Self(13);
+  /// Invalid memory access
+  pub const SIGSEGV: Self = Self(11);
+  /// Stop, which can neither be handled nor blocked
+  pub const SIGSTOP: Self = Self(19);
+  /// Stop from the terminal
+  pub const SIGTSTP: Self = Self(20);
+  /// Read from the terminal by a background process
+  pub const SIGTTIN: Self = Self(21);
+  /// Write to the terminal by a background process
+  pub const SIGTTOU: Self = Self(22);
+  /// Urgent data on a socket
+  pub const SIGURG: Self = Self(23);
+  /// Size of the terminal changed
+  pub const SIGWINCH: Self = Self(28);
+
+  /// Returns the signal with `number`, or [`None`] if there is none.
+  #[must_use]
+  pub const fn new(number: usize) -> Option<Self> {
+    if number == 0 || number > SIGNALS {
+      return None;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    Some(Self(number as u8))
+  }
+
+  /// Returns the number of the signal.
+  #[must_use]
+  pub const fn number(self) -> usize { self.0 as usize }
+
+  /// Returns the index of the signal in arrays that have an entry for every signal.
+  const fn index(self) -> usize { self.number() - 1 }
+
+  /// Returns whether the signal can neither be handled, ignored nor blocked.
+  #[must_use]
+  pub const fn is_unblockable(self) -> bool { self.0 == Self::SIGKILL.0 || self.0 == Self::SIGSTOP.0 }
+
+  /// Returns what happens on delivery if no handler is installed. Signals that produce a
+  /// core dump on Linux terminate the program; no core dump is written.
+  #[must_use]
+  pub const fn default_action(self) -> DefaultAction {
+    match self {
+      Self::SIGCHLD | Self::SIGURG | Self::SIGWINCH => DefaultAction::Ignore,
+      Self::SIGCONT => DefaultAction::Continue,
+      Self::SIGSTOP | Self::SIGTSTP | Self::SIGTTIN | Self::SIGTTOU => DefaultAction::Stop,
+      _ => DefaultAction::Terminate,
+    }
+  }
+}
+
+impl core::fmt::Display for Signal {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match NAMES.get(self.index()) {
+      Some(name) => write!(f, "{name}"),
+      None => write!(f, "SIGRTMIN+{}", self.number() - NAMES.len() - 1),
+    }
+  }
+}
+
+/// What happens when a signal without handler is delivered.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DefaultAction {
+  /// The program is terminated.
+  Terminate,
+  /// The signal is discarded.
+  Ignore,
+  /// The program is stopped until [`Signal::SIGCONT`] is raised.
+  Stop,
+  /// A stopped program continues; raising the signal already does this.
+  Continue,
+}
+
+/// A set of signals, which is represented like Linux's `sigset_t`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct SignalSet(u64);
+
+impl SignalSet {
+  /// The set without signals
+  pub const EMPTY: Self = Self(0);
+  /// The signals that can be neither handled, ignored nor blocked
+  pub const UNBLOCKABLE: Self = Self::EMPTY.with(Signal::SIGKILL).with(Signal::SIGSTOP);
+
+  /// Creates a set from the bit representation of `sigset_t`.
+  #[must_use]
+  pub const fn from_bits(bits: u64) -> Self { Self(bits) }
+
+  /// Returns the bit representation of `sigset_t`.
+  #[must_use]
+  pub const fn bits(self) -> u64 { self.0 }
+
+  /// Returns the set with `signal` added.
+  #[must_use]
+  pub const fn with(self, signal: Signal) -> Self { Self(self.0 | 1 << signal.index()) }
+
+  /// Returns whether the set contains `signal`.
+  #[must_use]
+  pub const fn contains(self, signal: Signal) -> bool { self.0 & 1 << signal.index() != 0 }
+
+  /// Returns the signals that are in this set or in `other`.
+  #[must_use]
+  pub const fn union(self, other: Self) -> Self { Self(self.0 | other.0) }
+
+  /// Returns the signals that are in this set, but not in `other`.
+  #[must_use]
+  pub const fn difference(self, other: Self) -> Self { Self(self.0 & !other.0) }
+}
+
+/// What a signal does when it is delivered.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Handler {
+  /// The [`DefaultAction`] of the signal applies (`SIG_DFL`).
+  Default,
+  /// The signal is discarded (`SIG_IGN`).
+  Ignore,
+  /// The function at this address in the program is called.
+  Function(usize),
+}
+
+impl Handler {
+  /// Returns the handler of the value of `sa_handler`.
+  #[must_use]
+  pub const fn from_raw(raw: usize) -> Self {
+    match raw {
+      0 => Self::Default,
+      1 => Self::Ignore,
+      address => Self::Function(address),
+    }
+  }
+
+  /// Returns the value of `sa_handler`.
+  #[must_use]
+  pub const fn raw(self) -> usize {
+    match self {
+      Self::Default => 0,
+      Self::Ignore => 1,
+      Self::Function(address) => address,
+    }
+  }
+}
+
+/// Flags of an [`Action`] (`sa_flags`). Handlers always receive the information and
+/// context of the signal, as with `SA_SIGINFO`.
+pub mod flags {
+  /// The signal is not blocked while its handler runs
+  pub const NODEFER: usize = 0x4000_0000;
+  /// The handler is reset to the default once the signal is delivered
+  pub const RESETHAND: usize = 0x8000_0000;
+}
+
+/// The action of a signal, which `sigaction` sets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Action {
+  /// What the signal does
+  pub handler: Handler,
+  /// The [`flags`]
+  pub flags:   usize,
+  /// The signals that are blocked in addition while the handler runs
+  pub mask:    SignalSet,
+}
+
+impl Action {
+  /// The action of all signals when a program starts
+  pub const DEFAULT: Self = Self {
+    handler: Handler::Default,
+    flags:   0,
+    mask:    SignalSet::EMPTY,
+  };
+
+  /// Returns whether delivering `signal` with this action discards it.
+  const fn ignores(&self, signal: Signal) -> bool {
+    match self.handler {
+      Handler::Ignore => true,
+      Handler::Default => matches!(
+        signal.default_action(),
+        DefaultAction::Ignore | DefaultAction::Continue
+      ),
+      Handler::Function(_) => false,
+    }
+  }
+}
+
+impl Default for Action {
+  fn default() -> Self { Self::DEFAULT }
+}
+
+/// Values of [`Information::code`] (`si_code`).
+pub mod code {
+  /// The signal was sent with `kill`
+  pub const USER: i32 = 0;
+  /// The signal was raised by the kernel
+  pub const KERNEL: i32 = 0x80;
+  /// [`super::Signal::SIGSEGV`]: the address is not mapped
+  pub const MAPPING_ERROR: i32 = 1;
+  /// [`super::Signal::SIGILL`]: the opcode is illegal
+  pub const ILLEGAL_OPCODE: i32 = 1;
+}
+
+/// Information about the cause of a signal, which its handler receives.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Information {
+  /// The signal
+  pub signal:  Signal,
+  /// Why the signal was raised, see [`code`]
+  pub code:    i32,
+  /// The address that caused a fault, or zero
+  pub address: usize,
+}
+
+impl Information {
+  /// Returns the information of `signal` sent with `kill`.
+  #[must_use]
+  pub const fn user(signal: Signal) -> Self {
+    Self {
+      signal,
+      code: code::USER,
+      address: 0,
+    }
+  }
+
+  /// Returns the information of `signal` raised by the kernel for no particular fault.
+  #[must_use]
+  pub const fn kernel(signal: Signal) -> Self {
+    Self {
+      signal,
+      code: code::KERNEL,
+      address: 0,
+    }
+  }
+}
+
+/// What happens on delivery of a signal, which the architecture carries out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Delivery {
+  /// The handler at `handler` is called with `information`. When it returns, the signal
+  /// mask is reset to `previous_mask`.
+  Handle {
+    /// The signal and its cause
+    information:   Information,
+    /// The address of the handler
+    handler:       usize,
+    /// The signal mask before the delivery
+    previous_mask: SignalSet,
+  },
+  /// The program is terminated by the signal.
+  Terminate(Signal),
+  /// The program is stopped by the signal.
+  Stop(Signal),
+}
+
+/// The signal state of a process: the actions, the mask and the pending signals.
+pub struct SignalState {
+  /// The action of every signal, indexed by [`Signal::index`]
+  actions:   spin::Mutex<[Action; SIGNALS]>,
+  /// The information of every pending signal; signals that are raised while they are
+  /// pending are not queued
+  pending:   spin::Mutex<[Option<Information>; SIGNALS]>,
+  /// The blocked signals
+  mask:      AtomicU64,
+  /// The stopped process waiting for [`Signal::SIGCONT`]
+  continued: WaitQueue,
+}
+
+impl SignalState {
+  /// Creates the state of a new program: all actions are the default and no signal is
+  /// blocked or pending.
+  #[must_use]
+  pub const fn new() -> Self {
+    Self {
+      actions:   spin::Mutex::new([Action::DEFAULT; SIGNALS]),
+      pending:   spin::Mutex::new([None; SIGNALS]),
+      mask:      AtomicU64::new(0),
+      continued: WaitQueue::new(),
+    }
+  }
+
+  /// Returns the action of `signal`.
+  #[must_use]
+  pub fn action(&self, signal: Signal) -> Action { self.actions.lock()[signal.index()] }
+
+  /// Sets the action of `signal` and returns the previous one. If the signal is now
+  /// ignored, it is no longer pending. The actions of [`Signal::SIGKILL`] and
+  /// [`Signal::SIGSTOP`] cannot be changed, in which case [`None`] is returned.
+  pub fn set_action(&self, signal: Signal, action: Action) -> Option<Action> {
+    if signal.is_unblockable() {
+      return None;
+    }
+
+    let previous = core::mem::replace(&mut self.actions.lock()[signal.index()], action);
+    if action.ignores(signal) {
+      self.pending.lock()[signal.index()] = None;
+    }
+    Some(previous)
+  }
+
+  /// Returns the blocked signals.
+  #[must_use]
+  pub fn mask(&self) -> SignalSet { SignalSet(self.mask.load(Ordering::Acquire)) }
+
+  /// Blocks the signals in `mask` and unblocks all others, except for
+  /// [`SignalSet::UNBLOCKABLE`]. The previous mask is returned.
+  pub fn set_mask(&self, mask: SignalSet) -> SignalSet {
+    let mask = mask.difference(SignalSet::UNBLOCKABLE);
+    SignalSet(self.mask.swap(mask.0, Ordering::AcqRel))
+  }
+
+  /// Returns the pending signals.
+  #[must_use]
+  pub fn pending(&self) -> SignalSet {
+    self
+      .pending
+      .lock()
+      .iter()
+      .flatten()
+      .fold(SignalSet::EMPTY, |set, information| set.with(information.signal))
+  }
+
+  /// Makes the signal of `information` pending, unless it is pending already.
+  /// [`Signal::SIGCONT`] continues a stopped program and discards pending stop signals;
+  /// stop signals discard a pending [`Signal::SIGCONT`].
+  pub fn raise(&self, information: Information) {
+    let signal = information.signal;
+    let mut pending = self.pending.lock();
+    if signal == Signal::SIGCONT {
+      for stop in pending.iter_mut() {
+        stop.take_if(|stop| stop.signal.default_action() == DefaultAction::Stop);
+      }
+    } else if signal.default_action() == DefaultAction::Stop {
+      pending[Signal::SIGCONT.index()] = None;
+    }
+    pending[signal.index()].get_or_insert(information);
+    drop(pending);
+
+    self.continued.wake_all();
+  }
+
+  /// Raises the signal of `information` such that it is delivered on the next return to
+  /// user mode: if it is blocked or ignored, it is unblocked and its action is reset to
+  /// the default.
+  pub fn force(&self, information: Information) {
+    let signal = information.signal;
+    let mut actions = self.actions.lock();
+    if self.mask().contains(signal) || actions[signal.index()].handler == Handler::Ignore {
+      actions[signal.index()].handler = Handler::Default;
+      self.mask.fetch_and(!(1 << signal.index()), Ordering::AcqRel);
+    }
+    drop(actions);
+
+    self.raise(information);
+  }
+
+  /// Takes the next pending signal that is neither blocked nor ignored and returns what
+  /// its delivery does. Before a handler is called, its signal mask is applied and, with
+  /// [`flags::RESETHAND`], its action is reset.
+  pub fn next(&self) -> Option<Delivery> {
+    loop {
+      let mask = self.mask();
+      let information = self
+        .pending
+        .lock()
+        .iter_mut()
+        .find_map(|pending| pending.take_if(|information| !mask.contains(information.signal)))?;
+      let signal = information.signal;
+
+      let mut actions = self.actions.lock();
+      let action = actions[signal.index()];
+      if action.ignores(signal) {
+        continue;
+      }
+
+      match action.handler {
+        Handler::Function(handler) => {
+          if action.flags & flags::RESETHAND != 0 {
+            actions[signal.index()] = Action::DEFAULT;
+          }
+          let mut blocked = mask.union(action.mask);
+          if action.flags & flags::NODEFER == 0 {
+            blocked = blocked.with(signal);
+          }
+          let previous_mask = self.set_mask(blocked);
+
+          return Some(Delivery::Handle {
+            information,
+            handler,
+            previous_mask,
+          });
+        },
+        _ if signal.default_action() == DefaultAction::Stop => return Some(Delivery::Stop(signal)),
+        _ => return Some(Delivery::Terminate(signal)),
+      }
+    }
+  }
+
+  /// Waits until a stopped program is continued by [`Signal::SIGCONT`] or killed by
+  /// [`Signal::SIGKILL`].
+  pub fn wait_while_stopped(&self) {
+    self.continued.wait_until(|| {
+      let pending = self.pending();
+      (pending.contains(Signal::SIGCONT) || pending.contains(Signal::SIGKILL)).then_some(())
+    });
+  }
+}
+
+impl Default for SignalState {
+  fn default() -> Self { Self::new() }
+}
+
+impl core::fmt::Debug for SignalState {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_struct("SignalState")
+      .field("mask", &self.mask())
+      .field("pending", &self.pending())
+      .finish_non_exhaustive()
+  }
+}
+
+/// The signal state the kernel uses until there are processes.
+static KERNEL_STATE: SignalState = SignalState::new();
+
+/// Returns the signal state of the running process. There are no processes yet, so this
+/// is always the state of the kernel.
+#[must_use]
+pub fn current() -> &'static SignalState { &KERNEL_STATE }
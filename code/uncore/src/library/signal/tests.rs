@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unit tests of signal masks, actions and delivery.
+
+use super::{
+  flags,
+  Action,
+  Delivery,
+  Handler,
+  Information,
+  Signal,
+  SignalSet,
+  SignalState,
+};
+
+#[test_case]
+fn signals_are_delivered_according_to_mask_and_action() {
+  let state = SignalState::new();
+  let handler = Action {
+    handler: Handler::Function(0x1000),
+    flags:   flags::RESETHAND,
+    mask:    SignalSet::EMPTY.with(Signal::SIGURG),
+  };
+  assert_eq!(state.set_action(Signal::SIGPIPE, handler), Some(Action::DEFAULT));
+  assert_eq!(state.set_action(Signal::SIGKILL, handler), None);
+
+  state.set_mask(SignalSet::EMPTY.with(Signal::SIGPIPE).with(Signal::SIGKILL));
+  assert_eq!(state.mask(), SignalSet::EMPTY.with(Signal::SIGPIPE));
+  state.raise(Information::user(Signal::SIGPIPE));
+  state.raise(Information::user(Signal::SIGCHLD));
+  assert_eq!(state.next(), None);
+  assert_eq!(state.pending(), SignalSet::EMPTY.with(Signal::SIGPIPE));
+
+  state.set_mask(SignalSet::EMPTY);
+  assert_eq!(
+    state.next(),
+    Some(Delivery::Handle {
+      information:   Information::user(Signal::SIGPIPE),
+      handler:       0x1000,
+      previous_mask: SignalSet::EMPTY,
+    })
+  );
+  assert_eq!(
+    state.mask(),
+    SignalSet::EMPTY.with(Signal::SIGPIPE).with(Signal::SIGURG)
+  );
+  assert_eq!(state.action(Signal::SIGPIPE), Action::DEFAULT);
+
+  state.raise(Information::user(Signal::SIGTSTP));
+  assert_eq!(state.next(), Some(Delivery::Stop(Signal::SIGTSTP)));
+  state.raise(Information::user(Signal::SIGTSTP));
+  state.raise(Information::user(Signal::SIGCONT));
+  state.wait_while_stopped();
+  assert_eq!(state.next(), None);
+
+  state.set_action(
+    Signal::SIGSEGV,
+    Action {
+      handler: Handler::Ignore,
+      ..Action::DEFAULT
+    },
+  );
+  state.force(Information::kernel(Signal::SIGSEGV));
+  assert_eq!(state.next(), Some(Delivery::Terminate(Signal::SIGSEGV)));
+}
@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains synchronization primitives that complement the locks of the
+//! [`spin`] crate.
+
+use core::sync::atomic::{
+  AtomicU64,
+  Ordering,
+};
+
+/// A queue of waiters that block until a condition holds, e.g. until a pipe contains
+/// data. Whoever changes the state the condition depends on calls [`WaitQueue::wake_all`]
+/// afterwards.
+///
+/// The kernel has no scheduler yet, so a waiter keeps its hart busy until it is woken by
+/// another hart. Once there is a scheduler, waiters give up the hart instead; the
+/// interface stays the same.
+#[derive(Debug)]
+pub struct WaitQueue {
+  /// Incremented on every wake-up, so that waiters notice wake-ups that happen between
+  /// checking the condition and starting to wait
+  generation: AtomicU64,
+}
+
+impl WaitQueue {
+  /// Creates a wait queue without waiters.
+  #[must_use]
+  pub const fn new() -> Self {
+    Self {
+      generation: AtomicU64::new(0),
+    }
+  }
+
+  /// Blocks until `condition` returns a value, which is then returned. The condition is
+  /// checked once immediately and again after every wake-up.
+  pub fn wait_until<T>(&self, mut condition: impl FnMut() -> Option<T>) -> T {
+    loop {
+      let generation = self.generation.load(Ordering::Acquire);
+      if let Some(value) = condition() {
+        return value;
+      }
+
+      while self.generation.load(Ordering::Acquire) == generation {
+        core::hint::spin_loop();
+      }
+    }
+  }
+
+  /// Wakes all waiters, which then check their condition again.
+  pub fn wake_all(&self) { self.generation.fetch_add(1, Ordering::Release); }
+}
+
+impl Default for WaitQueue {
+  fn default() -> Self { Self::new() }
+}
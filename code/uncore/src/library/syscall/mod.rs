@@ -0,0 +1,938 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module contains the system call interface of the kernel.
+//!
+//! User programs place the system call number in `a7` and the arguments in `a0` to `a5`
+//! and execute `ecall`; the result is returned in `a0`. The numbers and semantics follow
+//! the generic Linux system call table (`include/uapi/asm-generic/unistd.h`), so that
+//! existing C libraries can be used. Errors are returned as negative error numbers.
+//!
+//! File descriptors refer to the table returned by
+//! [`crate::library::fs::descriptor::current`]. There is no `dup2` in the generic table;
+//! C libraries implement it with `dup3`.
+//!
+//! Memory is mapped into the address space returned by
+//! [`crate::library::mem::address_space::current`]. There is no MMU support yet, so
+//! programs cannot access mappings directly; see [`crate::library::mem::address_space`].
+//! For the same reason, the buffers that system calls read or write are copied through
+//! the address space, and addresses that are not mapped result in [`error::EFAULT`].
+//!
+//! Signals refer to the state returned by [`crate::library::signal::current`]. There
+//! are no processes yet; the program that makes system calls has the process ID 1.
+//! `rt_sigreturn` is handled by the trap handler, as it replaces all registers, and so
+//! are `exit` and `exit_group`, which return to the kernel code that started the program
+//! (see [`crate::arch::run_program`]).
+//!
+//! The [IPC](crate::library::ipc) system calls have no counterpart in Linux and use
+//! numbers from 1024 on, which the generic table leaves unused. They refer to
+//! capabilities by their slot in the table returned by [`crate::library::ipc::current`]
+//! and pass messages in registers: the label in `a1`, the words in `a2` to `a4` and the
+//! slot of the granted capability (or [`NO_CAPABILITY`]) in `a5`. Received messages are
+//! returned in the same registers.
+
+#[cfg(test)]
+mod tests;
+
+use crate::library::{
+  fs::{
+    self,
+    OpenFlags,
+  },
+  ipc,
+  mem::{
+    address_space::{
+      self,
+      Mapping,
+      Placement,
+      Protection,
+    },
+    heap,
+    object::{
+      Anonymous,
+      VmObject,
+    },
+    PAGE_SIZE,
+  },
+  signal::{
+    self,
+    Signal,
+    SignalSet,
+  },
+};
+
+/// System call numbers.
+pub mod number {
+  /// `dup(int oldfd)`
+  pub const DUP: usize = 23;
+  /// `dup3(int oldfd, int newfd, int flags)`
+  pub const DUP3: usize = 24;
+  /// `fcntl(int fd, int cmd, ...)`
+  pub const FCNTL: usize = 25;
+  /// `openat(int dirfd, const char *pathname, int flags, mode_t mode)`
+  pub const OPENAT: usize = 56;
+  /// `close(int fd)`
+  pub const CLOSE: usize = 57;
+  /// `pipe2(int pipefd[2], int flags)`
+  pub const PIPE2: usize = 59;
+  /// `read(int fd, void *buf, size_t count)`
+  pub const READ: usize = 63;
+  /// `write(int fd, const void *buf, size_t count)`
+  pub const WRITE: usize = 64;
+  /// `exit(int status)`, which ends the program
+  pub const EXIT: usize = 93;
+  /// `exit_group(int status)`, which ends the program
+  pub const EXIT_GROUP: usize = 94;
+  /// `clock_gettime(clockid_t clock_id, struct timespec *tp)`
+  pub const CLOCK_GETTIME: usize = 113;
+  /// `syslog(int type, char *bufp, int len)`
+  pub const SYSLOG: usize = 116;
+  /// `kill(pid_t pid, int sig)`
+  pub const KILL: usize = 129;
+  /// `rt_sigaction(int sig, const struct sigaction *act, struct sigaction *oact, size_t
+  /// sigsetsize)`
+  pub const RT_SIGACTION: usize = 134;
+  /// `rt_sigprocmask(int how, const sigset_t *set, sigset_t *oset, size_t sigsetsize)`
+  pub const RT_SIGPROCMASK: usize = 135;
+  /// `rt_sigpending(sigset_t *set, size_t sigsetsize)`
+  pub const RT_SIGPENDING: usize = 136;
+  /// `rt_sigreturn()`, which signal handlers call through the trampoline on return
+  pub const RT_SIGRETURN: usize = 139;
+  /// `getpid()`
+  pub const GETPID: usize = 172;
+  /// `munmap(void *addr, size_t length)`
+  pub const MUNMAP: usize = 215;
+  /// `mmap(void *addr, size_t length, int prot, int flags, int fd, off_t offset)`
+  pub const MMAP: usize = 222;
+  /// `mprotect(void *addr, size_t len, int prot)`
+  pub const MPROTECT: usize = 226;
+  /// `msync(void *addr, size_t length, int flags)`
+  pub const MSYNC: usize = 227;
+  /// `endpoint_create()`, which returns the slot of a new endpoint
+  pub const ENDPOINT_CREATE: usize = 1024;
+  /// `memory_allocate(size_t pages)`, which returns the slot of new zeroed pages
+  pub const MEMORY_ALLOCATE: usize = 1025;
+  /// `memory_map(int slot)`, which returns the address and the number of pages of a
+  /// memory object in `a1` and `a2`
+  pub const MEMORY_MAP: usize = 1026;
+  /// `capability_copy(int slot, int rights)`, which returns the slot of the copy
+  pub const CAPABILITY_COPY: usize = 1027;
+  /// `capability_delete(int slot)`
+  pub const CAPABILITY_DELETE: usize = 1028;
+  /// `send(int endpoint, message)`
+  pub const SEND: usize = 1029;
+  /// `receive(int endpoint)`, which returns the message
+  pub const RECEIVE: usize = 1030;
+  /// `call(int endpoint, message)`, which returns the reply
+  pub const CALL: usize = 1031;
+  /// `reply(message)`, which answers the call that was received last
+  pub const REPLY: usize = 1032;
+}
+
+/// The value of `a5` for a message that grants no capability.
+pub const NO_CAPABILITY: usize = usize::MAX;
+
+/// Commands of [`number::FCNTL`].
+pub mod fcntl {
+  /// Duplicate the file descriptor to the lowest free one that is at least the argument
+  pub const DUPLICATE: usize = 0;
+  /// Return the file descriptor flags
+  pub const GET_FLAGS: usize = 1;
+  /// Set the file descriptor flags to the argument
+  pub const SET_FLAGS: usize = 2;
+  /// Like [`DUPLICATE`], but set the close-on-exec flag of the new file descriptor
+  pub const DUPLICATE_CLOSE_ON_EXEC: usize = 1030;
+  /// The file descriptor flag that closes the file descriptor when a program is executed
+  pub const CLOSE_ON_EXEC: usize = 1;
+}
+
+/// Operations of [`number::RT_SIGPROCMASK`].
+pub mod sigprocmask {
+  /// Block the signals in the set in addition
+  pub const BLOCK: usize = 0;
+  /// Unblock the signals in the set
+  pub const UNBLOCK: usize = 1;
+  /// Block exactly the signals in the set
+  pub const SET_MASK: usize = 2;
+}
+
+/// Flags of [`number::MMAP`].
+pub mod mmap {
+  /// Changes are visible through other mappings and written back to the file
+  pub const SHARED: usize = 0x01;
+  /// Changes are private to the mapping
+  pub const PRIVATE: usize = 0x02;
+  /// Map at exactly the given address, replacing what is mapped there
+  pub const FIXED: usize = 0x10;
+  /// Map zeroed memory instead of a file
+  pub const ANONYMOUS: usize = 0x20;
+}
+
+/// The ID of the only process.
+const PROCESS_ID: usize = 1;
+
+/// The layout of `struct sigaction` for [`number::RT_SIGACTION`].
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+struct SignalAction {
+  /// `sa_handler`
+  handler: usize,
+  /// `sa_flags`
+  flags:   usize,
+  /// `sa_mask`
+  mask:    u64,
+}
+
+/// The value of `dirfd` that resolves relative paths from the current directory. There
+/// are no processes yet, so the current directory is always `/`.
+const AT_FDCWD: usize = 0_usize.wrapping_sub(100);
+
+/// The maximum length of a path including the terminating null byte.
+const PATH_MAXIMUM: usize = 4096;
+
+/// Clock IDs for [`number::CLOCK_GETTIME`].
+pub mod clock {
+  /// The wall clock
+  pub const REALTIME: usize = 0;
+  /// The monotonic clock, which counts the time since the machine started
+  pub const MONOTONIC: usize = 1;
+}
+
+/// Actions of [`number::SYSLOG`].
+pub mod syslog {
+  /// Read all records of the kernel log that are still in memory
+  pub const READ_ALL: usize = 3;
+  /// Return the number of bytes that reading all records currently needs
+  pub const SIZE_BUFFER: usize = 10;
+}
+
+/// Error numbers, which are returned negated.
+pub mod error {
+  /// Operation not permitted
+  pub const EPERM: isize = 1;
+  /// No such file or directory
+  pub const ENOENT: isize = 2;
+  /// No such process
+  pub const ESRCH: isize = 3;
+  /// Input/output error
+  pub const EIO: isize = 5;
+  /// Bad file descriptor
+  pub const EBADF: isize = 9;
+  /// Resource temporarily unavailable
+  pub const EAGAIN: isize = 11;
+  /// Cannot allocate memory
+  pub const ENOMEM: isize = 12;
+  /// Permission denied
+  pub const EACCES: isize = 13;
+  /// Bad address
+  pub const EFAULT: isize = 14;
+  /// Device or resource busy
+  pub const EBUSY: isize = 16;
+  /// File exists
+  pub const EEXIST: isize = 17;
+  /// Invalid cross-device link
+  pub const EXDEV: isize = 18;
+  /// No such device
+  pub const ENODEV: isize = 19;
+  /// Not a directory
+  pub const ENOTDIR: isize = 20;
+  /// Is a directory
+  pub const EISDIR: isize = 21;
+  /// Invalid argument
+  pub const EINVAL: isize = 22;
+  /// Too many open files
+  pub const EMFILE: isize = 24;
+  /// No space left on device
+  pub const ENOSPC: isize = 28;
+  /// Read-only file system
+  pub const EROFS: isize = 30;
+  /// Broken pipe
+  pub const EPIPE: isize = 32;
+  /// File name too long
+  pub const ENAMETOOLONG: isize = 36;
+  /// Function not implemented
+  pub const ENOSYS: isize = 38;
+  /// Directory not empty
+  pub const ENOTEMPTY: isize = 39;
+  /// Too many levels of symbolic links
+  pub const ELOOP: isize = 40;
+  /// Operation not supported
+  pub const EOPNOTSUPP: isize = 95;
+  /// Structure needs cleaning
+  pub const EUCLEAN: isize = 117;
+
+  /// Returns the error number that corresponds to `error`.
+  #[must_use]
+  pub const fn from_fs(error: crate::library::fs::Error) -> isize {
+    use crate::library::fs::Error;
+
+    match error {
+      Error::NotFound | Error::InvalidPath => ENOENT,
+      Error::NotADirectory => ENOTDIR,
+      Error::IsADirectory => EISDIR,
+      Error::AlreadyExists => EEXIST,
+      Error::NotEmpty => ENOTEMPTY,
+      Error::TooManySymbolicLinks => ELOOP,
+      Error::Busy => EBUSY,
+      Error::CrossesDevices => EXDEV,
+      // The VFS reports this error if the file was not opened for the operation
+      Error::PermissionDenied | Error::BadDescriptor => EBADF,
+      Error::InvalidArgument => EINVAL,
+      Error::Unsupported => EOPNOTSUPP,
+      Error::Io => EIO,
+      Error::NoSpace => ENOSPC,
+      Error::ReadOnly => EROFS,
+      Error::Corrupted => EUCLEAN,
+      Error::TooManyOpenFiles => EMFILE,
+      Error::WouldBlock => EAGAIN,
+      Error::BrokenPipe => EPIPE,
+      Error::OutOfMemory => ENOMEM,
+    }
+  }
+
+  /// Returns the error number that corresponds to the memory `error`.
+  #[must_use]
+  pub const fn from_mem(error: crate::library::mem::Error) -> isize {
+    use crate::library::mem::Error;
+
+    match error {
+      Error::InvalidArgument => EINVAL,
+      Error::NotMapped | Error::OutOfAddressSpace | Error::OutOfMemory => ENOMEM,
+      Error::AccessDenied => EACCES,
+      Error::File(error) => from_fs(error),
+    }
+  }
+
+  /// Returns the error number that corresponds to the IPC `error`.
+  #[must_use]
+  pub const fn from_ipc(error: crate::library::ipc::Error) -> isize {
+    use crate::library::ipc::Error;
+
+    match error {
+      Error::InvalidCapability => EBADF,
+      Error::WrongType => EINVAL,
+      Error::InsufficientRights => EPERM,
+      Error::TableFull => EMFILE,
+      Error::NoReply => EPIPE,
+      Error::OutOfMemory => ENOMEM,
+    }
+  }
+}
+
+/// Executes the system call with `number` and `arguments` and returns its result.
+/// Additional return values are written to `arguments`, from which they are copied back
+/// into the registers.
+#[must_use]
+pub fn dispatch(number: usize, arguments: &mut [usize; 6]) -> isize {
+  let result = match number {
+    number::DUP => dup(arguments[0]),
+    number::DUP3 => dup3(arguments[0], arguments[1], arguments[2]),
+    number::FCNTL => fcntl(arguments[0], arguments[1], arguments[2]),
+    number::OPENAT => openat(arguments[0], arguments[1], arguments[2], arguments[3]),
+    number::CLOSE => close(arguments[0]),
+    number::PIPE2 => pipe2(arguments[0], arguments[1]),
+    number::READ => read(arguments[0], arguments[1], arguments[2]),
+    number::WRITE => write(arguments[0], arguments[1], arguments[2]),
+    number::CLOCK_GETTIME => clock_gettime(arguments[0], arguments[1]),
+    number::SYSLOG => syslog(arguments[0], arguments[1], arguments[2]),
+    number::KILL => kill(arguments[0], arguments[1]),
+    number::RT_SIGACTION => rt_sigaction(arguments[0], arguments[1], arguments[2], arguments[3]),
+    number::RT_SIGPROCMASK => rt_sigprocmask(arguments[0], arguments[1], arguments[2], arguments[3]),
+    number::RT_SIGPENDING => rt_sigpending(arguments[0], arguments[1]),
+    number::GETPID => isize::try_from(PROCESS_ID).map_err(|_| error::EINVAL),
+    number::MUNMAP => munmap(arguments[0], arguments[1]),
+    number::MMAP => mmap(arguments),
+    number::MPROTECT => mprotect(arguments[0], arguments[1], arguments[2]),
+    number::MSYNC => msync(arguments[0], arguments[1]),
+    number::ENDPOINT_CREATE => endpoint_create(),
+    number::MEMORY_ALLOCATE => memory_allocate(arguments[0]),
+    number::MEMORY_MAP => memory_map(arguments),
+    number::CAPABILITY_COPY => capability_copy(arguments[0], arguments[1]),
+    number::CAPABILITY_DELETE => capability_delete(arguments[0]),
+    number::SEND => send(arguments),
+    number::RECEIVE => receive(arguments),
+    number::CALL => call(arguments),
+    number::REPLY => reply(arguments),
+    _ => Err(error::ENOSYS),
+  };
+
+  result.unwrap_or_else(|error| -error)
+}
+
+/// Writes the time of `clock_id` to the `struct timespec` at `timespec`. If the wall
+/// clock is unknown, [`clock::REALTIME`] counts from the UNIX epoch.
+fn clock_gettime(clock_id: usize, timespec: usize) -> Result<isize, isize> {
+  let time = match clock_id {
+    clock::REALTIME => crate::library::time::realtime().unwrap_or_else(crate::library::time::uptime),
+    clock::MONOTONIC => crate::library::time::uptime(),
+    _ => return Err(error::EINVAL),
+  };
+
+  write_object(timespec, &crate::library::time::Timespec::from(time))?;
+  Ok(0)
+}
+
+/// Performs the `syslog` action `action`. [`syslog::READ_ALL`] copies the most recent
+/// records of the kernel log that fit into the buffer of `length` bytes at `address`, one
+/// per line, and returns the number of bytes copied.
+fn syslog(action: usize, address: usize, length: usize) -> Result<isize, isize> {
+  use core::fmt::Write;
+
+  let mut log = alloc::string::String::new();
+  let end = crate::library::log::next_sequence();
+  for entry in crate::library::log::records(0, end) {
+    writeln!(log, "{entry}").map_err(|_| error::ENOMEM)?;
+  }
+
+  match action {
+    syslog::READ_ALL => {
+      // The newest records are kept if not all of them fit
+      let mut start = log.len().saturating_sub(length);
+      while !log.is_char_boundary(start) {
+        start += 1;
+      }
+      let records = &log.as_bytes()[start..];
+      let copied = with_buffer(address, records.len(), true, |buffer| {
+        buffer.copy_from_slice(records);
+        Ok(buffer.len())
+      })?;
+      isize::try_from(copied).map_err(|_| error::EINVAL)
+    },
+    syslog::SIZE_BUFFER => isize::try_from(log.len()).map_err(|_| error::EINVAL),
+    _ => Err(error::EINVAL),
+  }
+}
+
+/// Converts the file descriptor `descriptor` into a result.
+fn descriptor_result(descriptor: usize) -> Result<isize, isize> {
+  isize::try_from(descriptor).map_err(|_| error::EMFILE)
+}
+
+/// Checks that the program can map all of the `length` bytes at `address`. Other
+/// addresses, e.g. those of the kernel, result in [`error::EFAULT`].
+const fn check_user_range(address: usize, length: usize) -> Result<(), isize> {
+  if address_space::is_user_range(address, length) {
+    Ok(())
+  } else {
+    Err(error::EFAULT)
+  }
+}
+
+/// Copies the memory of the program at `address` into `buffer`. This function and
+/// [`copy_out`] are the only ones that access memory of the program; addresses that are
+/// not mapped result in [`error::EFAULT`] as well (see [`check_user_range`]).
+fn copy_in(address: usize, buffer: &mut [u8]) -> Result<(), isize> {
+  check_user_range(address, buffer.len())?;
+  address_space::current()
+    .read(address, buffer)
+    .map_err(|_| error::EFAULT)
+}
+
+/// Copies `buffer` to the memory of the program at `address` (see [`copy_in`]).
+fn copy_out(address: usize, buffer: &[u8]) -> Result<(), isize> {
+  check_user_range(address, buffer.len())?;
+  address_space::current()
+    .write(address, buffer)
+    .map_err(|_| error::EFAULT)
+}
+
+/// Returns the object at `address` in the memory of the program. `T` must consist of
+/// integers only, so that every bit pattern is a valid value.
+fn read_object<T: Copy + Default>(address: usize) -> Result<T, isize> {
+  if !(address as *const T).is_aligned() {
+    return Err(error::EFAULT);
+  }
+  let mut value = T::default();
+  let bytes = unsafe {
+    core::slice::from_raw_parts_mut(
+      core::ptr::from_mut(&mut value).cast::<u8>(),
+      core::mem::size_of::<T>(),
+    )
+  };
+  copy_in(address, bytes)?;
+  Ok(value)
+}
+
+/// Writes `value` to `address` in the memory of the program. `T` must not contain
+/// padding.
+fn write_object<T: Copy>(address: usize, value: &T) -> Result<(), isize> {
+  if !(address as *const T).is_aligned() {
+    return Err(error::EFAULT);
+  }
+  let bytes = unsafe {
+    core::slice::from_raw_parts(core::ptr::from_ref(value).cast::<u8>(), core::mem::size_of::<T>())
+  };
+  copy_out(address, bytes)
+}
+
+/// Calls `operation` with the buffer of `length` bytes at `address` and returns its
+/// result. There is no MMU support yet, so the buffer is copied (see [`copy_in`]): into
+/// a copy before the operation, or, if the operation `fills` the buffer, out of it
+/// afterwards.
+fn with_buffer(
+  address: usize,
+  length: usize,
+  fills: bool,
+  operation: impl FnOnce(&mut [u8]) -> Result<usize, isize>,
+) -> Result<usize, isize> {
+  if length == 0 {
+    return operation(&mut []);
+  }
+  // Checked before the operation, so that e.g. no data is consumed for a bad buffer
+  check_user_range(address, length)?;
+
+  let mut copy = heap::try_vec(0, length).map_err(|_| error::ENOMEM)?;
+  if !fills {
+    copy_in(address, &mut copy)?;
+  }
+  let done = operation(&mut copy)?;
+  if fills {
+    copy_out(address, &copy[..done])?;
+  }
+  Ok(done)
+}
+
+/// Returns the null-terminated string at `address`, which must be valid UTF-8.
+fn string(address: usize) -> Result<alloc::string::String, isize> {
+  let mut bytes = heap::try_vec(0, PATH_MAXIMUM).map_err(|_| error::ENOMEM)?;
+  let mut length = 0;
+  while length < PATH_MAXIMUM {
+    // The string is read up to the end of a page at a time, as the next page may not be
+    // mapped if the string ends before
+    let current = address.checked_add(length).ok_or(error::EFAULT)?;
+    let chunk = &mut bytes[length..(length + PAGE_SIZE - current % PAGE_SIZE).min(PATH_MAXIMUM)];
+    copy_in(current, chunk)?;
+    if let Some(end) = chunk.iter().position(|&byte| byte == 0) {
+      bytes.truncate(length + end);
+      return alloc::string::String::from_utf8(bytes).map_err(|_| error::EINVAL);
+    }
+    length += chunk.len();
+  }
+  Err(error::ENAMETOOLONG)
+}
+
+/// Reads up to `length` bytes from `descriptor` into the buffer at `address`.
+fn read(descriptor: usize, address: usize, length: usize) -> Result<isize, isize> {
+  let description = fs::descriptor::current()
+    .get(descriptor)
+    .map_err(error::from_fs)?;
+  let read = with_buffer(address, length, true, |buffer| {
+    description.read(buffer).map_err(error::from_fs)
+  })?;
+  isize::try_from(read).map_err(|_| error::EINVAL)
+}
+
+/// Writes `length` bytes from the buffer at `address` to `descriptor`. Writing to a pipe
+/// without readers raises [`Signal::SIGPIPE`].
+fn write(descriptor: usize, address: usize, length: usize) -> Result<isize, isize> {
+  let description = fs::descriptor::current()
+    .get(descriptor)
+    .map_err(error::from_fs)?;
+  let written = with_buffer(address, length, false, |buffer| {
+    description
+      .write(buffer)
+      .inspect_err(|&error| {
+        if error == fs::Error::BrokenPipe {
+          signal::current().raise(signal::Information::kernel(Signal::SIGPIPE));
+        }
+      })
+      .map_err(error::from_fs)
+  })?;
+  isize::try_from(written).map_err(|_| error::EINVAL)
+}
+
+/// Opens the file at the path `path`, which is relative to the directory `directory`
+/// unless it is absolute, and returns a new file descriptor for it.
+fn openat(directory: usize, path: usize, flags: usize, mode: usize) -> Result<isize, isize> {
+  let path = &*string(path)?;
+  let flags = OpenFlags::from_bits(u32::try_from(flags).map_err(|_| error::EINVAL)?);
+  let mode = u16::try_from(mode & 0o7777).unwrap_or_default();
+
+  let vfs = fs::get();
+  let base = if path.starts_with('/') || directory == AT_FDCWD {
+    vfs.root().map_err(error::from_fs)?
+  } else {
+    let description = fs::descriptor::current().get(directory).map_err(error::from_fs)?;
+    description.dentry().cloned().ok_or(error::ENOTDIR)?
+  };
+
+  let file = vfs.open_at(&base, path, flags, mode).map_err(error::from_fs)?;
+  let descriptor = fs::descriptor::current()
+    .insert(file, flags.contains(OpenFlags::CLOSE_ON_EXEC))
+    .map_err(error::from_fs)?;
+  descriptor_result(descriptor)
+}
+
+/// Closes `descriptor`.
+fn close(descriptor: usize) -> Result<isize, isize> {
+  fs::descriptor::current()
+    .close(descriptor)
+    .map_err(error::from_fs)?;
+  Ok(0)
+}
+
+/// Creates a pipe and writes the file descriptors of its reading and its writing end to
+/// the array of two `int`s at `descriptors`.
+fn pipe2(descriptors: usize, flags: usize) -> Result<isize, isize> {
+  let known = (OpenFlags::NON_BLOCKING | OpenFlags::CLOSE_ON_EXEC).bits() as usize;
+  if flags & !known != 0 {
+    return Err(error::EINVAL);
+  }
+  let flags = OpenFlags::from_bits(u32::try_from(flags).map_err(|_| error::EINVAL)?);
+
+  let table = fs::descriptor::current();
+  let close_on_exec = flags.contains(OpenFlags::CLOSE_ON_EXEC);
+  let (reader, writer) = fs::pipe::new(flags.contains(OpenFlags::NON_BLOCKING));
+  let reader = table.insert(reader, close_on_exec).map_err(error::from_fs)?;
+  let writer = match table.insert(writer, close_on_exec) {
+    Ok(writer) => writer,
+    Err(fs_error) => {
+      let _ = table.close(reader);
+      return Err(error::from_fs(fs_error));
+    },
+  };
+
+  // File descriptors are below `MAXIMUM_DESCRIPTORS` and therefore fit into an `int`
+  #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+  write_object(descriptors, &[reader as i32, writer as i32]).inspect_err(|_| {
+    let _ = table.close(reader);
+    let _ = table.close(writer);
+  })?;
+  Ok(0)
+}
+
+/// Duplicates `descriptor` to the lowest free file descriptor.
+fn dup(descriptor: usize) -> Result<isize, isize> {
+  let duplicate = fs::descriptor::current()
+    .duplicate(descriptor, 0, false)
+    .map_err(error::from_fs)?;
+  descriptor_result(duplicate)
+}
+
+/// Makes `target` refer to the same file as `descriptor`. The only flag is
+/// [`OpenFlags::CLOSE_ON_EXEC`].
+fn dup3(descriptor: usize, target: usize, flags: usize) -> Result<isize, isize> {
+  let close_on_exec = OpenFlags::CLOSE_ON_EXEC.bits() as usize;
+  if descriptor == target || flags & !close_on_exec != 0 {
+    return Err(error::EINVAL);
+  }
+
+  fs::descriptor::current()
+    .duplicate_to(descriptor, target, flags != 0)
+    .map_err(error::from_fs)?;
+  descriptor_result(target)
+}
+
+/// Executes the command `command` of `fcntl` on `descriptor`. Only the commands in
+/// [`mod@fcntl`] are supported.
+fn fcntl(descriptor: usize, command: usize, argument: usize) -> Result<isize, isize> {
+  let table = fs::descriptor::current();
+  match command {
+    fcntl::DUPLICATE | fcntl::DUPLICATE_CLOSE_ON_EXEC => {
+      if argument >= fs::descriptor::MAXIMUM_DESCRIPTORS {
+        return Err(error::EINVAL);
+      }
+      let close_on_exec = command == fcntl::DUPLICATE_CLOSE_ON_EXEC;
+      let duplicate = table
+        .duplicate(descriptor, argument, close_on_exec)
+        .map_err(error::from_fs)?;
+      descriptor_result(duplicate)
+    },
+    fcntl::GET_FLAGS => {
+      let close_on_exec = table.close_on_exec(descriptor).map_err(error::from_fs)?;
+      // The only flag is `fcntl::CLOSE_ON_EXEC`, which is 1
+      Ok(isize::from(close_on_exec))
+    },
+    fcntl::SET_FLAGS => {
+      table
+        .set_close_on_exec(descriptor, argument & fcntl::CLOSE_ON_EXEC != 0)
+        .map_err(error::from_fs)?;
+      Ok(0)
+    },
+    _ => Err(error::EINVAL),
+  }
+}
+
+/// Converts the result of an IPC operation that returns a slot.
+fn slot_result(slot: crate::library::ipc::Result<usize>) -> Result<isize, isize> {
+  let slot = slot.map_err(error::from_ipc)?;
+  isize::try_from(slot).map_err(|_| error::EMFILE)
+}
+
+/// Returns the message in the registers `a1` to `a5`.
+const fn message(arguments: &[usize; 6]) -> ipc::Message {
+  ipc::Message {
+    label:      arguments[1],
+    words:      [arguments[2], arguments[3], arguments[4]],
+    capability: if arguments[5] == NO_CAPABILITY {
+      None
+    } else {
+      Some(arguments[5])
+    },
+  }
+}
+
+/// Puts `message` into the registers `a1` to `a5`.
+const fn return_message(arguments: &mut [usize; 6], message: &ipc::Message) {
+  arguments[1] = message.label;
+  arguments[2] = message.words[0];
+  arguments[3] = message.words[1];
+  arguments[4] = message.words[2];
+  arguments[5] = match message.capability {
+    Some(slot) => slot,
+    None => NO_CAPABILITY,
+  };
+}
+
+/// Creates an endpoint and returns the slot of a capability with all rights for it.
+fn endpoint_create() -> Result<isize, isize> {
+  slot_result(ipc::current().insert(ipc::Capability {
+    object: ipc::Object::Endpoint(ipc::Endpoint::new()),
+    rights: ipc::Rights::ALL,
+  }))
+}
+
+/// Allocates `pages` zeroed pages and returns the slot of a capability with all rights
+/// for them.
+fn memory_allocate(pages: usize) -> Result<isize, isize> {
+  let memory = ipc::MemoryObject::allocate(pages).map_err(error::from_ipc)?;
+  slot_result(ipc::current().insert(ipc::Capability {
+    object: ipc::Object::Memory(memory),
+    rights: ipc::Rights::ALL,
+  }))
+}
+
+/// Returns the address and the number of pages of the memory object in the slot in `a0`
+/// in `a1` and `a2`. There is no virtual memory yet, so the pages are accessible at their
+/// physical address.
+fn memory_map(arguments: &mut [usize; 6]) -> Result<isize, isize> {
+  let capability = ipc::current().get(arguments[0]).map_err(error::from_ipc)?;
+  let ipc::Object::Memory(memory) = capability.object else {
+    return Err(error::from_ipc(ipc::Error::WrongType));
+  };
+  if !capability.rights.contains(ipc::Rights::READ) {
+    return Err(error::from_ipc(ipc::Error::InsufficientRights));
+  }
+
+  arguments[1] = memory.address();
+  arguments[2] = memory.pages();
+  Ok(0)
+}
+
+/// Copies the capability in `slot` with at most `rights` and returns the new slot.
+fn capability_copy(slot: usize, rights: usize) -> Result<isize, isize> {
+  slot_result(ipc::current().copy(slot, ipc::Rights::from_bits(rights)))
+}
+
+/// Removes the capability in `slot`.
+fn capability_delete(slot: usize) -> Result<isize, isize> {
+  ipc::current().remove(slot).map_err(error::from_ipc)?;
+  Ok(0)
+}
+
+/// Sends the message in `a1` to `a5` to the endpoint in the slot in `a0`.
+fn send(arguments: &[usize; 6]) -> Result<isize, isize> {
+  ipc::current()
+    .send(arguments[0], message(arguments))
+    .map_err(error::from_ipc)?;
+  Ok(0)
+}
+
+/// Receives a message from the endpoint in the slot in `a0` into `a1` to `a5`.
+fn receive(arguments: &mut [usize; 6]) -> Result<isize, isize> {
+  let message = ipc::current().receive(arguments[0]).map_err(error::from_ipc)?;
+  return_message(arguments, &message);
+  Ok(0)
+}
+
+/// Sends the message in `a1` to `a5` to the endpoint in the slot in `a0` and returns the
+/// reply in the same registers.
+fn call(arguments: &mut [usize; 6]) -> Result<isize, isize> {
+  let reply = ipc::current()
+    .call(arguments[0], message(arguments))
+    .map_err(error::from_ipc)?;
+  return_message(arguments, &reply);
+  Ok(0)
+}
+
+/// Answers the call that was received last with the message in `a1` to `a5`.
+fn reply(arguments: &[usize; 6]) -> Result<isize, isize> {
+  ipc::current()
+    .reply(message(arguments))
+    .map_err(error::from_ipc)?;
+  Ok(0)
+}
+
+/// Returns the object at `address` (see [`read_object`]), or [`None`] if `address` is
+/// null.
+fn optional<T: Copy + Default>(address: usize) -> Result<Option<T>, isize> {
+  if address == 0 {
+    return Ok(None);
+  }
+  read_object(address).map(Some)
+}
+
+/// Checks that `size` is the size of `sigset_t`.
+const fn check_signal_set_size(size: usize) -> Result<(), isize> {
+  if size == core::mem::size_of::<u64>() {
+    Ok(())
+  } else {
+    Err(error::EINVAL)
+  }
+}
+
+/// Sends the signal `number` to the process `process`. Zero refers to the process group
+/// of the caller, which is the caller alone. If `number` is zero, it is only checked
+/// whether the process exists.
+fn kill(process: usize, number: usize) -> Result<isize, isize> {
+  if process != 0 && process != PROCESS_ID {
+    return Err(error::ESRCH);
+  }
+  if number != 0 {
+    let signal = Signal::new(number).ok_or(error::EINVAL)?;
+    signal::current().raise(signal::Information::user(signal));
+  }
+  Ok(0)
+}
+
+/// Returns the previous action of the signal `number` in the `struct sigaction` at
+/// `previous` and sets it to the one at `action`; both may be null.
+fn rt_sigaction(number: usize, action: usize, previous: usize, set_size: usize) -> Result<isize, isize> {
+  check_signal_set_size(set_size)?;
+  let signal = Signal::new(number).ok_or(error::EINVAL)?;
+  let state = signal::current();
+
+  let old = state.action(signal);
+  if let Some(action) = optional::<SignalAction>(action)? {
+    let action = signal::Action {
+      handler: signal::Handler::from_raw(action.handler),
+      flags:   action.flags,
+      mask:    SignalSet::from_bits(action.mask),
+    };
+    state.set_action(signal, action).ok_or(error::EINVAL)?;
+  }
+  if previous != 0 {
+    write_object(
+      previous,
+      &SignalAction {
+        handler: old.handler.raw(),
+        flags:   old.flags,
+        mask:    old.mask.bits(),
+      },
+    )?;
+  }
+  Ok(0)
+}
+
+/// Returns the previous signal mask in the set at `previous` and changes it with the set
+/// at `set` according to `how` (see [`mod@sigprocmask`]); both may be null.
+fn rt_sigprocmask(how: usize, set: usize, previous: usize, set_size: usize) -> Result<isize, isize> {
+  check_signal_set_size(set_size)?;
+  let state = signal::current();
+
+  let old = state.mask();
+  if let Some(set) = optional::<u64>(set)? {
+    let set = SignalSet::from_bits(set);
+    let mask = match how {
+      sigprocmask::BLOCK => old.union(set),
+      sigprocmask::UNBLOCK => old.difference(set),
+      sigprocmask::SET_MASK => set,
+      _ => return Err(error::EINVAL),
+    };
+    state.set_mask(mask);
+  }
+  if previous != 0 {
+    write_object(previous, &old.bits())?;
+  }
+  Ok(0)
+}
+
+/// Writes the set of pending signals to the set at `set`.
+fn rt_sigpending(set: usize, set_size: usize) -> Result<isize, isize> {
+  check_signal_set_size(set_size)?;
+  write_object(set, &signal::current().pending().bits())?;
+  Ok(0)
+}
+
+/// Maps the memory described by `arguments` (see [`number::MMAP`]) and returns its
+/// address. Shared mappings of a file require it to be opened for writing only if they
+/// may be written.
+fn mmap(arguments: &[usize; 6]) -> Result<isize, isize> {
+  let [address, length, protection, flags, descriptor, offset] = *arguments;
+  let protection = Protection::from_bits(protection).ok_or(error::EINVAL)?;
+  let shared = match flags & (mmap::SHARED | mmap::PRIVATE) {
+    mmap::SHARED => true,
+    mmap::PRIVATE => false,
+    _ => return Err(error::EINVAL),
+  };
+
+  let all = Protection::READ | Protection::WRITE | Protection::EXECUTE;
+  let (object, offset, maximum): (alloc::sync::Arc<dyn VmObject>, u64, Protection) =
+    if flags & mmap::ANONYMOUS != 0 {
+      (Anonymous::new(), 0, all)
+    } else {
+      let description = fs::descriptor::current()
+        .get(descriptor)
+        .map_err(error::from_fs)?;
+      let file = description.file().ok_or(error::ENODEV)?;
+      if !file.flags().is_readable() {
+        return Err(error::EACCES);
+      }
+      let maximum = if !shared || file.flags().is_writable() {
+        all
+      } else {
+        Protection::READ | Protection::EXECUTE
+      };
+      (fs::cache::get(file.dentry().inode()), offset as u64, maximum)
+    };
+  if !maximum.contains(protection) {
+    return Err(error::EACCES);
+  }
+
+  let placement = if flags & mmap::FIXED != 0 {
+    Placement::Fixed(address)
+  } else if address != 0 {
+    Placement::Hint(address)
+  } else {
+    Placement::Anywhere
+  };
+  let address = address_space::current()
+    .map(
+      placement,
+      length,
+      Mapping {
+        object,
+        offset,
+        protection,
+        maximum,
+        shared,
+      },
+    )
+    .map_err(error::from_mem)?;
+  isize::try_from(address).map_err(|_| error::EINVAL)
+}
+
+/// Unmaps the `length` bytes at `address`.
+fn munmap(address: usize, length: usize) -> Result<isize, isize> {
+  address_space::current()
+    .unmap(address, length)
+    .map_err(error::from_mem)?;
+  Ok(0)
+}
+
+/// Sets the protection of the `length` bytes at `address` to `protection`.
+fn mprotect(address: usize, length: usize, protection: usize) -> Result<isize, isize> {
+  let protection = Protection::from_bits(protection).ok_or(error::EINVAL)?;
+  address_space::current()
+    .protect(address, length, protection)
+    .map_err(error::from_mem)?;
+  Ok(0)
+}
+
+/// Writes the changes to the shared mappings among the `length` bytes at `address` back
+/// to their files. Changes are always written synchronously, so the flags are ignored.
+fn msync(address: usize, length: usize) -> Result<isize, isize> {
+  address_space::current()
+    .sync(address, length)
+    .map_err(error::from_mem)?;
+  Ok(0)
+}
@@ -0,0 +1,130 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Unit tests of system calls.
+
+use super::{
+  clock,
+  dispatch,
+  error,
+  mmap,
+  number,
+  AT_FDCWD,
+};
+use crate::library::mem::{
+  address_space,
+  PAGE_SIZE,
+};
+
+/// An address in the kernel image, which programs must not access.
+const KERNEL_ADDRESS: usize = 0x8020_0000;
+
+/// Executes the system call with `number` and `arguments`; missing arguments are zero.
+fn call(number: usize, arguments: &[usize]) -> isize {
+  let mut all = [0; 6];
+  all[..arguments.len()].copy_from_slice(arguments);
+  dispatch(number, &mut all)
+}
+
+/// Maps `pages` pages of zeroed memory that can be read and written and returns their
+/// address.
+fn map(pages: usize) -> usize {
+  let address = call(
+    number::MMAP,
+    &[0, pages * PAGE_SIZE, 0b011, mmap::PRIVATE | mmap::ANONYMOUS],
+  );
+  assert!(address > 0, "mmap failed with {address}");
+  usize::try_from(address).unwrap()
+}
+
+/// Returns the two `int`s at `address`.
+fn descriptors(address: usize) -> [usize; 2] {
+  let mut bytes = [0; 8];
+  address_space::current().read(address, &mut bytes).unwrap();
+  let [reader, writer] =
+    [&bytes[..4], &bytes[4..]].map(|bytes| u32::from_ne_bytes(bytes.try_into().unwrap()));
+  [reader as usize, writer as usize]
+}
+
+#[test_case]
+fn pointers_outside_of_mappings_result_in_efault() {
+  let page = map(2);
+  call(number::MUNMAP, &[page + PAGE_SIZE, PAGE_SIZE]);
+  let space = address_space::current();
+
+  assert_eq!(call(number::PIPE2, &[page, 0]), 0);
+  let [reader, writer] = descriptors(page);
+  call(number::CLOSE, &[reader]);
+  call(number::CLOSE, &[writer]);
+  // The descriptors of a pipe that could not be returned are closed again
+  assert_eq!(call(number::PIPE2, &[KERNEL_ADDRESS, 0]), -error::EFAULT);
+  assert_eq!(call(number::PIPE2, &[page + 1, 0]), -error::EFAULT);
+  assert_eq!(call(number::PIPE2, &[page, 0]), 0);
+  assert_eq!(descriptors(page), [reader, writer]);
+
+  space.write(page + 64, b"hello").unwrap();
+  for address in [0, KERNEL_ADDRESS, page + PAGE_SIZE, page + PAGE_SIZE - 2] {
+    assert_eq!(call(number::WRITE, &[writer, address, 5]), -error::EFAULT);
+  }
+  assert_eq!(call(number::WRITE, &[writer, page + 64, 5]), 5);
+  assert_eq!(call(number::READ, &[reader, KERNEL_ADDRESS, 5]), -error::EFAULT);
+  assert_eq!(call(number::READ, &[reader, usize::MAX - 1, 5]), -error::EFAULT);
+  assert_eq!(call(number::READ, &[reader, page + 128, 5]), 5);
+  let mut buffer = [0; 5];
+  space.read(page + 128, &mut buffer).unwrap();
+  assert_eq!(&buffer, b"hello");
+
+  // A path that runs into an unmapped page
+  space.write(page + PAGE_SIZE - 3, b"/ab").unwrap();
+  assert_eq!(
+    call(number::OPENAT, &[AT_FDCWD, page + PAGE_SIZE - 3]),
+    -error::EFAULT
+  );
+  assert_eq!(call(number::OPENAT, &[AT_FDCWD, KERNEL_ADDRESS]), -error::EFAULT);
+  assert_eq!(call(number::OPENAT, &[AT_FDCWD, 0]), -error::EFAULT);
+
+  assert_eq!(call(number::RT_SIGPENDING, &[0, 8]), -error::EFAULT);
+  assert_eq!(call(number::RT_SIGPENDING, &[page + 256, 8]), 0);
+  assert_eq!(
+    call(number::RT_SIGPROCMASK, &[0, KERNEL_ADDRESS, 0, 8]),
+    -error::EFAULT
+  );
+  assert_eq!(
+    call(number::RT_SIGPROCMASK, &[0, 0, KERNEL_ADDRESS, 8]),
+    -error::EFAULT
+  );
+  assert_eq!(
+    call(number::RT_SIGACTION, &[10, KERNEL_ADDRESS, 0, 8]),
+    -error::EFAULT
+  );
+
+  call(number::CLOSE, &[reader]);
+  call(number::CLOSE, &[writer]);
+  assert_eq!(call(number::MUNMAP, &[page, PAGE_SIZE]), 0);
+}
+
+#[test_case]
+fn clock_gettime_writes_a_timespec() {
+  let page = map(2);
+  call(number::MUNMAP, &[page + PAGE_SIZE, PAGE_SIZE]);
+
+  let before = crate::library::time::uptime();
+  assert_eq!(call(number::CLOCK_GETTIME, &[clock::MONOTONIC, page + 16]), 0);
+  let mut bytes = [0; 16];
+  address_space::current().read(page + 16, &mut bytes).unwrap();
+  let [seconds, nanoseconds] =
+    [&bytes[..8], &bytes[8..]].map(|bytes| i64::from_ne_bytes(bytes.try_into().unwrap()));
+  let written = core::time::Duration::new(
+    u64::try_from(seconds).unwrap(),
+    u32::try_from(nanoseconds).unwrap(),
+  );
+  assert!(written >= before);
+
+  for address in [0, KERNEL_ADDRESS, page + 4, page + PAGE_SIZE - 8] {
+    assert_eq!(
+      call(number::CLOCK_GETTIME, &[clock::MONOTONIC, address]),
+      -error::EFAULT
+    );
+  }
+  assert_eq!(call(number::CLOCK_GETTIME, &[42, page]), -error::EINVAL);
+  assert_eq!(call(number::MUNMAP, &[page, PAGE_SIZE]), 0);
+}
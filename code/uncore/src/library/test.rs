@@ -3,6 +3,11 @@
 //! This module contains all functionality required for unit-testing `unCORE`. It also
 //! contains the entrypoints of the unit-test binaries for every architecture.
 
+crate::library::cmdline::kernel_param! {
+  /// Only the unit tests whose name contains this string are run (all by default).
+  pub FILTER: &'static str = "", "test.filter"
+}
+
 /// ### Streamlining Testing
 ///
 /// This trait provides the tests runner with the ability to `.run`
@@ -15,6 +20,11 @@ pub trait Testable {
   /// function it contains, as `Testable` is implemented for all
   /// generics that implement `Fn()`.
   fn run(&self);
+
+  /// ### Name of the Test
+  ///
+  /// Returns the path of the test function, which [`FILTER`] is matched against.
+  fn name(&self) -> &'static str;
 }
 
 impl<T> Testable for T
@@ -22,10 +32,12 @@ impl<T> Testable for T
   T: Fn(),
 {
   fn run(&self) {
-    log::debug!("Testing {}", ::core::any::type_name::<Self>());
+    log::debug!("Testing {}", self.name());
     self();
     log::trace!("Most recent test passed");
   }
+
+  fn name(&self) -> &'static str { ::core::any::type_name::<Self>() }
 }
 
 /// ### A (Very) Simple Test Runner Implementation
@@ -34,12 +46,14 @@ fn run(&self) {
 /// Cargo test's unit tests.
 ///
 /// It will just execute all functions marked with `#[test_case]` one
-/// by one.
+/// by one, except for those that the `test.filter` argument of the
+/// kernel command line excludes.
 #[cfg(test)]
 pub fn runner(tests: &[&dyn Testable]) {
   log::info!("Running unit-tests");
 
-  for test in tests {
+  let filter = FILTER.get();
+  for test in tests.iter().filter(|test| test.name().contains(filter)) {
     test.run();
   }
 
@@ -62,8 +76,8 @@ fn trivial_assertion() {
 /// `lib.rs` are run.
 #[cfg(all(target_arch = "riscv64", test))]
 #[riscv_rt::entry]
-fn riscv64_entry(hart: usize) -> ! {
-  crate::arch::initialize(hart);
+fn riscv64_entry(hart: usize, device_tree_address: usize) -> ! {
+  crate::arch::initialize(hart, device_tree_address);
   crate::setup_kernel(hart);
   crate::__test_runner();
   crate::arch::exit_kernel(crate::UncoreResult::Ok);
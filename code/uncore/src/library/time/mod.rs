@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! This module provides the kernel's notion of time.
+//!
+//! There are two clocks: the monotonic clock counts the time since the kernel started and
+//! is derived from the architecture's timer (see [`crate::arch::Timer`]), and the wall
+//! clock provides the calendar time. The wall clock is the monotonic clock plus an offset
+//! that a real-time clock driver sets once via [`set_realtime`].
+
+use core::sync::atomic::{
+  AtomicU64,
+  Ordering,
+};
+use core::time::Duration;
+
+/// The number of nanoseconds per second.
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+/// The frequency of the architecture's timer in Hz; zero if it is unknown.
+static TICKS_PER_SECOND: AtomicU64 = AtomicU64::new(0);
+/// The wall-clock time (in nanoseconds since the UNIX epoch) at which the monotonic clock
+/// started; zero if the wall-clock time is unknown.
+static REALTIME_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Reads the frequency of the architecture's timer from the device tree. Until this
+/// function has been called, the monotonic clock does not advance.
+pub fn initialize() {
+  let frequency = crate::library::device_tree::get()
+    .and_then(|tree| tree.find_node("/cpus"))
+    .and_then(|cpus| cpus.property("timebase-frequency"))
+    .and_then(|property| property.as_u64());
+
+  if let Some(frequency) = frequency {
+    TICKS_PER_SECOND.store(frequency, Ordering::Relaxed);
+  }
+}
+
+/// Returns the time that has passed since the architecture's timer started, which is
+/// usually when the machine was started.
+#[must_use]
+pub fn uptime() -> Duration {
+  let frequency = TICKS_PER_SECOND.load(Ordering::Relaxed);
+  if frequency == 0 {
+    return Duration::ZERO;
+  }
+
+  let ticks = crate::arch::ticks();
+  let nanoseconds =
+    u128::from(ticks % frequency) * u128::from(NANOSECONDS_PER_SECOND) / u128::from(frequency);
+  Duration::new(ticks / frequency, u32::try_from(nanoseconds).unwrap_or(u32::MAX))
+}
+
+/// Sets the wall clock to `now`, which is the time since the UNIX epoch. This function
+/// is called by real-time clock drivers.
+pub fn set_realtime(now: Duration) {
+  let now = u64::try_from(now.as_nanos()).unwrap_or(u64::MAX);
+  let uptime = u64::try_from(uptime().as_nanos()).unwrap_or(u64::MAX);
+  REALTIME_OFFSET.store(now.saturating_sub(uptime).max(1), Ordering::Relaxed);
+}
+
+/// Returns the wall-clock time, i.e. the time since the UNIX epoch, if it is known.
+#[must_use]
+pub fn realtime() -> Option<Duration> {
+  match REALTIME_OFFSET.load(Ordering::Relaxed) {
+    0 => None,
+    offset => Some(Duration::from_nanos(offset) + uptime()),
+  }
+}
+
+/// A point in calendar time (in UTC), used to display wall-clock times.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+  /// The year
+  pub year:       i64,
+  /// The month (1 to 12)
+  pub month:      u8,
+  /// The day of the month (1 to 31)
+  pub day:        u8,
+  /// The hour (0 to 23)
+  pub hour:       u8,
+  /// The minute (0 to 59)
+  pub minute:     u8,
+  /// The second (0 to 59)
+  pub second:     u8,
+  /// The fraction of the second in nanoseconds
+  pub nanosecond: u32,
+}
+
+impl From<Duration> for DateTime {
+  /// Converts the time since the UNIX epoch into a calendar date. The algorithm is
+  /// `civil_from_days` from <https://howardhinnant.github.io/date_algorithms.html>.
+  #[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss
+  )]
+  fn from(since_epoch: Duration) -> Self {
+    let seconds = since_epoch.as_secs();
+    let days = (seconds / 86_400) as i64;
+    let seconds_of_day = seconds % 86_400;
+
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+      month_index + 3
+    } else {
+      month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    Self {
+      year,
+      month: month as u8,
+      day: day as u8,
+      hour: (seconds_of_day / 3_600) as u8,
+      minute: (seconds_of_day % 3_600 / 60) as u8,
+      second: (seconds_of_day % 60) as u8,
+      nanosecond: since_epoch.subsec_nanos(),
+    }
+  }
+}
+
+impl core::fmt::Display for DateTime {
+  /// Formats the date and time according to ISO 8601 with millisecond precision.
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(
+      f,
+      "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+      self.year,
+      self.month,
+      self.day,
+      self.hour,
+      self.minute,
+      self.second,
+      self.nanosecond / 1_000_000
+    )
+  }
+}
+
+/// A point in time as `struct timespec` represents it, which is used to hand times to
+/// user programs.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct Timespec {
+  /// Whole seconds
+  pub seconds:     i64,
+  /// Nanoseconds (0 to 999,999,999)
+  pub nanoseconds: i64,
+}
+
+impl From<Duration> for Timespec {
+  fn from(duration: Duration) -> Self {
+    Self {
+      seconds:     i64::try_from(duration.as_secs()).unwrap_or(i64::MAX),
+      nanoseconds: i64::from(duration.subsec_nanos()),
+    }
+  }
+}
@@ -14,14 +14,18 @@
 // ? MODULES and GLOBAL / CRATE-LEVEL FUNCTIONS
 // ? ---------------------------------------------------------------------
 
-use uncore::*;
+use uncore::{
+  arch,
+  setup_kernel,
+  UncoreResult,
+};
 
 /// The RISC-V 64bit entrypoint, called by the [`riscv-rt`] runtime after SBI has set up
-/// the machine.
+/// the machine. SBI passes the ID of the hart and the address of the device tree.
 #[cfg(target_arch = "riscv64")]
 #[riscv_rt::entry]
-fn riscv64_entry(hart: usize) -> ! {
-  arch::initialize(hart);
+fn riscv64_entry(hart: usize, device_tree_address: usize) -> ! {
+  arch::initialize(hart, device_tree_address);
   setup_kernel(hart);
 
   ::log::warn!("This is an integration test!");
@@ -0,0 +1,183 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// ? GLOBAL CRATE ATTRIBUTES AND DOCUMENTATION
+// ? ---------------------------------------------------------------------
+
+// This crate does not and cannot use the standard library.
+#![no_std]
+// As this is no ordinary program, we have a special entry-point,
+// which is not the `main()` function.
+#![no_main]
+
+//! This integration test checks the ext2 driver against the image the helper creates
+//! with `mke2fs -d` before launching QEMU. The helper checks the image with `e2fsck`
+//! after the test has finished.
+
+// ? MODULES and GLOBAL / CRATE-LEVEL FUNCTIONS
+// ? ---------------------------------------------------------------------
+
+extern crate alloc;
+
+use alloc::{
+  string::String,
+  vec::Vec,
+};
+
+use uncore::{
+  arch,
+  fs,
+  setup_kernel,
+  UncoreResult,
+};
+
+/// Returns the directory the image is mounted at. The helper attaches several images, and
+/// the order in which the kernel finds them is not fixed.
+fn mount_point() -> String {
+  fs::get()
+    .mounts()
+    .into_iter()
+    .find(|mount| mount.file_system().name() == "ext2")
+    .map(|mount| String::from(mount.path()))
+    .expect("The ext2 image is not mounted")
+}
+
+/// Returns the absolute path of `path`, which is relative to [`mount_point`].
+fn path(path: &str) -> String { alloc::format!("{}/{path}", mount_point()) }
+
+/// Reads the file at `path` (relative to [`mount_point`]) completely.
+fn read(name: &str) -> Vec<u8> {
+  let file = fs::get()
+    .open(&path(name), fs::OpenFlags::READ, 0)
+    .expect("Could not open file");
+  let mut contents = Vec::new();
+  let mut buffer = [0; 1000];
+  loop {
+    match file.read(&mut buffer).expect("Could not read file") {
+      0 => return contents,
+      length => contents.extend_from_slice(&buffer[..length]),
+    }
+  }
+}
+
+/// Returns `length` bytes of the pattern the helper writes into `large.bin`.
+fn pattern(length: u32) -> Vec<u8> {
+  (0..length)
+    .map(|index| u8::try_from(index % 251).unwrap_or_default())
+    .collect()
+}
+
+/// Checks the files and links the helper put on the image.
+fn files_from_the_host_are_read() {
+  let vfs = fs::get();
+  assert_eq!(read("hello.txt"), b"Hello from the host!\n");
+  assert_eq!(
+    read("directory/nested.txt"),
+    b"This file is located in a directory.\n"
+  );
+  assert_eq!(read("large.bin"), pattern(300 * 1024));
+
+  let metadata = vfs.metadata(&path("hello.txt")).expect("No metadata");
+  let link = vfs.metadata(&path("hello-link.txt")).expect("No metadata");
+  assert_eq!(metadata.inode, link.inode);
+  assert_eq!(metadata.links, 2);
+  assert_eq!(
+    vfs.metadata(&path("secret")).map(|metadata| metadata.mode),
+    Ok(0o600)
+  );
+
+  assert_eq!(
+    vfs.read_link(&path("link-to-nested")).as_deref(),
+    Ok("directory/nested.txt")
+  );
+  assert_eq!(read("link-to-nested"), b"This file is located in a directory.\n");
+}
+
+/// Creates, writes, truncates, links and removes files and directories.
+fn files_are_written() {
+  let vfs = fs::get();
+
+  vfs
+    .create_directory(&path("created by unCORE"), 0o750)
+    .expect("Could not create directory");
+  let flags = fs::OpenFlags::READ_WRITE | fs::OpenFlags::CREATE;
+  let file = vfs
+    .open(&path("created by unCORE/written.bin"), flags, 0o640)
+    .expect("Could not create file");
+  let data = pattern(100_000);
+  assert_eq!(file.write(&data), Ok(data.len()));
+  file.truncate(10_000).expect("Could not truncate file");
+  assert_eq!(read("created by unCORE/written.bin"), &data[..10_000]);
+
+  vfs
+    .hard_link(&path("created by unCORE/written.bin"), &path("second name.bin"))
+    .expect("Could not create hard link");
+  vfs
+    .symlink("created by unCORE/written.bin", &path("symbolic link"))
+    .expect("Could not create symbolic link");
+  assert_eq!(read("symbolic link"), &data[..10_000]);
+
+  vfs
+    .remove_file(&path("remove-me.txt"))
+    .expect("Could not remove file");
+  vfs
+    .remove_file(&path("hello-link.txt"))
+    .expect("Could not remove hard link");
+  assert_eq!(
+    vfs.metadata(&path("hello.txt")).map(|metadata| metadata.links),
+    Ok(1)
+  );
+  assert_eq!(vfs.remove_directory(&path("directory")), Err(fs::Error::NotEmpty));
+  vfs
+    .remove_file(&path("directory/nested.txt"))
+    .expect("Could not remove file");
+  vfs
+    .remove_directory(&path("directory"))
+    .expect("Could not remove directory");
+  vfs
+    .remove_file(&path("large.bin"))
+    .expect("Could not remove file");
+}
+
+/// Mounts the image again and checks that the changes were written to the device.
+fn changes_are_persistent() {
+  let vfs = fs::get();
+  let mount_point = mount_point();
+  let name = mount_point.trim_start_matches("/mnt/");
+  vfs.unmount(&mount_point).expect("Could not unmount");
+  let device = fs::block::get(name).expect("Block device disappeared");
+  vfs
+    .mount(&mount_point, fs::Ext2::new(device).expect("No ext2 file system"))
+    .expect("Could not mount");
+
+  assert_eq!(read("second name.bin"), pattern(10_000));
+  assert_eq!(
+    vfs
+      .metadata(&path("created by unCORE"))
+      .map(|metadata| metadata.mode),
+    Ok(0o750)
+  );
+  assert_eq!(
+    vfs
+      .metadata(&path("second name.bin"))
+      .map(|metadata| metadata.links),
+    Ok(2)
+  );
+  assert_eq!(vfs.lookup(&path("large.bin")).err(), Some(fs::Error::NotFound));
+  vfs.unmount(&mount_point).expect("Could not unmount");
+}
+
+/// The RISC-V 64bit entrypoint, called by the [`riscv-rt`] runtime after SBI has set up
+/// the machine. SBI passes the ID of the hart and the address of the device tree.
+#[cfg(target_arch = "riscv64")]
+#[riscv_rt::entry]
+fn riscv64_entry(hart: usize, device_tree_address: usize) -> ! {
+  arch::initialize(hart, device_tree_address);
+  setup_kernel(hart);
+
+  files_from_the_host_are_read();
+  files_are_written();
+  changes_are_persistent();
+  ::log::info!("The ext2 image was read and written successfully");
+
+  arch::exit_kernel(UncoreResult::Ok);
+}
@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// ? GLOBAL CRATE ATTRIBUTES AND DOCUMENTATION
+// ? ---------------------------------------------------------------------
+
+// This crate does not and cannot use the standard library.
+#![no_std]
+// As this is no ordinary program, we have a special entry-point,
+// which is not the `main()` function.
+#![no_main]
+
+//! This integration test checks the FAT32 driver against the image the helper creates
+//! with `mkfs.vfat` and `mtools` before launching QEMU. The helper checks the image with
+//! `fsck.vfat` after the test has finished.
+
+// ? MODULES and GLOBAL / CRATE-LEVEL FUNCTIONS
+// ? ---------------------------------------------------------------------
+
+extern crate alloc;
+
+use alloc::{
+  string::String,
+  vec::Vec,
+};
+
+use uncore::{
+  arch,
+  fs,
+  setup_kernel,
+  UncoreResult,
+};
+
+/// Returns the directory the image is mounted at. The helper attaches several images, and
+/// the order in which the kernel finds them is not fixed.
+fn mount_point() -> String {
+  fs::get()
+    .mounts()
+    .into_iter()
+    .find(|mount| mount.file_system().name() == "vfat")
+    .map(|mount| String::from(mount.path()))
+    .expect("The FAT32 image is not mounted")
+}
+
+/// Reads the file at `path` (relative to [`mount_point`]) completely.
+fn read(path: &str) -> Vec<u8> {
+  let file = fs::get()
+    .open(
+      &alloc::format!("{}/{path}", mount_point()),
+      fs::OpenFlags::READ,
+      0,
+    )
+    .expect("Could not open file");
+  let mut contents = Vec::new();
+  let mut buffer = [0; 64];
+  loop {
+    match file.read(&mut buffer).expect("Could not read file") {
+      0 => return contents,
+      length => contents.extend_from_slice(&buffer[..length]),
+    }
+  }
+}
+
+/// Checks the files the helper put on the image.
+fn files_from_the_host_are_read() {
+  assert_eq!(read("A file with a long name.txt"), b"Hello from the host!\n");
+  assert_eq!(read("a file with a LONG name.TXT"), b"Hello from the host!\n");
+  assert_eq!(read("readme"), b"This file has a short name.\n");
+  assert_eq!(
+    read("Directory/nested.txt"),
+    b"This file is located in a directory.\n"
+  );
+}
+
+/// Creates, writes, truncates and removes files and directories.
+fn files_are_written() {
+  let vfs = fs::get();
+  let mount_point = mount_point();
+  let path = |name: &str| alloc::format!("{mount_point}/{name}");
+
+  vfs
+    .create_directory(&path("Created by unCORE"), 0o755)
+    .expect("Could not create directory");
+  let flags = fs::OpenFlags::READ_WRITE | fs::OpenFlags::CREATE;
+  let file = vfs
+    .open(
+      &path("Created by unCORE/A file written by the kernel.txt"),
+      flags,
+      0o644,
+    )
+    .expect("Could not create file");
+  let data: Vec<u8> = (0..2000_u32)
+    .map(|index| u8::try_from(index % 251).unwrap_or_default())
+    .collect();
+  assert_eq!(file.write(&data), Ok(data.len()));
+  file.truncate(1000).expect("Could not truncate file");
+  assert_eq!(
+    read("created by unCORE/a file written by the kernel.txt"),
+    &data[..1000]
+  );
+
+  vfs
+    .remove_file(&path("Remove me.txt"))
+    .expect("Could not remove file");
+  assert_eq!(
+    vfs.create_directory(&path("Directory"), 0o755),
+    Err(fs::Error::AlreadyExists)
+  );
+  assert_eq!(vfs.remove_directory(&path("Directory")), Err(fs::Error::NotEmpty));
+  vfs
+    .remove_file(&path("Directory/nested.txt"))
+    .expect("Could not remove file");
+  vfs
+    .remove_directory(&path("Directory"))
+    .expect("Could not remove directory");
+}
+
+/// Mounts the image again and checks that the changes were written to the device.
+fn changes_are_persistent() {
+  let vfs = fs::get();
+  let mount_point = mount_point();
+  let name = mount_point.trim_start_matches("/mnt/");
+  vfs.unmount(&mount_point).expect("Could not unmount");
+  let device = fs::block::get(name).expect("Block device disappeared");
+  vfs
+    .mount(&mount_point, fs::Fat::new(device).expect("No FAT32 file system"))
+    .expect("Could not mount");
+
+  let data: Vec<u8> = (0..1000_u32)
+    .map(|index| u8::try_from(index % 251).unwrap_or_default())
+    .collect();
+  assert_eq!(read("Created by unCORE/A file written by the kernel.txt"), data);
+  assert_eq!(
+    vfs.lookup(&alloc::format!("{mount_point}/Remove me.txt")).err(),
+    Some(fs::Error::NotFound)
+  );
+  vfs.unmount(&mount_point).expect("Could not unmount");
+}
+
+/// The RISC-V 64bit entrypoint, called by the [`riscv-rt`] runtime after SBI has set up
+/// the machine. SBI passes the ID of the hart and the address of the device tree.
+#[cfg(target_arch = "riscv64")]
+#[riscv_rt::entry]
+fn riscv64_entry(hart: usize, device_tree_address: usize) -> ! {
+  arch::initialize(hart, device_tree_address);
+  setup_kernel(hart);
+
+  files_from_the_host_are_read();
+  files_are_written();
+  changes_are_persistent();
+  ::log::info!("The FAT32 image was read and written successfully");
+
+  arch::exit_kernel(UncoreResult::Ok);
+}